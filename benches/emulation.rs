@@ -0,0 +1,146 @@
+//! Instructions per second on a few representative workloads: a real game, and loops hammering
+//! the ALU, the display and memory. Decoding is also measured on its own.
+//!
+//! Run with `cargo bench --no-default-features`.
+
+use chip8rs::instruction::Instruction;
+use chip8rs::program::*;
+use chip8rs::Chip8;
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+
+/// Instructions run per 60Hz frame, as in the emulator at its default speed.
+const CYCLES_PER_FRAME: u32 = 10;
+
+/// Frames run per iteration: one second of emulated time.
+const FRAMES: u32 = 60;
+
+const SPACE_INVADERS: &[u8] = include_bytes!("../roms/Space Invaders [David Winter].ch8");
+
+/// Arithmetic and logic on registers, and skips.
+fn alu() -> Vec<u8> {
+    let mut p = Program::new();
+    let top = p.label();
+    p.bind(top)
+        .add(V0, 1)
+        .ld(V1, V0)
+        .xor(V1, V2)
+        .add(V2, V1)
+        .sub(V3, V1)
+        .shr(V4, V4)
+        .or(V5, V0)
+        .se(V0, 0)
+        .and(V6, V1)
+        .jp(top);
+    p.assemble()
+        .expect("the benchmark programs only use bound labels")
+}
+
+/// Sprites drawn all over the display.
+fn sprites() -> Vec<u8> {
+    let mut p = Program::new();
+    let top = p.label();
+    p.bind(top)
+        .ld_font(V2)
+        .drw(V0, V1, 5)
+        .add(V0, 5)
+        .add(V1, 3)
+        .add(V2, 1)
+        .jp(top);
+    p.assemble()
+        .expect("the benchmark programs only use bound labels")
+}
+
+/// Registers converted to BCD, saved and restored.
+fn memory() -> Vec<u8> {
+    let mut p = Program::new();
+    let top = p.label();
+    let scratch = p.label();
+    p.bind(top)
+        .add(V0, 7)
+        .ld_i(scratch)
+        .bcd(V0)
+        .store(VF)
+        .ld_i(scratch)
+        .load(VF)
+        .jp(top);
+    p.bind(scratch).db(&[0; 16]);
+    p.assemble()
+        .expect("the benchmark programs only use bound labels")
+}
+
+fn instructions(c: &mut Criterion) {
+    let workloads = [
+        ("space_invaders", SPACE_INVADERS.to_vec()),
+        ("alu", alu()),
+        ("sprites", sprites()),
+        ("memory", memory()),
+    ];
+    let mut group = c.benchmark_group("instructions");
+    group.throughput(Throughput::Elements((CYCLES_PER_FRAME * FRAMES) as u64));
+    for (name, rom) in &workloads {
+        let mut chip8 = Chip8::from_bytes(rom);
+        group.bench_function(*name, |b| {
+            b.iter(|| {
+                for _ in 0..FRAMES {
+                    chip8.step_frame(CYCLES_PER_FRAME).unwrap();
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+/// The same work as one `step_frame` call, one instruction at a time.
+fn step_vs_frame(c: &mut Criterion) {
+    let mut group = c.benchmark_group("step_vs_frame");
+    group.throughput(Throughput::Elements(CYCLES_PER_FRAME as u64));
+    let mut chip8 = Chip8::from_bytes(SPACE_INVADERS);
+    group.bench_function("step", |b| {
+        b.iter(|| {
+            for _ in 0..CYCLES_PER_FRAME {
+                let period = chip8.instruction_time(CYCLES_PER_FRAME * 60);
+                chip8.step().unwrap();
+                chip8.advance_time(period);
+            }
+        })
+    });
+    let mut chip8 = Chip8::from_bytes(SPACE_INVADERS);
+    group.bench_function("step_frame", |b| {
+        b.iter(|| chip8.step_frame(CYCLES_PER_FRAME).unwrap())
+    });
+    group.finish();
+}
+
+/// Fast-forwarding at a million instructions per second, with and without the cached
+/// interpreter.
+fn block_cache(c: &mut Criterion) {
+    const FAST_CYCLES_PER_FRAME: u32 = 1_000_000 / 60;
+
+    let mut group = c.benchmark_group("block_cache");
+    group.throughput(Throughput::Elements(FAST_CYCLES_PER_FRAME as u64));
+    for (name, cached) in [("plain", false), ("cached", true)] {
+        let mut chip8 = Chip8::from_bytes(SPACE_INVADERS);
+        chip8.set_block_cache(cached);
+        group.bench_function(name, |b| {
+            b.iter(|| chip8.step_frame(FAST_CYCLES_PER_FRAME).unwrap())
+        });
+    }
+    group.finish();
+}
+
+/// Decoding every opcode.
+fn decode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("decode");
+    group.throughput(Throughput::Elements(0x10000));
+    group.bench_function("all_opcodes", |b| {
+        b.iter(|| {
+            for opcode in 0..=u16::MAX {
+                let _ = black_box(Instruction::decode(black_box(opcode)));
+            }
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, instructions, step_vs_frame, block_cache, decode);
+criterion_main!(benches);
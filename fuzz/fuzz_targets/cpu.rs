@@ -0,0 +1,73 @@
+//! Run arbitrary programs on every kind of machine, with arbitrary quirks and RAM policies.
+//!
+//! The input starts with a header choosing the machine, then holds the program, loaded at 0x200
+//! like a ROM: a stream of opcodes that is also the RAM they read and write. Bad programs must
+//! stop with a `Chip8Error`; a panic is a bug in the interpreter.
+//!
+//! Run with `cargo fuzz run cpu` from the repository root. Add the crashes it finds as tests,
+//! as in `tests/wraparound.rs`.
+
+#![no_main]
+
+use chip8rs::config::{self, QuirkOverrides, Quirks};
+use chip8rs::ram::OutOfRange;
+use chip8rs::Chip8;
+use libfuzzer_sys::fuzz_target;
+
+/// Bytes before the program: the machine and RAM policy, the quirks, and the keypad.
+const HEADER_LEN: usize = 4;
+
+/// Frames run per input, at `CYCLES_PER_FRAME` instructions each.
+const FRAMES: usize = 200;
+const CYCLES_PER_FRAME: u32 = 10;
+
+fuzz_target!(|data: &[u8]| {
+    if data.len() < HEADER_LEN {
+        return;
+    }
+    let (header, rom) = data.split_at(HEADER_LEN);
+
+    // Byte 0: the machine, what happens past the end of RAM, the stack and the interpreter.
+    let (build, ram_size): (fn(&[u8]) -> Chip8, usize) = match header[0] & 0b11 {
+        1 => (Chip8::from_bytes_xochip, config::XO_RAM_SIZE),
+        2 => (Chip8::from_bytes_megachip, config::XO_RAM_SIZE),
+        _ => (Chip8::from_bytes, config::RAM_SIZE),
+    };
+    let rom = &rom[..rom.len().min(ram_size - config::PROG_ADDR as usize)];
+    let mut chip8 = build(rom);
+    chip8.interconnect.ram.out_of_range = match (header[0] >> 2) & 0b11 {
+        1 => OutOfRange::Wrap,
+        2 => OutOfRange::Zero,
+        _ => OutOfRange::Error,
+    };
+    if header[0] & 0x10 != 0 {
+        chip8
+            .set_stack(config::RAM_STACK_MAX_DEPTH, true)
+            .expect("the maximum depth is valid");
+    }
+    chip8.set_block_cache(header[0] & 0x20 != 0);
+    chip8.set_vip_timing(header[0] & 0x40 != 0);
+
+    // Bytes 1 and 2: one bit per quirk.
+    let bits = u16::from_le_bytes([header[1], header[2]]);
+    let overrides = Quirks::NAMES
+        .iter()
+        .enumerate()
+        .map(|(i, name)| (name.to_string(), bits & 1 << i != 0))
+        .collect();
+    let mut quirks = Quirks::default();
+    quirks.apply(&QuirkOverrides(overrides));
+    chip8.set_quirks(quirks);
+
+    // Byte 3: a key held down from the start, if its high bit is set.
+    if header[3] & 0x80 != 0 {
+        chip8.set_key(header[3] & 0xF, true);
+    }
+
+    for _ in 0..FRAMES {
+        if chip8.step_frame(CYCLES_PER_FRAME).is_err() {
+            break;
+        }
+        chip8.gfx_buffer();
+    }
+});
@@ -0,0 +1,186 @@
+//! Loading ROMs out of zip archives.
+//!
+//! Only what ROM collections need is supported: stored and deflated entries of archives that
+//! don't need the zip64 extensions.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, ensure, Context, Result};
+use log::info;
+
+use crate::detect;
+use crate::patch;
+
+const LOCAL_HEADER: u32 = 0x0403_4b50;
+const CENTRAL_HEADER: u32 = 0x0201_4b50;
+const END_OF_DIRECTORY: u32 = 0x0605_4b50;
+/// Size of the end of central directory record, without its comment.
+const END_OF_DIRECTORY_SIZE: usize = 22;
+
+const STORED: u16 = 0;
+const DEFLATED: u16 = 8;
+
+/// Entries larger than this are refused, since no ROM comes near it.
+const MAX_ENTRY_SIZE: usize = 1 << 20;
+
+/// A file in a zip archive.
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub name: String,
+    /// Uncompressed size.
+    pub size: usize,
+    method: u16,
+    crc: u32,
+    compressed_size: usize,
+    /// Offset of the entry's local header.
+    offset: usize,
+}
+
+impl Entry {
+    pub fn is_dir(&self) -> bool {
+        self.name.ends_with('/')
+    }
+}
+
+/// Whether `data` looks like a zip archive.
+pub fn is_zip(data: &[u8]) -> bool {
+    data.len() >= 4 && matches!(u32_at(data, 0), LOCAL_HEADER | END_OF_DIRECTORY)
+}
+
+/// The files and directories in the zip archive `zip`, as listed in its central directory.
+pub fn entries(zip: &[u8]) -> Result<Vec<Entry>> {
+    let end = (0..=zip.len().saturating_sub(END_OF_DIRECTORY_SIZE))
+        .rev()
+        .find(|&pos| {
+            zip.len() >= pos + END_OF_DIRECTORY_SIZE && u32_at(zip, pos) == END_OF_DIRECTORY
+        })
+        .context("Not a zip archive")?;
+    let count = u16_at(zip, end + 10) as usize;
+    let mut pos = u32_at(zip, end + 16) as usize;
+    ensure!(
+        count != 0xFFFF && pos != 0xFFFF_FFFF,
+        "Zip64 archives aren't supported"
+    );
+    let mut entries = Vec::with_capacity(count);
+    for _ in 0..count {
+        let header = zip.get(pos..pos + 46).context("Truncated zip archive")?;
+        ensure!(
+            u32_at(header, 0) == CENTRAL_HEADER,
+            "Corrupt zip central directory"
+        );
+        let name_len = u16_at(header, 28) as usize;
+        let extra_len = u16_at(header, 30) as usize;
+        let comment_len = u16_at(header, 32) as usize;
+        let name = zip
+            .get(pos + 46..pos + 46 + name_len)
+            .context("Truncated zip archive")?;
+        entries.push(Entry {
+            name: String::from_utf8_lossy(name).into_owned(),
+            size: u32_at(header, 24) as usize,
+            method: u16_at(header, 10),
+            crc: u32_at(header, 16),
+            compressed_size: u32_at(header, 20) as usize,
+            offset: u32_at(header, 42) as usize,
+        });
+        pos += 46 + name_len + extra_len + comment_len;
+    }
+    Ok(entries)
+}
+
+/// The contents of `entry` of the zip archive `zip`.
+pub fn extract(zip: &[u8], entry: &Entry) -> Result<Vec<u8>> {
+    ensure!(
+        entry.size <= MAX_ENTRY_SIZE,
+        "{} is too large ({} bytes)",
+        entry.name,
+        entry.size
+    );
+    let header = zip
+        .get(entry.offset..entry.offset + 30)
+        .context("Truncated zip archive")?;
+    ensure!(
+        u32_at(header, 0) == LOCAL_HEADER,
+        "Corrupt zip entry {}",
+        entry.name
+    );
+    let start = entry.offset + 30 + u16_at(header, 26) as usize + u16_at(header, 28) as usize;
+    let data = zip
+        .get(start..start + entry.compressed_size)
+        .context("Truncated zip archive")?;
+    let content = match entry.method {
+        STORED => data.to_vec(),
+        DEFLATED => miniz_oxide::inflate::decompress_to_vec_with_limit(data, MAX_ENTRY_SIZE)
+            .map_err(|e| anyhow::anyhow!("Failed to inflate {}: {:?}", entry.name, e))?,
+        method => bail!(
+            "{} uses an unsupported compression method ({})",
+            entry.name,
+            method
+        ),
+    };
+    ensure!(
+        content.len() == entry.size && patch::crc32(&content) == entry.crc,
+        "{} is corrupt (checksum mismatch)",
+        entry.name
+    );
+    Ok(content)
+}
+
+/// The name and contents of the ROM in the zip archive `zip`: its only file with a ROM extension
+/// (`.ch8`, `.sc8`, `.xo8` or `.mc8`), or its only file if none has one.
+pub fn find_rom(zip: &[u8]) -> Result<(String, Vec<u8>)> {
+    let files: Vec<Entry> = entries(zip)?
+        .into_iter()
+        // Resource forks added by macOS' archiver.
+        .filter(|entry| !entry.is_dir() && !entry.name.starts_with("__MACOSX/"))
+        .collect();
+    let roms: Vec<&Entry> = files
+        .iter()
+        .filter(|entry| detect::from_extension(Path::new(&entry.name)).is_some())
+        .collect();
+    let entry = match (&roms[..], &files[..]) {
+        ([rom], _) => *rom,
+        ([], [file]) => file,
+        ([], []) => bail!("The archive is empty"),
+        ([], _) => bail!(
+            "The archive has no .ch8, .sc8, .xo8 or .mc8 file, only {}",
+            names(&files)
+        ),
+        _ => bail!("The archive has several ROMs: {}", names(roms)),
+    };
+    Ok((entry.name.clone(), extract(zip, entry)?))
+}
+
+/// Read the ROM at `path`, or the one in it if it is a zip archive (see `unpack`).
+pub fn read_rom(path: &Path) -> Result<(PathBuf, Vec<u8>)> {
+    let data = std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    unpack(path, data)
+}
+
+/// The ROM in `data`, read from `path`: `data` itself, or the ROM in it if it is a zip archive
+/// (see `find_rom`). Also returns the path of the ROM file, inside the archive for a zip, to
+/// detect its interpreter from.
+pub fn unpack(path: &Path, data: Vec<u8>) -> Result<(PathBuf, Vec<u8>)> {
+    if !is_zip(&data) {
+        return Ok((path.to_path_buf(), data));
+    }
+    let (name, rom) = find_rom(&data).with_context(|| format!("Can't load {}", path.display()))?;
+    info!("using {} from {}", name, path.display());
+    Ok((PathBuf::from(name), rom))
+}
+
+/// The names of `entries`, separated by commas.
+fn names<'a>(entries: impl IntoIterator<Item = &'a Entry>) -> String {
+    let names: Vec<&str> = entries
+        .into_iter()
+        .map(|entry| entry.name.as_str())
+        .collect();
+    names.join(", ")
+}
+
+fn u16_at(data: &[u8], pos: usize) -> u16 {
+    u16::from_le_bytes([data[pos], data[pos + 1]])
+}
+
+fn u32_at(data: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]])
+}
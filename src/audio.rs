@@ -0,0 +1,220 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use log::warn;
+use rodio::cpal::traits::HostTrait;
+use rodio::source::{Buffered, Source};
+use rodio::{Decoder, DeviceTrait, OutputStream, OutputStreamHandle, Sink};
+
+/// Names of the available audio output devices.
+pub fn output_devices() -> Result<Vec<String>> {
+    let devices = rodio::cpal::default_host()
+        .output_devices()
+        .context("Failed to list audio devices")?;
+    Ok(devices.filter_map(|device| device.name().ok()).collect())
+}
+
+/// Open the output device called `name`, or the default one.
+fn open_output(name: Option<&str>) -> Result<(OutputStream, OutputStreamHandle)> {
+    let name = match name {
+        Some(name) => name,
+        None => return OutputStream::try_default().context("Failed to open audio output"),
+    };
+    let device = rodio::cpal::default_host()
+        .output_devices()
+        .context("Failed to list audio devices")?
+        .find(|device| device.name().is_ok_and(|n| n == name))
+        .with_context(|| format!("No audio device called {:?}", name))?;
+    OutputStream::try_from_device(&device)
+        .with_context(|| format!("Failed to open audio device {:?}", name))
+}
+
+/// Sample rate of the generated tone.
+const TONE_SAMPLE_RATE: u32 = 44_100;
+
+/// A square wave, the sound of the classic CHIP-8 buzzer.
+struct SquareWave {
+    /// Fraction of a period elapsed per sample.
+    step: f32,
+    phase: f32,
+}
+
+impl SquareWave {
+    fn new(frequency: f32) -> Self {
+        Self {
+            step: frequency / TONE_SAMPLE_RATE as f32,
+            phase: 0.0,
+        }
+    }
+}
+
+impl Iterator for SquareWave {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        self.phase = (self.phase + self.step).fract();
+        Some(if self.phase < 0.5 { 0.25 } else { -0.25 })
+    }
+}
+
+impl Source for SquareWave {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        1
+    }
+
+    fn sample_rate(&self) -> u32 {
+        TONE_SAMPLE_RATE
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+/// An XO-CHIP audio pattern and pitch, as set by `F002` and `FX3A`.
+pub type Pattern = ([u8; 16], u8);
+
+/// Plays the 128 1-bit samples of an XO-CHIP audio pattern in a loop.
+struct PatternSource {
+    bits: [u8; 16],
+    sample_rate: u32,
+    pos: usize,
+}
+
+impl PatternSource {
+    fn new((bits, pitch): Pattern) -> Self {
+        Self {
+            bits,
+            sample_rate: (4000.0 * 2f32.powf((pitch as f32 - 64.0) / 48.0)) as u32,
+            pos: 0,
+        }
+    }
+}
+
+impl Iterator for PatternSource {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let bit = self.bits[self.pos / 8] & (0x80 >> (self.pos % 8));
+        self.pos = (self.pos + 1) % 128;
+        Some(if bit == 0 { -0.25 } else { 0.25 })
+    }
+}
+
+impl Source for PatternSource {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        1
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+/// Sound played while the sound timer is active.
+pub struct Buzzer {
+    // Dropping the stream would silence the output, so it has to be kept around.
+    _stream: OutputStream,
+    handle: OutputStreamHandle,
+    /// Sound looped instead of the tone.
+    sample: Option<Buffered<Decoder<BufReader<File>>>>,
+    /// The audio pattern loaded by an XO-CHIP program, played instead of the sample or tone.
+    pattern: Option<Pattern>,
+    /// Frequency of the tone, in Hz.
+    frequency: f32,
+    /// Volume, from 0.0 (silent) to 1.0.
+    volume: f32,
+    /// Sink currently playing the sample, if the buzzer is on.
+    sink: Option<Sink>,
+}
+
+impl Buzzer {
+    /// A buzzer playing a 440Hz square wave on `device` or the default output.
+    pub fn new(device: Option<&str>) -> Result<Self> {
+        let (stream, handle) = open_output(device)?;
+        Ok(Self {
+            _stream: stream,
+            handle,
+            sample: None,
+            pattern: None,
+            frequency: 440.0,
+            volume: 1.0,
+            sink: None,
+        })
+    }
+
+    /// Use the sound in `path` (WAV or Ogg Vorbis), looped, as the buzzer, playing it on `device`
+    /// or the default output.
+    pub fn with_sample<P: AsRef<Path>>(path: P, device: Option<&str>) -> Result<Self> {
+        let path = path.as_ref();
+        let file =
+            File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+        let sample = Decoder::new(BufReader::new(file))
+            .with_context(|| format!("Failed to decode {}", path.display()))?
+            .buffered();
+        let mut buzzer = Self::new(device)?;
+        buzzer.sample = Some(sample);
+        Ok(buzzer)
+    }
+
+    /// Start or stop the buzzer. The sample starts over every time the buzzer is turned on.
+    pub fn set_active(&mut self, on: bool) {
+        // Dropping the sink stops it.
+        self.sink = None;
+        if on {
+            match Sink::try_new(&self.handle) {
+                Ok(sink) => {
+                    sink.set_volume(self.volume);
+                    if let Some(pattern) = self.pattern {
+                        sink.append(PatternSource::new(pattern));
+                    } else if let Some(sample) = &self.sample {
+                        sink.append(sample.clone().repeat_infinite());
+                    } else {
+                        sink.append(SquareWave::new(self.frequency));
+                    }
+                    self.sink = Some(sink);
+                }
+                Err(e) => warn!("Failed to play buzzer: {}", e),
+            }
+        }
+    }
+
+    /// Play a tone at `frequency` Hz when there is no sample, from the next time the buzzer is
+    /// turned on.
+    pub fn set_frequency(&mut self, frequency: f32) {
+        self.frequency = frequency;
+    }
+
+    /// Set the volume, from 0.0 (silent) to 1.0.
+    pub fn set_volume(&mut self, volume: f32) {
+        self.volume = volume;
+        if let Some(sink) = &self.sink {
+            sink.set_volume(volume);
+        }
+    }
+
+    /// Play `pattern` instead of the sample from now on, restarting the sound if it's playing.
+    pub fn set_pattern(&mut self, pattern: Option<Pattern>) {
+        if pattern != self.pattern {
+            self.pattern = pattern;
+            if self.sink.is_some() {
+                self.set_active(true);
+            }
+        }
+    }
+}
@@ -0,0 +1,112 @@
+//! A cached interpreter for fast runs: straight-line runs of instructions (blocks), up to and
+//! including the next jump, call, return or skip, are decoded once and kept by address, so that
+//! running them again skips fetching and decoding.
+//!
+//! The RAM holding cached instructions is marked with `Ram::mark_code`, and writing to it throws
+//! away the blocks decoded from it, so that self-modifying programs still run the code they wrote.
+
+use std::ops::Range;
+
+use crate::cpu::Cpu;
+use crate::error::Chip8Error;
+use crate::instruction::Instruction;
+use crate::interconnect::Interconnect;
+
+/// Most instructions decoded into a single block.
+const MAX_BLOCK_LEN: usize = 64;
+
+/// Blocks of decoded instructions, by address of their first instruction.
+#[derive(Default)]
+pub struct BlockCache {
+    /// The block starting at each address, if it was decoded. Empty until the first block is.
+    blocks: Vec<Option<Box<[Instruction]>>>,
+}
+
+impl BlockCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The block starting at `pc`, decoding it for `cpu` if it isn't cached yet. Fails like
+    /// `Cpu::emulate_cycle` would if the first instruction can't be fetched or decoded; a later
+    /// one that can't ends the block before it.
+    pub fn get(
+        &mut self,
+        pc: u16,
+        cpu: &Cpu,
+        interconnect: &mut Interconnect,
+    ) -> Result<&[Instruction], Chip8Error> {
+        if self.blocks.is_empty() {
+            self.blocks = (0..=u16::MAX).map(|_| None).collect();
+        }
+        let slot = &mut self.blocks[pc as usize];
+        if slot.is_none() {
+            let block = decode_block(pc, cpu, interconnect)?;
+            interconnect.ram.mark_code(pc as usize, 2 * block.len());
+            *slot = Some(block);
+        }
+        Ok(slot.as_deref().unwrap_or_default())
+    }
+
+    /// Throw away the blocks decoded from `range`, which was written to.
+    pub fn invalidate(&mut self, range: Range<usize>) {
+        let first = range.start.saturating_sub(2 * MAX_BLOCK_LEN);
+        let last = range.end.min(self.blocks.len());
+        for start in first..last {
+            let slot = &mut self.blocks[start];
+            if slot.as_ref().map_or(0, |block| start + 2 * block.len()) > range.start {
+                *slot = None;
+            }
+        }
+    }
+}
+
+/// Decode the instructions at `pc` up to the end of the block.
+fn decode_block(
+    pc: u16,
+    cpu: &Cpu,
+    interconnect: &Interconnect,
+) -> Result<Box<[Instruction]>, Chip8Error> {
+    let mut instructions = Vec::new();
+    let mut addr = pc;
+    loop {
+        let instruction = interconnect
+            .fetch_opcode(addr)
+            .and_then(|opcode| cpu.decode(opcode));
+        let instruction = match instruction {
+            Ok(instruction) => instruction,
+            Err(e) if instructions.is_empty() => return Err(e),
+            Err(_) => break,
+        };
+        instructions.push(instruction);
+        addr = addr.wrapping_add(2);
+        if ends_block(instruction) || instructions.len() == MAX_BLOCK_LEN || addr < pc {
+            break;
+        }
+    }
+    Ok(instructions.into())
+}
+
+/// Whether `instruction` may continue anywhere else than at the next instruction.
+fn ends_block(instruction: Instruction) -> bool {
+    use Instruction::*;
+
+    matches!(
+        instruction,
+        Sys(_)
+            | Return
+            | Exit
+            | Jump(_)
+            | Call(_)
+            | JumpOffset(_)
+            | SkipEq(..)
+            | SkipNe(..)
+            | SkipEqReg(..)
+            | SkipNeReg(..)
+            | SkipKey(_)
+            | SkipNoKey(_)
+            | WaitKey(_)
+            | LoadLong
+            | LoadHigh(_)
+    )
+}
@@ -0,0 +1,764 @@
+//! The whole machine, tying the CPU to the RAM, display, timers and keypad.
+
+use std::fmt::Write;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{bail, ensure, Context, Result};
+use log::info;
+
+use crate::archive;
+use crate::blocks::BlockCache;
+use crate::config::{self, Quirks};
+use crate::coverage::Coverage;
+use crate::cpu::Cpu;
+use crate::detect::Profile;
+use crate::error::{Chip8Error, Fault};
+use crate::gfx::{self, Afterglow, Gfx};
+use crate::idle::{IdleLoops, Skipped};
+use crate::instruction::Instruction;
+use crate::interconnect::{Interconnect, TIMER_PERIOD};
+use crate::metrics::Metrics;
+use crate::profiler::{CallProfiler, InstructionProfiler};
+use crate::ram::Ram;
+use crate::savestate::State;
+use crate::timing;
+use crate::trace::Tracer;
+
+/// The first instruction of hires CHIP-8 programs, a jump into the machine code that sets up their
+/// 64x64 display on the COSMAC VIP.
+const TWO_PAGE_START: [u8; 2] = [0x12, 0x60];
+/// Where hires CHIP-8 programs really start, after that machine code.
+const TWO_PAGE_ENTRY: u16 = 0x2C0;
+
+/// This represents the Chip-8 virtual machine. It is composed of a `Cpu` and an `Interconnect`.
+pub struct Chip8 {
+    rom: Vec<u8>,
+    pub cpu: Cpu,
+    pub interconnect: Interconnect,
+    profiler: Option<CallProfiler>,
+    instruction_profiler: Option<InstructionProfiler>,
+    tracer: Option<Tracer>,
+    idle: Option<IdleLoops>,
+    /// Where the program is loaded and starts.
+    load_addr: u16,
+    /// Whether instructions take as long as on the COSMAC VIP, see `timing`.
+    vip_timing: bool,
+    /// Decoded instructions for `step_frame`, if it uses the cached interpreter.
+    blocks: Option<BlockCache>,
+    /// Seed of the random number generator, if runs should be reproducible.
+    seed: Option<u64>,
+    /// Number of 60Hz frames (timer ticks) since the start.
+    frames: u64,
+    /// Number of instructions executed since the start.
+    cycles: u64,
+}
+
+impl Chip8 {
+    /// Create a machine running the ROM at `path`, or the ROM in it if it is a zip archive,
+    /// checking that it fits in RAM.
+    pub fn new<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let (_, rom) = archive::read_rom(path)?;
+        Self::check_rom(&rom, false).with_context(|| format!("Can't run {}", path.display()))?;
+        Ok(Self::from_bytes(&rom))
+    }
+
+    /// Check that `rom` isn't empty and fits in RAM from the program start, with the 64K of RAM
+    /// of XO-CHIP and Mega-Chip if `large_ram`.
+    pub fn check_rom(rom: &[u8], large_ram: bool) -> Result<()> {
+        ensure!(!rom.is_empty(), "The ROM is empty");
+        let ram_size = if large_ram {
+            config::XO_RAM_SIZE
+        } else {
+            config::RAM_SIZE
+        };
+        let available = ram_size - config::PROG_ADDR as usize;
+        ensure!(
+            rom.len() <= available,
+            "The ROM is {} bytes, but only {} bytes fit in RAM from {:#05x}{}",
+            rom.len(),
+            available,
+            config::PROG_ADDR,
+            if large_ram || rom.len() > config::XO_RAM_SIZE - config::PROG_ADDR as usize {
+                ""
+            } else {
+                " (XO-CHIP programs get 64K of RAM with --quirks xochip)"
+            }
+        );
+        Ok(())
+    }
+
+    /// Create a machine running `rom`, which must pass `check_rom`.
+    pub fn from_bytes(rom: &[u8]) -> Self {
+        Self::build(rom, config::RAM_SIZE, config::PROG_ADDR)
+    }
+
+    /// Create a machine running `rom` with the XO-CHIP extensions: 64K of RAM and the XO-CHIP
+    /// instructions. `rom` must pass `check_rom`.
+    pub fn from_bytes_xochip(rom: &[u8]) -> Self {
+        let mut chip8 = Self::build(rom, config::XO_RAM_SIZE, config::PROG_ADDR);
+        chip8.cpu.xochip = true;
+        chip8
+    }
+
+    /// Create a machine running `rom` with the Mega-Chip extensions: 64K of RAM and the Mega-Chip
+    /// instructions, with their 256x192 color display. `rom` must pass `check_rom`.
+    pub fn from_bytes_megachip(rom: &[u8]) -> Self {
+        let mut chip8 = Self::build(rom, config::XO_RAM_SIZE, config::PROG_ADDR);
+        chip8.cpu.megachip = true;
+        chip8
+    }
+
+    fn build(rom: &[u8], ram_size: usize, load_addr: u16) -> Self {
+        let mut ram = Ram::with_size(ram_size);
+        ram.load_at(config::FONT_DATA_ADDR, &config::FONT_DATA[..]);
+        ram.load_at(config::BIG_FONT_DATA_ADDR, &config::BIG_FONT_DATA[..]);
+        ram.load_at(load_addr, rom);
+
+        let mut cpu = Cpu::new();
+        cpu.set_pc(load_addr);
+        let mut chip8 = Self {
+            rom: rom.to_vec(),
+            cpu,
+            interconnect: Interconnect {
+                ram,
+                gfx: Gfx::new(),
+                delay_timer: 0,
+                sound_timer: 0,
+                timer_clock: Duration::ZERO,
+                keys: [false; 16],
+                audio_pattern: None,
+                pitch: 64,
+                metrics: Arc::new(Metrics::default()),
+                coverage: None,
+            },
+            profiler: None,
+            instruction_profiler: None,
+            tracer: None,
+            idle: None,
+            load_addr,
+            vip_timing: false,
+            blocks: None,
+            seed: None,
+            frames: 0,
+            cycles: 0,
+        };
+        if ram_size == config::RAM_SIZE
+            && load_addr == config::PROG_ADDR
+            && rom.starts_with(&TWO_PAGE_START)
+        {
+            info!("running in the 64x64 mode of hires CHIP-8");
+            chip8.interconnect.gfx.set_two_page();
+            chip8.cpu.set_pc(TWO_PAGE_ENTRY);
+        }
+        chip8
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.cpu.quirks = quirks;
+    }
+
+    /// Allow `depth` nested subroutine calls, keeping the return addresses in the interpreter
+    /// area of RAM if `in_ram`, as the COSMAC VIP did.
+    pub fn set_stack(&mut self, depth: usize, in_ram: bool) -> Result<()> {
+        let max = if in_ram {
+            config::RAM_STACK_MAX_DEPTH
+        } else {
+            config::MAX_STACK_DEPTH
+        };
+        ensure!(
+            (1..=max).contains(&depth),
+            "The stack depth must be between 1 and {}",
+            max
+        );
+        self.cpu.set_stack(depth, in_ram);
+        Ok(())
+    }
+
+    /// Load the program at `addr` instead of `config::PROG_ADDR`, as the ETI-660 did at 0x600, and
+    /// restart it from there.
+    pub fn set_load_addr(&mut self, addr: u16) -> Result<()> {
+        let fonts_end = config::BIG_FONT_DATA_ADDR as usize + config::BIG_FONT_DATA.len();
+        ensure!(
+            addr as usize >= fonts_end,
+            "Programs can't be loaded below {:#05x}, where the fonts are",
+            fonts_end
+        );
+        Self::check_fits(self.interconnect.ram.size(), &self.rom, addr)?;
+        self.load_addr = addr;
+        self.reset();
+        Ok(())
+    }
+
+    /// Where the program is loaded and starts.
+    pub fn load_addr(&self) -> u16 {
+        self.load_addr
+    }
+
+    /// Check that `rom` fits in `ram_size` bytes of RAM from `addr`.
+    fn check_fits(ram_size: usize, rom: &[u8], addr: u16) -> Result<()> {
+        let available = ram_size.saturating_sub(addr as usize);
+        ensure!(
+            rom.len() <= available,
+            "The ROM is {} bytes, but only {} bytes fit in RAM from {:#05x}",
+            rom.len(),
+            available,
+            addr
+        );
+        Ok(())
+    }
+
+    /// Time instructions as the COSMAC VIP interpreter ran them, each with its own cost, rather
+    /// than at a flat number of instructions per second.
+    pub fn set_vip_timing(&mut self, vip_timing: bool) {
+        self.vip_timing = vip_timing;
+    }
+
+    /// Have `step_frame` run blocks of instructions decoded once and cached, see `blocks`, rather
+    /// than decoding every instruction it executes. It falls back to the plain interpreter while
+    /// coverage or profiling is on.
+    pub fn set_block_cache(&mut self, enabled: bool) {
+        self.blocks = enabled.then(BlockCache::new);
+        self.interconnect.ram.clear_code();
+    }
+
+    /// How long the instruction at the PC takes: `1 / ips` seconds, or its cost on the COSMAC VIP
+    /// with `set_vip_timing`. With the `display_wait` quirk, draw instructions also take the rest
+    /// of the current 60Hz frame.
+    pub fn instruction_time(&self, ips: u32) -> Duration {
+        let instruction = self
+            .interconnect
+            .fetch_opcode(self.cpu.pc())
+            .and_then(|opcode| self.cpu.decode(opcode));
+        self.time(instruction.ok(), Duration::from_secs(1) / ips)
+    }
+
+    /// How long `instruction`, about to be executed, takes when instructions otherwise take
+    /// `flat`. See `instruction_time`.
+    fn time(&self, instruction: Option<Instruction>, flat: Duration) -> Duration {
+        let time = match instruction {
+            Some(instruction) if self.vip_timing => {
+                timing::MACHINE_CYCLE * timing::cycles(instruction, &self.cpu)
+            }
+            _ => flat,
+        };
+        match instruction {
+            Some(Instruction::Draw(..)) if self.cpu.quirks.display_wait => {
+                time.max(TIMER_PERIOD - self.interconnect.timer_clock)
+            }
+            _ => time,
+        }
+    }
+
+    /// Seed the random number generator, making runs with the same input reproducible.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = Some(seed);
+        self.cpu.seed_rng(seed);
+    }
+
+    /// Restart the program from scratch, keeping the quirks, the `0NNN` handling, the seed and the
+    /// instrumentation.
+    pub fn reset(&mut self) {
+        self.rebuild(self.interconnect.ram.size());
+    }
+
+    /// Restart from scratch with `ram_size` bytes of RAM, keeping the same settings as `reset`.
+    fn rebuild(&mut self, ram_size: usize) {
+        let mut fresh = Self::build(&self.rom, ram_size, self.load_addr);
+        fresh.cpu.xochip = self.cpu.xochip;
+        fresh.cpu.megachip = self.cpu.megachip;
+        fresh.cpu.quirks = self.cpu.quirks;
+        fresh.cpu.sys_policy = self.cpu.sys_policy;
+        fresh.cpu.sys_hooks = std::mem::take(&mut self.cpu.sys_hooks);
+        fresh
+            .cpu
+            .set_stack(self.cpu.stack_depth(), self.cpu.stack_in_ram());
+        fresh.interconnect.ram.out_of_range = self.interconnect.ram.out_of_range;
+        fresh.interconnect.metrics = self.interconnect.metrics.clone();
+        fresh.interconnect.coverage = self.interconnect.coverage.take();
+        fresh.profiler = self.profiler.take();
+        fresh.instruction_profiler = self.instruction_profiler.take();
+        fresh.tracer = self.tracer.take();
+        fresh.idle = self.idle.take();
+        if let Some(idle) = &mut fresh.idle {
+            idle.interrupt();
+        }
+        fresh.vip_timing = self.vip_timing;
+        fresh.blocks = self.blocks.as_ref().map(|_| BlockCache::new());
+        let afterglow = self.interconnect.gfx.afterglow().map(Afterglow::frames);
+        fresh.interconnect.gfx.set_afterglow(afterglow);
+        fresh
+            .interconnect
+            .gfx
+            .set_palette(*self.interconnect.gfx.palette());
+        if let Some(seed) = self.seed {
+            fresh.set_seed(seed);
+        }
+        *self = fresh;
+    }
+
+    /// Restart with `rom` instead of the current program, keeping the same settings as `reset`.
+    pub fn reset_with_rom(&mut self, rom: &[u8]) -> Result<()> {
+        Self::check_rom(rom, self.interconnect.ram.size() > config::RAM_SIZE)?;
+        Self::check_fits(self.interconnect.ram.size(), rom, self.load_addr)?;
+        self.rom = rom.to_vec();
+        self.reset();
+        Ok(())
+    }
+
+    /// Restart with `rom` on the machine `profile` runs on: with the XO-CHIP or Mega-Chip
+    /// extensions and their 64K of RAM if it's one of those. Otherwise the same settings are kept
+    /// as with `reset`.
+    pub fn reset_with_rom_as(&mut self, rom: &[u8], profile: Option<Profile>) -> Result<()> {
+        let large_ram = matches!(profile, Some(Profile::XoChip | Profile::MegaChip));
+        Self::check_rom(rom, large_ram)?;
+        let ram_size = if large_ram {
+            config::XO_RAM_SIZE
+        } else {
+            config::RAM_SIZE
+        };
+        Self::check_fits(ram_size, rom, self.load_addr)?;
+        self.rom = rom.to_vec();
+        self.cpu.xochip = profile == Some(Profile::XoChip);
+        self.cpu.megachip = profile == Some(Profile::MegaChip);
+        self.rebuild(ram_size);
+        Ok(())
+    }
+
+    /// The instruction at `addr`, if it's in RAM.
+    pub fn opcode_at(&self, addr: u16) -> Option<u16> {
+        (addr as usize + 1 < self.interconnect.ram.size())
+            .then(|| self.interconnect.fetch_opcode(addr).ok())
+            .flatten()
+    }
+
+    /// A readable dump of the registers, timers, stack and RAM.
+    pub fn dump_state(&self) -> String {
+        let mut out = String::new();
+        let cpu = &self.cpu;
+        writeln!(out, "PC: {:#05x}  I: {:#05x}", cpu.pc(), cpu.i()).unwrap();
+        for x in 0..16 {
+            let sep = if x % 8 == 7 { "\n" } else { "  " };
+            write!(out, "V{:X}: {:02X}{}", x, cpu.v(x), sep).unwrap();
+        }
+        writeln!(
+            out,
+            "DT: {:02X}  ST: {:02X}",
+            self.interconnect.delay_timer, self.interconnect.sound_timer
+        )
+        .unwrap();
+        let stack: Vec<String> = cpu.stack().iter().map(|a| format!("{:#05x}", a)).collect();
+        writeln!(out, "Stack: [{}]", stack.join(", ")).unwrap();
+        writeln!(out, "\nRAM:").unwrap();
+        for line in (0..self.interconnect.ram.size()).step_by(16) {
+            let bytes: Vec<String> = (line..line + 16)
+                .map(|addr| format!("{:02X}", self.interconnect.ram[addr as u16]))
+                .collect();
+            writeln!(out, "{:03X}: {}", line, bytes.join(" ")).unwrap();
+        }
+        out
+    }
+
+    /// The registers, timers, stack, RAM and display as a JSON object, for scripts to inspect.
+    pub fn state_json(&self) -> String {
+        fn list<T: ToString>(values: impl Iterator<Item = T>) -> String {
+            let values: Vec<String> = values.map(|v| v.to_string()).collect();
+            format!("[{}]", values.join(","))
+        }
+        let cpu = &self.cpu;
+        let display: Vec<String> = self
+            .display()
+            .chunks(self.display_width())
+            .map(|line| list(line.iter()))
+            .collect();
+        let mut out = String::from("{\n");
+        writeln!(out, "  \"pc\": {},", cpu.pc()).unwrap();
+        writeln!(out, "  \"i\": {},", cpu.i()).unwrap();
+        writeln!(out, "  \"v\": {},", list((0..16).map(|x| cpu.v(x)))).unwrap();
+        writeln!(out, "  \"delay_timer\": {},", self.interconnect.delay_timer).unwrap();
+        writeln!(out, "  \"sound_timer\": {},", self.interconnect.sound_timer).unwrap();
+        writeln!(out, "  \"stack\": {},", list(cpu.stack().iter())).unwrap();
+        let ram = (0..self.interconnect.ram.size()).map(|addr| self.interconnect.ram[addr as u16]);
+        writeln!(out, "  \"ram\": {},", list(ram)).unwrap();
+        writeln!(out, "  \"display\": [{}]", display.join(",")).unwrap();
+        out.push_str("}\n");
+        out
+    }
+
+    /// A snapshot of the machine, to be saved with `State::encode`.
+    pub fn save_state(&self) -> State {
+        let cpu = &self.cpu;
+        let ram = (0..self.interconnect.ram.size()).map(|addr| self.interconnect.ram[addr as u16]);
+        State {
+            pc: cpu.pc(),
+            i: cpu.i(),
+            v: std::array::from_fn(|x| cpu.v(x as u8)),
+            delay_timer: self.interconnect.delay_timer,
+            sound_timer: self.interconnect.sound_timer,
+            keys: self.interconnect.keys,
+            stack: cpu.stack().to_vec(),
+            frame_phase: (self.interconnect.timer_clock.as_nanos() * 256 / TIMER_PERIOD.as_nanos())
+                as u8,
+            frames: self.frames,
+            ram: ram.collect(),
+            display_width: self.interconnect.gfx.width() as u16,
+            display_height: self.interconnect.gfx.height() as u16,
+            display: self.display().to_vec(),
+            rpl: cpu.rpl(),
+            planes: self.interconnect.gfx.planes(),
+            pitch: self.interconnect.pitch,
+            audio_pattern: self.interconnect.audio_pattern,
+            megachip: cpu
+                .megachip
+                .then(|| self.interconnect.gfx.megachip_registers()),
+        }
+    }
+
+    /// Resume from a snapshot taken by `save_state`. The quirks, seed and instrumentation are
+    /// kept, and the state must come from a machine with the same amount of RAM.
+    pub fn load_state(&mut self, state: &State) -> Result<()> {
+        let ram = &mut self.interconnect.ram;
+        if state.ram.len() != ram.size() {
+            bail!(
+                "The save state has {} bytes of RAM, this machine has {}; is it for another --quirks profile?",
+                state.ram.len(),
+                ram.size()
+            );
+        }
+        let size = (state.display_width as usize, state.display_height as usize);
+        if ![gfx::LORES, gfx::TWO_PAGE, gfx::HIRES, gfx::MEGACHIP].contains(&size) {
+            bail!("Invalid display size {}x{} in save state", size.0, size.1);
+        }
+        if state.megachip.is_some() && !self.cpu.megachip {
+            bail!("The save state is for a Mega-Chip machine; is it for another --quirks profile?");
+        }
+        if state.display.len() != size.0 * size.1 {
+            bail!(
+                "The save state has {} bytes of display for a {}x{} display",
+                state.display.len(),
+                size.0,
+                size.1
+            );
+        }
+        self.cpu
+            .restore(state.pc, state.i, state.v, &state.stack, state.rpl)?;
+        ram.load_at(0, &state.ram);
+        let interconnect = &mut self.interconnect;
+        interconnect.delay_timer = state.delay_timer;
+        interconnect.sound_timer = state.sound_timer;
+        interconnect.keys = state.keys;
+        interconnect.pitch = state.pitch;
+        interconnect.audio_pattern = state.audio_pattern;
+        interconnect.gfx.restore(size, &state.display);
+        interconnect.gfx.select_planes(state.planes);
+        if let Some(megachip) = &state.megachip {
+            interconnect.gfx.restore_megachip_registers(megachip);
+        }
+        // Round up, so that saving the state again gives back the same phase.
+        let period = TIMER_PERIOD.as_nanos() as u64;
+        interconnect.timer_clock =
+            Duration::from_nanos((period * state.frame_phase as u64).div_ceil(256));
+        self.frames = state.frames;
+        if let Some(idle) = &mut self.idle {
+            idle.interrupt();
+        }
+        Ok(())
+    }
+
+    /// Execute one instruction, turning an error into a `Fault`. Panics are bugs in the
+    /// interpreter, but they shouldn't take the whole window down either.
+    pub fn try_step(&mut self) -> Result<(), Fault> {
+        let pc = self.cpu.pc();
+        match panic::catch_unwind(AssertUnwindSafe(|| self.step())) {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(e)) => Err(Fault::from_error(pc, self.opcode_at(pc), e)),
+            Err(payload) => Err(Fault::from_panic(pc, self.opcode_at(pc), payload)),
+        }
+    }
+
+    /// The display in RGBA, see `Gfx::get_frame`.
+    pub fn gfx_buffer(&mut self) -> &[u8] {
+        self.interconnect.gfx.get_frame()
+    }
+
+    /// The current content of the display, without marking it as rendered.
+    pub fn display(&self) -> &[u8] {
+        self.interconnect.gfx.buffer()
+    }
+
+    /// Width of the display in its current resolution.
+    pub fn display_width(&self) -> usize {
+        self.interconnect.gfx.width()
+    }
+
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    pub fn metrics(&self) -> Arc<Metrics> {
+        self.interconnect.metrics.clone()
+    }
+
+    /// Start recording which bytes of the ROM are executed or read.
+    pub fn enable_coverage(&mut self, coverage: Coverage) {
+        self.interconnect.coverage = Some(coverage);
+    }
+
+    pub fn coverage(&self) -> Option<&Coverage> {
+        self.interconnect.coverage.as_ref()
+    }
+
+    /// Start attributing executed instructions to subroutines.
+    pub fn enable_profiler(&mut self) {
+        self.profiler = Some(CallProfiler::at(self.load_addr));
+    }
+
+    pub fn profiler(&self) -> Option<&CallProfiler> {
+        self.profiler.as_ref()
+    }
+
+    /// Start counting executed instructions by kind and by address.
+    pub fn enable_instruction_profiler(&mut self) {
+        self.instruction_profiler = Some(InstructionProfiler::new());
+    }
+
+    pub fn instruction_profiler(&self) -> Option<&InstructionProfiler> {
+        self.instruction_profiler.as_ref()
+    }
+
+    /// Start writing a line for every executed instruction with `tracer`.
+    pub fn enable_trace(&mut self, tracer: Tracer) {
+        self.tracer = Some(tracer);
+    }
+
+    pub fn is_tracing(&self) -> bool {
+        self.tracer.is_some()
+    }
+
+    /// Stop tracing, returning the tracer to finish the trace with.
+    pub fn take_tracer(&mut self) -> Option<Tracer> {
+        self.tracer.take()
+    }
+
+    /// Start looking for idle loops, and let `skip_idle` skip them if `skip` is set.
+    pub fn enable_idle_detection(&mut self, skip: bool) {
+        self.idle = Some(IdleLoops::new(skip));
+    }
+
+    pub fn idle_loops(&self) -> Option<&IdleLoops> {
+        self.idle.as_ref()
+    }
+
+    /// If the last instruction ended an idle iteration, skip the iterations that would run before
+    /// the next timer tick, up to `max_cycles` instructions: only their instructions and their
+    /// time are counted, since they wouldn't change anything. Nothing is skipped unless
+    /// `enable_idle_detection` allowed it, or while the instrumentation needs to see every
+    /// instruction.
+    pub fn skip_idle(&mut self, max_cycles: u64) -> Option<Skipped> {
+        let idle = self.idle.as_mut()?;
+        idle.check(&self.cpu, &self.interconnect);
+        if !idle.skip
+            || self.interconnect.coverage.is_some()
+            || self.profiler.is_some()
+            || self.instruction_profiler.is_some()
+            || self.tracer.is_some()
+        {
+            return None;
+        }
+        let repeat = idle.repeat()?;
+        let period = repeat.time.as_nanos();
+        if period == 0 || repeat.instructions == 0 {
+            return None;
+        }
+        // Stop short of the tick, which may change the delay timer.
+        let left = (TIMER_PERIOD - self.interconnect.timer_clock).as_nanos();
+        let iterations = (left.saturating_sub(1) / period)
+            .min((max_cycles / repeat.instructions) as u128) as u64;
+        if iterations == 0 {
+            return None;
+        }
+        let skipped = Skipped {
+            instructions: iterations * repeat.instructions,
+            time: Duration::from_nanos((iterations as u128 * period) as u64),
+        };
+        idle.skipped(repeat.head, skipped.instructions);
+        self.interconnect.timer_clock += skipped.time;
+        self.interconnect
+            .metrics
+            .add_instructions(skipped.instructions);
+        self.cycles += skipped.instructions;
+        Some(skipped)
+    }
+
+    /// Whether the buzzer is currently sounding.
+    pub fn sound_on(&self) -> bool {
+        self.interconnect.sound_timer > 0
+    }
+
+    /// The XO-CHIP audio pattern and pitch, once the program has loaded a pattern.
+    pub fn audio_pattern(&self) -> Option<([u8; 16], u8)> {
+        let interconnect = &self.interconnect;
+        interconnect
+            .audio_pattern
+            .map(|pattern| (pattern, interconnect.pitch))
+    }
+
+    /// Whether the CPU is parked on an `FX0A` instruction, waiting for a key press.
+    pub fn waiting_for_key(&self) -> bool {
+        self.interconnect
+            .fetch_opcode(self.cpu.pc())
+            .is_ok_and(|opcode| opcode & 0xF0FF == 0xF00A)
+            && !self.interconnect.keys.iter().any(|k| *k)
+    }
+
+    pub fn set_key(&mut self, key: u8, is_down: bool) {
+        if is_down && !self.interconnect.keys[key as usize] {
+            self.interconnect.metrics.inc_key_presses();
+        }
+        self.interconnect.keys[key as usize] = is_down;
+    }
+
+    /// Let `elapsed` time pass on the 60Hz timers. They run on their own clock, so the caller
+    /// decides how fast instructions are executed in the meantime.
+    pub fn advance_time(&mut self, elapsed: Duration) {
+        self.frames += self.interconnect.advance(elapsed) as u64;
+        if let Some(idle) = &mut self.idle {
+            idle.elapse(elapsed);
+        }
+    }
+
+    /// Execute one instruction. The timers are left alone, see `advance_time`.
+    pub fn step(&mut self) -> Result<(), Chip8Error> {
+        let pc = self.cpu.pc();
+        self.interconnect.metrics.inc_instructions();
+        if let Some(coverage) = &mut self.interconnect.coverage {
+            coverage.mark_executed(self.cpu.pc());
+        }
+        if let Some(profiler) = &mut self.profiler {
+            profiler.record(self.interconnect.fetch_opcode(self.cpu.pc())?);
+        }
+        if let Some(profiler) = &mut self.instruction_profiler {
+            let opcode = self.interconnect.fetch_opcode(self.cpu.pc())?;
+            profiler.record(self.cpu.pc(), self.cpu.decode(opcode)?);
+        }
+        if let Some(tracer) = &mut self.tracer {
+            let opcode = self.interconnect.fetch_opcode(self.cpu.pc())?;
+            tracer.before(&self.cpu, &self.interconnect, opcode);
+        }
+        let idle_instruction = match &mut self.idle {
+            Some(idle) => {
+                idle.check(&self.cpu, &self.interconnect);
+                Some(self.cpu.decode(self.interconnect.fetch_opcode(pc)?)?)
+            }
+            None => None,
+        };
+        let result = self.cpu.emulate_cycle(&mut self.interconnect);
+        if let Some(tracer) = &mut self.tracer {
+            match &result {
+                Ok(()) => tracer.after(&self.cpu, &self.interconnect),
+                Err(fault) => tracer.fault(&self.cpu, &self.interconnect, fault),
+            }
+        }
+        result?;
+        if let (Some(idle), Some(instruction)) = (&mut self.idle, idle_instruction) {
+            idle.executed(instruction, pc, self.cpu.pc());
+        }
+        self.cycles += 1;
+        Ok(())
+    }
+
+    /// Execute `cycles` instructions, a frame's worth at `60 * cycles` instructions per second,
+    /// advancing the timers by the time each one takes (see `instruction_time`). This is the same
+    /// as alternating `step` and `advance_time`, without going back to the caller in between.
+    pub fn step_frame(&mut self, cycles: u32) -> Result<(), Chip8Error> {
+        self.run(cycles as u64, cycles.saturating_mul(60).max(1))
+    }
+
+    /// Execute `cycles` instructions timed at `ips` instructions per second, like `step_frame`.
+    /// Idle loops are skipped if `enable_idle_detection` allows it, see `skip_idle`.
+    pub fn run(&mut self, cycles: u64, ips: u32) -> Result<(), Chip8Error> {
+        if ips == 0 {
+            return Err(Chip8Error::ZeroSpeed);
+        }
+        match self.blocks.take() {
+            // Coverage, profiling and tracing need to see every instruction fetched.
+            Some(mut blocks)
+                if self.interconnect.coverage.is_none()
+                    && self.profiler.is_none()
+                    && self.instruction_profiler.is_none()
+                    && self.tracer.is_none() =>
+            {
+                let result = self.run_blocks(&mut blocks, cycles, ips);
+                self.blocks = Some(blocks);
+                result
+            }
+            blocks => {
+                self.blocks = blocks;
+                let mut cycles = cycles;
+                while cycles > 0 {
+                    let period = self.instruction_time(ips);
+                    self.step()?;
+                    self.advance_time(period);
+                    cycles -= 1;
+                    if let Some(skipped) = self.skip_idle(cycles) {
+                        cycles -= skipped.instructions;
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Execute `cycles` instructions from the blocks cached in `blocks`, timed at `ips`
+    /// instructions per second.
+    fn run_blocks(
+        &mut self,
+        blocks: &mut BlockCache,
+        mut cycles: u64,
+        ips: u32,
+    ) -> Result<(), Chip8Error> {
+        let flat = Duration::from_secs(1) / ips;
+        let mut written = self.interconnect.ram.take_code_written();
+        while cycles > 0 {
+            if let Some(range) = written.take() {
+                blocks.invalidate(range);
+            }
+            let block = blocks.get(self.cpu.pc(), &self.cpu, &mut self.interconnect)?;
+            for &instruction in block.iter().take(cycles as usize) {
+                let period = self.time(Some(instruction), flat);
+                let pc = self.cpu.pc();
+                self.interconnect.metrics.inc_instructions();
+                if let Some(idle) = &mut self.idle {
+                    idle.check(&self.cpu, &self.interconnect);
+                }
+                self.cpu.execute(instruction, &mut self.interconnect)?;
+                if let Some(idle) = &mut self.idle {
+                    idle.executed(instruction, pc, self.cpu.pc());
+                }
+                self.cycles += 1;
+                cycles -= 1;
+                self.advance_time(period);
+                // The rest of the block may have just been overwritten.
+                written = self.interconnect.ram.take_code_written();
+                if written.is_some() {
+                    break;
+                }
+            }
+            // Blocks end with jumps, so idle loops end with a block.
+            if let Some(skipped) = self.skip_idle(cycles) {
+                cycles -= skipped.instructions;
+            }
+        }
+        if let Some(range) = written {
+            blocks.invalidate(range);
+        }
+        Ok(())
+    }
+}
@@ -1,5 +1,30 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+use anyhow::{ensure, Context, Result};
+use directories::ProjectDirs;
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::detect::Profile;
+
 pub const FONT_DATA_ADDR: u16 = 0x0000;
+pub const BIG_FONT_DATA_ADDR: u16 = 0x0050;
 pub const PROG_ADDR: u16 = 0x0200;
+/// Number of nested subroutine calls, unless configured otherwise.
+pub const DEFAULT_STACK_DEPTH: usize = 16;
+/// Most nested subroutine calls that can be configured, as save states store the depth in a byte.
+pub const MAX_STACK_DEPTH: usize = 255;
+/// Stacks kept in RAM grow down from here, in the interpreter area between the fonts and the
+/// program.
+pub const RAM_STACK_TOP: u16 = PROG_ADDR;
+/// Most return addresses a stack kept in RAM can hold without overwriting the big font.
+pub const RAM_STACK_MAX_DEPTH: usize = (RAM_STACK_TOP - BIG_FONT_DATA_ADDR - 16 * 10) as usize / 2;
+pub const RAM_SIZE: usize = 4096;
+pub const XO_RAM_SIZE: usize = 0x10000;
+/// Instructions executed per second at normal speed, unless configured otherwise.
+pub const DEFAULT_IPS: u32 = 1000;
 #[rustfmt::skip]
 pub const FONT_DATA: [u8; 5 * 16] = [
     0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
@@ -19,4 +44,467 @@ pub const FONT_DATA: [u8; 5 * 16] = [
     0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
     0xF0, 0x80, 0xF0, 0x80, 0x80, // F
 ];
+/// The SUPER-CHIP 8x10 digits, selected with `FX30`.
+#[rustfmt::skip]
+pub const BIG_FONT_DATA: [u8; 10 * 10] = [
+    0x3C, 0x7E, 0xE7, 0xC3, 0xC3, 0xC3, 0xC3, 0xE7, 0x7E, 0x3C, // 0
+    0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C, // 1
+    0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF, // 2
+    0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C, // 3
+    0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF, 0x06, 0x06, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C, // 5
+    0x3E, 0x7C, 0xC0, 0xC0, 0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C, // 6
+    0xFF, 0xFF, 0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x60, 0x60, // 7
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C, // 8
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F, 0x03, 0x03, 0x3E, 0x7C, // 9
+];
+
+/// Behaviors that differ between interpreters, which ROMs may depend on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    /// `8XY6`/`8XYE` shift VX in place instead of shifting VY into VX (CHIP-48, SCHIP).
+    pub shift_vx_only: bool,
+    /// `FX55`/`FX65` leave I unchanged instead of incrementing it past the last register
+    /// (SCHIP).
+    pub load_store_keeps_i: bool,
+    /// `FX55`/`FX65` increment I by X, leaving it on the last register, instead of X + 1
+    /// (CHIP-48). Ignored with `load_store_keeps_i`.
+    pub load_store_i_by_x: bool,
+    /// `8XY1`/`8XY2`/`8XY3` reset VF to 0 (original COSMAC VIP interpreter).
+    pub vf_reset: bool,
+    /// `DXY0` draws an 8x16 sprite in low resolution, like SCHIP 1.0/1.1 on the HP48, instead of
+    /// a 16x16 one.
+    pub lores_dxy0_8x16: bool,
+    /// Scroll instructions move half as many pixels in low resolution, as the HP48 scrolled by
+    /// high resolution pixels (SCHIP 1.1).
+    pub half_pixel_scroll: bool,
+    /// `BXNN` jumps to XNN + VX instead of `BNNN` jumping to NNN + V0 (CHIP-48, SCHIP).
+    pub jump_vx: bool,
+    /// `FX0A` completes as soon as a key is pressed instead of once it's released, so that
+    /// holding a key goes through several of them (what chip8rs used to do).
+    pub wait_key_on_press: bool,
+    /// `FX1E` sets VF to 1 when I goes past 0xFFF and to 0 otherwise (CHIP-8 for the Amiga,
+    /// which Spacefight 2091! relies on).
+    pub add_i_vf: bool,
+    /// `FX1E` keeps I within 12 bits, wrapping around past 0xFFF, instead of letting it point
+    /// past the 4KB of RAM.
+    pub add_i_wrap: bool,
+    /// `DXYN` waits for the start of the next 60Hz frame, as the original COSMAC VIP interpreter
+    /// waited for the vertical blank, limiting programs to 60 sprites per second.
+    pub display_wait: bool,
+    /// Pixels of sprites drawn across an edge of the display wrap around to the opposite edge
+    /// instead of being clipped (XO-CHIP).
+    pub wrap_sprites: bool,
+}
+
+impl Quirks {
+    /// Names of the quirks, as used in the settings file and with `--quirk`.
+    pub const NAMES: [&'static str; 12] = [
+        "shift_vx_only",
+        "load_store_keeps_i",
+        "load_store_i_by_x",
+        "vf_reset",
+        "lores_dxy0_8x16",
+        "half_pixel_scroll",
+        "jump_vx",
+        "wait_key_on_press",
+        "add_i_vf",
+        "add_i_wrap",
+        "display_wait",
+        "wrap_sprites",
+    ];
+
+    /// The behaviors of an interpreter family.
+    pub fn for_profile(profile: Profile) -> Self {
+        match profile {
+            Profile::Chip8 => Self {
+                shift_vx_only: false,
+                load_store_keeps_i: false,
+                load_store_i_by_x: false,
+                vf_reset: true,
+                lores_dxy0_8x16: false,
+                half_pixel_scroll: false,
+                jump_vx: false,
+                wait_key_on_press: false,
+                add_i_vf: false,
+                add_i_wrap: false,
+                display_wait: true,
+                wrap_sprites: false,
+            },
+            Profile::Chip48 => Self {
+                shift_vx_only: true,
+                load_store_keeps_i: false,
+                load_store_i_by_x: true,
+                vf_reset: false,
+                lores_dxy0_8x16: false,
+                half_pixel_scroll: false,
+                jump_vx: true,
+                wait_key_on_press: false,
+                add_i_vf: false,
+                add_i_wrap: false,
+                display_wait: false,
+                wrap_sprites: false,
+            },
+            // Mega-Chip extends SUPER-CHIP.
+            Profile::Schip | Profile::MegaChip => Self {
+                shift_vx_only: true,
+                load_store_keeps_i: true,
+                load_store_i_by_x: false,
+                vf_reset: false,
+                lores_dxy0_8x16: false,
+                half_pixel_scroll: false,
+                jump_vx: true,
+                wait_key_on_press: false,
+                add_i_vf: false,
+                add_i_wrap: false,
+                display_wait: false,
+                wrap_sprites: false,
+            },
+            Profile::SchipLegacy => Self {
+                shift_vx_only: true,
+                load_store_keeps_i: true,
+                load_store_i_by_x: false,
+                vf_reset: false,
+                lores_dxy0_8x16: true,
+                half_pixel_scroll: true,
+                jump_vx: true,
+                wait_key_on_press: false,
+                add_i_vf: false,
+                add_i_wrap: false,
+                display_wait: false,
+                wrap_sprites: false,
+            },
+            Profile::XoChip => Self {
+                shift_vx_only: false,
+                load_store_keeps_i: false,
+                load_store_i_by_x: false,
+                vf_reset: false,
+                lores_dxy0_8x16: false,
+                half_pixel_scroll: false,
+                jump_vx: false,
+                wait_key_on_press: false,
+                add_i_vf: false,
+                add_i_wrap: false,
+                display_wait: false,
+                wrap_sprites: true,
+            },
+        }
+    }
+
+    fn get_mut(&mut self, name: &str) -> Option<&mut bool> {
+        Some(match name {
+            "shift_vx_only" => &mut self.shift_vx_only,
+            "load_store_keeps_i" => &mut self.load_store_keeps_i,
+            "load_store_i_by_x" => &mut self.load_store_i_by_x,
+            "vf_reset" => &mut self.vf_reset,
+            "lores_dxy0_8x16" => &mut self.lores_dxy0_8x16,
+            "half_pixel_scroll" => &mut self.half_pixel_scroll,
+            "jump_vx" => &mut self.jump_vx,
+            "wait_key_on_press" => &mut self.wait_key_on_press,
+            "add_i_vf" => &mut self.add_i_vf,
+            "add_i_wrap" => &mut self.add_i_wrap,
+            "display_wait" => &mut self.display_wait,
+            "wrap_sprites" => &mut self.wrap_sprites,
+            _ => return None,
+        })
+    }
+
+    /// Apply `overrides`, e.g. from the settings file.
+    pub fn apply(&mut self, overrides: &QuirkOverrides) {
+        for (name, value) in &overrides.0 {
+            match self.get_mut(name) {
+                Some(quirk) => *quirk = *value,
+                None => warn!("Ignoring unknown quirk {:?}", name),
+            }
+        }
+    }
+}
+
+impl Default for Quirks {
+    /// What chip8rs has always done: in-place shifts, but incrementing loads and stores.
+    fn default() -> Self {
+        Self {
+            shift_vx_only: true,
+            load_store_keeps_i: false,
+            load_store_i_by_x: false,
+            vf_reset: false,
+            lores_dxy0_8x16: false,
+            half_pixel_scroll: false,
+            jump_vx: false,
+            wait_key_on_press: false,
+            add_i_vf: false,
+            add_i_wrap: false,
+            display_wait: false,
+            wrap_sprites: false,
+        }
+    }
+}
+
+/// Individual quirks forced on or off regardless of the preset, by name.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct QuirkOverrides(pub BTreeMap<String, bool>);
+
+impl QuirkOverrides {
+    /// Parse a `--quirk` value: a quirk name, optionally followed by `=true` or `=false`.
+    pub fn parse_one(&mut self, spec: &str) -> Result<()> {
+        let (name, value) = match spec.split_once('=') {
+            Some((name, value)) => (name, value.parse().context("Expected true or false")?),
+            None => (spec, true),
+        };
+        ensure!(
+            Quirks::NAMES.contains(&name),
+            "Unknown quirk {:?} (expected one of {})",
+            name,
+            Quirks::NAMES.join(", ")
+        );
+        self.0.insert(name.to_string(), value);
+        Ok(())
+    }
+}
+
+/// Keyboard keys or controller buttons mapped to keypad keys, by name, with the keypad keys as
+/// hex digits, e.g.
+///
+/// ```toml
+/// [keys]
+/// Up = "5"
+/// Space = "6"
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct KeyMap(pub BTreeMap<String, String>);
+
+impl KeyMap {
+    /// Parse a `--keymap` value: a key name, `=` and a keypad key.
+    pub fn parse_one(&mut self, spec: &str) -> Result<()> {
+        let (key, digit) = spec.split_once('=').context("Expected KEY=DIGIT")?;
+        Self::parse_digit(digit)?;
+        self.0.insert(key.to_string(), digit.to_string());
+        Ok(())
+    }
+
+    /// Parse a keypad key, a single hex digit.
+    pub fn parse_digit(digit: &str) -> Result<u8> {
+        u8::from_str_radix(digit, 16)
+            .ok()
+            .filter(|_| digit.len() == 1)
+            .with_context(|| format!("Invalid keypad key {:?} (expected 0 to F)", digit))
+    }
+}
+
+/// Smallest window scale: a high resolution pixel, half a low resolution one, must still take a
+/// whole screen pixel.
+pub const MIN_SCALE: f32 = 2.0;
+
+/// User settings, read from the platform's config directory (e.g.
+/// `~/.config/chip8rs/config.toml` on Linux) or the file given with `--config`, and written back
+/// there when they're changed at runtime. Command line flags take precedence over them.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// Name of the display palette.
+    pub palette: String,
+    /// Color replacing the palette's background, as `#RRGGBB` hex.
+    pub background: Option<String>,
+    /// Color replacing the palette's foreground, as `#RRGGBB` hex.
+    pub foreground: Option<String>,
+    /// How many 60Hz frames pixels take to fade out once turned off, to reduce flicker, or 0 for
+    /// them to disappear at once.
+    pub afterglow: u8,
+    /// Emulation speed, as a multiple of the normal speed.
+    pub speed: f32,
+    /// Instructions executed per second at normal speed.
+    pub ips: u32,
+    /// Size of a low resolution pixel on screen, in screen pixels, at least `MIN_SCALE`. Only read
+    /// on startup.
+    pub scale: f32,
+    /// Quirks preset used when the ROM's extension doesn't tell, as with `--quirks`. Only read
+    /// on startup.
+    pub quirks_preset: Option<String>,
+    /// Frequency of the buzzer tone, in Hz. Only read on startup.
+    pub tone: f32,
+    /// Volume of the buzzer, from 0.0 to 1.0. Only read on startup.
+    pub volume: f32,
+    /// Sound file looped by the buzzer instead of the tone. Only read on startup.
+    pub buzzer: Option<PathBuf>,
+    /// Keyboard key bound to each keypad key, by winit `VirtualKeyCode` name (e.g. `Key1`, `Q`).
+    pub keypad: Option<Vec<String>>,
+    /// Name of the audio output device, as listed by `chip8rs audio-devices`, if not the default
+    /// one. Only read on startup.
+    pub audio_device: Option<String>,
+    /// Keyboard keys that type a sequence of keypad keys.
+    pub macros: Vec<InputMacro>,
+    /// Extra keyboard keys for the keypad, in a `[keys]` table.
+    pub keys: KeyMap,
+    /// Controller buttons for the keypad, by gilrs `Button` name (e.g. `DPadUp`, `South`), on
+    /// top of the default bindings.
+    pub gamepad: KeyMap,
+    /// Controller buttons for specific ROMs, by ROM file name, on top of `gamepad`, e.g.
+    /// `[gamepad_roms."pong.ch8"]`.
+    pub gamepad_roms: BTreeMap<String, KeyMap>,
+    /// Quirks to force on or off, e.g. `vf_reset = true`. Only read on startup.
+    pub quirks: QuirkOverrides,
+}
+
+/// A keyboard key bound to a timed sequence of keypad presses, e.g.
+///
+/// ```toml
+/// [[macros]]
+/// key = "F2"
+/// keypad = "5 5 A"
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InputMacro {
+    /// Keyboard key playing the macro, by winit `VirtualKeyCode` name.
+    pub key: String,
+    /// Keypad keys pressed in turn, as hex digits. Whitespace is ignored.
+    pub keypad: String,
+    /// How long each keypad key is held down, in milliseconds.
+    #[serde(default = "InputMacro::default_hold_ms")]
+    pub hold_ms: u64,
+    /// Pause after releasing each keypad key, in milliseconds.
+    #[serde(default = "InputMacro::default_gap_ms")]
+    pub gap_ms: u64,
+}
+
+impl InputMacro {
+    fn default_hold_ms() -> u64 {
+        100
+    }
+
+    fn default_gap_ms() -> u64 {
+        100
+    }
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            palette: "classic".to_string(),
+            background: None,
+            foreground: None,
+            afterglow: 0,
+            speed: 1.0,
+            ips: DEFAULT_IPS,
+            scale: 8.0,
+            quirks_preset: None,
+            tone: 440.0,
+            volume: 1.0,
+            buzzer: None,
+            keypad: None,
+            audio_device: None,
+            macros: Vec::new(),
+            keys: KeyMap::default(),
+            gamepad: KeyMap::default(),
+            gamepad_roms: BTreeMap::new(),
+            quirks: QuirkOverrides::default(),
+        }
+    }
+}
+
+impl Settings {
+    /// Location of the settings file, if the platform has a config directory.
+    pub fn path() -> Option<PathBuf> {
+        ProjectDirs::from("", "", "chip8rs").map(|dirs| dirs.config_dir().join("config.toml"))
+    }
+
+    /// Load the settings file, falling back to the defaults if there is none.
+    pub fn load() -> Result<Self> {
+        match Self::path() {
+            Some(path) if path.exists() => Self::load_from(&path),
+            _ => Ok(Self::default()),
+        }
+    }
+
+    /// Load the settings file at `path`, which must exist.
+    pub fn load_from(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let settings: Self = toml::from_str(&content)
+            .with_context(|| format!("Invalid settings in {}", path.display()))?;
+        settings
+            .validate()
+            .with_context(|| format!("Invalid settings in {}", path.display()))?;
+        Ok(settings)
+    }
+
+    /// Check the values that can't be fixed up when they're applied.
+    pub fn validate(&self) -> Result<()> {
+        ensure!(
+            self.scale.is_finite() && self.scale >= MIN_SCALE,
+            "The scale must be at least {}",
+            MIN_SCALE
+        );
+        ensure!(self.ips > 0, "ips must be positive");
+        ensure!(self.tone > 0.0, "The tone frequency must be positive");
+        ensure!(
+            (0.0..=1.0).contains(&self.volume),
+            "The volume must be between 0.0 and 1.0"
+        );
+        if let Some(preset) = &self.quirks_preset {
+            preset.parse::<Profile>()?;
+        }
+        Ok(())
+    }
+
+    /// Write the settings to `path`, creating its directory if needed.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(path, toml::to_string(self)?)
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+}
+
+/// How often the settings file is checked for changes.
+const WATCH_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Detects changes made to the settings file while the emulator is running.
+pub struct SettingsWatcher {
+    path: Option<PathBuf>,
+    modified: Option<SystemTime>,
+    last_check: Instant,
+}
+
+impl SettingsWatcher {
+    /// Watch the settings file at `path`, if any.
+    pub fn new(path: Option<PathBuf>) -> Self {
+        let modified = path.as_deref().and_then(modified_time);
+        Self {
+            path,
+            modified,
+            last_check: Instant::now(),
+        }
+    }
+
+    /// Return the new settings if the file has been modified since the last call. This is cheap
+    /// to call every frame: the file is only looked at every `WATCH_INTERVAL`.
+    pub fn poll(&mut self) -> Result<Option<Settings>> {
+        if self.last_check.elapsed() < WATCH_INTERVAL {
+            return Ok(None);
+        }
+        self.last_check = Instant::now();
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+        let modified = modified_time(path);
+        if modified.is_none() || modified == self.modified {
+            return Ok(None);
+        }
+        self.modified = modified;
+        Settings::load_from(path).map(Some)
+    }
+}
+
+impl Default for SettingsWatcher {
+    fn default() -> Self {
+        Self::new(Settings::path())
+    }
+}
 
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
@@ -0,0 +1,356 @@
+//! A generated conformance suite: one tiny ROM per opcode family, with the machine state it
+//! should leave behind.
+//!
+//! The programs only exercise behavior that all interpreters agree on (e.g. shifts are only
+//! tested with X == Y, and SUPER-CHIP sprites and scrolling only in high resolution), so the
+//! suite is independent of quirk settings.
+
+use std::fmt;
+
+use crate::config;
+use crate::program::*;
+
+/// Something that must hold once a test ROM has run to completion.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Expect {
+    /// Register VX holds the given value.
+    Reg(u8, u8),
+    /// I holds the given value.
+    I(u16),
+    /// The byte at the given address holds the given value.
+    Mem(u16, u8),
+    /// The pixel at (x, y) is on (`true`) or off.
+    Pixel(u8, u8, bool),
+}
+
+impl fmt::Display for Expect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expect::Reg(x, v) => write!(f, "V{:X} == {:#04x}", x, v),
+            Expect::I(v) => write!(f, "I == {:#05x}", v),
+            Expect::Mem(addr, v) => write!(f, "[{:#05x}] == {:#04x}", addr, v),
+            Expect::Pixel(x, y, on) => {
+                write!(
+                    f,
+                    "pixel ({}, {}) is {}",
+                    x,
+                    y,
+                    if *on { "on" } else { "off" }
+                )
+            }
+        }
+    }
+}
+
+/// A test ROM and its expected outcome.
+pub struct Case {
+    pub name: &'static str,
+    pub rom: Vec<u8>,
+    /// Address of the final `jump` to itself, reached once the test is over.
+    pub halt: u16,
+    pub expect: Vec<Expect>,
+}
+
+/// Maximum number of instructions a test may execute before reaching its halt address.
+pub const MAX_CYCLES: usize = 1000;
+
+/// Address used by the tests as scratch memory.
+const SCRATCH: u16 = 0x0E00;
+
+fn case(name: &'static str, build: impl FnOnce(&mut Program), expect: &[Expect]) -> Case {
+    let mut p = Program::new();
+    build(&mut p);
+    let halt = p.here();
+    let end = p.label();
+    p.bind(end).jp(end);
+    Case {
+        name,
+        rom: p
+            .assemble()
+            .expect("conformance programs only use bound labels"),
+        halt,
+        expect: expect.to_vec(),
+    }
+}
+
+/// Build all the conformance test cases.
+pub fn cases() -> Vec<Case> {
+    use Expect::*;
+
+    vec![
+        case(
+            "00E0 clear",
+            |p| {
+                p.ld(V0, 0).ld_font(V0).drw(V0, V0, 5).cls();
+            },
+            &[Pixel(0, 0, false), Pixel(3, 4, false)],
+        ),
+        case(
+            "1NNN jump",
+            |p| {
+                let skip = p.label();
+                p.ld(V0, 1).jp(skip).ld(V0, 2).bind(skip);
+            },
+            &[Reg(0, 1)],
+        ),
+        case(
+            "2NNN/00EE call and return",
+            |p| {
+                let sub = p.label();
+                let end = p.label();
+                p.call(sub).ld(V1, 2).jp(end);
+                p.bind(sub).ld(V0, 1).ret();
+                p.bind(end);
+            },
+            &[Reg(0, 1), Reg(1, 2)],
+        ),
+        case(
+            "3XNN/4XNN skips",
+            |p| {
+                p.ld(V0, 5).ld(V1, 0);
+                p.se(V0, 5).add(V1, 1);
+                p.se(V0, 6).add(V1, 2);
+                p.sne(V0, 5).add(V1, 4);
+                p.sne(V0, 6).add(V1, 8);
+            },
+            &[Reg(1, 6)],
+        ),
+        case(
+            "5XY0/9XY0 skips",
+            |p| {
+                p.ld(V0, 5).ld(V2, 5).ld(V3, 6).ld(V1, 0);
+                p.se(V0, V2).add(V1, 1);
+                p.se(V0, V3).add(V1, 2);
+                p.sne(V0, V2).add(V1, 4);
+                p.sne(V0, V3).add(V1, 8);
+            },
+            &[Reg(1, 6)],
+        ),
+        case(
+            "6XNN load",
+            |p| {
+                p.ld(V0, 0x12).ld(VE, 0xFE);
+            },
+            &[Reg(0, 0x12), Reg(0xE, 0xFE)],
+        ),
+        case(
+            "7XNN add without carry",
+            |p| {
+                p.ld(VF, 7).ld(V0, 0xF0).add(V0, 0x20);
+            },
+            &[Reg(0, 0x10), Reg(0xF, 7)],
+        ),
+        case(
+            "8XY0-8XY3 logic",
+            |p| {
+                p.ld(V1, 0b1100).ld(V2, 0b1010);
+                p.ld(V3, V1).or(V3, V2);
+                p.ld(V4, V1).and(V4, V2);
+                p.ld(V5, V1).xor(V5, V2);
+                p.ld(V6, V2);
+            },
+            &[
+                Reg(3, 0b1110),
+                Reg(4, 0b1000),
+                Reg(5, 0b0110),
+                Reg(6, 0b1010),
+            ],
+        ),
+        case(
+            "8XY4 add with carry",
+            |p| {
+                p.ld(V0, 0xF0).ld(V1, 0x20).add(V0, V1).ld(V2, VF);
+                p.ld(V3, 1).ld(V4, 2).add(V3, V4).ld(V5, VF);
+            },
+            &[Reg(0, 0x10), Reg(2, 1), Reg(3, 3), Reg(5, 0)],
+        ),
+        case(
+            "8XY5/8XY7 subtract with borrow",
+            |p| {
+                p.ld(V0, 5).ld(V1, 3).sub(V0, V1).ld(V2, VF);
+                p.ld(V3, 3).ld(V4, 5).sub(V3, V4).ld(V5, VF);
+                p.ld(V6, 3).ld(V7, 5).subn(V6, V7).ld(V8, VF);
+            },
+            &[
+                Reg(0, 2),
+                Reg(2, 1),
+                Reg(3, 0xFE),
+                Reg(5, 0),
+                Reg(6, 2),
+                Reg(8, 1),
+            ],
+        ),
+        case(
+            "8XY6/8XYE shifts",
+            |p| {
+                p.ld(V0, 0x81).shr(V0, V0).ld(V1, VF);
+                p.ld(V2, 0x81).shl(V2, V2).ld(V3, VF);
+            },
+            &[Reg(0, 0x40), Reg(1, 1), Reg(2, 0x02), Reg(3, 1)],
+        ),
+        case(
+            "ANNN load I",
+            |p| {
+                p.ld_i(0x123u16);
+            },
+            &[I(0x123)],
+        ),
+        case(
+            "BNNN jump with offset",
+            |p| {
+                let table = p.label();
+                let end = p.label();
+                p.ld(V0, 2).jp_v0(table);
+                p.bind(table).ld(V1, 1).ld(V2, 2).jp(end);
+                p.bind(end);
+            },
+            &[Reg(1, 0), Reg(2, 2)],
+        ),
+        case(
+            "CXNN random with mask",
+            |p| {
+                p.ld(V0, 0xFF)
+                    .rnd(V0, 0)
+                    .ld(V1, 0xFF)
+                    .rnd(V1, 0x0F)
+                    .ld(V2, 0xF0)
+                    .and(V2, V1);
+            },
+            &[Reg(0, 0), Reg(2, 0)],
+        ),
+        case(
+            "DXYN draw and collision",
+            |p| {
+                let sprite = p.label();
+                let end = p.label();
+                p.ld(V0, 10).ld(V1, 5).ld_i(sprite);
+                p.drw(V0, V1, 2).ld(V2, VF);
+                p.drw(V0, V1, 1).ld(V3, VF).jp(end);
+                p.bind(sprite).db(&[0b1000_0001, 0b0100_0000]);
+                p.bind(end);
+            },
+            &[
+                Reg(2, 0),
+                Reg(3, 1),
+                Pixel(10, 5, false),
+                Pixel(17, 5, false),
+                Pixel(11, 6, true),
+            ],
+        ),
+        case(
+            "DXYN clears VF without collision",
+            |p| {
+                let sprite = p.label();
+                let end = p.label();
+                p.ld(VF, 1).ld(V0, 10).ld(V1, 5).ld_i(sprite);
+                p.drw(V0, V1, 1).ld(V2, VF);
+                p.ld(VF, 1).ld(V0, 30).drw(V0, V1, 1).ld(V3, VF).jp(end);
+                p.bind(sprite).db(&[0b1000_0001]);
+                p.bind(end);
+            },
+            &[Reg(2, 0), Reg(3, 0), Pixel(10, 5, true), Pixel(30, 5, true)],
+        ),
+        case(
+            "EX9E/EXA1 keys (none pressed)",
+            |p| {
+                p.ld(V0, 5).ld(V1, 0);
+                p.skp(V0).add(V1, 1);
+                p.sknp(V0).add(V1, 2);
+            },
+            &[Reg(1, 1)],
+        ),
+        case(
+            "FX07/FX15/FX18 timers",
+            |p| {
+                p.ld(V0, 42).ld_dt(V0).ld_st(V0).ld_from_dt(V1);
+            },
+            &[Reg(1, 42)],
+        ),
+        case(
+            "FX1E add to I",
+            |p| {
+                p.ld_i(0x100u16).ld(V0, 0x22).add_i(V0);
+            },
+            &[I(0x122)],
+        ),
+        case(
+            "FX29 font glyph",
+            |p| {
+                p.ld(V0, 0xA).ld_font(V0);
+            },
+            &[I(config::FONT_DATA_ADDR + 0xA * 5)],
+        ),
+        case(
+            "FX33 BCD",
+            |p| {
+                p.ld(V0, 254).ld_i(SCRATCH).bcd(V0);
+            },
+            &[Mem(SCRATCH, 2), Mem(SCRATCH + 1, 5), Mem(SCRATCH + 2, 4)],
+        ),
+        case(
+            "FX55/FX65 store and load",
+            |p| {
+                p.ld(V0, 1).ld(V1, 2).ld(V2, 3).ld_i(SCRATCH).store(V2);
+                p.ld(V0, 0)
+                    .ld(V1, 0)
+                    .ld(V2, 0)
+                    .ld(V3, 9)
+                    .ld_i(SCRATCH)
+                    .load(V1);
+            },
+            &[
+                Mem(SCRATCH, 1),
+                Mem(SCRATCH + 1, 2),
+                Mem(SCRATCH + 2, 3),
+                Reg(0, 1),
+                Reg(1, 2),
+                Reg(2, 0),
+                Reg(3, 9),
+            ],
+        ),
+        case(
+            "00FF/DXY0 high resolution 16x16 sprite",
+            |p| {
+                let sprite = p.label();
+                let end = p.label();
+                p.high().ld(V0, 100).ld(V1, 40).ld_i(sprite);
+                p.drw(V0, V1, 0).jp(end);
+                p.bind(sprite).db(&[0x80, 0x01].repeat(16));
+                p.bind(end);
+            },
+            &[
+                Pixel(100, 40, true),
+                Pixel(115, 55, true),
+                Pixel(116, 40, false),
+                Pixel(100, 56, false),
+            ],
+        ),
+        case(
+            "00CN/00FB/00FC scrolling",
+            |p| {
+                let sprite = p.label();
+                let end = p.label();
+                p.high().ld(V0, 10).ld_i(sprite).drw(V0, V0, 1);
+                p.scd(3).scr().scl().scl().jp(end);
+                p.bind(sprite).db(&[0x80]);
+                p.bind(end);
+            },
+            &[Pixel(10, 10, false), Pixel(6, 13, true)],
+        ),
+        case(
+            "FX30 large font glyph",
+            |p| {
+                p.ld(V0, 7).ld_big_font(V0);
+            },
+            &[I(config::BIG_FONT_DATA_ADDR + 7 * 10)],
+        ),
+        case(
+            "FX75/FX85 RPL flags",
+            |p| {
+                p.ld(V0, 1).ld(V1, 2).store_rpl(V1);
+                p.ld(V0, 0).ld(V1, 0).load_rpl(V0);
+            },
+            &[Reg(0, 1), Reg(1, 0)],
+        ),
+    ]
+}
@@ -0,0 +1,264 @@
+//! Tracking of which RAM bytes are executed, read as data and written during a run, and export
+//! of the result as a report, a byte mask or an annotated disassembly of the ROM.
+
+use std::fmt::Write;
+use std::str::FromStr;
+
+use anyhow::{bail, Error};
+
+use crate::instruction::Instruction;
+
+/// Bits of each byte of `Coverage::mask`.
+pub const MASK_EXECUTED: u8 = 1;
+pub const MASK_READ: u8 = 2;
+pub const MASK_WRITTEN: u8 = 4;
+
+/// Per-address record of how memory was accessed during a run.
+pub struct Coverage {
+    origin: u16,
+    rom: Vec<u8>,
+    executed: Vec<bool>,
+    read: Vec<bool>,
+    written: Vec<bool>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Code,
+    Data,
+    /// Both executed and read as data, e.g. self-modifying code or sprites made of opcodes.
+    Both,
+    Unused,
+}
+
+/// How coverage is exported, see `Coverage::export`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CoverageFormat {
+    /// A summary, then the ROM split into ranges of code, data and unused bytes.
+    Report,
+    /// One byte per RAM address, made of the `MASK_*` bits.
+    Mask,
+    /// Every ROM instruction or run of data bytes on a line, with how it was accessed.
+    Disassembly,
+}
+
+impl FromStr for CoverageFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "report" => CoverageFormat::Report,
+            "mask" => CoverageFormat::Mask,
+            "disasm" => CoverageFormat::Disassembly,
+            _ => bail!(
+                "Unknown coverage format {:?} (expected report, mask or disasm)",
+                s
+            ),
+        })
+    }
+}
+
+impl Coverage {
+    /// Track coverage of `rom` loaded at `origin`, in a RAM of `ram_size` bytes.
+    pub fn new(origin: u16, rom: &[u8], ram_size: usize) -> Self {
+        Self {
+            origin,
+            rom: rom.to_vec(),
+            executed: vec![false; ram_size],
+            read: vec![false; ram_size],
+            written: vec![false; ram_size],
+        }
+    }
+
+    /// Record that the instruction at `addr` was executed.
+    pub fn mark_executed(&mut self, addr: u16) {
+        mark(&mut self.executed, addr, 2);
+    }
+
+    /// Record that `len` bytes starting at `addr` were read as data.
+    pub fn mark_read(&mut self, addr: u16, len: u16) {
+        mark(&mut self.read, addr, len);
+    }
+
+    /// Record that `len` bytes starting at `addr` were written.
+    pub fn mark_written(&mut self, addr: u16, len: u16) {
+        mark(&mut self.written, addr, len);
+    }
+
+    fn kind(&self, addr: usize) -> Kind {
+        match (self.executed[addr], self.read[addr]) {
+            (true, false) => Kind::Code,
+            (false, true) => Kind::Data,
+            (true, true) => Kind::Both,
+            (false, false) => Kind::Unused,
+        }
+    }
+
+    /// A text report: a summary followed by the ROM split into ranges of code, data and never
+    /// touched bytes.
+    pub fn report(&self) -> String {
+        let (start, end) = self.rom_range();
+        let count = |kind| (start..end).filter(|&a| self.kind(a) == kind).count();
+        let percent = |n: usize| 100.0 * n as f64 / (end - start).max(1) as f64;
+
+        let mut out = String::new();
+        writeln!(out, "ROM size: {} bytes", end - start).unwrap();
+        for (label, kind) in [
+            ("code", Kind::Code),
+            ("data", Kind::Data),
+            ("code+data", Kind::Both),
+            ("unused", Kind::Unused),
+        ] {
+            let n = count(kind);
+            writeln!(out, "{:>10}: {:5} bytes ({:5.1}%)", label, n, percent(n)).unwrap();
+        }
+        let written = (start..end).filter(|&a| self.written[a]).count();
+        writeln!(
+            out,
+            "{:>10}: {:5} bytes ({:5.1}%), and {} bytes outside the ROM",
+            "written",
+            written,
+            percent(written),
+            self.written.iter().filter(|&&w| w).count() - written
+        )
+        .unwrap();
+        writeln!(out).unwrap();
+
+        let mut range_start = start;
+        while range_start < end {
+            let kind = self.kind(range_start);
+            let mut range_end = range_start + 1;
+            while range_end < end && self.kind(range_end) == kind {
+                range_end += 1;
+            }
+            let label = match kind {
+                Kind::Code => "code",
+                Kind::Data => "data",
+                Kind::Both => "code+data",
+                Kind::Unused => "unused",
+            };
+            writeln!(
+                out,
+                "{:#05x}-{:#05x} {:5} bytes  {}",
+                range_start,
+                range_end - 1,
+                range_end - range_start,
+                label
+            )
+            .unwrap();
+            range_start = range_end;
+        }
+        out
+    }
+
+    /// The addresses of the ROM in RAM, as a start and an end (excluded).
+    fn rom_range(&self) -> (usize, usize) {
+        let start = self.origin as usize;
+        (start, (start + self.rom.len()).min(self.executed.len()))
+    }
+
+    /// How the byte at `addr` was accessed, as `MASK_*` bits.
+    fn access(&self, addr: usize) -> u8 {
+        let mut bits = 0;
+        if self.executed[addr] {
+            bits |= MASK_EXECUTED;
+        }
+        if self.read[addr] {
+            bits |= MASK_READ;
+        }
+        if self.written[addr] {
+            bits |= MASK_WRITTEN;
+        }
+        bits
+    }
+
+    /// One byte per RAM address, made of the `MASK_*` bits.
+    pub fn mask(&self) -> Vec<u8> {
+        (0..self.executed.len()).map(|a| self.access(a)).collect()
+    }
+
+    /// The ROM with one executed instruction or run of up to 8 other bytes per line, each with
+    /// how it was accessed: `X` for executed, `R` for read as data and `W` for written.
+    pub fn disassembly(&self) -> String {
+        let (start, end) = self.rom_range();
+        let flags = |range: std::ops::Range<usize>| {
+            let bits = range.map(|a| self.access(a)).fold(0, |acc, b| acc | b);
+            [(MASK_EXECUTED, 'X'), (MASK_READ, 'R'), (MASK_WRITTEN, 'W')]
+                .iter()
+                .map(|&(bit, c)| if bits & bit != 0 { c } else { '.' })
+                .collect::<String>()
+        };
+        let hex = |range: std::ops::Range<usize>| {
+            let bytes: Vec<String> = self.rom[range.start - start..range.end - start]
+                .iter()
+                .map(|b| format!("{:02X}", b))
+                .collect();
+            bytes.join(" ")
+        };
+        let opcode = |a: usize| (self.rom[a - start] as u16) << 8 | self.rom[a + 1 - start] as u16;
+
+        let mut out = String::from("; X: executed, R: read as data, W: written\n");
+        let mut addr = start;
+        while addr < end {
+            if self.executed[addr] && addr + 1 < end {
+                let (text, len) = match opcode(addr) {
+                    0xF000 if addr + 3 < end => {
+                        (format!("i := long 0x{:04X}", opcode(addr + 2)), 4)
+                    }
+                    op => match Instruction::decode(op) {
+                        Ok(instruction) => (instruction.to_string(), 2),
+                        Err(_) => ("?".to_string(), 2),
+                    },
+                };
+                let range = addr..addr + len;
+                writeln!(
+                    out,
+                    "{:#05x}  {}  {:<23}  {}",
+                    addr,
+                    flags(range.clone()),
+                    hex(range),
+                    text
+                )
+                .unwrap();
+                addr += len;
+            } else {
+                let bits = self.access(addr);
+                let mut run_end = addr + 1;
+                while run_end < end
+                    && run_end - addr < 8
+                    && !self.executed[run_end]
+                    && self.access(run_end) == bits
+                {
+                    run_end += 1;
+                }
+                let range = addr..run_end;
+                writeln!(
+                    out,
+                    "{:#05x}  {}  {}",
+                    addr,
+                    flags(range.clone()),
+                    hex(range)
+                )
+                .unwrap();
+                addr = run_end;
+            }
+        }
+        out
+    }
+
+    /// The coverage in `format`.
+    pub fn export(&self, format: CoverageFormat) -> Vec<u8> {
+        match format {
+            CoverageFormat::Report => self.report().into_bytes(),
+            CoverageFormat::Mask => self.mask(),
+            CoverageFormat::Disassembly => self.disassembly().into_bytes(),
+        }
+    }
+}
+
+/// Set the `len` flags of `flags` from `addr`, ignoring those past the end of RAM.
+fn mark(flags: &mut [bool], addr: u16, len: u16) {
+    let start = (addr as usize).min(flags.len());
+    let end = (addr as usize + len as usize).min(flags.len());
+    flags[start..end].iter_mut().for_each(|f| *f = true);
+}
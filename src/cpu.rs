@@ -1,17 +1,86 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{bail, Error};
 use log::{warn, debug};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::config::{self, Quirks};
+use crate::error::Chip8Error;
+use crate::instruction::Instruction;
+use crate::interconnect::Interconnect;
+use crate::ram::Ram;
+
+/// What `0NNN` calls to machine code routines of the original interpreter do when no hook handles
+/// them, as the routines can't be run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SysPolicy {
+    /// Carry on as if the routine returned at once.
+    Ignore,
+    /// Carry on, logging a warning and counting a fault.
+    #[default]
+    Warn,
+    /// Stop the program with `Chip8Error::MachineCode`.
+    Halt,
+}
+
+impl fmt::Display for SysPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SysPolicy::Ignore => "ignore",
+            SysPolicy::Warn => "warn",
+            SysPolicy::Halt => "halt",
+        })
+    }
+}
+
+impl FromStr for SysPolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "ignore" => SysPolicy::Ignore,
+            "warn" => SysPolicy::Warn,
+            "halt" => SysPolicy::Halt,
+            _ => bail!(
+                "Unknown machine code policy {:?} (expected ignore, warn or halt)",
+                s
+            ),
+        })
+    }
+}
 
-use crate::config;
-use crate::Interconnect;
+/// A handler for `0NNN` calls, given the address of the routine called. It can stand in for a
+/// routine a program relies on.
+pub type SysHook = Box<dyn FnMut(u16, &mut Interconnect) -> Result<(), Chip8Error> + Send>;
 
 /// The CPU of the Chip-8 machine.
 ///
 /// It decodes and executes instructions fetched from RAM (via the `Interconnect`), and maintains a
 /// set of registers and a stack.
-/// TODO: The stack should really be a pointer to some address in the RAM.
 pub struct Cpu {
     pc: u16,
     regs: Registers,
     stack: Stack,
+    pub quirks: Quirks,
+    /// Whether the XO-CHIP instructions are available.
+    pub xochip: bool,
+    /// Whether the Mega-Chip instructions are available.
+    pub megachip: bool,
+    /// Source of `CXNN` random numbers.
+    rng: StdRng,
+    /// The SUPER-CHIP "RPL user flags" that `FX75`/`FX85` save and restore registers to. There
+    /// are 8 of them, or 16 with XO-CHIP.
+    rpl: [u8; 16],
+    /// Key pressed during an `FX0A`, which completes once it's released.
+    pressed_key: Option<u8>,
+    /// What `0NNN` calls without a hook do.
+    pub sys_policy: SysPolicy,
+    /// Hooks for `0NNN` calls to an address, or to any address without a hook of its own for
+    /// `None`.
+    pub(crate) sys_hooks: HashMap<Option<u16>, SysHook>,
 }
 
 impl Cpu {
@@ -19,12 +88,119 @@ impl Cpu {
         Cpu {
             pc: config::PROG_ADDR,
             regs: Registers::default(),
-            stack: Stack::new(),
+            stack: Stack::new(config::DEFAULT_STACK_DEPTH, false),
+            quirks: Quirks::default(),
+            xochip: false,
+            megachip: false,
+            rng: StdRng::from_entropy(),
+            rpl: [0; 16],
+            pressed_key: None,
+            sys_policy: SysPolicy::default(),
+            sys_hooks: HashMap::new(),
         }
     }
 
-    pub fn emulate_cycle(&mut self, interconnect: &mut Interconnect) {
-        let opcode = interconnect.fetch_opcode(self.pc);
+    /// Handle `0NNN` calls to `addr` with `hook`, or calls to any address without a hook of its
+    /// own if `addr` is `None`, instead of following `sys_policy`.
+    pub fn set_sys_hook(&mut self, addr: Option<u16>, hook: SysHook) {
+        self.sys_hooks.insert(addr, hook);
+    }
+
+    /// Make `CXNN` produce the same sequence of numbers on every run with the same `seed`.
+    pub fn seed_rng(&mut self, seed: u64) {
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    /// Continue execution at `pc`.
+    pub fn set_pc(&mut self, pc: u16) {
+        self.pc = pc;
+    }
+
+    pub fn i(&self) -> u16 {
+        self.regs.I
+    }
+
+    /// Value of register VX.
+    pub fn v(&self, x: u8) -> u8 {
+        self.regs[x]
+    }
+
+    /// Return addresses on the stack, the oldest first. With a stack in RAM, these are the
+    /// addresses pushed, even if the program has overwritten them since.
+    pub fn stack(&self) -> &[u16] {
+        self.stack.entries()
+    }
+
+    /// Allow `depth` nested subroutine calls, keeping the return addresses below
+    /// `config::RAM_STACK_TOP` in RAM if `in_ram`, where the program can see and change them as on
+    /// the COSMAC VIP. The stack is emptied.
+    pub fn set_stack(&mut self, depth: usize, in_ram: bool) {
+        self.stack = Stack::new(depth, in_ram);
+    }
+
+    /// Number of nested subroutine calls allowed.
+    pub fn stack_depth(&self) -> usize {
+        self.stack.depth
+    }
+
+    /// Whether the return addresses are kept in RAM.
+    pub fn stack_in_ram(&self) -> bool {
+        self.stack.in_ram
+    }
+
+    /// The SUPER-CHIP RPL user flags.
+    pub fn rpl(&self) -> [u8; 16] {
+        self.rpl
+    }
+
+    /// Put the registers, stack and flags back to a saved state. A key press an `FX0A` was
+    /// waiting to see released is forgotten.
+    pub fn restore(
+        &mut self,
+        pc: u16,
+        i: u16,
+        v: [u8; 16],
+        stack: &[u16],
+        rpl: [u8; 16],
+    ) -> Result<(), Chip8Error> {
+        self.pc = pc;
+        self.regs.I = i;
+        for (x, value) in v.into_iter().enumerate() {
+            self.regs[x as u8] = value;
+        }
+        self.stack.restore(stack)?;
+        self.rpl = rpl;
+        self.pressed_key = None;
+        Ok(())
+    }
+
+    /// Fetch, decode and execute the instruction at the PC.
+    pub fn emulate_cycle(&mut self, interconnect: &mut Interconnect) -> Result<(), Chip8Error> {
+        let opcode = self.fetch(interconnect)?;
+        let instruction = self.decode(opcode)?;
+        self.execute(instruction, interconnect)
+    }
+
+    /// Decode `opcode` for this machine: XO-CHIP instructions are unknown without the extensions,
+    /// and Mega-Chip ones are machine code calls on other machines.
+    pub fn decode(&self, opcode: u16) -> Result<Instruction, Chip8Error> {
+        let instruction = Instruction::decode(opcode)?;
+        if instruction.is_xochip() && !self.xochip {
+            return Err(Chip8Error::UnknownOpcode(opcode));
+        }
+        if instruction.is_megachip() && !self.megachip {
+            return Ok(Instruction::Sys(opcode & 0x0FFF));
+        }
+        Ok(instruction)
+    }
+
+    /// The opcode at the PC.
+    pub fn fetch(&self, interconnect: &Interconnect) -> Result<u16, Chip8Error> {
+        let opcode = interconnect.fetch_opcode(self.pc)?;
         debug!("op={:#04x}, pc={:#04x}, I={:04x}, regs=[{:x},{:x},{:x},{:x},{:x},{:x},{:x},{:x},{:x},{:x},{:x},{:x},{:x},{:x},{:x},{:x},]",
                opcode,
                self.pc,
@@ -46,252 +222,390 @@ impl Cpu {
                self.regs[0xe],
                self.regs[0xf],
                );
+        Ok(opcode)
+    }
 
-        match opcode & 0xF000 {
-            0x0000 => {
-                if opcode == 0x00E0 {
-                    // Clear the screen
-                    interconnect.gfx.clear();
-                } else if opcode == 0x00EE {
-                    // Return from subroutine
-                    self.pc = self.stack.pop();
-                    debug!("Returning from subroutine to {:#04x}", self.pc);
-                } else {
-                    // Call RCA1802 program
-                    warn!("unimplemented opcode {:#04x}", opcode);
+    /// Execute `instruction`, fetched from the PC.
+    pub fn execute(
+        &mut self,
+        instruction: Instruction,
+        interconnect: &mut Interconnect,
+    ) -> Result<(), Chip8Error> {
+        use Instruction::*;
+
+        let pc = self.pc;
+        self.pc = pc.wrapping_add(2);
+        match instruction {
+            // Hires CHIP-8 programs clear their 64x64 display with a machine code routine.
+            Sys(0x230) if interconnect.gfx.is_two_page() => interconnect.gfx.clear(),
+            Sys(addr) => self.call_machine_code(addr, interconnect)?,
+            MegaOff => interconnect.gfx.set_megachip(false),
+            MegaOn => interconnect.gfx.set_megachip(true),
+            MegaScrollUp(n) => interconnect.gfx.scroll_up(n as usize),
+            LoadHigh(nn) => {
+                // Load the 24-bit address made of NN and the next 16 bits into I. RAM only goes
+                // up to 64K, I being 16 bits wide.
+                let addr = (nn as usize) << 16 | interconnect.fetch_opcode(self.pc)? as usize;
+                if addr >= interconnect.ram.size() {
+                    return Err(Chip8Error::AddressOutOfRange(addr));
                 }
-                self.pc += 2;
+                self.regs.I = addr as u16;
+                self.pc = self.pc.wrapping_add(2);
             }
-            0x1000 => {
-                // jump
-                let addr = opcode & 0x0FFF;
-                self.pc = addr;
+            LoadPalette(nn) => {
+                interconnect.record_read(self.regs.I, nn as u16 * 4);
+                let colors = (0..nn as usize * 4)
+                    .map(|offset| interconnect.ram.read(self.regs.I as usize + offset))
+                    .collect::<Result<Vec<u8>, _>>()?;
+                interconnect.gfx.load_palette(&colors);
+            }
+            SpriteWidth(nn) => interconnect.gfx.set_sprite_width(nn),
+            SpriteHeight(nn) => interconnect.gfx.set_sprite_height(nn),
+            Alpha(_) | PlaySound(_) | StopSound => {
+                debug!(
+                    "unsupported Mega-Chip instruction {} at {:#05x}",
+                    instruction, pc
+                );
+            }
+            BlendMode(mode) => {
+                if mode != 0 {
+                    debug!("unsupported Mega-Chip blend mode {} at {:#05x}", mode, pc);
+                }
+            }
+            CollisionColor(nn) => interconnect.gfx.set_collision_color(nn),
+            ScrollDown(n) => {
+                let n = self.scroll_amount(interconnect, n as usize);
+                interconnect.gfx.scroll_down(n);
+            }
+            ScrollUp(n) => interconnect.gfx.scroll_up(n as usize),
+            Clear => interconnect.gfx.clear(),
+            Return => {
+                self.pc = self.stack.pop(&interconnect.ram)?.wrapping_add(2);
+                debug!("Returning from subroutine to {:#04x}", self.pc);
+            }
+            ScrollRight => {
+                let n = self.scroll_amount(interconnect, 4);
+                interconnect.gfx.scroll_right(n);
             }
-            0x2000 => {
-                // Call subroutine
-                let addr = opcode & 0x0FFF;
+            ScrollLeft => {
+                let n = self.scroll_amount(interconnect, 4);
+                interconnect.gfx.scroll_left(n);
+            }
+            Exit => {
+                // Exit the interpreter: stay on this instruction forever
+                debug!("Program exited at {:#04x}", pc);
+                self.pc = pc;
+            }
+            Lores => interconnect.gfx.set_hires(false),
+            Hires => interconnect.gfx.set_hires(true),
+            Jump(addr) => self.pc = addr,
+            Call(addr) => {
                 debug!("Calling subroutine at {:#04x}", addr);
-                self.stack.push(self.pc);
+                self.stack.push(pc, &mut interconnect.ram)?;
                 self.pc = addr;
             }
-            0x3000 => {
-                // Skip next instruction if VX == NN
-                let reg = ((opcode & 0x0F00) >> 8) as u8;
-                let value = (opcode & 0x00FF) as u8;
-                if self.regs[reg] == value {
-                    self.pc += 4;
-                } else {
-                    self.pc += 2;
+            SkipEq(x, nn) => self.skip_if(self.regs[x] == nn, interconnect),
+            SkipNe(x, nn) => self.skip_if(self.regs[x] != nn, interconnect),
+            SkipEqReg(x, y) => self.skip_if(self.regs[x] == self.regs[y], interconnect),
+            SaveRange(x, y) => {
+                // Save VX to VY (in either order) at I, leaving I unchanged
+                interconnect.record_write(self.regs.I, x.abs_diff(y) as u16 + 1);
+                for (offset, reg) in Self::reg_range(x, y).enumerate() {
+                    interconnect
+                        .ram
+                        .write(self.regs.I as usize + offset, self.regs[reg])?;
+                }
+            }
+            LoadRange(x, y) => {
+                // Load VX to VY (in either order) from I, leaving I unchanged
+                interconnect.record_read(self.regs.I, x.abs_diff(y) as u16 + 1);
+                for (offset, reg) in Self::reg_range(x, y).enumerate() {
+                    self.regs[reg] = interconnect.ram.read(self.regs.I as usize + offset)?;
                 }
             }
-            0x4000 => {
-                // Skip next instruction if VX == NN
-                let reg = ((opcode & 0x0F00) >> 8) as u8;
-                let value = (opcode & 0x00FF) as u8;
-                if self.regs[reg] != value {
-                    self.pc += 4;
+            Load(x, nn) => self.regs[x] = nn,
+            Add(x, nn) => self.regs[x] = self.regs[x].wrapping_add(nn),
+            Move(x, y) => self.regs[x] = self.regs[y],
+            Or(x, y) => {
+                self.regs[x] |= self.regs[y];
+                self.reset_vf();
+            }
+            And(x, y) => {
+                self.regs[x] &= self.regs[y];
+                self.reset_vf();
+            }
+            Xor(x, y) => {
+                self.regs[x] ^= self.regs[y];
+                self.reset_vf();
+            }
+            AddReg(x, y) => {
+                let (sum, overflow) = self.regs[x].overflowing_add(self.regs[y]);
+                self.regs[x] = sum;
+                self.regs.set_carry(overflow);
+            }
+            Sub(x, y) => {
+                let (diff, overflow) = self.regs[x].overflowing_sub(self.regs[y]);
+                self.regs[x] = diff;
+                self.regs.set_carry(!overflow);
+            }
+            ShiftRight(x, y) => {
+                let v = self.regs[if self.quirks.shift_vx_only { x } else { y }];
+                let lsb = v & 0x01;
+                self.regs[x] = v >> 1;
+                if lsb == 1 {
+                    self.regs.set_carry(true);
                 } else {
-                    self.pc += 2;
+                    self.regs.set_carry(false);
                 }
             }
-            0x5000 => {
-                // Skip the next instruction if VX == VY
-                let x = ((opcode & 0x0F00) >> 8) as u8;
-                let y = ((opcode & 0x00F0) >> 4) as u8;
-                if self.regs[x] == self.regs[y] {
-                    self.pc += 4;
+            SubReverse(x, y) => {
+                let (diff, overflow) = self.regs[y].overflowing_sub(self.regs[x]);
+                self.regs[x] = diff;
+                self.regs.set_carry(!overflow);
+            }
+            ShiftLeft(x, y) => {
+                let v = self.regs[if self.quirks.shift_vx_only { x } else { y }];
+                let msb = v & 0x80;
+                self.regs[x] = v << 1;
+                if msb != 0 {
+                    self.regs.set_carry(true);
                 } else {
-                    self.pc += 2;
+                    self.regs.set_carry(false);
                 }
             }
-            0x6000 => {
-                // Set VX to NN
-                let reg = ((opcode & 0x0F00) >> 8) as u8;
-                let value = (opcode & 0x00FF) as u8;
-                self.regs[reg] = value;
-                self.pc += 2;
-            }
-            0x7000 => {
-                // Add NN to VX (don't set carry flag)
-                let reg = ((opcode & 0x0F00) >> 8) as u8;
-                let value = (opcode & 0x00FF) as u8;
-                self.regs[reg] = self.regs[reg].wrapping_add(value);
-                self.pc += 2;
-            }
-            0x8000 => {
-                let x = ((opcode & 0x0F00) >> 8) as u8;
-                let y = ((opcode & 0x00F0) >> 4) as u8;
-                let op = (opcode & 0x000F) as u8;
-
-                match op {
-                    // Set Vx to Vy
-                    0 => self.regs[x] = self.regs[y],
-                    1 => self.regs[x] |= self.regs[y],
-                    2 => self.regs[x] &= self.regs[y],
-                    3 => self.regs[x] ^= self.regs[y],
-                    4 => {
-                        let (sum, overflow) = self.regs[x].overflowing_add(self.regs[y]);
-                        self.regs[x] = sum;
-                        self.regs.set_carry(overflow);
-                    }
-                    5 => {
-                        let (diff, overflow) = self.regs[x].overflowing_sub(self.regs[y]);
-                        self.regs[x] = diff;
-                        self.regs.set_carry(!overflow);
-                    }
-                    6 => {
-                        let lsb = self.regs[x] & 0x01;
-                        self.regs[x] >>= 1;
-                        if lsb == 1 {
-                            self.regs.set_carry(true);
-                        } else {
-                            self.regs.set_carry(false);
-                        }
-                    }
-                    7 => {
-                        let (diff, overflow) = self.regs[y].overflowing_sub(self.regs[x]);
-                        self.regs[x] = diff;
-                        self.regs.set_carry(!overflow);
+            SkipNeReg(x, y) => self.skip_if(self.regs[x] != self.regs[y], interconnect),
+            LoadI(addr) => self.regs.I = addr,
+            JumpOffset(addr) => {
+                let offset = if self.quirks.jump_vx {
+                    self.regs[(addr >> 8) as u8]
+                } else {
+                    self.regs[0]
+                };
+                self.pc = addr + offset as u16;
+            }
+            Random(x, nn) => self.regs[x] = self.rng.gen::<u8>() & nn,
+            Draw(x, y, n) => {
+                let (vx, vy) = (self.regs[x], self.regs[y]);
+                let wrap = self.quirks.wrap_sprites;
+                let collision = match n {
+                    // Mega-Chip sprites are in color, except for the font below the program.
+                    _ if interconnect.gfx.is_megachip() && self.regs.I >= config::PROG_ADDR => {
+                        interconnect.draw_indexed_sprite(self.regs.I, vx, vy)?
                     }
-                    0x0E => {
-                        let msb = self.regs[x] & 0x80;
-                        self.regs[x] <<= 1;
-                        if msb == 1 {
-                            self.regs.set_carry(true);
-                        } else {
-                            self.regs.set_carry(false);
-                        }
+                    0 if self.quirks.lores_dxy0_8x16 && !interconnect.gfx.is_hires() => {
+                        interconnect.draw_sprite(self.regs.I, vx, vy, 16, wrap)?
                     }
-                    _ => panic!("invalid opcode {:#04x}", opcode),
+                    0 => interconnect.draw_large_sprite(self.regs.I, vx, vy, wrap)?,
+                    n => interconnect.draw_sprite(self.regs.I, vx, vy, n, wrap)?,
+                };
+                // collision -> set the CF flag
+                self.regs.set_carry(collision);
+            }
+            // The VIP only wires the low nibble of VX to the keypad.
+            SkipKey(x) => {
+                let pressed = interconnect.keys[(self.regs[x] & 0xF) as usize];
+                if pressed {
+                    debug!("Key {} pressed", self.regs[x]);
                 }
-                self.pc += 2;
-            }
-            0x9000 => {
-                // Skip the next instruction if VX != VY
-                let x = ((opcode & 0x0F00) >> 8) as u8;
-                let y = ((opcode & 0x00F0) >> 4) as u8;
-                if self.regs[x] != self.regs[y] {
-                    self.pc += 4;
-                } else {
-                    self.pc += 2;
+                self.skip_if(pressed, interconnect);
+            }
+            SkipNoKey(x) => {
+                let pressed = interconnect.keys[(self.regs[x] & 0xF) as usize];
+                if pressed {
+                    debug!("Key {} pressed", self.regs[x]);
                 }
+                self.skip_if(!pressed, interconnect);
             }
-            0xA000 => {
-                let addr = opcode & 0x0FFF;
-                self.regs.I = addr;
-                self.pc += 2;
+            LoadLong => {
+                // Load the 16-bit address following this instruction into I
+                self.regs.I = interconnect.fetch_opcode(self.pc)?;
+                self.pc = self.pc.wrapping_add(2);
             }
-            0xB000 => {
-                let addr = opcode & 0x0FFF;
-                self.pc = addr + self.regs[0] as u16;
+            Plane(n) => interconnect.gfx.select_planes(n),
+            Audio => {
+                interconnect.record_read(self.regs.I, 16);
+                let mut pattern = [0; 16];
+                for (offset, byte) in pattern.iter_mut().enumerate() {
+                    *byte = interconnect.ram.read(self.regs.I as usize + offset)?;
+                }
+                interconnect.audio_pattern = Some(pattern);
             }
-            0xC000 => {
-                let x = ((opcode & 0x0F00) >> 8) as u8;
-                let n = (opcode & 0x00FF) as u8;
-
-                self.regs[x] = rand::random::<u8>() & n;
-                self.pc += 2;
+            GetDelay(x) => self.regs[x] = interconnect.delay_timer,
+            WaitKey(x) if self.quirks.wait_key_on_press => {
+                // find the first key that's pressed
+                if let Some(idx) = interconnect.keys.iter().position(|v| *v) {
+                    self.regs[x] = idx as u8;
+                    // reset the key we just read so we don't read it again in the next cycle
+                    interconnect.keys[idx] = false;
+                } else {
+                    // do not move the PC: the program is effectively halted until a key is
+                    // pressed.
+                    self.pc = pc;
+                }
             }
-            0xD000 => {
-                let x = ((opcode & 0x0F00) >> 8) as u8;
-                let y = ((opcode & 0x00F0) >> 4) as u8;
-                let n = (opcode & 0x000F) as u8;
-                let collision = interconnect.draw_sprite(self.regs.I, self.regs[x], self.regs[y], n);
-                // collision -> set the CF flag
-                self.regs.set_carry(collision);
-                self.pc += 2;
-            }
-            0xE000 => {
-                let x = ((opcode & 0x0F00) >> 8) as u8;
-                let op = opcode & 0x00FF;
-
-                match op {
-                    0x9E => {
-                        if interconnect.keys[self.regs[x] as usize] {
-                            debug!("Key {} pressed", self.regs[x]);
-                            self.pc += 4;
-                        } else {
-                            self.pc += 2;
-                        }
-                    }
-                    0xA1 => {
-                        if !interconnect.keys[self.regs[x] as usize] {
-                            self.pc += 4;
-                        } else {
-                            debug!("Key {} pressed", self.regs[x]);
-                            self.pc += 2;
-                        }
+            WaitKey(x) => match self.pressed_key {
+                // like the COSMAC VIP, only complete once the key is released
+                Some(key) if !interconnect.keys[key as usize] => {
+                    self.regs[x] = key;
+                    self.pressed_key = None;
+                }
+                _ => {
+                    if self.pressed_key.is_none() {
+                        self.pressed_key = interconnect
+                            .keys
+                            .iter()
+                            .position(|v| *v)
+                            .map(|idx| idx as u8);
                     }
-                    _ => panic!("Unkown opcode {:#x}", opcode),
+                    self.pc = pc;
+                }
+            },
+            SetDelay(x) => interconnect.delay_timer = self.regs[x],
+            SetSound(x) => interconnect.sound_timer = self.regs[x],
+            AddI(x) => {
+                let i = self.regs.I.wrapping_add(self.regs[x] as u16);
+                self.regs.I = if self.quirks.add_i_wrap { i & 0xFFF } else { i };
+                if self.quirks.add_i_vf {
+                    self.regs[0xF] = (i > 0xFFF) as u8;
                 }
             }
-            // Misc
-            0xF000 => {
-                let x = ((opcode & 0x0F00) >> 8) as u8;
-                let op = opcode & 0x00FF;
-
-                match op {
-                    0x07 => {
-                        self.regs[x] = interconnect.delay_timer;
-                    }
-                    0x0A => {
-                        // find the first key that's pressed
-                        if let Some(idx) = interconnect.keys.iter().position(|v| *v) {
-                            self.regs[x] = idx as u8;
-                            // reset the key we just read so we don't read it again in the next
-                            // cycle
-                            interconnect.keys[idx] = false;
-                        } else {
-                            // do not increment PC: the program is effectively halted until a key
-                            // is pressed.
-                            self.pc -= 2;
-                        }
-                    }
-                    0x15 => {
-                        interconnect.delay_timer = self.regs[x];
-                    }
-                    0x18 => {
-                        interconnect.sound_timer = self.regs[x];
-                    }
-                    0x1E => {
-                        self.regs.I += self.regs[x] as u16;
-                    }
-                    0x29 => {
-                        self.regs.I = config::FONT_DATA_ADDR + self.regs[x] as u16 * 5;
-                    }
-                    0x33 => {
-                        // BCD
-                        let mut v = self.regs[x];
-                        let units = v % 10;
-                        v /= 10;
-                        let tens = v % 10;
-                        v /= 10;
-                        let hundreds = v % 10;
-                        interconnect.ram[self.regs.I] = hundreds;
-                        interconnect.ram[self.regs.I + 1] = tens;
-                        interconnect.ram[self.regs.I + 2] = units;
-                    }
-                    0x55 => {
-                        for i in 0..=x {
-                            interconnect.ram[self.regs.I] = self.regs[i];
-                            self.regs.I += 1;
-                        }
-                    }
-                    0x65 => {
-                        for i in 0..=x {
-                            self.regs[i] = interconnect.ram[self.regs.I];
-                            self.regs.I += 1;
-                        }
-                    }
-                    _ => panic!("unknown opcode {:#04x}", opcode),
+            Font(x) => self.regs.I = config::FONT_DATA_ADDR + self.regs[x] as u16 * 5,
+            BigFont(x) => self.regs.I = config::BIG_FONT_DATA_ADDR + self.regs[x] as u16 * 10,
+            Bcd(x) => {
+                let mut v = self.regs[x];
+                let units = v % 10;
+                v /= 10;
+                let tens = v % 10;
+                v /= 10;
+                let hundreds = v % 10;
+                let i = self.regs.I as usize;
+                interconnect.record_write(self.regs.I, 3);
+                interconnect.ram.write(i, hundreds)?;
+                interconnect.ram.write(i + 1, tens)?;
+                interconnect.ram.write(i + 2, units)?;
+            }
+            Pitch(x) => interconnect.pitch = self.regs[x],
+            Store(x) => {
+                let start = self.regs.I;
+                interconnect.record_write(start, x as u16 + 1);
+                for i in 0..=x {
+                    interconnect.ram.write(self.regs.I as usize, self.regs[i])?;
+                    self.regs.I = self.regs.I.wrapping_add(1);
                 }
-                self.pc += 2;
+                if self.quirks.load_store_keeps_i {
+                    self.regs.I = start;
+                } else if self.quirks.load_store_i_by_x {
+                    self.regs.I = start.wrapping_add(x as u16);
+                }
+            }
+            Restore(x) => {
+                let start = self.regs.I;
+                interconnect.record_read(self.regs.I, x as u16 + 1);
+                for i in 0..=x {
+                    self.regs[i] = interconnect.ram.read(self.regs.I as usize)?;
+                    self.regs.I = self.regs.I.wrapping_add(1);
+                }
+                if self.quirks.load_store_keeps_i {
+                    self.regs.I = start;
+                } else if self.quirks.load_store_i_by_x {
+                    self.regs.I = start.wrapping_add(x as u16);
+                }
+            }
+            SaveFlags(x) => {
+                for i in 0..=x.min(self.last_rpl_flag()) {
+                    self.rpl[i as usize] = self.regs[i];
+                }
+            }
+            LoadFlags(x) => {
+                for i in 0..=x.min(self.last_rpl_flag()) {
+                    self.regs[i] = self.rpl[i as usize];
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Handle a `0NNN` call to the machine code routine at `addr`, with its hook or the catch-all
+    /// one if there is one, or as `sys_policy` says.
+    fn call_machine_code(
+        &mut self,
+        addr: u16,
+        interconnect: &mut Interconnect,
+    ) -> Result<(), Chip8Error> {
+        let key = Some(addr).filter(|addr| self.sys_hooks.contains_key(&Some(*addr)));
+        if let Some(hook) = self.sys_hooks.get_mut(&key) {
+            return hook(addr, interconnect);
+        }
+        match self.sys_policy {
+            SysPolicy::Ignore => debug!("ignoring call to machine code at {:#05x}", addr),
+            SysPolicy::Warn => {
+                warn!("unimplemented opcode {:#04x}", addr);
+                interconnect.metrics.inc_faults();
             }
-            _ => panic!("unknown opcode {:#04x}", opcode),
+            SysPolicy::Halt => return Err(Chip8Error::MachineCode(addr)),
+        }
+        Ok(())
+    }
+
+    /// Skip the next instruction if `condition` holds. The PC must already point to it.
+    fn skip_if(&mut self, condition: bool, interconnect: &Interconnect) {
+        if condition {
+            self.skip(interconnect);
+        }
+    }
+
+    /// Reset VF after a logic instruction, with the `vf_reset` quirk.
+    fn reset_vf(&mut self) {
+        if self.quirks.vf_reset {
+            self.regs.set_carry(false);
+        }
+    }
+
+    /// Skip the next instruction, which is 4 bytes long if it's an XO-CHIP `F000 NNNN` or a
+    /// Mega-Chip `01NN NNNN`.
+    fn skip(&mut self, interconnect: &Interconnect) {
+        let long = match interconnect.fetch_opcode(self.pc) {
+            Ok(0xF000) => self.xochip,
+            Ok(opcode) => self.megachip && opcode & 0xFF00 == 0x0100,
+            Err(_) => false,
+        };
+        self.pc = self.pc.wrapping_add(if long { 4 } else { 2 });
+    }
+
+    /// The registers from VX to VY, in that order, for `5XY2`/`5XY3`.
+    fn reg_range(x: u8, y: u8) -> Box<dyn Iterator<Item = u8>> {
+        if x <= y {
+            Box::new(x..=y)
+        } else {
+            Box::new((y..=x).rev())
+        }
+    }
+
+    /// Index of the last RPL flag: only V0 to V7 fit in them, or all registers with XO-CHIP.
+    fn last_rpl_flag(&self) -> u8 {
+        if self.xochip {
+            15
+        } else {
+            7
+        }
+    }
+
+    /// How many display pixels a scroll instruction moves by, given its nominal amount.
+    fn scroll_amount(&self, interconnect: &Interconnect, n: usize) -> usize {
+        if self.quirks.half_pixel_scroll && !interconnect.gfx.is_hires() {
+            n / 2
+        } else {
+            n
         }
     }
 }
 
+impl Default for Cpu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 /// Holds general purpose registers
 #[allow(non_snake_case)]
 #[derive(Debug, Default)]
@@ -373,28 +687,60 @@ impl std::ops::IndexMut<u8> for Registers {
 }
 
 struct Stack {
-    st: [u16; 16],
-    sp: u16,
+    st: Vec<u16>,
+    /// Maximum number of entries.
+    depth: usize,
+    /// Whether the entries are also written to RAM, growing down from `config::RAM_STACK_TOP`,
+    /// and read back from there.
+    in_ram: bool,
 }
 
 impl Stack {
-    pub fn new() -> Self {
+    pub fn new(depth: usize, in_ram: bool) -> Self {
         Self {
-            st: [0u16; 16],
-            sp: 0,
+            st: Vec::with_capacity(depth),
+            depth,
+            in_ram,
         }
     }
 
-    pub fn push(&mut self, v: u16) {
-        assert!(self.sp < 16, "stack overflow");
-        self.sp += 1;
-        self.st[self.sp as usize] = v;
+    /// Address in RAM of entry `n`, when the stack is kept there.
+    fn addr(n: usize) -> usize {
+        config::RAM_STACK_TOP as usize - 2 * (n + 1)
     }
 
-    pub fn pop(&mut self) -> u16 {
-        assert!(self.sp > 0, "stack underflow");
-        let v = self.st[self.sp as usize];
-        self.sp -= 1;
-        v
+    pub fn push(&mut self, v: u16, ram: &mut Ram) -> Result<(), Chip8Error> {
+        if self.st.len() == self.depth {
+            return Err(Chip8Error::StackOverflow);
+        }
+        if self.in_ram {
+            let addr = Self::addr(self.st.len());
+            ram.write(addr, (v >> 8) as u8)?;
+            ram.write(addr + 1, v as u8)?;
+        }
+        self.st.push(v);
+        Ok(())
+    }
+
+    pub fn entries(&self) -> &[u16] {
+        &self.st
+    }
+
+    pub fn pop(&mut self, ram: &Ram) -> Result<u16, Chip8Error> {
+        let v = self.st.pop().ok_or(Chip8Error::StackUnderflow)?;
+        if self.in_ram {
+            let addr = Self::addr(self.st.len());
+            return Ok(((ram.read(addr)? as u16) << 8) | ram.read(addr + 1)? as u16);
+        }
+        Ok(v)
+    }
+
+    /// Replace the entries with `entries`, which are already in RAM if the stack is kept there.
+    pub fn restore(&mut self, entries: &[u16]) -> Result<(), Chip8Error> {
+        if entries.len() > self.depth {
+            return Err(Chip8Error::StackOverflow);
+        }
+        self.st = entries.to_vec();
+        Ok(())
     }
 }
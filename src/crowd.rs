@@ -0,0 +1,126 @@
+use std::io::{BufRead, BufReader};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use log::{debug, info, warn};
+
+/// How long the winning key is held down at the end of a voting window.
+const PRESS_DURATION: Duration = Duration::from_millis(150);
+
+/// Aggregates keypad votes coming from a network feed ("Twitch plays CHIP-8").
+///
+/// Clients (typically a bridge relaying IRC or Twitch chat) connect over TCP and send one vote per
+/// line. A vote is a single hexadecimal keypad digit, optionally prefixed with `!` as is customary
+/// for chat commands. At the end of every voting window, the key with the most votes is pressed.
+pub struct Crowd {
+    votes: Receiver<u8>,
+    tally: [u32; 16],
+    window: Duration,
+    window_start: Instant,
+    pressed: Option<(u8, Instant)>,
+}
+
+impl Crowd {
+    /// Start accepting votes on `addr`, electing a key every `window`.
+    pub fn listen<A: ToSocketAddrs>(addr: A, window: Duration) -> Result<Self> {
+        let listener = TcpListener::bind(addr).context("Failed to bind crowd-play listener")?;
+        info!("accepting crowd votes on {}", listener.local_addr()?);
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        let tx = tx.clone();
+                        thread::spawn(move || read_votes(stream, tx));
+                    }
+                    Err(e) => warn!("crowd connection failed: {}", e),
+                }
+            }
+        });
+
+        Ok(Self {
+            votes: rx,
+            tally: [0; 16],
+            window,
+            window_start: Instant::now(),
+            pressed: None,
+        })
+    }
+
+    /// Collect pending votes and elect a winner if the voting window is over.
+    ///
+    /// Return `true` if the pressed key changed.
+    pub fn update(&mut self) -> bool {
+        for key in self.votes.try_iter() {
+            self.tally[key as usize] += 1;
+        }
+
+        let mut changed = false;
+        if let Some((_, since)) = self.pressed {
+            if since.elapsed() >= PRESS_DURATION {
+                self.pressed = None;
+                changed = true;
+            }
+        }
+
+        if self.window_start.elapsed() >= self.window {
+            if let Some(key) = self.winner() {
+                info!("crowd elected key {:X} ({} votes)", key, self.tally[key as usize]);
+                self.pressed = Some((key, Instant::now()));
+                changed = true;
+            }
+            self.tally = [0; 16];
+            self.window_start = Instant::now();
+        }
+
+        changed
+    }
+
+    /// The key currently pressed by the crowd, if any.
+    pub fn pressed_key(&self) -> Option<u8> {
+        self.pressed.map(|(key, _)| key)
+    }
+
+    /// Return the key with the most votes, ties going to the lowest key.
+    fn winner(&self) -> Option<u8> {
+        let mut winner = None;
+        let mut best = 0;
+        for (key, &count) in self.tally.iter().enumerate() {
+            if count > best {
+                best = count;
+                winner = Some(key as u8);
+            }
+        }
+        winner
+    }
+}
+
+fn read_votes(stream: TcpStream, tx: Sender<u8>) {
+    let peer = stream.peer_addr().ok();
+    debug!("crowd client connected: {:?}", peer);
+    for line in BufReader::new(stream).lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+        if let Some(key) = parse_vote(&line) {
+            if tx.send(key).is_err() {
+                break;
+            }
+        }
+    }
+    debug!("crowd client disconnected: {:?}", peer);
+}
+
+/// Parse a vote such as `a`, `!A` or `7` into a keypad key.
+fn parse_vote(line: &str) -> Option<u8> {
+    let vote = line.trim();
+    let vote = vote.strip_prefix('!').unwrap_or(vote);
+    if vote.len() != 1 {
+        return None;
+    }
+    u8::from_str_radix(vote, 16).ok()
+}
@@ -0,0 +1,718 @@
+//! A Debug Adapter Protocol (https://microsoft.github.io/debug-adapter-protocol/) server, so that
+//! editors such as VS Code can debug a ROM: breakpoints on lines of Octo source or on addresses,
+//! stepping, the registers and the stack as variables, watch expressions and a disassembly view.
+//!
+//! The program runs without a window, at its usual speed. Messages are JSON objects, each after
+//! a `Content-Length` header, exchanged on stdin and stdout or on a TCP connection.
+//!
+//! Sessions keep their own breakpoints and stepping rather than reuse the GUI's
+//! [`Debugger`](crate::debugger::Debugger): that is an egui window with a single set of address
+//! breakpoints, while a client sets line and instruction breakpoints separately, and steps over
+//! and out of subroutines by the depth of the stack.
+
+use std::collections::BTreeSet;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, TryRecvError};
+use std::thread;
+use std::time::Instant;
+
+use anyhow::{anyhow, ensure, Context, Result};
+use log::{info, warn};
+use serde_json::{json, Value};
+
+use chip8rs::config::{self, Quirks};
+use chip8rs::detect;
+use chip8rs::interconnect::TIMER_PERIOD;
+use chip8rs::octo::{self, SourceMap};
+use chip8rs::rom;
+
+use crate::watch::{Symbols, Watch};
+use crate::Chip8;
+
+/// The only thread: a CHIP-8 has a single CPU.
+const THREAD_ID: u64 = 1;
+
+/// Variable references of the scopes.
+const REGISTERS: u64 = 1;
+const STACK: u64 = 2;
+
+/// `00FD`, which stops SUPER-CHIP programs.
+const EXIT: u16 = 0x00FD;
+
+/// The largest message body accepted, so that a bad header can't make us allocate any amount.
+const MAX_MESSAGE: usize = 1 << 20;
+
+/// Debug one program, talking to the client on stdin and stdout.
+pub fn serve_stdio() -> Result<()> {
+    run_session(BufReader::new(io::stdin()), io::stdout())
+}
+
+/// Debug programs for the clients connecting on `addr`, one at a time.
+pub fn serve<A: ToSocketAddrs>(addr: A) -> Result<()> {
+    let listener = TcpListener::bind(addr).context("Failed to bind debug adapter address")?;
+    info!("debug adapter listening on {}", listener.local_addr()?);
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                warn!("connection failed: {}", e);
+                continue;
+            }
+        };
+        let peer = stream.peer_addr().ok();
+        info!("debug session started for {:?}", peer);
+        let result = stream
+            .try_clone()
+            .map_err(Into::into)
+            .and_then(|reader| run_session(BufReader::new(reader), stream));
+        if let Err(e) = result {
+            warn!("debug session for {:?} failed: {:#}", peer, e);
+        }
+        info!("debug session ended for {:?}", peer);
+    }
+    Ok(())
+}
+
+/// Serve requests read from `input` until the client disconnects. Requests are read on their own
+/// thread, so that a running program can be paused.
+fn run_session(input: impl BufRead + Send + 'static, out: impl Write + 'static) -> Result<()> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut input = input;
+        loop {
+            match read_message(&mut input) {
+                Ok(Some(message)) => {
+                    if tx.send(message).is_err() {
+                        return;
+                    }
+                }
+                Ok(None) => return,
+                Err(e) => {
+                    warn!("invalid debug adapter message: {:#}", e);
+                    return;
+                }
+            }
+        }
+    });
+
+    let mut session = Session::new(Box::new(out));
+    loop {
+        let message = if session.run == Run::Stopped {
+            match rx.recv() {
+                Ok(message) => Some(message),
+                Err(_) => return Ok(()),
+            }
+        } else {
+            match rx.try_recv() {
+                Ok(message) => Some(message),
+                Err(TryRecvError::Empty) => None,
+                Err(TryRecvError::Disconnected) => return Ok(()),
+            }
+        };
+        match message {
+            Some(message) => {
+                if !session.handle(&message)? {
+                    return Ok(());
+                }
+            }
+            None => session.run_frame()?,
+        }
+    }
+}
+
+/// Read a message: headers, of which only `Content-Length` matters, then a JSON body. Return
+/// `None` at the end of the input.
+fn read_message(input: &mut impl BufRead) -> Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(value) = line.strip_prefix("Content-Length:") {
+            length = Some(
+                value
+                    .trim()
+                    .parse::<usize>()
+                    .context("Invalid Content-Length")?,
+            );
+        }
+    }
+    let length = length.context("Missing Content-Length header")?;
+    ensure!(
+        length <= MAX_MESSAGE,
+        "Message of {} bytes is larger than the {} bytes allowed",
+        length,
+        MAX_MESSAGE
+    );
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    Ok(Some(
+        serde_json::from_slice(&body).context("Invalid JSON message")?,
+    ))
+}
+
+/// How the program is running.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Run {
+    Stopped,
+    /// Until a breakpoint or a pause.
+    Continue,
+    /// Until an instruction with at most `depth` subroutines on the stack, on another line than
+    /// `line` when stepping by lines of source.
+    Step {
+        depth: usize,
+        line: Option<usize>,
+    },
+}
+
+/// The program being debugged.
+struct Target {
+    chip8: Chip8,
+    /// The Octo source the program was assembled from, if any.
+    source: Option<(PathBuf, SourceMap)>,
+    symbols: Symbols,
+    ips: u32,
+}
+
+impl Target {
+    /// The line of the source at `addr`.
+    fn line(&self, addr: u16) -> Option<usize> {
+        self.source.as_ref().and_then(|(_, map)| map.line(addr))
+    }
+
+    /// The `Source` object of the protocol, for the Octo source.
+    fn source_json(&self) -> Value {
+        match &self.source {
+            Some((path, _)) => json!({
+                "name": path.file_name().map(|name| name.to_string_lossy()),
+                "path": path,
+            }),
+            None => Value::Null,
+        }
+    }
+
+    /// The name of the subroutine at `entry`.
+    fn name(&self, entry: u16) -> String {
+        match self.symbols.name_of(entry) {
+            Some(name) => name.to_string(),
+            None => format!("{:#05x}", entry),
+        }
+    }
+
+    /// The subroutine called from `call`: the target of the `2NNN` there, if it still is one.
+    fn callee(&self, call: u16) -> Option<u16> {
+        let opcode = self.chip8.interconnect.fetch_opcode(call).ok()?;
+        (opcode & 0xF000 == 0x2000).then_some(opcode & 0x0FFF)
+    }
+}
+
+struct Session {
+    out: Box<dyn Write>,
+    /// Sequence number of the last message sent.
+    seq: u64,
+    /// Events to send after the response to the current request.
+    events: Vec<Value>,
+    target: Option<Target>,
+    /// Breakpoints set on lines of the source, by address.
+    line_breakpoints: BTreeSet<u16>,
+    instruction_breakpoints: BTreeSet<u16>,
+    run: Run,
+    stop_on_entry: bool,
+}
+
+impl Session {
+    fn new(out: Box<dyn Write>) -> Self {
+        Self {
+            out,
+            seq: 0,
+            events: Vec::new(),
+            target: None,
+            line_breakpoints: BTreeSet::new(),
+            instruction_breakpoints: BTreeSet::new(),
+            run: Run::Stopped,
+            stop_on_entry: false,
+        }
+    }
+
+    fn target(&self) -> Result<&Target> {
+        self.target.as_ref().context("No program was launched")
+    }
+
+    fn target_mut(&mut self) -> Result<&mut Target> {
+        self.target.as_mut().context("No program was launched")
+    }
+
+    /// Answer `message`, if it is a request. Return `false` once the client disconnected.
+    fn handle(&mut self, message: &Value) -> Result<bool> {
+        if message["type"] != "request" {
+            return Ok(true);
+        }
+        let command = message["command"].as_str().unwrap_or_default();
+        let args = &message["arguments"];
+        let result = match command {
+            "initialize" => Ok(json!({
+                "supportsConfigurationDoneRequest": true,
+                "supportsInstructionBreakpoints": true,
+                "supportsDisassembleRequest": true,
+                "supportsSteppingGranularity": true,
+                "supportsEvaluateForHovers": true,
+                "supportsRestartRequest": true,
+                "supportsTerminateRequest": true,
+            })),
+            "launch" => self.launch(args),
+            "setBreakpoints" => self.set_breakpoints(args),
+            "setInstructionBreakpoints" => self.set_instruction_breakpoints(args),
+            "setExceptionBreakpoints" => Ok(json!({ "breakpoints": [] })),
+            "configurationDone" => self.start(),
+            "threads" => Ok(json!({ "threads": [{ "id": THREAD_ID, "name": "CHIP-8" }] })),
+            "stackTrace" => self.stack_trace(),
+            "scopes" => Ok(json!({
+                "scopes": [
+                    { "name": "Registers", "presentationHint": "registers",
+                      "variablesReference": REGISTERS, "expensive": false },
+                    { "name": "Stack", "variablesReference": STACK, "expensive": false },
+                ]
+            })),
+            "variables" => self.variables(args),
+            "evaluate" => self.evaluate(args),
+            "disassemble" => self.disassemble(args),
+            "continue" => self.resume(),
+            "next" | "stepIn" | "stepOut" => self.step(command, args),
+            "pause" => self.pause(),
+            "restart" => self.restart(),
+            "disconnect" | "terminate" => {
+                self.respond(message, Ok(Value::Null))?;
+                if command == "terminate" {
+                    self.event("terminated", Value::Null);
+                    self.flush_events()?;
+                }
+                return Ok(false);
+            }
+            _ => Err(anyhow!("Unsupported request {:?}", command)),
+        };
+        self.respond(message, result)?;
+        self.flush_events()?;
+        Ok(true)
+    }
+
+    /// Load the program named by the `program` argument, assembling it if it is Octo source.
+    /// The other arguments are `stopOnEntry`, `quirks` (a profile name such as `schip`), `ips`
+    /// and `symbols` (a symbol file, see `Symbols::load`).
+    fn launch(&mut self, args: &Value) -> Result<Value> {
+        let program = args["program"]
+            .as_str()
+            .context("Missing program to debug")?;
+        let (file, data) = crate::load_rom(program)?;
+        let profile = match args["quirks"].as_str() {
+            Some(profile) => Some(profile.parse()?),
+            None => detect::from_extension(&file),
+        };
+        let (rom, source) = if file.extension().is_some_and(|ext| ext == "8o") {
+            let text = String::from_utf8(data)
+                .with_context(|| format!("{} isn't a text file", program))?;
+            let (rom, map) = octo::assemble_mapped(&text, config::PROG_ADDR)
+                .with_context(|| format!("Failed to assemble {}", program))?;
+            (rom, Some((PathBuf::from(program), map)))
+        } else {
+            (data, None)
+        };
+        let mut chip8 = crate::machine(&rom, profile)?;
+        if let Some(profile) = profile {
+            chip8.set_quirks(Quirks::for_profile(profile));
+        }
+        let mut symbols = match args["symbols"].as_str() {
+            Some(path) => Symbols::load(path)?,
+            None => Symbols::default(),
+        };
+        if let Some((_, map)) = &source {
+            for (name, &addr) in &map.labels {
+                symbols.insert(name.clone(), addr);
+            }
+        }
+        let ips = match args["ips"].as_u64() {
+            Some(ips) => u32::try_from(ips)
+                .ok()
+                .filter(|&ips| ips > 0)
+                .with_context(|| format!("Invalid ips {}", ips))?,
+            None => config::DEFAULT_IPS,
+        };
+        info!("debugging {}", program);
+        self.stop_on_entry = args["stopOnEntry"].as_bool().unwrap_or(false);
+        self.target = Some(Target {
+            chip8,
+            source,
+            symbols,
+            ips,
+        });
+        // Breakpoints can only be placed now that the source is assembled.
+        self.event("initialized", Value::Null);
+        Ok(Value::Null)
+    }
+
+    /// Run the program from where it is, or stop at once with `stopOnEntry`.
+    fn start(&mut self) -> Result<Value> {
+        self.target()?;
+        if self.stop_on_entry {
+            self.stop("entry", None);
+        } else {
+            self.run = Run::Continue;
+        }
+        Ok(Value::Null)
+    }
+
+    fn resume(&mut self) -> Result<Value> {
+        self.target()?;
+        self.run = Run::Continue;
+        Ok(json!({ "allThreadsContinued": true }))
+    }
+
+    fn pause(&mut self) -> Result<Value> {
+        self.target()?;
+        self.stop("pause", None);
+        Ok(Value::Null)
+    }
+
+    /// Restart the program from scratch, keeping the breakpoints.
+    fn restart(&mut self) -> Result<Value> {
+        self.target_mut()?.chip8.reset();
+        self.start()
+    }
+
+    fn set_breakpoints(&mut self, args: &Value) -> Result<Value> {
+        let target = self.target()?;
+        let map = match (&target.source, args["source"]["path"].as_str()) {
+            (Some((path, map)), Some(requested)) if same_file(path, Path::new(requested)) => {
+                Some(map)
+            }
+            _ => None,
+        };
+        let mut addresses = BTreeSet::new();
+        let mut breakpoints = Vec::new();
+        for breakpoint in args["breakpoints"].as_array().into_iter().flatten() {
+            let line = breakpoint["line"].as_u64().unwrap_or_default() as usize;
+            match map.and_then(|map| map.address(line)) {
+                Some(addr) => {
+                    addresses.insert(addr);
+                    breakpoints.push(json!({
+                        "verified": true,
+                        "line": target.line(addr),
+                        "instructionReference": format!("{:#05x}", addr),
+                    }));
+                }
+                None => breakpoints.push(json!({
+                    "verified": false,
+                    "line": line,
+                    "message": match map {
+                        Some(_) => "No code at or after this line",
+                        None => "Not the source of the program being debugged",
+                    },
+                })),
+            }
+        }
+        self.line_breakpoints = addresses;
+        Ok(json!({ "breakpoints": breakpoints }))
+    }
+
+    fn set_instruction_breakpoints(&mut self, args: &Value) -> Result<Value> {
+        let mut addresses = BTreeSet::new();
+        let mut breakpoints = Vec::new();
+        for breakpoint in args["breakpoints"].as_array().into_iter().flatten() {
+            let reference = breakpoint["instructionReference"]
+                .as_str()
+                .unwrap_or_default();
+            let offset = breakpoint["offset"].as_i64().unwrap_or_default();
+            let addr = rom::parse_number(reference)
+                .ok()
+                .and_then(|addr| u16::try_from(addr as i64 + offset).ok());
+            match addr {
+                Some(addr) => {
+                    addresses.insert(addr);
+                    breakpoints.push(json!({
+                        "verified": true,
+                        "instructionReference": format!("{:#05x}", addr),
+                    }));
+                }
+                None => breakpoints.push(json!({
+                    "verified": false,
+                    "message": format!("Invalid address {:?}", reference),
+                })),
+            }
+        }
+        self.instruction_breakpoints = addresses;
+        Ok(json!({ "breakpoints": breakpoints }))
+    }
+
+    /// One frame per subroutine on the stack, the current one first.
+    fn stack_trace(&self) -> Result<Value> {
+        let target = self.target()?;
+        let cpu = &target.chip8.cpu;
+        let mut frames = Vec::new();
+        let mut addr = cpu.pc();
+        // The stack holds the addresses of the calls, where the callers are stopped.
+        for (depth, &call) in cpu.stack().iter().enumerate().rev() {
+            let entry = target.callee(call).unwrap_or(addr);
+            frames.push(frame(target, depth + 1, entry, addr));
+            addr = call;
+        }
+        frames.push(frame(target, 0, target.chip8.load_addr(), addr));
+        Ok(json!({ "stackFrames": frames, "totalFrames": frames.len() }))
+    }
+
+    fn variables(&self, args: &Value) -> Result<Value> {
+        let target = self.target()?;
+        let cpu = &target.chip8.cpu;
+        let interconnect = &target.chip8.interconnect;
+        let variables: Vec<Value> = match args["variablesReference"].as_u64() {
+            Some(REGISTERS) => (0..16)
+                .map(|x| {
+                    let v = cpu.v(x);
+                    variable(format!("V{:X}", x), format!("{:#04x} ({})", v, v))
+                })
+                .chain([
+                    variable("I".to_string(), format!("{:#05x}", cpu.i())),
+                    variable("PC".to_string(), format!("{:#05x}", cpu.pc())),
+                    variable("DT".to_string(), interconnect.delay_timer.to_string()),
+                    variable("ST".to_string(), interconnect.sound_timer.to_string()),
+                ])
+                .collect(),
+            Some(STACK) => cpu
+                .stack()
+                .iter()
+                .enumerate()
+                .map(|(i, call)| variable(i.to_string(), format!("{:#05x}", call)))
+                .collect(),
+            _ => Vec::new(),
+        };
+        Ok(json!({ "variables": variables }))
+    }
+
+    /// Evaluate a watch expression, see `Watch`.
+    fn evaluate(&self, args: &Value) -> Result<Value> {
+        let target = self.target()?;
+        let expression = args["expression"].as_str().unwrap_or_default();
+        let mut watch = Watch::parse(expression, &target.symbols)?;
+        watch.update(&target.chip8);
+        let value = watch
+            .value()
+            .with_context(|| format!("{} can't be evaluated", expression))?;
+        Ok(json!({ "result": format!("{} ({:#x})", value, value), "variablesReference": 0 }))
+    }
+
+    /// Decode `instructionCount` instructions from `memoryReference`, moved by `offset` bytes
+    /// and `instructionOffset` instructions.
+    fn disassemble(&self, args: &Value) -> Result<Value> {
+        let target = self.target()?;
+        let reference = args["memoryReference"].as_str().unwrap_or_default();
+        let base = rom::parse_number(reference)? as i64
+            + args["offset"].as_i64().unwrap_or_default()
+            + 2 * args["instructionOffset"].as_i64().unwrap_or_default();
+        let count = args["instructionCount"].as_u64().unwrap_or_default();
+        let ram_size = target.chip8.interconnect.ram.size() as i64;
+        let instructions: Vec<Value> = (0..count as i64)
+            .map(|n| base + 2 * n)
+            .map(|addr| {
+                if addr < 0 || addr + 1 >= ram_size {
+                    return json!({
+                        "address": format!("{:#05x}", addr.rem_euclid(ram_size)),
+                        "instruction": "",
+                        "presentationHint": "invalid",
+                    });
+                }
+                let addr = addr as u16;
+                let opcode = target
+                    .chip8
+                    .interconnect
+                    .fetch_opcode(addr)
+                    .unwrap_or_default();
+                let text = match target.chip8.cpu.decode(opcode) {
+                    Ok(instruction) => instruction.to_string(),
+                    Err(_) => format!("{:#04x} {:#04x}", opcode >> 8, opcode & 0xFF),
+                };
+                let mut instruction = json!({
+                    "address": format!("{:#05x}", addr),
+                    "instructionBytes": format!("{:04X}", opcode),
+                    "instruction": text,
+                });
+                if let Some(symbol) = target.symbols.name_of(addr) {
+                    instruction["symbol"] = symbol.into();
+                }
+                if let Some(line) = target.line(addr) {
+                    instruction["line"] = line.into();
+                    instruction["location"] = target.source_json();
+                }
+                instruction
+            })
+            .collect();
+        Ok(json!({ "instructions": instructions }))
+    }
+
+    /// Start stepping: `stepIn` stops at the next instruction or line, `next` also runs through
+    /// subroutine calls, and `stepOut` runs until the current subroutine returns.
+    fn step(&mut self, command: &str, args: &Value) -> Result<Value> {
+        let target = self.target()?;
+        let depth = target.chip8.cpu.stack().len();
+        let line = match args["granularity"].as_str() {
+            Some("instruction") => None,
+            _ => target.line(target.chip8.cpu.pc()),
+        };
+        self.run = match command {
+            "stepIn" => Run::Step {
+                depth: usize::MAX,
+                line,
+            },
+            "next" => Run::Step { depth, line },
+            _ => match depth.checked_sub(1) {
+                Some(depth) => Run::Step { depth, line: None },
+                None => Run::Continue,
+            },
+        };
+        Ok(Value::Null)
+    }
+
+    /// Run the program until the next 60Hz frame starts, or until it has to stop, then wait for
+    /// the frame to end.
+    fn run_frame(&mut self) -> Result<()> {
+        let start = Instant::now();
+        let frame = self.target()?.chip8.frames();
+        while self.run != Run::Stopped && self.target()?.chip8.frames() == frame {
+            self.step_instruction()?;
+        }
+        self.flush_events()?;
+        if self.run != Run::Stopped {
+            if let Some(left) = TIMER_PERIOD.checked_sub(start.elapsed()) {
+                thread::sleep(left);
+            }
+        }
+        Ok(())
+    }
+
+    /// Execute one instruction, and stop if it faulted, ended the program, or ended a step or
+    /// hit a breakpoint.
+    fn step_instruction(&mut self) -> Result<()> {
+        let target = self.target.as_mut().context("No program was launched")?;
+        let chip8 = &mut target.chip8;
+        let pc = chip8.cpu.pc();
+        let period = chip8.instruction_time(target.ips);
+        if let Err(fault) = chip8.try_step() {
+            let message = format!("{} at {:#05x}", fault.message, fault.pc);
+            self.stop("exception", Some(message));
+            return Ok(());
+        }
+        chip8.advance_time(period);
+
+        let next = chip8.cpu.pc();
+        if next == pc && chip8.interconnect.fetch_opcode(pc).ok() == Some(EXIT) {
+            info!("program exited at {:#05x}", pc);
+            self.run = Run::Stopped;
+            self.event("exited", json!({ "exitCode": 0 }));
+            self.event("terminated", Value::Null);
+            return Ok(());
+        }
+        let depth = chip8.cpu.stack().len();
+        let line = target.line(next);
+        if self.line_breakpoints.contains(&next) {
+            self.stop("breakpoint", None);
+        } else if self.instruction_breakpoints.contains(&next) {
+            self.stop("instruction breakpoint", None);
+        } else if let Run::Step {
+            depth: max_depth,
+            line: from,
+        } = self.run
+        {
+            if depth <= max_depth && (from.is_none() || line != from) {
+                self.stop("step", None);
+            }
+        }
+        Ok(())
+    }
+
+    fn stop(&mut self, reason: &str, description: Option<String>) {
+        self.run = Run::Stopped;
+        let mut body = json!({
+            "reason": reason,
+            "threadId": THREAD_ID,
+            "allThreadsStopped": true,
+        });
+        if let Some(description) = description {
+            body["description"] = description.clone().into();
+            body["text"] = description.into();
+        }
+        self.event("stopped", body);
+    }
+
+    fn event(&mut self, event: &str, body: Value) {
+        let mut message = json!({ "type": "event", "event": event });
+        if !body.is_null() {
+            message["body"] = body;
+        }
+        self.events.push(message);
+    }
+
+    fn flush_events(&mut self) -> Result<()> {
+        for event in std::mem::take(&mut self.events) {
+            self.send(event)?;
+        }
+        Ok(())
+    }
+
+    fn respond(&mut self, request: &Value, result: Result<Value>) -> Result<()> {
+        let mut response = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": result.is_ok(),
+        });
+        match result {
+            Ok(Value::Null) => {}
+            Ok(body) => response["body"] = body,
+            Err(e) => response["message"] = format!("{:#}", e).into(),
+        }
+        self.send(response)
+    }
+
+    fn send(&mut self, mut message: Value) -> Result<()> {
+        self.seq += 1;
+        message["seq"] = self.seq.into();
+        let body = message.to_string();
+        write!(self.out, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+/// A `StackFrame` of the protocol, for the subroutine at `entry` stopped at `addr`.
+fn frame(target: &Target, id: usize, entry: u16, addr: u16) -> Value {
+    let mut frame = json!({
+        "id": id,
+        "name": target.name(entry),
+        "line": 0,
+        "column": 0,
+        "instructionPointerReference": format!("{:#05x}", addr),
+    });
+    if let Some(line) = target.line(addr) {
+        frame["line"] = line.into();
+        frame["column"] = 1.into();
+        frame["source"] = target.source_json();
+    }
+    frame
+}
+
+/// A `Variable` of the protocol, with no children.
+fn variable(name: String, value: String) -> Value {
+    json!({ "name": name, "value": value, "variablesReference": 0 })
+}
+
+/// Whether `a` and `b` name the same file, as far as can be told.
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
@@ -0,0 +1,176 @@
+//! A database of settings for known ROMs, bundled with the emulator and looked up by the SHA-1 of
+//! the ROM. The format is described in `db/roms.toml`.
+//!
+//! ```
+//! use chip8rs::db::Database;
+//!
+//! let db = Database::bundled();
+//! assert!(db.lookup(b"not a known ROM").is_none());
+//! ```
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use anyhow::{ensure, Context, Result};
+use serde::Deserialize;
+
+use crate::detect::Profile;
+use crate::palette::{self, Color};
+
+const BUNDLED: &str = include_str!("../db/roms.toml");
+
+/// The settings known for a ROM.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Entry {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    quirks: Option<String>,
+    pub ips: Option<u32>,
+    background: Option<String>,
+    foreground: Option<String>,
+}
+
+impl Entry {
+    /// The quirks preset the ROM needs.
+    pub fn quirks(&self) -> Option<Profile> {
+        self.quirks.as_ref().and_then(|quirks| quirks.parse().ok())
+    }
+
+    pub fn background(&self) -> Option<Color> {
+        self.background
+            .as_ref()
+            .and_then(|color| palette::parse_color(color).ok())
+    }
+
+    pub fn foreground(&self) -> Option<Color> {
+        self.foreground
+            .as_ref()
+            .and_then(|color| palette::parse_color(color).ok())
+    }
+
+    /// A name for the program, with its author if known.
+    pub fn display_name(&self) -> Option<String> {
+        let title = self.title.as_ref()?;
+        Some(match &self.author {
+            Some(author) => format!("{} by {}", title, author),
+            None => title.clone(),
+        })
+    }
+
+    fn validate(&self) -> Result<()> {
+        if let Some(quirks) = &self.quirks {
+            quirks.parse::<Profile>()?;
+        }
+        ensure!(self.ips != Some(0), "ips must be positive");
+        for color in self.background.iter().chain(&self.foreground) {
+            palette::parse_color(color)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Database {
+    /// Entries by the SHA-1 of their ROM, in lowercase hex.
+    entries: BTreeMap<String, Entry>,
+}
+
+impl Database {
+    /// The database shipped with the emulator.
+    pub fn bundled() -> Self {
+        Self::parse(BUNDLED).expect("Invalid bundled ROM database")
+    }
+
+    /// Read a database in the format of `db/roms.toml`.
+    pub fn parse(text: &str) -> Result<Self> {
+        let entries: BTreeMap<String, Entry> = toml::from_str(text)?;
+        for (hash, entry) in &entries {
+            ensure!(
+                hash.len() == 40 && hash.chars().all(|c| matches!(c, '0'..='9' | 'a'..='f')),
+                "Invalid SHA-1 {:?}",
+                hash
+            );
+            entry
+                .validate()
+                .with_context(|| format!("Invalid entry {}", hash))?;
+        }
+        Ok(Self { entries })
+    }
+
+    /// The settings for `rom`, if it's a known ROM.
+    pub fn lookup(&self, rom: &[u8]) -> Option<&Entry> {
+        self.entries.get(&sha1_hex(rom))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/// The SHA-1 of `data`, in lowercase hex.
+pub fn sha1_hex(data: &[u8]) -> String {
+    let mut hex = String::with_capacity(40);
+    for b in sha1(data) {
+        write!(hex, "{:02x}", b).unwrap();
+    }
+    hex
+}
+
+/// The SHA-1 digest of `data`.
+pub fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [
+        0x6745_2301,
+        0xEFCD_AB89,
+        0x98BA_DCFE,
+        0x1032_5476,
+        0xC3D2_E1F0,
+    ];
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&(data.len() as u64 * 8).to_be_bytes());
+    for block in message.chunks_exact(64) {
+        let mut w = [0u32; 80];
+        for (i, word) in block.chunks_exact(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (i, &word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5A82_7999),
+                20..=39 => (b ^ c ^ d, 0x6ED9_EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1B_BCDC),
+                _ => (b ^ c ^ d, 0xCA62_C1D6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (h, v) in h.iter_mut().zip([a, b, c, d, e]) {
+            *h = h.wrapping_add(v);
+        }
+    }
+    let mut digest = [0; 20];
+    for (bytes, word) in digest.chunks_exact_mut(4).zip(h) {
+        bytes.copy_from_slice(&word.to_be_bytes());
+    }
+    digest
+}
@@ -0,0 +1,149 @@
+use std::collections::BTreeSet;
+
+use egui::CtxRef;
+use log::info;
+
+use chip8rs::rom;
+
+use crate::inspector::{self, describe};
+use crate::watch::Symbols;
+use crate::Chip8;
+
+/// What the user asked the debugger to do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DebugAction {
+    Continue,
+    Pause,
+    Step,
+}
+
+/// The debugger window: PC breakpoints, single stepping and a view of the registers.
+pub struct Debugger {
+    pub open: bool,
+    /// Whether emulation is suspended.
+    pub paused: bool,
+    breakpoints: BTreeSet<u16>,
+    /// Address or symbol of the breakpoint being added.
+    new_breakpoint: String,
+    /// Why the last breakpoint couldn't be added.
+    error: Option<String>,
+    /// Set when resuming, so that a breakpoint on the current instruction doesn't stop it again.
+    resuming: bool,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Self {
+            open: false,
+            paused: false,
+            breakpoints: BTreeSet::new(),
+            new_breakpoint: String::new(),
+            error: None,
+            resuming: false,
+        }
+    }
+
+    /// Open the debugger and suspend emulation.
+    pub fn pause(&mut self) {
+        self.open = true;
+        self.paused = true;
+    }
+
+    pub fn resume(&mut self) {
+        self.paused = false;
+        self.resuming = true;
+    }
+
+    pub fn has_breakpoints(&self) -> bool {
+        !self.breakpoints.is_empty()
+    }
+
+    /// Called before each instruction: pause and return `true` if there is a breakpoint at `pc`.
+    pub fn check(&mut self, pc: u16) -> bool {
+        let resuming = std::mem::take(&mut self.resuming);
+        if resuming || !self.breakpoints.contains(&pc) {
+            return false;
+        }
+        info!("breakpoint at {:#05x}", pc);
+        self.pause();
+        true
+    }
+
+    pub fn ui(&mut self, ctx: &CtxRef, chip8: &Chip8, symbols: &Symbols) -> Option<DebugAction> {
+        let mut action = None;
+        let mut open = self.open;
+        egui::Window::new("Debugger")
+            .open(&mut open)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    if self.paused {
+                        if ui.button("Continue").clicked() {
+                            action = Some(DebugAction::Continue);
+                        }
+                    } else if ui.button("Pause").clicked() {
+                        action = Some(DebugAction::Pause);
+                    }
+                    if ui
+                        .add_enabled(self.paused, egui::Button::new("Step"))
+                        .clicked()
+                    {
+                        action = Some(DebugAction::Step);
+                    }
+                });
+
+                ui.separator();
+                inspector::registers(ui, chip8, symbols);
+                ui.separator();
+                inspector::disassembly(ui, chip8, symbols, 4);
+
+                ui.separator();
+                ui.heading("Breakpoints");
+                let mut removed = None;
+                for &addr in &self.breakpoints {
+                    ui.horizontal(|ui| {
+                        ui.monospace(describe(addr, symbols));
+                        if ui.small_button("remove").clicked() {
+                            removed = Some(addr);
+                        }
+                    });
+                }
+                if let Some(addr) = removed {
+                    self.breakpoints.remove(&addr);
+                }
+                ui.horizontal(|ui| {
+                    ui.text_edit_singleline(&mut self.new_breakpoint);
+                    if ui.button("Add").clicked() {
+                        self.add_breakpoint(symbols);
+                    }
+                });
+                if let Some(error) = &self.error {
+                    ui.colored_label(egui::Color32::RED, error);
+                }
+            });
+        self.open = open;
+        // Closing the window shouldn't leave emulation suspended with no way to resume it.
+        if !self.open && self.paused {
+            action = Some(DebugAction::Continue);
+        }
+        action
+    }
+
+    /// Add a breakpoint at the address or symbol typed in the window.
+    fn add_breakpoint(&mut self, symbols: &Symbols) {
+        let text = self.new_breakpoint.trim();
+        let addr = symbols.get(text).or_else(|| {
+            rom::parse_number(text)
+                .ok()
+                .and_then(|addr| u16::try_from(addr).ok())
+        });
+        match addr {
+            Some(addr) => {
+                self.breakpoints.insert(addr);
+                self.new_breakpoint.clear();
+                self.error = None;
+            }
+            None => self.error = Some(format!("Not an address or symbol: {:?}", text)),
+        }
+    }
+}
@@ -0,0 +1,63 @@
+//! The demo program run when no ROM is given.
+//!
+//! It's written with the `program` DSL so that it's compiled into the binary without shipping
+//! someone else's ROM: a "CHIP8RS" title and a ball bouncing off the edges of the screen, beeping
+//! on every bounce.
+
+use crate::program::*;
+
+/// Letters of the title, 4 pixels wide and 5 pixels high.
+const TITLE: [[u8; 5]; 7] = [
+    [0xF0, 0x80, 0x80, 0x80, 0xF0], // C
+    [0x90, 0x90, 0xF0, 0x90, 0x90], // H
+    [0xE0, 0x40, 0x40, 0x40, 0xE0], // I
+    [0xF0, 0x90, 0xF0, 0x80, 0x80], // P
+    [0x60, 0x90, 0x60, 0x90, 0x60], // 8
+    [0xE0, 0x90, 0xE0, 0xA0, 0x90], // R
+    [0xF0, 0x80, 0xF0, 0x10, 0xF0], // S
+];
+const BALL: [u8; 2] = [0xC0, 0xC0];
+
+/// Assemble the demo ROM.
+pub fn rom() -> Vec<u8> {
+    let mut p = Program::new();
+    let title = p.label();
+    let ball = p.label();
+    let main_loop = p.label();
+    let wait = p.label();
+    let (left, right, top, bottom, beep) = (p.label(), p.label(), p.label(), p.label(), p.label());
+
+    // Title, centered near the top.
+    p.cls().ld(V0, 15).ld(V1, 6).ld(V6, 5).ld_i(title);
+    for _ in TITLE {
+        p.drw(V0, V1, 5).add(V0, 5).add_i(V6);
+    }
+
+    // V2, V3: ball position; V4, V5: ball direction (1 or -1).
+    p.ld(V2, 1).ld(V3, 20).ld(V4, 1).ld(V5, 1);
+    p.ld_i(ball).drw(V2, V3, 2);
+
+    p.bind(main_loop).ld_i(ball).drw(V2, V3, 2);
+    p.add(V2, V4).add(V3, V5);
+    p.sne(V2, 0).call(left).sne(V2, 62).call(right);
+    p.sne(V3, 0).call(top).sne(V3, 30).call(bottom);
+    p.drw(V2, V3, 2);
+    // Move every other tick.
+    p.ld(V6, 2).ld_dt(V6);
+    p.bind(wait).ld_from_dt(V6).se(V6, 0).jp(wait);
+    p.jp(main_loop);
+
+    p.bind(left).ld(V4, 1).jp(beep);
+    p.bind(right).ld(V4, 0xFF).jp(beep);
+    p.bind(top).ld(V5, 1).jp(beep);
+    p.bind(bottom).ld(V5, 0xFF).jp(beep);
+    p.bind(beep).ld(V6, 3).ld_st(V6).ret();
+
+    p.bind(title);
+    for letter in TITLE {
+        p.db(&letter);
+    }
+    p.bind(ball).db(&BALL);
+
+    p.assemble().expect("the demo only uses bound labels")
+}
@@ -0,0 +1,232 @@
+//! Heuristic detection of the interpreter a ROM was written for.
+//!
+//! Only instructions reachable from the entry point are looked at, so that sprite data and other
+//! bytes that happen to look like extended opcodes don't skew the result.
+
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::{bail, Error};
+
+use crate::config;
+use crate::disasm;
+
+/// Interpreter families with distinct behaviors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Profile {
+    Chip8,
+    /// The CHIP-48 interpreter for the HP48, which SUPER-CHIP was built on.
+    Chip48,
+    /// Modern SUPER-CHIP, as implemented by most emulators.
+    Schip,
+    /// The original SUPER-CHIP 1.0/1.1 on the HP48, which some of its games depend on.
+    SchipLegacy,
+    XoChip,
+    /// Mega-Chip, a SUPER-CHIP extension with a 256x192 color display.
+    MegaChip,
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Profile::Chip8 => "chip8",
+            Profile::Chip48 => "chip48",
+            Profile::Schip => "schip",
+            Profile::SchipLegacy => "schip-legacy",
+            Profile::XoChip => "xochip",
+            Profile::MegaChip => "megachip",
+        })
+    }
+}
+
+impl FromStr for Profile {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "chip8" => Profile::Chip8,
+            "chip48" => Profile::Chip48,
+            "schip" => Profile::Schip,
+            "schip-legacy" => Profile::SchipLegacy,
+            "xochip" => Profile::XoChip,
+            "megachip" => Profile::MegaChip,
+            _ => bail!(
+                "Unknown profile {:?} (expected chip8, chip48, schip, schip-legacy, xochip or megachip)",
+                s
+            ),
+        })
+    }
+}
+
+/// The interpreter family implied by the conventional extension of `path`: `.ch8` (or `.c8`),
+/// `.sc8`, `.xo8` or `.mc8`.
+pub fn from_extension(path: &Path) -> Option<Profile> {
+    let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+    match extension.as_str() {
+        "ch8" | "c8" => Some(Profile::Chip8),
+        "sc8" => Some(Profile::Schip),
+        "xo8" => Some(Profile::XoChip),
+        "mc8" => Some(Profile::MegaChip),
+        _ => None,
+    }
+}
+
+/// What could be inferred about a ROM.
+#[derive(Debug)]
+pub struct Detection {
+    /// The most likely interpreter family.
+    pub profile: Profile,
+    /// Whether `8XY6`/`8XYE` shift VX in place (`Some(true)`) or VY into VX (`Some(false)`).
+    pub shift_vx_only: Option<bool>,
+    /// Whether `FX55`/`FX65` are expected to leave I unchanged (`Some(true)`) or increment it
+    /// (`Some(false)`).
+    pub load_store_keeps_i: Option<bool>,
+    /// Human readable evidence for the above.
+    pub reasons: Vec<String>,
+}
+
+/// `0011`, which switches to Mega-Chip mode: other interpreters would call a machine code
+/// routine there, which CHIP-8 programs never do.
+const MEGA_ON: u16 = 0x0011;
+
+/// Number of instructions after `FX55`/`FX65` in which we look for a use or reload of I.
+const LOAD_STORE_WINDOW: usize = 8;
+
+/// Guess the interpreter `rom` was written for.
+pub fn detect(rom: &[u8]) -> Detection {
+    detect_at(rom, config::PROG_ADDR)
+}
+
+/// Guess the interpreter `rom`, loaded at address `origin`, was written for.
+pub fn detect_at(rom: &[u8], origin: u16) -> Detection {
+    let code = disasm::trace_code(rom, origin);
+    let opcodes: Vec<(u16, u16)> = code
+        .iter()
+        .enumerate()
+        .filter(|(offset, &is_code)| is_code && offset + 1 < rom.len())
+        .map(|(offset, _)| {
+            let opcode = u16::from_be_bytes([rom[offset], rom[offset + 1]]);
+            (origin + offset as u16, opcode)
+        })
+        .collect();
+
+    let mut reasons = Vec::new();
+    let mut schip = false;
+    let mut xochip = false;
+    let mut megachip = false;
+    let (mut shifts_vx, mut shifts_vy) = (0, 0);
+    let mut relies_on_increment = false;
+
+    for (i, &(addr, opcode)) in opcodes.iter().enumerate() {
+        let x = (opcode & 0x0F00) >> 8;
+        let y = (opcode & 0x00F0) >> 4;
+        if opcode == MEGA_ON {
+            if !megachip {
+                reasons.push(format!(
+                    "Mega-Chip instruction {:04X} at {:#05x}",
+                    opcode, addr
+                ));
+            }
+            megachip = true;
+        } else if is_xochip(opcode) {
+            if !xochip {
+                reasons.push(format!(
+                    "XO-CHIP instruction {:04X} at {:#05x}",
+                    opcode, addr
+                ));
+            }
+            xochip = true;
+        } else if is_schip(opcode) {
+            if !schip {
+                reasons.push(format!("SCHIP instruction {:04X} at {:#05x}", opcode, addr));
+            }
+            schip = true;
+        }
+
+        match opcode & 0xF00F {
+            // Shifts written as `8X06` ignore VY: the author expected VX to be shifted in place.
+            0x8006 | 0x800E if y == 0 && x != 0 => shifts_vx += 1,
+            0x8006 | 0x800E if y != x => shifts_vy += 1,
+            _ => {}
+        }
+
+        if matches!(opcode & 0xF0FF, 0xF055 | 0xF065)
+            && !relies_on_increment
+            && uses_incremented_i(&opcodes[i + 1..])
+        {
+            relies_on_increment = true;
+            reasons.push(format!(
+                "I is used without being reloaded after {:04X} at {:#05x}",
+                opcode, addr
+            ));
+        }
+    }
+
+    let shift_vx_only = match shifts_vx.cmp(&shifts_vy) {
+        std::cmp::Ordering::Greater => Some(true),
+        std::cmp::Ordering::Less => Some(false),
+        std::cmp::Ordering::Equal => None,
+    };
+    if let Some(vx_only) = shift_vx_only {
+        reasons.push(format!(
+            "{} of {} shifts ignore VY",
+            shifts_vx,
+            shifts_vx + shifts_vy
+        ));
+        if vx_only && !schip && !xochip {
+            reasons.push("VX-only shifts suggest a CHIP-48/SCHIP interpreter".to_string());
+        }
+    }
+
+    let profile = if megachip {
+        Profile::MegaChip
+    } else if xochip {
+        Profile::XoChip
+    } else if schip || shift_vx_only == Some(true) {
+        Profile::Schip
+    } else {
+        Profile::Chip8
+    };
+
+    Detection {
+        profile,
+        shift_vx_only,
+        load_store_keeps_i: relies_on_increment.then_some(false),
+        reasons,
+    }
+}
+
+/// Whether the straight-line code in `following` reads I before reloading it.
+fn uses_incremented_i(following: &[(u16, u16)]) -> bool {
+    for &(_, opcode) in following.iter().take(LOAD_STORE_WINDOW) {
+        match opcode & 0xF000 {
+            // Reloaded
+            0xA000 => return false,
+            0xF000 if opcode & 0xFF == 0x29 => return false,
+            0xD000 => return true,
+            0xF000 if matches!(opcode & 0xFF, 0x1E | 0x33 | 0x55 | 0x65) => return true,
+            // Control flow: we can't follow it from here.
+            0x0000 | 0x1000 | 0x2000 | 0x3000 | 0x4000 | 0x5000 | 0x9000 | 0xB000 | 0xE000 => {
+                return false
+            }
+            _ => {}
+        }
+    }
+    false
+}
+
+fn is_schip(opcode: u16) -> bool {
+    matches!(opcode, 0x00FB..=0x00FF)
+        || opcode & 0xFFF0 == 0x00C0
+        || opcode & 0xF00F == 0xD000
+        || matches!(opcode & 0xF0FF, 0xF030 | 0xF075 | 0xF085)
+}
+
+fn is_xochip(opcode: u16) -> bool {
+    opcode == 0xF000
+        || opcode == 0xF002
+        || opcode & 0xFFF0 == 0x00D0
+        || matches!(opcode & 0xF00F, 0x5002 | 0x5003)
+        || matches!(opcode & 0xF0FF, 0xF001 | 0xF03A)
+}
@@ -0,0 +1,245 @@
+//! Disassembly of CHIP-8 ROMs into Octo source.
+//!
+//! Code is found by following the control flow from the entry point; everything that is never
+//! reached is emitted as data bytes. Jump, call and `i :=` targets inside the ROM get generated
+//! labels. The output assembles back to a byte-identical ROM.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use crate::config;
+use crate::instruction::Instruction;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum LabelKind {
+    Main,
+    Sub,
+    Label,
+    Data,
+}
+
+struct Disassembler<'a> {
+    rom: &'a [u8],
+    origin: u16,
+    /// Whether an instruction starts at each ROM offset.
+    code: Vec<bool>,
+    labels: BTreeMap<u16, LabelKind>,
+}
+
+/// Disassemble `rom`, loaded at the usual program address, into Octo source.
+pub fn disassemble(rom: &[u8]) -> String {
+    disassemble_at(rom, config::PROG_ADDR)
+}
+
+/// Disassemble `rom`, loaded at address `origin`, into Octo source.
+pub fn disassemble_at(rom: &[u8], origin: u16) -> String {
+    let mut d = Disassembler {
+        rom,
+        origin,
+        code: vec![false; rom.len()],
+        labels: BTreeMap::new(),
+    };
+    d.trace();
+    d.emit()
+}
+
+/// Return, for each byte of `rom` loaded at `origin`, whether an instruction reachable from the
+/// entry point starts there.
+pub(crate) fn trace_code(rom: &[u8], origin: u16) -> Vec<bool> {
+    let mut d = Disassembler {
+        rom,
+        origin,
+        code: vec![false; rom.len()],
+        labels: BTreeMap::new(),
+    };
+    d.trace();
+    d.code
+}
+
+impl<'a> Disassembler<'a> {
+    fn contains(&self, addr: u16) -> bool {
+        addr >= self.origin && ((addr - self.origin) as usize) < self.rom.len()
+    }
+
+    fn opcode(&self, addr: u16) -> Option<u16> {
+        let offset = addr.checked_sub(self.origin)? as usize;
+        let hi = *self.rom.get(offset)?;
+        let lo = *self.rom.get(offset + 1)?;
+        Some(((hi as u16) << 8) | lo as u16)
+    }
+
+    fn add_label(&mut self, addr: u16, kind: LabelKind) {
+        if self.contains(addr) {
+            let entry = self.labels.entry(addr).or_insert(kind);
+            *entry = (*entry).min(kind);
+        }
+    }
+
+    /// Follow every reachable path from the entry point, marking instructions and labels.
+    fn trace(&mut self) {
+        if self.rom.is_empty() {
+            return;
+        }
+        self.add_label(self.origin, LabelKind::Main);
+        let mut pending = vec![self.origin];
+        while let Some(mut addr) = pending.pop() {
+            while let Some(opcode) = self.opcode(addr) {
+                let offset = (addr - self.origin) as usize;
+                if self.code[offset] {
+                    break;
+                }
+                self.code[offset] = true;
+
+                let nnn = opcode & 0x0FFF;
+                let next = addr + 2;
+                match opcode & 0xF000 {
+                    0x0000 if opcode == 0x00EE || opcode == 0x00FD => break,
+                    0x1000 => {
+                        self.add_label(nnn, LabelKind::Label);
+                        if nnn == addr {
+                            break;
+                        }
+                        addr = nnn;
+                        continue;
+                    }
+                    0x2000 => {
+                        self.add_label(nnn, LabelKind::Sub);
+                        pending.push(nnn);
+                    }
+                    0x3000 | 0x4000 | 0x5000 | 0x9000 | 0xE000 => {
+                        // Skipping over an XO-CHIP `i := long` skips 4 bytes.
+                        let len = if self.opcode(next) == Some(0xF000) {
+                            4
+                        } else {
+                            2
+                        };
+                        pending.push(next + len);
+                    }
+                    0xA000 => self.add_label(nnn, LabelKind::Data),
+                    0xF000 if opcode == 0xF000 => {
+                        if let Some(long) = self.opcode(next) {
+                            self.add_label(long, LabelKind::Data);
+                        }
+                        addr = next + 2;
+                        continue;
+                    }
+                    // Computed jump: we can't know where it goes.
+                    0xB000 => {
+                        self.add_label(nnn, LabelKind::Data);
+                        break;
+                    }
+                    _ => {}
+                }
+                addr = next;
+            }
+        }
+    }
+
+    fn label_name(&self, addr: u16) -> Option<String> {
+        self.labels.get(&addr).map(|kind| match kind {
+            LabelKind::Main => "main".to_string(),
+            LabelKind::Sub => format!("sub_{:03x}", addr),
+            LabelKind::Label => format!("label_{:03x}", addr),
+            LabelKind::Data => format!("data_{:03x}", addr),
+        })
+    }
+
+    /// A jump/call/load target: a label if we have one, otherwise a literal address.
+    fn target(&self, addr: u16) -> String {
+        self.label_name(addr)
+            .unwrap_or_else(|| format!("0x{:03X}", addr))
+    }
+
+    fn long_target(&self, addr: u16) -> String {
+        self.label_name(addr)
+            .unwrap_or_else(|| format!("0x{:04X}", addr))
+    }
+
+    fn is_skip(&self, addr: u16) -> bool {
+        self.opcode(addr)
+            .and_then(|opcode| Instruction::decode(opcode).ok())
+            .is_some_and(|instruction| instruction.is_skip())
+    }
+
+    /// Return the Octo source for `opcode`, or `None` if it can't be expressed as an instruction.
+    fn instruction(&self, opcode: u16) -> Option<String> {
+        let s = match Instruction::decode(opcode).ok()? {
+            // `i := long` is handled with the address that follows it.
+            Instruction::Sys(_) | Instruction::LoadLong => return None,
+            instruction if instruction.is_megachip() => return None,
+            Instruction::Jump(addr) => format!("jump {}", self.target(addr)),
+            Instruction::Call(addr) => match self.label_name(addr) {
+                Some(label) => label,
+                None => format!(":call 0x{:03X}", addr),
+            },
+            Instruction::LoadI(addr) => format!("i := {}", self.target(addr)),
+            Instruction::JumpOffset(addr) => format!("jump0 {}", self.target(addr)),
+            instruction => instruction.to_string(),
+        };
+        Some(s)
+    }
+
+    fn emit(&self) -> String {
+        let mut out = String::new();
+        let mut offset = 0;
+        // Number of bytes on the current data line, if we're in a data section.
+        let mut data: Option<usize> = None;
+        let mut after_label = false;
+
+        while offset < self.rom.len() {
+            let addr = self.origin + offset as u16;
+            if let Some(label) = self.label_name(addr) {
+                if data.take().unwrap_or(0) > 0 {
+                    out.push('\n');
+                }
+                writeln!(out, "\n: {}", label).unwrap();
+                after_label = true;
+            }
+
+            // Only emit an instruction if no label points inside it, and a skip only if there is
+            // a statement after it.
+            let instruction = match self.opcode(addr) {
+                // XO-CHIP `i := long`, followed by its 16-bit address.
+                Some(0xF000) => self
+                    .opcode(addr + 2)
+                    .map(|long| (format!("i := long {}", self.long_target(long)), 4)),
+                op => op.and_then(|op| self.instruction(op)).map(|i| (i, 2)),
+            }
+            .filter(|&(_, len)| {
+                self.code[offset] && (1..len).all(|i| !self.labels.contains_key(&(addr + i)))
+            })
+            .filter(|&(_, len)| offset + (len as usize) < self.rom.len() || !self.is_skip(addr));
+
+            if let Some((instruction, len)) = instruction {
+                if let Some(n) = data.take() {
+                    out.push_str(if n > 0 { "\n\n" } else { "\n" });
+                }
+                writeln!(out, "\t{}", instruction).unwrap();
+                offset += len as usize;
+            } else {
+                let n = match data {
+                    Some(n) => n,
+                    None if after_label || out.is_empty() => 0,
+                    None => {
+                        out.push('\n');
+                        0
+                    }
+                };
+                out.push(if n == 0 { '\t' } else { ' ' });
+                write!(out, "0x{:02X}", self.rom[offset]).unwrap();
+                if n == 7 {
+                    out.push('\n');
+                    data = Some(0);
+                } else {
+                    data = Some(n + 1);
+                }
+                offset += 1;
+            }
+            after_label = false;
+        }
+        if data.unwrap_or(0) > 0 {
+            out.push('\n');
+        }
+        out.trim_start().to_string()
+    }
+}
@@ -0,0 +1,75 @@
+use std::any::Any;
+use std::fmt;
+
+/// An error raised by the machine while executing an instruction, usually because of a bug in the
+/// program.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Chip8Error {
+    /// The opcode isn't an instruction, at least not with the enabled extensions.
+    UnknownOpcode(u16),
+    /// A subroutine was called with the stack already full.
+    StackOverflow,
+    /// A subroutine returned with the stack empty.
+    StackUnderflow,
+    /// An instruction accessed memory past the end of RAM.
+    AddressOutOfRange(usize),
+    /// A machine code routine was called with `0NNN`, with the `halt` policy.
+    MachineCode(u16),
+    /// The machine was asked to run at 0 instructions per second.
+    ZeroSpeed,
+}
+
+impl fmt::Display for Chip8Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Chip8Error::UnknownOpcode(opcode) => write!(f, "unknown opcode {:04X}", opcode),
+            Chip8Error::StackOverflow => write!(f, "stack overflow"),
+            Chip8Error::StackUnderflow => write!(f, "stack underflow: return without a call"),
+            Chip8Error::AddressOutOfRange(addr) => {
+                write!(f, "address {:#06x} is out of RAM", addr)
+            }
+            Chip8Error::MachineCode(addr) => {
+                write!(f, "call to machine code at {:#05x}", addr)
+            }
+            Chip8Error::ZeroSpeed => write!(f, "can't run at 0 instructions per second"),
+        }
+    }
+}
+
+impl std::error::Error for Chip8Error {}
+
+/// A machine error that stopped emulation, e.g. an unknown opcode or a stack overflow.
+#[derive(Debug, Clone)]
+pub struct Fault {
+    pub pc: u16,
+    /// The instruction at `pc`, if it's in RAM.
+    pub opcode: Option<u16>,
+    pub message: String,
+}
+
+impl Fault {
+    /// Build a fault from an error raised by the instruction at `pc`.
+    pub fn from_error(pc: u16, opcode: Option<u16>, error: Chip8Error) -> Self {
+        Self {
+            pc,
+            opcode,
+            message: error.to_string(),
+        }
+    }
+
+    /// Build a fault from the payload of a panic raised while executing the instruction at `pc`.
+    pub fn from_panic(pc: u16, opcode: Option<u16>, payload: Box<dyn Any + Send>) -> Self {
+        let message = match payload.downcast::<String>() {
+            Ok(message) => *message,
+            Err(payload) => match payload.downcast::<&str>() {
+                Ok(message) => message.to_string(),
+                Err(_) => "unknown error".to_string(),
+            },
+        };
+        Self {
+            pc,
+            opcode,
+            message,
+        }
+    }
+}
@@ -0,0 +1,48 @@
+use egui::{Align2, CtxRef};
+
+use chip8rs::error::Fault;
+
+/// What the user chose to do about a fault.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaultAction {
+    Reset,
+    DumpState,
+    Quit,
+}
+
+/// Show `fault` in a window in the middle of the screen.
+pub fn ui(ctx: &CtxRef, fault: &Fault) -> Option<FaultAction> {
+    let mut action = None;
+    egui::Window::new("Machine fault")
+        .anchor(Align2::CENTER_CENTER, [0.0, 0.0])
+        .resizable(false)
+        .collapsible(false)
+        .show(ctx, |ui| {
+            ui.label(&fault.message);
+            ui.separator();
+            egui::Grid::new("fault_state").show(ui, |ui| {
+                ui.label("PC");
+                ui.monospace(format!("{:#05x}", fault.pc));
+                ui.end_row();
+                ui.label("Opcode");
+                ui.monospace(match fault.opcode {
+                    Some(opcode) => format!("{:04X}", opcode),
+                    None => "-".to_string(),
+                });
+                ui.end_row();
+            });
+            ui.separator();
+            ui.horizontal(|ui| {
+                if ui.button("Reset").clicked() {
+                    action = Some(FaultAction::Reset);
+                }
+                if ui.button("Dump state").clicked() {
+                    action = Some(FaultAction::DumpState);
+                }
+                if ui.button("Quit").clicked() {
+                    action = Some(FaultAction::Quit);
+                }
+            });
+        });
+    action
+}
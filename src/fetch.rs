@@ -0,0 +1,71 @@
+//! Reading ROMs from standard input and from the web.
+
+use std::io::Read;
+
+use anyhow::{ensure, Context, Result};
+
+/// Downloads larger than this are refused, since no ROM or archive of one comes near it.
+const MAX_SIZE: u64 = 16 << 20;
+
+/// Whether the ROM argument `rom` is a URL to download rather than a file.
+pub fn is_url(rom: &str) -> bool {
+    rom.starts_with("http://") || rom.starts_with("https://")
+}
+
+/// The name of the file at `url`: its last path segment, without any query or fragment. A URL
+/// without one, such as `https://example.com/`, is named after its host.
+pub fn file_name(url: &str) -> &str {
+    let url = url.split(['?', '#']).next().unwrap_or(url);
+    let url = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let (host, path) = url.split_once('/').unwrap_or((url, ""));
+    match path.rsplit('/').next() {
+        Some(name) if !name.is_empty() => name,
+        _ => {
+            let host = host.rsplit('@').next().unwrap_or(host);
+            host.split(':').next().unwrap_or(host)
+        }
+    }
+}
+
+/// Read all of standard input.
+pub fn stdin() -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+    std::io::stdin()
+        .lock()
+        .take(MAX_SIZE + 1)
+        .read_to_end(&mut data)
+        .context("Failed to read standard input")?;
+    ensure!(
+        data.len() as u64 <= MAX_SIZE,
+        "Standard input is too large for a ROM"
+    );
+    Ok(data)
+}
+
+/// Download the file at `url`.
+#[cfg(feature = "net")]
+pub fn download(url: &str) -> Result<Vec<u8>> {
+    let response = ureq::get(url)
+        .call()
+        .with_context(|| format!("Failed to download {}", url))?;
+    let mut data = Vec::new();
+    response
+        .into_reader()
+        .take(MAX_SIZE + 1)
+        .read_to_end(&mut data)
+        .with_context(|| format!("Failed to download {}", url))?;
+    ensure!(
+        data.len() as u64 <= MAX_SIZE,
+        "{} is too large for a ROM",
+        url
+    );
+    Ok(data)
+}
+
+#[cfg(not(feature = "net"))]
+pub fn download(url: &str) -> Result<Vec<u8>> {
+    anyhow::bail!(
+        "Can't download {}: chip8rs was built without the net feature",
+        url
+    )
+}
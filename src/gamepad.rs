@@ -0,0 +1,165 @@
+use anyhow::{anyhow, Context, Result};
+use gilrs::ff::{BaseEffect, BaseEffectType, Effect, EffectBuilder, Repeat, Replay, Ticks};
+use gilrs::{Button, EventType, Gilrs};
+use log::{info, warn};
+
+use chip8rs::config::KeyMap;
+
+/// Keypad keys of the controller buttons unless configured otherwise: the d-pad on 2, 4, 6 and
+/// 8 and the main buttons on 5 and A, which is how most games use the keypad.
+const DEFAULT_BUTTONS: [(Button, u8); 6] = [
+    (Button::DPadUp, 0x2),
+    (Button::DPadDown, 0x8),
+    (Button::DPadLeft, 0x4),
+    (Button::DPadRight, 0x6),
+    (Button::South, 0x5),
+    (Button::East, 0xA),
+];
+
+/// Names of the buttons in the settings, as in gilrs.
+const BUTTON_NAMES: [(&str, Button); 19] = [
+    ("South", Button::South),
+    ("East", Button::East),
+    ("North", Button::North),
+    ("West", Button::West),
+    ("C", Button::C),
+    ("Z", Button::Z),
+    ("LeftTrigger", Button::LeftTrigger),
+    ("LeftTrigger2", Button::LeftTrigger2),
+    ("RightTrigger", Button::RightTrigger),
+    ("RightTrigger2", Button::RightTrigger2),
+    ("Select", Button::Select),
+    ("Start", Button::Start),
+    ("Mode", Button::Mode),
+    ("LeftThumb", Button::LeftThumb),
+    ("RightThumb", Button::RightThumb),
+    ("DPadUp", Button::DPadUp),
+    ("DPadDown", Button::DPadDown),
+    ("DPadLeft", Button::DPadLeft),
+    ("DPadRight", Button::DPadRight),
+];
+
+/// Game controllers, used for the keypad and force feedback.
+pub struct Gamepads {
+    gilrs: Gilrs,
+    /// Effect played on every connected controller that supports it while the buzzer sounds.
+    rumble: Option<Effect>,
+    /// Keypad key of each bound button.
+    buttons: Vec<(Button, u8)>,
+}
+
+impl Gamepads {
+    /// Open the connected controllers. If `rumble` is greater than 0, their rumble motor will be
+    /// pulsed at this intensity (up to 1.0) while the sound timer is active.
+    pub fn new(rumble: f32) -> Result<Self> {
+        let mut gilrs = Gilrs::new().map_err(|e| anyhow!("Failed to open controllers: {}", e))?;
+        for (_, gamepad) in gilrs.gamepads() {
+            info!("found controller {}", gamepad.name());
+        }
+
+        let rumble = if rumble > 0.0 {
+            let ids: Vec<_> = gilrs
+                .gamepads()
+                .filter(|(_, gamepad)| gamepad.is_ff_supported())
+                .map(|(id, _)| id)
+                .collect();
+            let effect = EffectBuilder::new()
+                .add_effect(BaseEffect {
+                    kind: BaseEffectType::Strong {
+                        magnitude: (rumble.min(1.0) * u16::MAX as f32) as u16,
+                    },
+                    scheduling: Replay {
+                        play_for: Ticks::from_ms(100),
+                        with_delay: Ticks::from_ms(50),
+                        ..Default::default()
+                    },
+                    envelope: Default::default(),
+                })
+                .gamepads(&ids)
+                .repeat(Repeat::Infinitely)
+                .finish(&mut gilrs)
+                .context("Failed to create rumble effect")?;
+            Some(effect)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            gilrs,
+            rumble,
+            buttons: DEFAULT_BUTTONS.to_vec(),
+        })
+    }
+
+    /// Bind the buttons in `buttons` to keypad keys, on top of the default bindings.
+    pub fn set_buttons(&mut self, buttons: &KeyMap) -> Result<()> {
+        let mut bindings = DEFAULT_BUTTONS.to_vec();
+        for (name, digit) in &buttons.0 {
+            let button = parse_button(name)?;
+            let digit = KeyMap::parse_digit(digit)?;
+            bindings.retain(|&(b, _)| b != button);
+            bindings.push((button, digit));
+        }
+        self.buttons = bindings;
+        Ok(())
+    }
+
+    /// Whether keypad key `k` is held down on any controller.
+    pub fn is_key_down(&self, k: u8) -> bool {
+        self.buttons.iter().any(|&(button, digit)| {
+            digit == k
+                && self
+                    .gilrs
+                    .gamepads()
+                    .any(|(_, gamepad)| gamepad.is_pressed(button))
+        })
+    }
+
+    /// Process pending controller events. Return `true` if a button was pressed or released.
+    pub fn poll(&mut self) -> bool {
+        let mut changed = false;
+        while let Some(event) = self.gilrs.next_event() {
+            if matches!(
+                event.event,
+                EventType::ButtonPressed(..) | EventType::ButtonReleased(..)
+            ) {
+                changed = true;
+            }
+            if event.event == EventType::Connected {
+                let gamepad = self.gilrs.gamepad(event.id);
+                info!("controller {} connected", gamepad.name());
+                if let Some(effect) = &self.rumble {
+                    if gamepad.is_ff_supported() {
+                        if let Err(e) = effect.add_gamepad(&gamepad) {
+                            warn!("Failed to enable rumble on {}: {}", gamepad.name(), e);
+                        }
+                    }
+                }
+            }
+        }
+        changed
+    }
+
+    /// Start or stop the rumble effect, if enabled.
+    pub fn set_rumble(&self, on: bool) {
+        if let Some(effect) = &self.rumble {
+            let result = if on { effect.play() } else { effect.stop() };
+            if let Err(e) = result {
+                warn!(
+                    "Failed to {} rumble: {}",
+                    if on { "start" } else { "stop" },
+                    e
+                );
+            }
+        }
+    }
+}
+
+/// Parse a gilrs `Button` variant name.
+fn parse_button(name: &str) -> Result<Button> {
+    BUTTON_NAMES
+        .iter()
+        .find(|(n, _)| *n == name)
+        .map(|&(_, button)| button)
+        .ok_or_else(|| anyhow!("Unknown controller button {:?}", name))
+}
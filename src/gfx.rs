@@ -1,70 +1,570 @@
-const W: u8 = 64;
-const H: u8 = 32;
+use crate::palette::{Color, Palette};
+use crate::savestate::MegaChipRegisters;
+
+/// Size of the display in low resolution, the only one of the original CHIP-8.
+pub const LORES: (usize, usize) = (64, 32);
+/// Size of the display in SUPER-CHIP high resolution.
+pub const HIRES: (usize, usize) = (128, 64);
+/// Size of the display of hires CHIP-8 programs, which use two pages of the COSMAC VIP's memory
+/// for it.
+pub const TWO_PAGE: (usize, usize) = (64, 64);
+/// Size of the display in Mega-Chip mode.
+pub const MEGACHIP: (usize, usize) = (256, 192);
+
+/// Color in which 1-bit sprites, such as the font, are drawn in Mega-Chip mode.
+const MEGACHIP_MONO: u8 = 255;
 
 /// Represents the display of the Chip-8 machine.
 ///
-/// It consists of 64x32 1-bit pixels.
+/// It consists of 64x32 1-bit pixels, 64x64 for hires CHIP-8 programs, or 128x64 in SUPER-CHIP
+/// high resolution. XO-CHIP adds a second plane: each pixel holds one bit per plane, so its value
+/// ranges from 0 to 3. In Mega-Chip mode, it is 256x192 pixels, each holding the index of its
+/// color in a palette loaded by the program.
 pub struct Gfx {
-    buf: [u8; (W as usize * H as usize)],
-    pub dirty: bool,
+    buf: Vec<u8>,
+    width: usize,
+    height: usize,
+    /// Planes affected by drawing, clearing and scrolling, one bit per plane.
+    planes: u8,
+    megachip: MegaChip,
+    afterglow: Option<Afterglow>,
+    palette: Palette,
+    /// The display in RGBA, as last returned by `get_frame`.
+    rgba: Vec<u8>,
+    /// Whether each row changed since the last `get_frame`.
+    dirty_rows: Vec<bool>,
+}
+
+/// The Mega-Chip registers, and the display as last shown.
+struct MegaChip {
+    /// Colors loaded by `02NN`. Color 0 is transparent, letting the background show through.
+    colors: [Color; 256],
+    sprite_width: usize,
+    sprite_height: usize,
+    /// Color whose pixels report a collision when drawn over, none until one is set.
+    collision_color: Option<u8>,
+    /// Mega-Chip programs draw each frame in the background and show it with `00E0`, which
+    /// copies it here before clearing it.
+    shown: Vec<u8>,
+}
+
+impl MegaChip {
+    fn new() -> Self {
+        Self {
+            colors: [[0xFF; 4]; 256],
+            sprite_width: 0,
+            sprite_height: 0,
+            collision_color: None,
+            shown: Vec::new(),
+        }
+    }
+}
+
+/// Phosphor decay: pixels that are turned off keep glowing for a few frames, which hides the
+/// flicker of sprites erased and redrawn every frame.
+pub struct Afterglow {
+    /// Brightness of each pixel, from 255 while it's lit down to 0.
+    intensity: Vec<u8>,
+    /// Value of each pixel when it was last lit, for the color of its glow.
+    value: Vec<u8>,
+    /// How many 60Hz frames pixels take to fade out.
+    frames: u8,
+}
+
+impl Afterglow {
+    /// An afterglow fading out over `frames` 60Hz frames.
+    pub fn new(frames: u8) -> Self {
+        Self {
+            intensity: Vec::new(),
+            value: Vec::new(),
+            frames: frames.max(1),
+        }
+    }
+
+    pub fn frames(&self) -> u8 {
+        self.frames
+    }
+
+    /// Follow the display `buf`, `width` pixels wide, for one frame, marking the rows where
+    /// pixels are still fading in `dirty_rows`.
+    fn update(&mut self, buf: &[u8], width: usize, dirty_rows: &mut [bool]) {
+        if self.intensity.len() != buf.len() {
+            self.intensity = vec![0; buf.len()];
+            self.value = vec![0; buf.len()];
+        }
+        let decay = 255 / self.frames;
+        let pixels = self.intensity.iter_mut().zip(&mut self.value);
+        for (i, ((intensity, value), &pixel)) in pixels.zip(buf).enumerate() {
+            if pixel != 0 {
+                *intensity = 255;
+                *value = pixel;
+            } else if *intensity > 0 {
+                *intensity = intensity.saturating_sub(decay);
+                dirty_rows[i / width] = true;
+            }
+        }
+    }
+
+    /// The value pixel `i` had when it was last lit, and how bright it still is.
+    pub fn pixel(&self, i: usize) -> (u8, u8) {
+        match self.intensity.get(i) {
+            Some(&intensity) => (self.value[i], intensity),
+            None => (0, 0),
+        }
+    }
 }
 
 impl Gfx {
     pub fn new() -> Self {
         Self {
-            buf: [0u8; (W as usize * H as usize)],
-            dirty: true,
+            buf: vec![0u8; LORES.0 * LORES.1],
+            width: LORES.0,
+            height: LORES.1,
+            planes: 1,
+            megachip: MegaChip::new(),
+            afterglow: None,
+            palette: Palette::default(),
+            rgba: Vec::new(),
+            dirty_rows: vec![true; LORES.1],
         }
     }
 
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn is_hires(&self) -> bool {
+        self.width == HIRES.0
+    }
+
+    /// Switch between low and high resolution, clearing the display and leaving Mega-Chip mode.
+    pub fn set_hires(&mut self, hires: bool) {
+        self.resize(if hires { HIRES } else { LORES });
+    }
+
+    /// Switch to the 64x64 display of hires CHIP-8, clearing it.
+    pub fn set_two_page(&mut self) {
+        self.resize(TWO_PAGE);
+    }
+
+    pub fn is_two_page(&self) -> bool {
+        (self.width, self.height) == TWO_PAGE
+    }
+
+    pub fn is_megachip(&self) -> bool {
+        self.width == MEGACHIP.0
+    }
+
+    /// Enter Mega-Chip mode, or leave it for low resolution, clearing the display.
+    pub fn set_megachip(&mut self, megachip: bool) {
+        self.resize(if megachip { MEGACHIP } else { LORES });
+        self.megachip.shown = if megachip {
+            self.buf.clone()
+        } else {
+            Vec::new()
+        };
+    }
+
+    fn resize(&mut self, (width, height): (usize, usize)) {
+        (self.width, self.height) = (width, height);
+        self.buf = vec![0u8; width * height];
+        self.dirty_rows = vec![true; height];
+        // What was glowing was in the old resolution.
+        self.afterglow = self
+            .afterglow
+            .as_ref()
+            .map(|afterglow| Afterglow::new(afterglow.frames));
+    }
+
+    /// Whether anything changed since the last `get_frame`.
+    pub fn is_dirty(&self) -> bool {
+        self.dirty_rows.contains(&true)
+    }
+
+    /// Which rows changed since the last `get_frame`, one flag per row.
+    pub fn dirty_rows(&self) -> &[bool] {
+        &self.dirty_rows
+    }
+
+    /// Mark the whole display as changed, so that it is drawn again.
+    pub fn set_dirty(&mut self) {
+        self.dirty_rows.fill(true);
+    }
+
+    /// Forget the changes, once they were shown some other way than with `get_frame`.
+    pub fn clear_dirty(&mut self) {
+        self.dirty_rows.fill(false);
+    }
+
+    /// Set colors 1 and up to the ARGB colors in `argb`, 4 bytes each (Mega-Chip `02NN`). The
+    /// alpha channel is ignored.
+    pub fn load_palette(&mut self, argb: &[u8]) {
+        let colors = self.megachip.colors[1..].iter_mut();
+        for (color, argb) in colors.zip(argb.chunks_exact(4)) {
+            *color = [argb[1], argb[2], argb[3], 0xFF];
+        }
+        self.set_dirty();
+    }
+
+    /// Set the width of Mega-Chip sprites, 256 if `width` is 0.
+    pub fn set_sprite_width(&mut self, width: u8) {
+        self.megachip.sprite_width = if width == 0 { 256 } else { width as usize };
+    }
+
+    /// Set the height of Mega-Chip sprites, 256 if `height` is 0.
+    pub fn set_sprite_height(&mut self, height: u8) {
+        self.megachip.sprite_height = if height == 0 { 256 } else { height as usize };
+    }
+
+    /// Width and height of Mega-Chip sprites, in pixels of one byte each.
+    pub fn sprite_size(&self) -> (usize, usize) {
+        (self.megachip.sprite_width, self.megachip.sprite_height)
+    }
+
+    pub fn set_collision_color(&mut self, color: u8) {
+        self.megachip.collision_color = Some(color);
+    }
+
+    /// The Mega-Chip registers, to be saved.
+    pub fn megachip_registers(&self) -> MegaChipRegisters {
+        let megachip = &self.megachip;
+        MegaChipRegisters {
+            colors: megachip.colors[1..]
+                .iter()
+                .map(|&[r, g, b, _]| [r, g, b])
+                .collect(),
+            sprite_width: megachip.sprite_width as u16,
+            sprite_height: megachip.sprite_height as u16,
+            collision_color: megachip.collision_color,
+        }
+    }
+
+    /// Restore the Mega-Chip registers saved by `megachip_registers`.
+    pub fn restore_megachip_registers(&mut self, registers: &MegaChipRegisters) {
+        let megachip = &mut self.megachip;
+        for (color, &[r, g, b]) in megachip.colors[1..].iter_mut().zip(&registers.colors) {
+            *color = [r, g, b, 0xFF];
+        }
+        megachip.sprite_width = registers.sprite_width as usize;
+        megachip.sprite_height = registers.sprite_height as usize;
+        megachip.collision_color = registers.collision_color;
+        self.set_dirty();
+    }
+
+    /// Show the display in the colors of `palette`.
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+        self.set_dirty();
+    }
+
+    pub fn palette(&self) -> &Palette {
+        &self.palette
+    }
+
+    /// The color of pixel value `value`: from the Mega-Chip palette in that mode, otherwise from
+    /// the palette. Mega-Chip pixels of color 0 show the background of the palette.
+    pub fn color(&self, value: u8) -> Color {
+        if self.is_megachip() && value != 0 {
+            self.megachip.colors[value as usize]
+        } else {
+            self.palette.color(value)
+        }
+    }
+
+    /// The color of pixel `i`, still glowing if it was turned off recently. Mega-Chip pixels
+    /// don't glow, their colors not being from the palette.
+    fn pixel_color(&self, i: usize) -> Color {
+        let value = self.buffer()[i];
+        match &self.afterglow {
+            Some(afterglow) if value == 0 && !self.is_megachip() => {
+                let (value, intensity) = afterglow.pixel(i);
+                self.palette.dimmed(value, intensity)
+            }
+            _ => self.color(value),
+        }
+    }
+
+    /// Select the planes that later instructions affect (XO-CHIP `FN01`).
+    pub fn select_planes(&mut self, planes: u8) {
+        self.planes = planes & 0b11;
+    }
+
+    /// The planes selected by `select_planes`, one bit per plane.
+    pub fn planes(&self) -> u8 {
+        self.planes
+    }
+
+    /// Number of selected planes, i.e. how many sprites a draw instruction reads.
+    pub fn plane_count(&self) -> usize {
+        self.planes.count_ones() as usize
+    }
+
+    /// Let pixels that are turned off fade out over `frames` 60Hz frames, or disappear at once
+    /// with `None`.
+    pub fn set_afterglow(&mut self, frames: Option<u8>) {
+        self.afterglow = frames.map(Afterglow::new);
+        self.set_dirty();
+    }
+
+    pub fn afterglow(&self) -> Option<&Afterglow> {
+        self.afterglow.as_ref()
+    }
+
+    /// Called once per 60Hz frame to fade out the afterglow.
+    pub fn end_frame(&mut self) {
+        let shown = if self.is_megachip() {
+            &self.megachip.shown
+        } else {
+            &self.buf
+        };
+        if let Some(afterglow) = &mut self.afterglow {
+            afterglow.update(shown, self.width, &mut self.dirty_rows);
+        }
+    }
+
+    /// Clear the selected planes. In Mega-Chip mode, show what was drawn before clearing it.
     pub fn clear(&mut self) {
+        if self.is_megachip() {
+            self.megachip.shown.copy_from_slice(&self.buf);
+        }
+        let planes = self.selected_planes();
         for v in self.buf.iter_mut() {
-            *v = 0;
+            *v &= !planes;
+        }
+        self.set_dirty();
+    }
+
+    /// Bits of the pixel values affected by clearing and scrolling: all of them in Mega-Chip mode.
+    fn selected_planes(&self) -> u8 {
+        if self.is_megachip() {
+            0xFF
+        } else {
+            self.planes
         }
-        self.dirty = true;
     }
 
-    /// Draw the sprite in `data` at coordinates (x, y) with height `height`.
+    /// Draw the sprite in `data` at coordinates (x, y) with height `height`, once per selected
+    /// plane: `data` holds the sprite for the first selected plane, followed by the one for the
+    /// second.
+    ///
+    /// The coordinates wrap around the display. Pixels past its edges are clipped, or wrap around
+    /// to the opposite edge if `wrap` is `true`.
     ///
     /// Return `true` if any set pixel was unset in the process.
-    pub fn draw_sprite(&mut self, x: u8, y: u8, height: u8, data: &[u8]) -> bool {
-        let x = x % W;
-        let y = y % H;
+    ///
+    /// ```
+    /// use chip8rs::gfx::Gfx;
+    ///
+    /// let pixel = |gfx: &Gfx, x: usize, y: usize| gfx.buffer()[y * gfx.width() + x];
+    /// let mut gfx = Gfx::new();
+    /// gfx.draw_sprite(60, 31, 2, &[0xFF, 0xFF], false);
+    /// assert_eq!(pixel(&gfx, 63, 31), 1);
+    /// assert_eq!(pixel(&gfx, 0, 31), 0);
+    /// assert_eq!(pixel(&gfx, 60, 0), 0);
+    ///
+    /// gfx.clear();
+    /// gfx.draw_sprite(60, 31, 2, &[0xFF, 0xFF], true);
+    /// assert_eq!(pixel(&gfx, 63, 31), 1);
+    /// assert_eq!(pixel(&gfx, 0, 31), 1);
+    /// assert_eq!(pixel(&gfx, 3, 0), 1);
+    /// assert_eq!(pixel(&gfx, 4, 0), 0);
+    ///
+    /// // Only the pixels past the edges wrap, not the coordinates of the sprite.
+    /// gfx.clear();
+    /// gfx.draw_sprite(64 + 8, 0, 1, &[0x80], false);
+    /// assert_eq!(pixel(&gfx, 8, 0), 1);
+    /// ```
+    pub fn draw_sprite(&mut self, x: u8, y: u8, height: u8, data: &[u8], wrap: bool) -> bool {
+        self.draw(x, y, 1, height as usize, data, wrap)
+    }
+
+    /// Draw the 16x16 SUPER-CHIP sprite in `data` (two bytes per row) at coordinates (x, y), once
+    /// per selected plane, clipping or wrapping it at the edges like `draw_sprite`.
+    ///
+    /// Return `true` if any set pixel was unset in the process.
+    pub fn draw_large_sprite(&mut self, x: u8, y: u8, data: &[u8], wrap: bool) -> bool {
+        self.draw(x, y, 2, 16, data, wrap)
+    }
+
+    /// Draw a sprite `bytes_per_row` bytes wide and `height` rows high on each selected plane.
+    fn draw(
+        &mut self,
+        x: u8,
+        y: u8,
+        bytes_per_row: usize,
+        height: usize,
+        data: &[u8],
+        wrap: bool,
+    ) -> bool {
+        let x = x as usize % self.width;
+        let y = y as usize % self.height;
 
         let mut collision = false;
 
-        for dy in 0..height {
-            let sprite_byte = data[dy as usize];
-            for dx in 0..8 {
-                let bit = sprite_byte & (0x80 >> dx);
-                let pixel = if bit == 0 {
-                    0
+        let selected = self.planes;
+        let planes = (0..2).map(|p| 1 << p).filter(|p| selected & p != 0);
+        for (plane, sprite) in planes.zip(data.chunks(bytes_per_row * height)) {
+            for (dy, row) in sprite.chunks(bytes_per_row).enumerate() {
+                for (byte_index, sprite_byte) in row.iter().enumerate() {
+                    for bit in 0..8 {
+                        if sprite_byte & (0x80 >> bit) == 0 {
+                            continue;
+                        }
+                        let (mut px, mut py) = (x + byte_index * 8 + bit, y + dy);
+                        if wrap {
+                            px %= self.width;
+                            py %= self.height;
+                        }
+                        collision |= self.flip(px, py, plane);
+                    }
+                }
+            }
+        }
+
+        collision
+    }
+
+    /// Flip the pixel at (x, y) on `plane`, returning `true` if it was set. In Mega-Chip mode,
+    /// the pixel is flipped between color 0 and `MEGACHIP_MONO`.
+    fn flip(&mut self, x: usize, y: usize, plane: u8) -> bool {
+        if x >= self.width || y >= self.height {
+            return false;
+        }
+        let megachip = self.is_megachip();
+        self.dirty_rows[y] = true;
+        let pixel = &mut self.buf[y * self.width + x];
+        if megachip {
+            let was_set = *pixel != 0;
+            *pixel = if was_set { 0 } else { MEGACHIP_MONO };
+            was_set
+        } else {
+            *pixel ^= plane;
+            *pixel & plane == 0
+        }
+    }
+
+    /// Draw the Mega-Chip sprite in `data`, one color per byte and `sprite_size` pixels large, at
+    /// coordinates (x, y). Pixels of color 0 are transparent, and the sprite is clipped at the
+    /// edges of the display.
+    ///
+    /// Return `true` if any pixel of the collision color was drawn over, if one is set.
+    pub fn draw_indexed(&mut self, x: u8, y: u8, data: &[u8]) -> bool {
+        let (x, y) = (x as usize, y as usize);
+        let (sprite_width, collision_color) =
+            (self.megachip.sprite_width, self.megachip.collision_color);
+        let mut collision = false;
+        for (dy, row) in data.chunks(sprite_width.max(1)).enumerate() {
+            if y + dy >= self.height {
+                break;
+            }
+            for (dx, &color) in row.iter().enumerate() {
+                if color == 0 || x + dx >= self.width {
+                    continue;
+                }
+                let pixel = &mut self.buf[(y + dy) * self.width + x + dx];
+                collision |= Some(*pixel) == collision_color;
+                *pixel = color;
+                self.dirty_rows[y + dy] = true;
+            }
+        }
+
+        collision
+    }
+
+    /// Move the content of the selected planes down by `n` lines, leaving blank lines at the top.
+    pub fn scroll_down(&mut self, n: usize) {
+        self.scroll(0, n as isize);
+    }
+
+    /// Move the content of the selected planes up by `n` lines, leaving blank lines at the bottom
+    /// (XO-CHIP).
+    pub fn scroll_up(&mut self, n: usize) {
+        self.scroll(0, -(n as isize));
+    }
+
+    /// Move the content of the selected planes `n` pixels to the right, leaving blank columns on
+    /// the left.
+    pub fn scroll_right(&mut self, n: usize) {
+        self.scroll(n as isize, 0);
+    }
+
+    /// Move the content of the selected planes `n` pixels to the left, leaving blank columns on
+    /// the right.
+    pub fn scroll_left(&mut self, n: usize) {
+        self.scroll(-(n as isize), 0);
+    }
+
+    fn scroll(&mut self, dx: isize, dy: isize) {
+        let planes = self.selected_planes();
+        let old = self.buf.clone();
+        let (width, height) = (self.width as isize, self.height as isize);
+        for y in 0..height {
+            for x in 0..width {
+                let (from_x, from_y) = (x - dx, y - dy);
+                let moved = if (0..width).contains(&from_x) && (0..height).contains(&from_y) {
+                    old[(from_y * width + from_x) as usize]
                 } else {
-                    0xFF
+                    0
                 };
-                collision |= self.set(x + dx, y + dy, pixel);
+                let pixel = &mut self.buf[(y * width + x) as usize];
+                *pixel = (*pixel & !planes) | (moved & planes);
             }
         }
-        self.dirty = true;
+        self.set_dirty();
+    }
 
-        collision
+    /// Replace the display with `buf`, as returned by `buffer`, in the resolution `size`. The
+    /// Mega-Chip palette and sprite registers are kept.
+    pub fn restore(&mut self, size: (usize, usize), buf: &[u8]) {
+        match size {
+            MEGACHIP => self.set_megachip(true),
+            TWO_PAGE => self.set_two_page(),
+            (width, _) => self.set_hires(width == HIRES.0),
+        }
+        self.buf.copy_from_slice(buf);
+        if self.is_megachip() {
+            self.megachip.shown.copy_from_slice(buf);
+        }
     }
 
-    pub fn set(&mut self, x: u8, y: u8, v: u8) -> bool {
-        if x < W && y < H {
-            let pixel_index = (y as u16 * W as u16 + x as u16) as usize;
-            let old_pixel = self.buf[pixel_index];
-            let new_pixel = old_pixel ^ v;
-            self.buf[pixel_index] = new_pixel;
-            // Return true if a set pixel was changed to unset
-            old_pixel != 0 && v != 0
+    /// Return the content of the display, leaving it marked as changed. In Mega-Chip mode, this
+    /// is what was shown by the last `00E0`.
+    pub fn buffer(&self) -> &[u8] {
+        if self.is_megachip() {
+            &self.megachip.shown[..]
         } else {
-            false
+            &self.buf[..]
         }
     }
 
+    /// Return the display in RGBA, 4 bytes per pixel, with the palette and afterglow applied.
+    /// Only the rows that changed since the last call are converted again.
     pub fn get_frame(&mut self) -> &[u8] {
-        self.dirty = false;
-        &self.buf[..]
+        let mut rgba = std::mem::take(&mut self.rgba);
+        if rgba.len() != 4 * self.width * self.height {
+            rgba = vec![0; 4 * self.width * self.height];
+            self.set_dirty();
+        }
+        for (y, row) in rgba.chunks_exact_mut(4 * self.width).enumerate() {
+            if !self.dirty_rows[y] {
+                continue;
+            }
+            for (x, pixel) in row.chunks_exact_mut(4).enumerate() {
+                pixel.copy_from_slice(&self.pixel_color(y * self.width + x));
+            }
+        }
+        self.rgba = rgba;
+        self.clear_dirty();
+        &self.rgba
+    }
+}
+
+impl Default for Gfx {
+    fn default() -> Self {
+        Self::new()
     }
 }
@@ -0,0 +1,84 @@
+use egui::{ClippedMesh, CtxRef};
+use egui_wgpu_backend::{BackendError, RenderPass, ScreenDescriptor};
+use pixels::{wgpu, PixelsContext};
+use winit::window::Window;
+
+/// Manages all the state required for rendering egui over `Pixels`.
+pub struct Framework {
+    egui_ctx: CtxRef,
+    egui_state: egui_winit::State,
+    screen_descriptor: ScreenDescriptor,
+    rpass: RenderPass,
+    paint_jobs: Vec<ClippedMesh>,
+}
+
+impl Framework {
+    pub fn new(width: u32, height: u32, scale_factor: f32, pixels: &pixels::Pixels) -> Self {
+        let egui_ctx = CtxRef::default();
+        let egui_state = egui_winit::State::from_pixels_per_point(scale_factor);
+        let screen_descriptor = ScreenDescriptor {
+            physical_width: width,
+            physical_height: height,
+            scale_factor,
+        };
+        let rpass = RenderPass::new(pixels.device(), pixels.render_texture_format(), 1);
+
+        Self {
+            egui_ctx,
+            egui_state,
+            screen_descriptor,
+            rpass,
+            paint_jobs: Vec::new(),
+        }
+    }
+
+    /// Handle input events from the window manager.
+    pub fn handle_event(&mut self, event: &winit::event::WindowEvent) {
+        self.egui_state.on_event(&self.egui_ctx, event);
+    }
+
+    pub fn resize(&mut self, width: u32, height: u32) {
+        if width > 0 && height > 0 {
+            self.screen_descriptor.physical_width = width;
+            self.screen_descriptor.physical_height = height;
+        }
+    }
+
+    pub fn scale_factor(&mut self, scale_factor: f64) {
+        self.screen_descriptor.scale_factor = scale_factor as f32;
+    }
+
+    /// Run `run_ui` to build the UI for this frame and tessellate it, ready to be rendered.
+    pub fn prepare(&mut self, window: &Window, run_ui: impl FnOnce(&CtxRef)) {
+        let raw_input = self.egui_state.take_egui_input(window);
+        let (output, paint_commands) = self.egui_ctx.run(raw_input, run_ui);
+        self.egui_state.handle_output(window, &self.egui_ctx, output);
+        self.paint_jobs = self.egui_ctx.tessellate(paint_commands);
+    }
+
+    /// Render the prepared UI on top of `render_target`.
+    pub fn render(
+        &mut self,
+        encoder: &mut wgpu::CommandEncoder,
+        render_target: &wgpu::TextureView,
+        context: &PixelsContext,
+    ) -> Result<(), BackendError> {
+        self.rpass
+            .update_texture(&context.device, &context.queue, &self.egui_ctx.font_image());
+        self.rpass
+            .update_user_textures(&context.device, &context.queue);
+        self.rpass.update_buffers(
+            &context.device,
+            &context.queue,
+            &self.paint_jobs,
+            &self.screen_descriptor,
+        );
+        self.rpass.execute(
+            encoder,
+            render_target,
+            &self.paint_jobs,
+            &self.screen_descriptor,
+            None,
+        )
+    }
+}
@@ -0,0 +1,337 @@
+use anyhow::{anyhow, bail, ensure, Result};
+use egui::CtxRef;
+use serde::de::{value, IntoDeserializer};
+use serde::Deserialize;
+use winit::event::VirtualKeyCode;
+
+use crate::config::{InputMacro, KeyMap};
+
+/// Emulator functions that can be bound to a key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Help,
+    Menu,
+    SpriteEditor,
+    Debugger,
+    Inspector,
+    CyclePalette,
+    Stats,
+    SaveState,
+    LoadState,
+    Screenshot,
+    Record,
+    Pause,
+    FrameAdvance,
+    Fullscreen,
+    /// Held rather than pressed.
+    Rewind,
+    /// Held rather than pressed.
+    Turbo,
+    Quit,
+}
+
+impl Action {
+    pub fn description(&self) -> &'static str {
+        match self {
+            Action::Help => "Show or hide this help",
+            Action::Menu => "Pause and show the settings menu",
+            Action::SpriteEditor => "Show or hide the sprite editor",
+            Action::Debugger => "Show or hide the debugger",
+            Action::Inspector => "Show or hide the machine state",
+            Action::CyclePalette => "Switch to the next palette",
+            Action::Stats => "Print run statistics",
+            Action::SaveState => "Save the machine state",
+            Action::LoadState => "Load the saved machine state",
+            Action::Screenshot => "Save the display as a PNG image",
+            Action::Record => "Start or stop recording the display as an APNG",
+            Action::Pause => "Pause or resume emulation",
+            Action::FrameAdvance => "Run one frame while paused",
+            Action::Fullscreen => "Switch between a window and fullscreen",
+            Action::Rewind => "Rewind while held",
+            Action::Turbo => "Fast-forward while held",
+            Action::Quit => "Quit",
+        }
+    }
+}
+
+/// Keyboard bindings for the emulator functions and the CHIP-8 keypad.
+pub struct Hotkeys {
+    pub actions: Vec<(VirtualKeyCode, Action)>,
+    /// Keyboard key for each keypad key, indexed by keypad value.
+    pub keypad: [VirtualKeyCode; 16],
+    /// Extra keyboard keys for keypad keys, on top of `keypad`.
+    pub keymap: Vec<(VirtualKeyCode, u8)>,
+    pub macros: Vec<Macro>,
+}
+
+/// A keyboard key typing a sequence of keypad keys.
+#[derive(Debug, Clone)]
+pub struct Macro {
+    pub key: VirtualKeyCode,
+    pub keypad: Vec<u8>,
+    /// How long each keypad key is held, in 60Hz frames.
+    pub hold: u64,
+    /// Pause after each keypad key, in 60Hz frames.
+    pub gap: u64,
+    /// The macro as it appears in the settings.
+    pub settings: InputMacro,
+}
+
+impl Macro {
+    pub fn parse(settings: &InputMacro) -> Result<Self> {
+        let keypad = settings
+            .keypad
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .map(|c| {
+                c.to_digit(16)
+                    .map(|k| k as u8)
+                    .ok_or_else(|| anyhow!("Invalid keypad key {:?}", c))
+            })
+            .collect::<Result<Vec<u8>>>()?;
+        let frames = |ms: u64| (ms * 60).div_ceil(1000);
+        Ok(Self {
+            key: parse_key(&settings.key)?,
+            keypad,
+            hold: frames(settings.hold_ms).max(1),
+            gap: frames(settings.gap_ms),
+            settings: settings.clone(),
+        })
+    }
+}
+
+/// A macro being played back.
+pub struct Playback {
+    keypad: Vec<u8>,
+    hold: u64,
+    gap: u64,
+    /// Frame at which the macro started.
+    start: u64,
+}
+
+impl Playback {
+    pub fn new(m: &Macro, frame: u64) -> Self {
+        Self {
+            keypad: m.keypad.clone(),
+            hold: m.hold,
+            gap: m.gap,
+            start: frame,
+        }
+    }
+
+    /// The keypad key held down at `frame`, or `None` between keys.
+    pub fn key_at(&self, frame: u64) -> Option<u8> {
+        let period = self.hold + self.gap;
+        let elapsed = frame.saturating_sub(self.start);
+        let step = (elapsed / period) as usize;
+        let key = *self.keypad.get(step)?;
+        (elapsed % period < self.hold).then_some(key)
+    }
+
+    /// Whether all the keys have been pressed and released by `frame`.
+    pub fn is_done(&self, frame: u64) -> bool {
+        let period = self.hold + self.gap;
+        frame.saturating_sub(self.start) >= period * self.keypad.len() as u64
+    }
+}
+
+impl Default for Hotkeys {
+    fn default() -> Self {
+        Self {
+            actions: vec![
+                (VirtualKeyCode::F1, Action::Help),
+                (VirtualKeyCode::F2, Action::Screenshot),
+                (VirtualKeyCode::F3, Action::Record),
+                (VirtualKeyCode::F4, Action::SpriteEditor),
+                (VirtualKeyCode::F5, Action::SaveState),
+                (VirtualKeyCode::F9, Action::LoadState),
+                (VirtualKeyCode::P, Action::Pause),
+                (VirtualKeyCode::Period, Action::FrameAdvance),
+                (VirtualKeyCode::Back, Action::Rewind),
+                (VirtualKeyCode::Tab, Action::Turbo),
+                (VirtualKeyCode::F10, Action::Menu),
+                (VirtualKeyCode::F11, Action::Fullscreen),
+                (VirtualKeyCode::F12, Action::Inspector),
+                (VirtualKeyCode::F6, Action::CyclePalette),
+                (VirtualKeyCode::F7, Action::Stats),
+                (VirtualKeyCode::F8, Action::Debugger),
+                (VirtualKeyCode::Escape, Action::Quit),
+            ],
+            keypad: [
+                VirtualKeyCode::X,
+                VirtualKeyCode::Key1,
+                VirtualKeyCode::Key2,
+                VirtualKeyCode::Key3,
+                VirtualKeyCode::Q,
+                VirtualKeyCode::W,
+                VirtualKeyCode::E,
+                VirtualKeyCode::A,
+                VirtualKeyCode::S,
+                VirtualKeyCode::D,
+                VirtualKeyCode::Z,
+                VirtualKeyCode::C,
+                VirtualKeyCode::Key4,
+                VirtualKeyCode::R,
+                VirtualKeyCode::F,
+                VirtualKeyCode::V,
+            ],
+            keymap: Vec::new(),
+            macros: Vec::new(),
+        }
+    }
+}
+
+impl Hotkeys {
+    /// Names of the keyboard keys bound to the keypad, as stored in the settings.
+    pub fn keypad_names(&self) -> Vec<String> {
+        self.keypad.iter().map(|key| format!("{:?}", key)).collect()
+    }
+
+    /// Bind the keypad to the keyboard keys named in `names`.
+    pub fn set_keypad_names(&mut self, names: &[String]) -> Result<()> {
+        ensure!(
+            names.len() == 16,
+            "Expected 16 keypad keys, got {}",
+            names.len()
+        );
+        for (key, name) in self.keypad.iter_mut().zip(names) {
+            *key = parse_key(name)?;
+        }
+        Ok(())
+    }
+
+    /// The extra keypad keys, as stored in the settings.
+    pub fn keymap_settings(&self) -> KeyMap {
+        let keys = self
+            .keymap
+            .iter()
+            .map(|(key, k)| (format!("{:?}", key), format!("{:X}", k)))
+            .collect();
+        KeyMap(keys)
+    }
+
+    /// Replace the extra keypad keys with the ones in `keymap`. Keys bound to an emulator
+    /// function can't be used for the keypad.
+    pub fn set_keymap(&mut self, keymap: &KeyMap) -> Result<()> {
+        let mut bindings = Vec::new();
+        for (name, digit) in &keymap.0 {
+            let key = parse_key(name)?;
+            if let Some((_, action)) = self.actions.iter().find(|(k, _)| *k == key) {
+                bail!("{} is already bound to {:?}", name, action);
+            }
+            bindings.push((key, KeyMap::parse_digit(digit)?));
+        }
+        self.keymap = bindings;
+        Ok(())
+    }
+
+    /// Whether keypad key `k` is held down on the keyboard.
+    pub fn keypad_held(&self, k: u8, held: impl Fn(VirtualKeyCode) -> bool) -> bool {
+        held(self.keypad[k as usize])
+            || self
+                .keymap
+                .iter()
+                .any(|&(key, digit)| digit == k && held(key))
+    }
+
+    /// Replace the macros with the ones in the settings.
+    pub fn set_macros(&mut self, macros: &[InputMacro]) -> Result<()> {
+        self.macros = macros.iter().map(Macro::parse).collect::<Result<_>>()?;
+        Ok(())
+    }
+}
+
+/// Parse a winit `VirtualKeyCode` variant name.
+pub fn parse_key(name: &str) -> Result<VirtualKeyCode> {
+    let de: value::StrDeserializer<value::Error> = name.into_deserializer();
+    VirtualKeyCode::deserialize(de).map_err(|_| anyhow!("Unknown key {:?}", name))
+}
+
+/// Layout of the keys on the original COSMAC VIP keypad.
+const KEYPAD_LAYOUT: [[u8; 4]; 4] = [
+    [0x1, 0x2, 0x3, 0xC],
+    [0x4, 0x5, 0x6, 0xD],
+    [0x7, 0x8, 0x9, 0xE],
+    [0xA, 0x0, 0xB, 0xF],
+];
+
+/// A readable name for a keyboard key.
+pub fn key_name(key: VirtualKeyCode) -> String {
+    let name = format!("{:?}", key);
+    match name.strip_prefix("Key") {
+        Some(digit) => digit.to_string(),
+        None => name,
+    }
+}
+
+/// The F1 overlay listing the current bindings.
+pub struct Help {
+    pub open: bool,
+}
+
+impl Help {
+    pub fn new() -> Self {
+        Self { open: false }
+    }
+
+    pub fn ui(&mut self, ctx: &CtxRef, hotkeys: &Hotkeys) {
+        egui::Window::new("Help")
+            .open(&mut self.open)
+            .resizable(false)
+            .collapsible(false)
+            .show(ctx, |ui| {
+                ui.heading("Hotkeys");
+                egui::Grid::new("help_hotkeys")
+                    .striped(true)
+                    .show(ui, |ui| {
+                        for (key, action) in &hotkeys.actions {
+                            ui.monospace(key_name(*key));
+                            ui.label(action.description());
+                            ui.end_row();
+                        }
+                    });
+
+                ui.separator();
+                ui.heading("Keypad");
+                ui.label("CHIP-8 key → keyboard key");
+                egui::Grid::new("help_keypad").show(ui, |ui| {
+                    for row in KEYPAD_LAYOUT {
+                        for k in row {
+                            ui.monospace(format!(
+                                "{:X} → {}",
+                                k,
+                                key_name(hotkeys.keypad[k as usize])
+                            ));
+                        }
+                        ui.end_row();
+                    }
+                });
+
+                if !hotkeys.keymap.is_empty() {
+                    ui.label("Also:");
+                    egui::Grid::new("help_keymap").show(ui, |ui| {
+                        for (i, (key, k)) in hotkeys.keymap.iter().enumerate() {
+                            ui.monospace(format!("{:X} → {}", k, key_name(*key)));
+                            if i % 4 == 3 {
+                                ui.end_row();
+                            }
+                        }
+                    });
+                }
+
+                if !hotkeys.macros.is_empty() {
+                    ui.separator();
+                    ui.heading("Macros");
+                    egui::Grid::new("help_macros").show(ui, |ui| {
+                        for m in &hotkeys.macros {
+                            ui.monospace(key_name(m.key));
+                            let keypad: Vec<String> =
+                                m.keypad.iter().map(|k| format!("{:X}", k)).collect();
+                            ui.label(keypad.join(" "));
+                            ui.end_row();
+                        }
+                    });
+                }
+            });
+    }
+}
@@ -0,0 +1,290 @@
+//! Detection of busy-wait loops: a program spinning on the delay timer, the keypad or nothing at
+//! all, until the timers tick or a key is pressed.
+//!
+//! A loop is idle when an iteration, from one jump back to its head to the next, only reads and
+//! writes registers, and leaves them as they were. Nothing changes until the next timer tick, so
+//! the iterations up to it can be skipped by only counting their instructions and their time.
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Write as _};
+use std::time::Duration;
+
+use crate::cpu::Cpu;
+use crate::instruction::Instruction;
+use crate::interconnect::Interconnect;
+
+/// What an idle loop waits for, from what its instructions read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum IdleKind {
+    /// Nothing: the program is stuck, or done.
+    Spin,
+    /// The delay timer to reach some value.
+    DelayTimer,
+    /// A key press, possibly with a timeout on the delay timer.
+    Key,
+}
+
+impl IdleKind {
+    fn of(instruction: Instruction) -> Self {
+        use Instruction::*;
+        match instruction {
+            SkipKey(_) | SkipNoKey(_) | WaitKey(_) => IdleKind::Key,
+            GetDelay(_) => IdleKind::DelayTimer,
+            _ => IdleKind::Spin,
+        }
+    }
+}
+
+impl fmt::Display for IdleKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            IdleKind::Spin => "spin",
+            IdleKind::DelayTimer => "delay timer",
+            IdleKind::Key => "key",
+        })
+    }
+}
+
+/// Whether `instruction` only touches registers, so that running it again with the same
+/// registers, delay timer and keys does the same thing. `FX0A` also notes the key held down, but
+/// only the first time round.
+fn is_idle_safe(instruction: Instruction) -> bool {
+    use Instruction::*;
+    matches!(
+        instruction,
+        Jump(_)
+            | JumpOffset(_)
+            | SkipEq(..)
+            | SkipNe(..)
+            | SkipEqReg(..)
+            | SkipNeReg(..)
+            | SkipKey(_)
+            | SkipNoKey(_)
+            | Load(..)
+            | Add(..)
+            | Move(..)
+            | Or(..)
+            | And(..)
+            | Xor(..)
+            | AddReg(..)
+            | Sub(..)
+            | ShiftRight(..)
+            | SubReverse(..)
+            | ShiftLeft(..)
+            | LoadI(_)
+            | GetDelay(_)
+            | WaitKey(_)
+            | AddI(_)
+            | Font(_)
+            | BigFont(_)
+    )
+}
+
+/// Everything an idle-safe instruction reads or writes.
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct State {
+    v: [u8; 16],
+    i: u16,
+    delay_timer: u8,
+    keys: [bool; 16],
+}
+
+impl State {
+    fn of(cpu: &Cpu, interconnect: &Interconnect) -> Self {
+        Self {
+            v: std::array::from_fn(|x| cpu.v(x as u8)),
+            i: cpu.i(),
+            delay_timer: interconnect.delay_timer,
+            keys: interconnect.keys,
+        }
+    }
+}
+
+/// An iteration of a loop under way, since the last jump back to its head.
+struct Iteration {
+    head: u16,
+    /// The state at the head.
+    state: State,
+    instructions: u64,
+    time: Duration,
+    idle_safe: bool,
+    kind: IdleKind,
+}
+
+/// An idle iteration that has just ended: running it again would end in the same state.
+#[derive(Clone, Copy)]
+pub struct Repeat {
+    pub head: u16,
+    pub instructions: u64,
+    pub time: Duration,
+}
+
+/// Idle iterations skipped by `Chip8::skip_idle`.
+#[derive(Debug, Clone, Copy)]
+pub struct Skipped {
+    pub instructions: u64,
+    pub time: Duration,
+}
+
+/// What was seen of an idle loop.
+#[derive(Debug, Clone, Copy)]
+pub struct IdleLoop {
+    pub kind: IdleKind,
+    /// Idle iterations run, not counting skipped ones.
+    pub iterations: u64,
+    /// Instructions spent in idle iterations, skipped ones included.
+    pub instructions: u64,
+    pub skipped: u64,
+}
+
+/// Follows the jumps back of a running program to find its idle loops.
+pub struct IdleLoops {
+    /// Whether the machine may skip idle iterations, see `Chip8::skip_idle`.
+    pub skip: bool,
+    current: Option<Iteration>,
+    /// Where the last instruction jumped back to, until the state there is checked.
+    jumped_back: Option<u16>,
+    repeat: Option<Repeat>,
+    loops: BTreeMap<u16, IdleLoop>,
+    instructions: u64,
+}
+
+impl IdleLoops {
+    pub fn new(skip: bool) -> Self {
+        Self {
+            skip,
+            current: None,
+            jumped_back: None,
+            repeat: None,
+            loops: BTreeMap::new(),
+            instructions: 0,
+        }
+    }
+
+    /// Record that `instruction` ran at `pc` and left the PC at `next`.
+    pub fn executed(&mut self, instruction: Instruction, pc: u16, next: u16) {
+        self.instructions += 1;
+        self.repeat = None;
+        if let Some(iteration) = &mut self.current {
+            iteration.instructions += 1;
+            iteration.idle_safe &= is_idle_safe(instruction);
+            iteration.kind = iteration.kind.max(IdleKind::of(instruction));
+        }
+        if next <= pc {
+            self.jumped_back = Some(next);
+        }
+    }
+
+    /// Count `elapsed` time in the current iteration.
+    pub fn elapse(&mut self, elapsed: Duration) {
+        if let Some(iteration) = &mut self.current {
+            iteration.time += elapsed;
+        }
+    }
+
+    /// Called between instructions: if the last one jumped back, end the current iteration and
+    /// start the next one from the state of `cpu` and `interconnect`.
+    pub fn check(&mut self, cpu: &Cpu, interconnect: &Interconnect) {
+        let head = match self.jumped_back.take() {
+            Some(head) => head,
+            None => return,
+        };
+        let state = State::of(cpu, interconnect);
+        match self.current.take() {
+            Some(iteration)
+                if iteration.head == head && iteration.idle_safe && iteration.state == state =>
+            {
+                let seen = self.loops.entry(head).or_insert(IdleLoop {
+                    kind: iteration.kind,
+                    iterations: 0,
+                    instructions: 0,
+                    skipped: 0,
+                });
+                seen.kind = seen.kind.max(iteration.kind);
+                seen.iterations += 1;
+                seen.instructions += iteration.instructions;
+                self.repeat = Some(Repeat {
+                    head,
+                    instructions: iteration.instructions,
+                    time: iteration.time,
+                });
+            }
+            _ => self.repeat = None,
+        }
+        self.current = Some(Iteration {
+            head,
+            state,
+            instructions: 0,
+            time: Duration::ZERO,
+            idle_safe: true,
+            kind: IdleKind::Spin,
+        });
+    }
+
+    /// The idle iteration that has just ended, if the last instruction ended one.
+    pub fn repeat(&self) -> Option<Repeat> {
+        self.repeat
+    }
+
+    /// Record that `instructions` of the loop at `head` were skipped rather than run.
+    pub fn skipped(&mut self, head: u16, instructions: u64) {
+        self.instructions += instructions;
+        if let Some(seen) = self.loops.get_mut(&head) {
+            seen.instructions += instructions;
+            seen.skipped += instructions;
+        }
+    }
+
+    /// Forget the iteration under way, after the machine was changed from outside.
+    pub fn interrupt(&mut self) {
+        self.current = None;
+        self.jumped_back = None;
+        self.repeat = None;
+    }
+
+    /// The idle loops seen so far, by address of their head.
+    pub fn loops(&self) -> &BTreeMap<u16, IdleLoop> {
+        &self.loops
+    }
+
+    /// The share of instructions spent in idle loops, and each loop with what it waits for.
+    pub fn report(&self) -> String {
+        let idle: u64 = self.loops.values().map(|l| l.instructions).sum();
+        let skipped: u64 = self.loops.values().map(|l| l.skipped).sum();
+        let percent = |n: u64| 100.0 * n as f64 / self.instructions.max(1) as f64;
+
+        let mut out = String::new();
+        writeln!(
+            out,
+            "{} of {} instructions ({:.2}%) in idle loops, {} ({:.2}%) skipped",
+            idle,
+            self.instructions,
+            percent(idle),
+            skipped,
+            percent(skipped)
+        )
+        .unwrap();
+        if self.loops.is_empty() {
+            return out;
+        }
+        writeln!(
+            out,
+            "{:>7}  {:<12} {:>12} {:>14} {:>14}",
+            "head", "waits for", "iterations", "instructions", "skipped"
+        )
+        .unwrap();
+        for (head, seen) in &self.loops {
+            writeln!(
+                out,
+                "{:#7x}  {:<12} {:>12} {:>14} {:>14}",
+                head,
+                seen.kind.to_string(),
+                seen.iterations,
+                seen.instructions,
+                seen.skipped
+            )
+            .unwrap();
+        }
+        out
+    }
+}
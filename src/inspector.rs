@@ -0,0 +1,98 @@
+use egui::{CtxRef, Ui};
+
+use chip8rs::instruction::Instruction;
+
+use crate::watch::Symbols;
+use crate::Chip8;
+
+/// Number of instructions shown around the PC in the inspector's disassembly.
+const DISASSEMBLY_LINES: u16 = 16;
+
+/// The F12 overlay following the machine state as it runs.
+pub struct Inspector {
+    pub open: bool,
+}
+
+impl Inspector {
+    pub fn new() -> Self {
+        Self { open: false }
+    }
+
+    pub fn ui(&mut self, ctx: &CtxRef, chip8: &Chip8, symbols: &Symbols) {
+        egui::Window::new("Machine state")
+            .open(&mut self.open)
+            .resizable(false)
+            .show(ctx, |ui| {
+                registers(ui, chip8, symbols);
+                ui.separator();
+                disassembly(ui, chip8, symbols, DISASSEMBLY_LINES);
+            });
+    }
+}
+
+/// Show the PC, stack pointer, I, timers, V registers and stack of `chip8`.
+pub fn registers(ui: &mut Ui, chip8: &Chip8, symbols: &Symbols) {
+    let cpu = &chip8.cpu;
+    let io = &chip8.interconnect;
+    egui::Grid::new("registers_pc").show(ui, |ui| {
+        ui.label("PC");
+        ui.monospace(describe(cpu.pc(), symbols));
+        ui.end_row();
+        ui.label("SP");
+        ui.monospace(cpu.stack().len().to_string());
+        ui.end_row();
+        ui.label("I");
+        ui.monospace(format!("{:#05x}", cpu.i()));
+        ui.end_row();
+        ui.label("DT / ST");
+        ui.monospace(format!("{:02X} / {:02X}", io.delay_timer, io.sound_timer));
+        ui.end_row();
+    });
+    egui::Grid::new("registers_v").show(ui, |ui| {
+        for x in 0..16 {
+            ui.monospace(format!("V{:X} {:02X}", x, cpu.v(x)));
+            if x % 4 == 3 {
+                ui.end_row();
+            }
+        }
+    });
+    let stack: Vec<String> = cpu
+        .stack()
+        .iter()
+        .map(|&addr| describe(addr, symbols))
+        .collect();
+    ui.monospace(format!("Stack: [{}]", stack.join(", ")));
+}
+
+/// Show `lines` instructions around the PC, the next one marked with `>`. Instructions are
+/// assumed to be 2-byte aligned with the PC, which data in the middle of code can make wrong.
+pub fn disassembly(ui: &mut Ui, chip8: &Chip8, symbols: &Symbols, lines: u16) {
+    let pc = chip8.cpu.pc();
+    let start = pc.saturating_sub(lines / 4 * 2);
+    for addr in (start..=u16::MAX).step_by(2).take(lines as usize) {
+        let opcode = match chip8.opcode_at(addr) {
+            Some(opcode) => opcode,
+            None => break,
+        };
+        if let Some(name) = symbols.name_of(addr) {
+            ui.monospace(format!("{}:", name));
+        }
+        ui.monospace(format!(
+            "{} {:03X}  {:04X}  {}",
+            if addr == pc { ">" } else { " " },
+            addr,
+            opcode,
+            Instruction::decode(opcode)
+                .map(|instruction| instruction.to_string())
+                .unwrap_or_default()
+        ));
+    }
+}
+
+/// `addr` in hex, followed by the name of the symbol there if there is one.
+pub fn describe(addr: u16, symbols: &Symbols) -> String {
+    match symbols.name_of(addr) {
+        Some(name) => format!("{:#05x} ({})", addr, name),
+        None => format!("{:#05x}", addr),
+    }
+}
@@ -0,0 +1,498 @@
+//! Decoding of opcodes into instructions.
+//!
+//! `Instruction::decode` knows every CHIP-8, SUPER-CHIP, XO-CHIP and Mega-Chip instruction.
+//! Whether an instruction is available depends on the machine, see `Instruction::is_xochip` and
+//! `Instruction::is_megachip`. Instructions display as Octo source, with literal addresses.
+//!
+//! ```
+//! use chip8rs::instruction::Instruction;
+//!
+//! let instruction = Instruction::decode(0x8AB4).unwrap();
+//! assert_eq!(instruction, Instruction::AddReg(0xA, 0xB));
+//! assert_eq!(instruction.to_string(), "va += vb");
+//! ```
+
+use std::fmt;
+use std::sync::OnceLock;
+
+use crate::error::Chip8Error;
+
+/// A decoded instruction. `x` and `y` are register numbers, `addr` 12-bit addresses, and `n`
+/// or `nn` immediate values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    /// `0NNN`: call a machine code routine of the original interpreter. Unsupported.
+    Sys(u16),
+    /// `0010`: leave Mega-Chip mode.
+    MegaOff,
+    /// `0011`: enter Mega-Chip mode, with its 256x192 color display.
+    MegaOn,
+    /// `00BN`: scroll the display up N lines (Mega-Chip).
+    MegaScrollUp(u8),
+    /// `01NN NNNN`: load the 24-bit address made of NN and the next two bytes into I (Mega-Chip).
+    LoadHigh(u8),
+    /// `02NN`: load NN ARGB colors at I into the palette, from color 1 (Mega-Chip).
+    LoadPalette(u8),
+    /// `03NN`: set the width of sprites, 256 if NN is 0 (Mega-Chip).
+    SpriteWidth(u8),
+    /// `04NN`: set the height of sprites, 256 if NN is 0 (Mega-Chip).
+    SpriteHeight(u8),
+    /// `05NN`: set the opacity of the display (Mega-Chip).
+    Alpha(u8),
+    /// `060N`: play the digitized sound at I, looping unless N is 0 (Mega-Chip).
+    PlaySound(u8),
+    /// `0700`: stop the digitized sound (Mega-Chip).
+    StopSound,
+    /// `080N`: set how sprites blend with the display (Mega-Chip).
+    BlendMode(u8),
+    /// `09NN`: set the color whose pixels detect collisions (Mega-Chip).
+    CollisionColor(u8),
+    /// `00CN`: scroll the display down N lines (SUPER-CHIP).
+    ScrollDown(u8),
+    /// `00DN`: scroll the display up N lines (XO-CHIP).
+    ScrollUp(u8),
+    /// `00E0`
+    Clear,
+    /// `00EE`
+    Return,
+    /// `00FB`: scroll the display right 4 pixels (SUPER-CHIP).
+    ScrollRight,
+    /// `00FC`: scroll the display left 4 pixels (SUPER-CHIP).
+    ScrollLeft,
+    /// `00FD`: stop the program (SUPER-CHIP).
+    Exit,
+    /// `00FE`: switch to low resolution (SUPER-CHIP).
+    Lores,
+    /// `00FF`: switch to high resolution (SUPER-CHIP).
+    Hires,
+    /// `1NNN`
+    Jump(u16),
+    /// `2NNN`
+    Call(u16),
+    /// `3XNN`: skip the next instruction if VX == NN.
+    SkipEq(u8, u8),
+    /// `4XNN`: skip the next instruction if VX != NN.
+    SkipNe(u8, u8),
+    /// `5XY0`: skip the next instruction if VX == VY.
+    SkipEqReg(u8, u8),
+    /// `5XY2`: save VX to VY at I (XO-CHIP).
+    SaveRange(u8, u8),
+    /// `5XY3`: load VX to VY from I (XO-CHIP).
+    LoadRange(u8, u8),
+    /// `6XNN`
+    Load(u8, u8),
+    /// `7XNN`, without carry.
+    Add(u8, u8),
+    /// `8XY0`
+    Move(u8, u8),
+    /// `8XY1`
+    Or(u8, u8),
+    /// `8XY2`
+    And(u8, u8),
+    /// `8XY3`
+    Xor(u8, u8),
+    /// `8XY4`, with carry.
+    AddReg(u8, u8),
+    /// `8XY5`: VX -= VY, with borrow.
+    Sub(u8, u8),
+    /// `8XY6`
+    ShiftRight(u8, u8),
+    /// `8XY7`: VX = VY - VX, with borrow.
+    SubReverse(u8, u8),
+    /// `8XYE`
+    ShiftLeft(u8, u8),
+    /// `9XY0`: skip the next instruction if VX != VY.
+    SkipNeReg(u8, u8),
+    /// `ANNN`
+    LoadI(u16),
+    /// `BNNN`: jump to NNN plus V0, or VX with the `jump_vx` quirk.
+    JumpOffset(u16),
+    /// `CXNN`: VX = a random number AND NN.
+    Random(u8, u8),
+    /// `DXYN`: draw the N-line sprite at I at (VX, VY), or a 16x16 one if N is 0.
+    Draw(u8, u8, u8),
+    /// `EX9E`: skip the next instruction if key VX is pressed.
+    SkipKey(u8),
+    /// `EXA1`: skip the next instruction if key VX isn't pressed.
+    SkipNoKey(u8),
+    /// `F000 NNNN`: load the 16-bit address in the next two bytes into I (XO-CHIP).
+    LoadLong,
+    /// `FN01`: select the planes to draw on (XO-CHIP).
+    Plane(u8),
+    /// `F002`: load the audio pattern at I (XO-CHIP).
+    Audio,
+    /// `FX07`
+    GetDelay(u8),
+    /// `FX0A`: wait for a key to be pressed and released, and store it in VX.
+    WaitKey(u8),
+    /// `FX15`
+    SetDelay(u8),
+    /// `FX18`
+    SetSound(u8),
+    /// `FX1E`: add VX to I, setting VF on overflow past 0xFFF with the `add_i_vf` quirk.
+    AddI(u8),
+    /// `FX29`: point I at the small font glyph for VX.
+    Font(u8),
+    /// `FX30`: point I at the big font glyph for VX (SUPER-CHIP).
+    BigFont(u8),
+    /// `FX33`: store the decimal digits of VX at I.
+    Bcd(u8),
+    /// `FX3A`: set the audio pitch (XO-CHIP).
+    Pitch(u8),
+    /// `FX55`: store V0 to VX at I.
+    Store(u8),
+    /// `FX65`: load V0 to VX from I.
+    Restore(u8),
+    /// `FX75`: save V0 to VX in the RPL flags (SUPER-CHIP).
+    SaveFlags(u8),
+    /// `FX85`: load V0 to VX from the RPL flags (SUPER-CHIP).
+    LoadFlags(u8),
+}
+
+impl Instruction {
+    /// Decode `opcode`, failing if it isn't an instruction of any of the supported machines.
+    pub fn decode(opcode: u16) -> Result<Self, Chip8Error> {
+        decoded()[opcode as usize].ok_or(Chip8Error::UnknownOpcode(opcode))
+    }
+
+    /// Whether this instruction only exists with the XO-CHIP extensions.
+    pub fn is_xochip(&self) -> bool {
+        use Instruction::*;
+
+        matches!(
+            self,
+            ScrollUp(_) | SaveRange(..) | LoadRange(..) | LoadLong | Plane(_) | Audio | Pitch(_)
+        )
+    }
+
+    /// Whether this instruction skips the next one on some condition, `if ... then` in Octo.
+    pub fn is_skip(&self) -> bool {
+        use Instruction::*;
+
+        matches!(
+            self,
+            SkipEq(..) | SkipNe(..) | SkipEqReg(..) | SkipNeReg(..) | SkipKey(_) | SkipNoKey(_)
+        )
+    }
+
+    /// Whether this instruction only exists on Mega-Chip machines, which otherwise run it as
+    /// `Sys`.
+    pub fn is_megachip(&self) -> bool {
+        use Instruction::*;
+
+        matches!(
+            self,
+            MegaOff
+                | MegaOn
+                | MegaScrollUp(_)
+                | LoadHigh(_)
+                | LoadPalette(_)
+                | SpriteWidth(_)
+                | SpriteHeight(_)
+                | Alpha(_)
+                | PlaySound(_)
+                | StopSound
+                | BlendMode(_)
+                | CollisionColor(_)
+        )
+    }
+
+    /// The opcode pattern of this kind of instruction, e.g. `8XY4`.
+    pub fn pattern(&self) -> &'static str {
+        use Instruction::*;
+
+        match self {
+            Sys(_) => "0NNN",
+            MegaOff => "0010",
+            MegaOn => "0011",
+            MegaScrollUp(_) => "00BN",
+            LoadHigh(_) => "01NN",
+            LoadPalette(_) => "02NN",
+            SpriteWidth(_) => "03NN",
+            SpriteHeight(_) => "04NN",
+            Alpha(_) => "05NN",
+            PlaySound(_) => "060N",
+            StopSound => "0700",
+            BlendMode(_) => "080N",
+            CollisionColor(_) => "09NN",
+            ScrollDown(_) => "00CN",
+            ScrollUp(_) => "00DN",
+            Clear => "00E0",
+            Return => "00EE",
+            ScrollRight => "00FB",
+            ScrollLeft => "00FC",
+            Exit => "00FD",
+            Lores => "00FE",
+            Hires => "00FF",
+            Jump(_) => "1NNN",
+            Call(_) => "2NNN",
+            SkipEq(..) => "3XNN",
+            SkipNe(..) => "4XNN",
+            SkipEqReg(..) => "5XY0",
+            SaveRange(..) => "5XY2",
+            LoadRange(..) => "5XY3",
+            Load(..) => "6XNN",
+            Add(..) => "7XNN",
+            Move(..) => "8XY0",
+            Or(..) => "8XY1",
+            And(..) => "8XY2",
+            Xor(..) => "8XY3",
+            AddReg(..) => "8XY4",
+            Sub(..) => "8XY5",
+            ShiftRight(..) => "8XY6",
+            SubReverse(..) => "8XY7",
+            ShiftLeft(..) => "8XYE",
+            SkipNeReg(..) => "9XY0",
+            LoadI(_) => "ANNN",
+            JumpOffset(_) => "BNNN",
+            Random(..) => "CXNN",
+            Draw(..) => "DXYN",
+            SkipKey(_) => "EX9E",
+            SkipNoKey(_) => "EXA1",
+            LoadLong => "F000",
+            Plane(_) => "FN01",
+            Audio => "F002",
+            GetDelay(_) => "FX07",
+            WaitKey(_) => "FX0A",
+            SetDelay(_) => "FX15",
+            SetSound(_) => "FX18",
+            AddI(_) => "FX1E",
+            Font(_) => "FX29",
+            BigFont(_) => "FX30",
+            Bcd(_) => "FX33",
+            Pitch(_) => "FX3A",
+            Store(_) => "FX55",
+            Restore(_) => "FX65",
+            SaveFlags(_) => "FX75",
+            LoadFlags(_) => "FX85",
+        }
+    }
+}
+
+/// Every opcode, decoded once on first use, so that decoding is a single lookup. `None` marks
+/// unknown opcodes.
+fn decoded() -> &'static [Option<Instruction>] {
+    static DECODED: OnceLock<Vec<Option<Instruction>>> = OnceLock::new();
+    DECODED.get_or_init(|| {
+        (0..=u16::MAX)
+            .map(|opcode| DECODERS[(opcode >> 12) as usize](opcode))
+            .collect()
+    })
+}
+
+/// Decoders of the opcode families, indexed by the first nibble of the opcode.
+const DECODERS: [fn(u16) -> Option<Instruction>; 16] = [
+    decode_0,
+    |opcode| Some(Instruction::Jump(nnn(opcode))),
+    |opcode| Some(Instruction::Call(nnn(opcode))),
+    |opcode| Some(Instruction::SkipEq(x(opcode), nn(opcode))),
+    |opcode| Some(Instruction::SkipNe(x(opcode), nn(opcode))),
+    decode_5,
+    |opcode| Some(Instruction::Load(x(opcode), nn(opcode))),
+    |opcode| Some(Instruction::Add(x(opcode), nn(opcode))),
+    decode_8,
+    |opcode| (n(opcode) == 0).then(|| Instruction::SkipNeReg(x(opcode), y(opcode))),
+    |opcode| Some(Instruction::LoadI(nnn(opcode))),
+    |opcode| Some(Instruction::JumpOffset(nnn(opcode))),
+    |opcode| Some(Instruction::Random(x(opcode), nn(opcode))),
+    |opcode| Some(Instruction::Draw(x(opcode), y(opcode), n(opcode))),
+    decode_e,
+    decode_f,
+];
+
+fn x(opcode: u16) -> u8 {
+    ((opcode & 0x0F00) >> 8) as u8
+}
+
+fn y(opcode: u16) -> u8 {
+    ((opcode & 0x00F0) >> 4) as u8
+}
+
+fn n(opcode: u16) -> u8 {
+    (opcode & 0x000F) as u8
+}
+
+fn nn(opcode: u16) -> u8 {
+    (opcode & 0x00FF) as u8
+}
+
+fn nnn(opcode: u16) -> u16 {
+    opcode & 0x0FFF
+}
+
+/// `0NNN`: the SUPER-CHIP display instructions, the Mega-Chip ones, and machine code calls.
+fn decode_0(opcode: u16) -> Option<Instruction> {
+    use Instruction::*;
+
+    let instruction = match opcode {
+        0x0010 => MegaOff,
+        0x0011 => MegaOn,
+        0x00B0..=0x00BF => MegaScrollUp(n(opcode)),
+        0x0100..=0x01FF => LoadHigh(nn(opcode)),
+        0x0200..=0x02FF => LoadPalette(nn(opcode)),
+        0x0300..=0x03FF => SpriteWidth(nn(opcode)),
+        0x0400..=0x04FF => SpriteHeight(nn(opcode)),
+        0x0500..=0x05FF => Alpha(nn(opcode)),
+        0x0600..=0x060F => PlaySound(n(opcode)),
+        0x0700 => StopSound,
+        0x0800..=0x080F => BlendMode(n(opcode)),
+        0x0900..=0x09FF => CollisionColor(nn(opcode)),
+        0x00C0..=0x00CF => ScrollDown(n(opcode)),
+        0x00D0..=0x00DF => ScrollUp(n(opcode)),
+        0x00E0 => Clear,
+        0x00EE => Return,
+        0x00FB => ScrollRight,
+        0x00FC => ScrollLeft,
+        0x00FD => Exit,
+        0x00FE => Lores,
+        0x00FF => Hires,
+        _ => Sys(nnn(opcode)),
+    };
+    Some(instruction)
+}
+
+/// `5XYN`: register comparison, and the XO-CHIP register range loads and stores.
+fn decode_5(opcode: u16) -> Option<Instruction> {
+    use Instruction::*;
+
+    let (x, y) = (x(opcode), y(opcode));
+    match n(opcode) {
+        0x0 => Some(SkipEqReg(x, y)),
+        0x2 => Some(SaveRange(x, y)),
+        0x3 => Some(LoadRange(x, y)),
+        _ => None,
+    }
+}
+
+/// `8XYN`: arithmetic and logic between registers.
+fn decode_8(opcode: u16) -> Option<Instruction> {
+    use Instruction::*;
+
+    let (x, y) = (x(opcode), y(opcode));
+    match n(opcode) {
+        0x0 => Some(Move(x, y)),
+        0x1 => Some(Or(x, y)),
+        0x2 => Some(And(x, y)),
+        0x3 => Some(Xor(x, y)),
+        0x4 => Some(AddReg(x, y)),
+        0x5 => Some(Sub(x, y)),
+        0x6 => Some(ShiftRight(x, y)),
+        0x7 => Some(SubReverse(x, y)),
+        0xE => Some(ShiftLeft(x, y)),
+        _ => None,
+    }
+}
+
+/// `EXNN`: key skips.
+fn decode_e(opcode: u16) -> Option<Instruction> {
+    use Instruction::*;
+
+    match nn(opcode) {
+        0x9E => Some(SkipKey(x(opcode))),
+        0xA1 => Some(SkipNoKey(x(opcode))),
+        _ => None,
+    }
+}
+
+/// `FXNN`: timers, keys, I, memory, and the XO-CHIP planes and audio.
+fn decode_f(opcode: u16) -> Option<Instruction> {
+    use Instruction::*;
+
+    let x = x(opcode);
+    let instruction = match nn(opcode) {
+        0x00 if x == 0 => LoadLong,
+        0x01 => Plane(x),
+        0x02 if x == 0 => Audio,
+        0x07 => GetDelay(x),
+        0x0A => WaitKey(x),
+        0x15 => SetDelay(x),
+        0x18 => SetSound(x),
+        0x1E => AddI(x),
+        0x29 => Font(x),
+        0x30 => BigFont(x),
+        0x33 => Bcd(x),
+        0x3A => Pitch(x),
+        0x55 => Store(x),
+        0x65 => Restore(x),
+        0x75 => SaveFlags(x),
+        0x85 => LoadFlags(x),
+        _ => return None,
+    };
+    Some(instruction)
+}
+
+/// Octo source for the instruction. `Sys` and the Mega-Chip instructions have no Octo syntax and
+/// show up as the raw bytes of their opcode, and `LoadLong` and `LoadHigh` lack the address that
+/// follows them.
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use Instruction::*;
+
+        match *self {
+            Sys(addr) => write!(f, "0x{:02X} 0x{:02X}", addr >> 8, addr & 0xFF),
+            MegaOff => write!(f, "0x00 0x10"),
+            MegaOn => write!(f, "0x00 0x11"),
+            MegaScrollUp(n) => write!(f, "0x00 0x{:02X}", 0xB0 | n),
+            LoadHigh(nn) => write!(f, "0x01 0x{:02X}", nn),
+            LoadPalette(nn) => write!(f, "0x02 0x{:02X}", nn),
+            SpriteWidth(nn) => write!(f, "0x03 0x{:02X}", nn),
+            SpriteHeight(nn) => write!(f, "0x04 0x{:02X}", nn),
+            Alpha(nn) => write!(f, "0x05 0x{:02X}", nn),
+            PlaySound(n) => write!(f, "0x06 0x{:02X}", n),
+            StopSound => write!(f, "0x07 0x00"),
+            BlendMode(n) => write!(f, "0x08 0x{:02X}", n),
+            CollisionColor(nn) => write!(f, "0x09 0x{:02X}", nn),
+            ScrollDown(n) => write!(f, "scroll-down {}", n),
+            ScrollUp(n) => write!(f, "scroll-up {}", n),
+            Clear => write!(f, "clear"),
+            Return => write!(f, "return"),
+            ScrollRight => write!(f, "scroll-right"),
+            ScrollLeft => write!(f, "scroll-left"),
+            Exit => write!(f, "exit"),
+            Lores => write!(f, "lores"),
+            Hires => write!(f, "hires"),
+            Jump(addr) => write!(f, "jump 0x{:03X}", addr),
+            Call(addr) => write!(f, ":call 0x{:03X}", addr),
+            // Octo spells conditional skips as the condition for running the next instruction.
+            SkipEq(x, nn) => write!(f, "if v{:x} != 0x{:02X} then", x, nn),
+            SkipNe(x, nn) => write!(f, "if v{:x} == 0x{:02X} then", x, nn),
+            SkipEqReg(x, y) => write!(f, "if v{:x} != v{:x} then", x, y),
+            SaveRange(x, y) => write!(f, "save v{:x} - v{:x}", x, y),
+            LoadRange(x, y) => write!(f, "load v{:x} - v{:x}", x, y),
+            Load(x, nn) => write!(f, "v{:x} := 0x{:02X}", x, nn),
+            Add(x, nn) => write!(f, "v{:x} += 0x{:02X}", x, nn),
+            Move(x, y) => write!(f, "v{:x} := v{:x}", x, y),
+            Or(x, y) => write!(f, "v{:x} |= v{:x}", x, y),
+            And(x, y) => write!(f, "v{:x} &= v{:x}", x, y),
+            Xor(x, y) => write!(f, "v{:x} ^= v{:x}", x, y),
+            AddReg(x, y) => write!(f, "v{:x} += v{:x}", x, y),
+            Sub(x, y) => write!(f, "v{:x} -= v{:x}", x, y),
+            ShiftRight(x, y) => write!(f, "v{:x} >>= v{:x}", x, y),
+            SubReverse(x, y) => write!(f, "v{:x} =- v{:x}", x, y),
+            ShiftLeft(x, y) => write!(f, "v{:x} <<= v{:x}", x, y),
+            SkipNeReg(x, y) => write!(f, "if v{:x} == v{:x} then", x, y),
+            LoadI(addr) => write!(f, "i := 0x{:03X}", addr),
+            JumpOffset(addr) => write!(f, "jump0 0x{:03X}", addr),
+            Random(x, nn) => write!(f, "v{:x} := random 0x{:02X}", x, nn),
+            Draw(x, y, n) => write!(f, "sprite v{:x} v{:x} 0x{:X}", x, y, n),
+            SkipKey(x) => write!(f, "if v{:x} -key then", x),
+            SkipNoKey(x) => write!(f, "if v{:x} key then", x),
+            LoadLong => write!(f, "i := long"),
+            Plane(n) => write!(f, "plane {}", n),
+            Audio => write!(f, "audio"),
+            GetDelay(x) => write!(f, "v{:x} := delay", x),
+            WaitKey(x) => write!(f, "v{:x} := key", x),
+            SetDelay(x) => write!(f, "delay := v{:x}", x),
+            SetSound(x) => write!(f, "buzzer := v{:x}", x),
+            AddI(x) => write!(f, "i += v{:x}", x),
+            Font(x) => write!(f, "i := hex v{:x}", x),
+            BigFont(x) => write!(f, "i := bighex v{:x}", x),
+            Bcd(x) => write!(f, "bcd v{:x}", x),
+            Pitch(x) => write!(f, "pitch := v{:x}", x),
+            Store(x) => write!(f, "save v{:x}", x),
+            Restore(x) => write!(f, "load v{:x}", x),
+            SaveFlags(x) => write!(f, "saveflags v{:x}", x),
+            LoadFlags(x) => write!(f, "loadflags v{:x}", x),
+        }
+    }
+}
@@ -1,5 +1,14 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::error::Chip8Error;
 use crate::gfx::Gfx;
+use crate::metrics::Metrics;
 use crate::ram::Ram;
+use crate::coverage::Coverage;
+
+/// Time between two updates of the delay and sound timers, which count down at 60Hz.
+pub const TIMER_PERIOD: Duration = Duration::from_nanos(1_000_000_000 / 60);
 
 /// Main "Bus" of the Chip-8 machine.
 ///
@@ -9,10 +18,32 @@ pub struct Interconnect {
     pub gfx: Gfx,
     pub delay_timer: u8,
     pub sound_timer: u8,
+    /// Time elapsed since the timers were last updated.
+    pub timer_clock: Duration,
     pub keys: [bool; 16],
+    /// The XO-CHIP audio pattern loaded by `F002`, if any: 128 1-bit samples.
+    pub audio_pattern: Option<[u8; 16]>,
+    /// The XO-CHIP pitch register, setting the playback rate of `audio_pattern`.
+    pub pitch: u8,
+    pub metrics: Arc<Metrics>,
+    pub coverage: Option<Coverage>,
 }
 
 impl Interconnect {
+    /// Let `elapsed` time pass, updating the timers and the display's afterglow once per 60th
+    /// of a second. Return how many times they were updated.
+    pub fn advance(&mut self, elapsed: Duration) -> u32 {
+        self.timer_clock += elapsed;
+        let mut ticks = 0;
+        while self.timer_clock >= TIMER_PERIOD {
+            self.timer_clock -= TIMER_PERIOD;
+            self.tick();
+            self.gfx.end_frame();
+            ticks += 1;
+        }
+        ticks
+    }
+
     pub fn tick(&mut self) {
         if self.delay_timer > 0 {
             self.delay_timer -= 1;
@@ -23,13 +54,82 @@ impl Interconnect {
     }
 
     /// Fetch the 2-byte long instruction at address `pc`.
-    pub fn fetch_opcode(&self, pc: u16) -> u16 {
-        ((self.ram[pc] as u16) << 8) | (self.ram[pc + 1] as u16)
+    pub fn fetch_opcode(&self, pc: u16) -> Result<u16, Chip8Error> {
+        let pc = pc as usize;
+        Ok(((self.ram.read(pc)? as u16) << 8) | (self.ram.read(pc + 1)? as u16))
+    }
+
+    /// Record that `len` bytes at `addr` were read as data, if coverage is being tracked.
+    pub fn record_read(&mut self, addr: u16, len: u16) {
+        if let Some(coverage) = &mut self.coverage {
+            coverage.mark_read(addr, len);
+        }
+    }
+
+    /// Record that `len` bytes at `addr` were written, if coverage is being tracked.
+    pub fn record_write(&mut self, addr: u16, len: u16) {
+        if let Some(coverage) = &mut self.coverage {
+            coverage.mark_written(addr, len);
+        }
+    }
+
+    /// Draw sprite located at address `addr` at coordinates (vx, vy) with height `n`, wrapping it
+    /// around the edges of the display if `wrap` is `true`
+    pub fn draw_sprite(
+        &mut self,
+        addr: u16,
+        vx: u8,
+        vy: u8,
+        n: u8,
+        wrap: bool,
+    ) -> Result<bool, Chip8Error> {
+        self.metrics.inc_draw_calls();
+        let len = n as u16 * self.gfx.plane_count() as u16;
+        self.record_read(addr, len);
+        let collision = self
+            .gfx
+            .draw_sprite(vx, vy, n, &self.ram.get_sprite(addr, len)?, wrap);
+        if collision {
+            self.metrics.inc_collisions();
+        }
+        Ok(collision)
     }
 
-    /// Draw sprite located at address `addr` at coordinates (vx, vy) with height `n`
-    pub fn draw_sprite(&mut self, addr: u16, vx: u8, vy: u8, n: u8) -> bool {
-        self.gfx
-            .draw_sprite(vx, vy, n, self.ram.get_sprite(addr, n))
+    /// Draw the Mega-Chip sprite located at address `addr` at coordinates (vx, vy), with the size
+    /// set by `03NN` and `04NN`
+    pub fn draw_indexed_sprite(&mut self, addr: u16, vx: u8, vy: u8) -> Result<bool, Chip8Error> {
+        self.metrics.inc_draw_calls();
+        let (width, height) = self.gfx.sprite_size();
+        let len = width * height;
+        self.record_read(addr, len.min(u16::MAX as usize) as u16);
+        let data = (addr as usize..addr as usize + len)
+            .map(|addr| self.ram.read(addr))
+            .collect::<Result<Vec<u8>, _>>()?;
+        let collision = self.gfx.draw_indexed(vx, vy, &data);
+        if collision {
+            self.metrics.inc_collisions();
+        }
+        Ok(collision)
+    }
+
+    /// Draw the 16x16 sprite located at address `addr` at coordinates (vx, vy), wrapping it around
+    /// the edges of the display if `wrap` is `true`
+    pub fn draw_large_sprite(
+        &mut self,
+        addr: u16,
+        vx: u8,
+        vy: u8,
+        wrap: bool,
+    ) -> Result<bool, Chip8Error> {
+        self.metrics.inc_draw_calls();
+        let len = 32 * self.gfx.plane_count() as u16;
+        self.record_read(addr, len);
+        let collision = self
+            .gfx
+            .draw_large_sprite(vx, vy, &self.ram.get_sprite(addr, len)?, wrap);
+        if collision {
+            self.metrics.inc_collisions();
+        }
+        Ok(collision)
     }
 }
@@ -0,0 +1,36 @@
+pub mod archive;
+pub mod blocks;
+pub mod chip8;
+pub mod config;
+pub mod conformance;
+pub mod coverage;
+pub mod cpu;
+pub mod demo;
+pub mod db;
+pub mod detect;
+pub mod disasm;
+pub mod error;
+pub mod gfx;
+pub mod idle;
+pub mod instruction;
+pub mod interconnect;
+pub mod metadata;
+pub mod metrics;
+pub mod movie;
+pub mod octo;
+pub mod palette;
+pub mod patch;
+pub mod profiler;
+pub mod program;
+pub mod ram;
+pub mod recent;
+pub mod recording;
+pub mod rewind;
+pub mod rom;
+pub mod savestate;
+pub mod screenshot;
+pub mod selftest;
+pub mod timing;
+pub mod trace;
+
+pub use chip8::Chip8;
@@ -1,161 +1,2495 @@
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
 
-use anyhow::{bail, Context, Result};
-use clap::{App, Arg};
+use anyhow::{bail, ensure, Context, Result};
+use clap::{App, AppSettings, Arg, ArgMatches};
 use game_loop::game_loop;
-use log::{error, info};
+use log::{error, info, warn};
 use pixels::{Pixels, SurfaceTexture};
 use winit::{
-    dpi::LogicalSize,
-    event::{Event, VirtualKeyCode},
+    dpi::{PhysicalPosition, PhysicalSize},
+    event::{ElementState, Event, KeyboardInput, WindowEvent},
     event_loop::EventLoop,
-    window::WindowBuilder,
+    monitor::MonitorHandle,
+    window::{Fullscreen, Icon, Window, WindowBuilder},
 };
 use winit_input_helper::WinitInputHelper;
 
-mod config;
-mod cpu;
-mod gfx;
-mod interconnect;
-mod ram;
+mod audio;
+mod crowd;
+mod dap;
+mod debugger;
+mod fault;
+mod fetch;
+mod gamepad;
+mod gui;
+mod hotkeys;
+mod inspector;
+mod menu;
+mod osc;
+mod server;
+mod sprite_editor;
+mod watch;
+mod watchdog;
 
-use cpu::Cpu;
-use gfx::Gfx;
-use interconnect::Interconnect;
-use ram::Ram;
+use chip8rs::config::{self, KeyMap, QuirkOverrides, Quirks, Settings, SettingsWatcher};
+use chip8rs::conformance::{self, Expect};
+use chip8rs::coverage::{Coverage, CoverageFormat};
+use chip8rs::cpu::SysPolicy;
+use chip8rs::db::{Database, Entry};
+use chip8rs::detect::{self, Profile};
+use chip8rs::error::Fault;
+use chip8rs::gfx;
+use chip8rs::idle::IdleLoops;
+use chip8rs::metadata::Metadata;
+use chip8rs::metrics::Metrics;
+use chip8rs::movie::{Movie, Player};
+use chip8rs::palette::{self, Color, Palette};
+use chip8rs::profiler::{self, InstructionProfiler};
+use chip8rs::ram::OutOfRange;
+use chip8rs::recent::RecentRoms;
+use chip8rs::recording::Recorder;
+use chip8rs::rewind::Rewind;
+use chip8rs::savestate::State;
+use chip8rs::screenshot;
+use chip8rs::selftest::{self, Verdict};
+use chip8rs::trace::Tracer;
+use chip8rs::{archive, demo, disasm, metrics, octo, patch, rom, Chip8};
+use audio::Buzzer;
+use crowd::Crowd;
+use debugger::{DebugAction, Debugger};
+use fault::FaultAction;
+use gamepad::Gamepads;
+use gui::Framework;
+use hotkeys::{Action, Help, Hotkeys, Playback};
+use inspector::Inspector;
+use menu::PauseMenu;
+use osc::OscBridge;
+use sprite_editor::SpriteEditor;
+use watch::{Symbols, Watch};
+use watchdog::Watchdog;
 
-const WIDTH: usize = 64;
-const HEIGHT: usize = 32;
+/// Size of the rendered frame: the SUPER-CHIP high resolution, with low resolution pixels doubled.
+/// It grows to the Mega-Chip resolution while a machine is in that mode.
+const WIDTH: usize = gfx::HIRES.0;
+const HEIGHT: usize = gfx::HIRES.1;
+/// Rate at which `Game::update` is called by the game loop.
+const UPDATES_PER_SECOND: u32 = 1000;
+/// Speed multiplier while the turbo key is held.
+const TURBO_SPEED: f32 = 4.0;
+/// How far back the rewind key can go, in 60Hz frames.
+const REWIND_FRAMES: usize = 10 * 60;
+/// How long to sleep per loop iteration while nothing can happen until a key is pressed.
+const IDLE_SLEEP: Duration = Duration::from_millis(10);
 
-/// This represents the Chip-8 virtual machine. It is composed of a `Cpu` and an `Interconnect`.
-pub struct Chip8 {
-    cpu: Cpu,
-    interconnect: Interconnect,
-    ticks: u64,
+/// Whether emulation runs on its own, as set with the pause and frame advance keys.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RunState {
+    Running,
+    Paused,
+    /// Paused, but running until the next 60Hz frame starts.
+    FrameAdvance,
 }
 
-impl Chip8 {
-    pub fn new<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
-        let rom = std::fs::read(path)?;
-        let mut ram = Ram::default();
-        ram.load_at(config::FONT_DATA_ADDR, &config::FONT_DATA[..]);
-        ram.load_at(config::PROG_ADDR, &rom);
+/// Display and speed settings from the command line or the ROM database. They take precedence
+/// over the settings file, but aren't saved to it.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Overrides {
+    pub palette: Option<Palette>,
+    pub background: Option<Color>,
+    pub foreground: Option<Color>,
+    pub ips: Option<u32>,
+}
+
+pub struct Game {
+    chip8: Chip8,
+    /// Second machine running the same ROM with different quirks, shown to the right of the
+    /// first one.
+    compare: Option<Chip8>,
+    pixels: Pixels,
+    /// Size of the frame of each machine in `pixels`: the largest display resolution in use.
+    frame_size: (usize, usize),
+    framework: Framework,
+    input: WinitInputHelper,
+    sprite_editor: SpriteEditor,
+    help: Help,
+    menu: PauseMenu,
+    debugger: Debugger,
+    inspector: Inspector,
+    /// The settings when the menu was opened, to detect changes made with it.
+    settings_before_menu: Option<Settings>,
+    /// Where the settings are saved, if anywhere.
+    settings_path: Option<PathBuf>,
+    settings_watcher: SettingsWatcher,
+    /// The settings last applied, so that saving doesn't lose the ones only read on startup.
+    saved_settings: Settings,
+    overrides: Overrides,
+    /// How ROMs opened from the GUI are loaded, as the one given on the command line.
+    rom_options: RomOptions,
+    /// File name of the ROM, to find its settings.
+    rom_name: Option<String>,
+    /// Recently opened ROMs, offered in the menu, and where they're saved.
+    recent: RecentRoms,
+    recent_path: Option<PathBuf>,
+    hotkeys: Hotkeys,
+    gui_visible: bool,
+    /// Whether the GUI follows the monitor's scale factor.
+    dpi_aware: bool,
+    palette: Palette,
+    /// Colors replacing the palette's background and foreground, if set.
+    background: Option<Color>,
+    foreground: Option<Color>,
+    /// Frames pixels take to fade out once turned off, 0 for none.
+    afterglow: u8,
+    /// Size of a low resolution pixel in screenshots, the on-screen size rounded.
+    scale: usize,
+    /// Emulation speed, as a multiple of the normal speed.
+    speed: f32,
+    /// Instructions executed per second at normal speed.
+    ips: u32,
+    /// Whether the turbo key is held down.
+    turbo: bool,
+    run_state: RunState,
+    /// Emulated time owed to the CPU at the current speed, in seconds. It goes negative when an
+    /// instruction takes longer than what was owed.
+    step_budget: f32,
+    metrics: Arc<Metrics>,
+    crowd: Option<Crowd>,
+    osc: Option<OscBridge>,
+    gamepads: Option<Gamepads>,
+    buzzer: Option<Buzzer>,
+    watches: Vec<Watch>,
+    screenshots: Option<screenshot::Series>,
+    /// Recording of the display started with the record key, if any.
+    recorder: Option<Recorder>,
+    coverage_output: Option<(PathBuf, CoverageFormat)>,
+    profile_output: Option<PathBuf>,
+    flamegraph_output: Option<PathBuf>,
+    instruction_profile_output: Option<PathBuf>,
+    idle_report_output: Option<PathBuf>,
+    symbols: Symbols,
+    watchdog: Watchdog,
+    /// The error that stopped emulation, if any.
+    fault: Option<Fault>,
+    /// Set when the user asked to quit from the GUI.
+    quit: bool,
+    /// Where to write the run statistics on exit, `-` for stdout.
+    stats_output: Option<PathBuf>,
+    state_output: Option<PathBuf>,
+    /// Where F5 saves the machine state and F9 loads it from.
+    state_file: PathBuf,
+    /// Snapshots of the recent frames, to rewind through.
+    history: Rewind,
+    /// Frame of the last snapshot in `history`.
+    history_frame: Option<u64>,
+    /// Whether the rewind key is held down.
+    rewinding: bool,
+    /// Updates since the last frame was rewound.
+    rewind_updates: u32,
+    started: Instant,
+    sound_on: bool,
+    /// Movie recording the keypad input, and where to save it on exit.
+    movie: Option<Movie>,
+    movie_output: Option<PathBuf>,
+    /// Movie being replayed instead of reading the keypad input.
+    movie_player: Option<Player>,
+    /// Input macro being played, if any.
+    playback: Option<Playback>,
+    /// Keypad key currently pressed by the macro.
+    macro_key: Option<u8>,
+    fps_start: Instant,
+    fps_frames: u32,
+}
+
+impl Game {
+    pub fn new(pixels: Pixels, framework: Framework, chip8: Chip8) -> Result<Self> {
+        let input = WinitInputHelper::new();
+        let metrics = chip8.metrics();
+        let recent_path = RecentRoms::path();
+        let recent = match recent_path.as_deref().map(RecentRoms::load) {
+            Some(Ok(recent)) => recent,
+            Some(Err(e)) => {
+                warn!("Ignoring the recent ROMs: {:#}", e);
+                RecentRoms::default()
+            }
+            None => RecentRoms::default(),
+        };
+        Ok(Self {
+            chip8,
+            compare: None,
+            pixels,
+            frame_size: (WIDTH, HEIGHT),
+            framework,
+            input,
+            sprite_editor: SpriteEditor::new(),
+            help: Help::new(),
+            menu: PauseMenu::new(),
+            debugger: Debugger::new(),
+            inspector: Inspector::new(),
+            settings_before_menu: None,
+            settings_path: Settings::path(),
+            settings_watcher: SettingsWatcher::default(),
+            saved_settings: Settings::default(),
+            overrides: Overrides::default(),
+            rom_options: RomOptions::default(),
+            rom_name: None,
+            recent,
+            recent_path,
+            hotkeys: Hotkeys::default(),
+            gui_visible: false,
+            dpi_aware: true,
+            palette: Palette::default(),
+            background: None,
+            foreground: None,
+            afterglow: 0,
+            scale: 8,
+            speed: 1.0,
+            ips: config::DEFAULT_IPS,
+            turbo: false,
+            run_state: RunState::Running,
+            step_budget: 0.0,
+            metrics,
+            crowd: None,
+            osc: None,
+            gamepads: None,
+            buzzer: None,
+            watches: Vec::new(),
+            screenshots: None,
+            recorder: None,
+            coverage_output: None,
+            profile_output: None,
+            flamegraph_output: None,
+            instruction_profile_output: None,
+            idle_report_output: None,
+            symbols: Symbols::default(),
+            watchdog: Watchdog::new(UPDATES_PER_SECOND),
+            fault: None,
+            quit: false,
+            stats_output: None,
+            state_output: None,
+            state_file: PathBuf::from("demo.c8s"),
+            history: Rewind::new(REWIND_FRAMES),
+            history_frame: None,
+            rewinding: false,
+            rewind_updates: 0,
+            started: Instant::now(),
+            sound_on: false,
+            movie: None,
+            movie_output: None,
+            movie_player: None,
+            playback: None,
+            macro_key: None,
+            fps_start: Instant::now(),
+            fps_frames: 0,
+        })
+    }
+
+    pub fn set_dpi_aware(&mut self, dpi_aware: bool) {
+        self.dpi_aware = dpi_aware;
+    }
+
+    /// Resize the drawing surface to the new size of the window.
+    pub fn resize(&mut self, width: u32, height: u32) {
+        self.pixels.resize_surface(width, height);
+        self.framework.resize(width, height);
+    }
+
+    /// Follow the window to a monitor with a different scale factor.
+    pub fn set_scale_factor(&mut self, scale_factor: f64) {
+        if self.dpi_aware {
+            self.framework.scale_factor(scale_factor);
+        }
+    }
+
+    /// Read and save the settings at `path` instead of the default location.
+    pub fn set_settings_path(&mut self, path: PathBuf) {
+        self.settings_watcher = SettingsWatcher::new(Some(path.clone()));
+        self.settings_path = Some(path);
+    }
+
+    pub fn set_rom_options(&mut self, options: RomOptions) {
+        self.rom_options = options;
+    }
+
+    pub fn set_rom_name(&mut self, name: String) {
+        self.rom_name = Some(name);
+    }
+
+    /// Put `rom` at the top of the recently opened ROMs.
+    pub fn add_recent_rom(&mut self, rom: &Path) {
+        self.recent.add(rom);
+        if let Some(path) = &self.recent_path {
+            if let Err(e) = self.recent.save(path) {
+                warn!("Failed to save the recent ROMs: {:#}", e);
+            }
+        }
+    }
+
+    /// Bind the controller buttons from the settings, with the ones for this ROM taking
+    /// precedence.
+    fn bind_gamepad_buttons(&mut self) {
+        let gamepads = match &mut self.gamepads {
+            Some(gamepads) => gamepads,
+            None => return,
+        };
+        let mut buttons = self.saved_settings.gamepad.clone();
+        let rom_buttons = self
+            .rom_name
+            .as_ref()
+            .and_then(|name| self.saved_settings.gamepad_roms.get(name));
+        if let Some(rom_buttons) = rom_buttons {
+            buttons.0.extend(rom_buttons.0.clone());
+        }
+        if let Err(e) = gamepads.set_buttons(&buttons) {
+            warn!("Ignoring controller bindings in settings: {:#}", e);
+        }
+    }
+
+    pub fn set_keymap(&mut self, keymap: &KeyMap) -> Result<()> {
+        self.hotkeys.set_keymap(keymap)
+    }
+
+    /// Let pixels fade out over `frames` 60Hz frames once turned off, or disappear at once with 0.
+    pub fn set_afterglow(&mut self, frames: u8) {
+        self.afterglow = frames;
+        let frames = (frames > 0).then_some(frames);
+        self.chip8.interconnect.gfx.set_afterglow(frames);
+        if let Some(compare) = &mut self.compare {
+            compare.interconnect.gfx.set_afterglow(frames);
+        }
+    }
+
+    pub fn set_scale(&mut self, scale: usize) {
+        self.scale = scale;
+    }
+
+    /// Use `palette`, with the custom background and foreground colors if set.
+    pub fn set_palette(&mut self, palette: Palette) {
+        let palette = palette.with_colors(self.background, self.foreground);
+        self.palette = palette;
+        // The borders around the display when the window doesn't fit it exactly.
+        let [r, g, b, _] = palette.colors[0].map(srgb_to_linear);
+        self.pixels
+            .set_clear_color(pixels::wgpu::Color { r, g, b, a: 1.0 });
+        self.chip8.interconnect.gfx.set_palette(palette);
+        if let Some(compare) = &mut self.compare {
+            compare.interconnect.gfx.set_palette(palette);
+        }
+    }
+
+    /// Replace the palette's background and foreground colors, or go back to them with `None`.
+    pub fn set_custom_colors(&mut self, background: Option<Color>, foreground: Option<Color>) {
+        self.background = background;
+        self.foreground = foreground;
+        self.set_palette(Palette::by_name(self.palette.name).unwrap_or_default());
+    }
+
+    /// Switch to the next built-in palette.
+    pub fn cycle_palette(&mut self) {
+        self.set_palette(self.palette.next());
+        info!("palette: {}", self.palette.name);
+        self.save_settings();
+    }
+
+    /// The current user settings. The overrides are left out, unless they have been changed since.
+    pub fn settings(&self) -> Settings {
+        let (saved, overrides) = (&self.saved_settings, &self.overrides);
+        let palette = match overrides.palette {
+            Some(palette) if palette.name == self.palette.name => saved.palette.clone(),
+            _ => self.palette.name.to_string(),
+        };
+        let color = |current: Option<Color>, overridden: Option<Color>, saved: &Option<String>| {
+            if overridden.is_some() && current == overridden {
+                saved.clone()
+            } else {
+                current.map(palette::color_hex)
+            }
+        };
+        let ips = if overrides.ips == Some(self.ips) {
+            saved.ips
+        } else {
+            self.ips
+        };
+        Settings {
+            palette,
+            background: color(self.background, overrides.background, &saved.background),
+            foreground: color(self.foreground, overrides.foreground, &saved.foreground),
+            afterglow: self.afterglow,
+            speed: self.speed,
+            ips,
+            keypad: Some(self.hotkeys.keypad_names()),
+            keys: self.hotkeys.keymap_settings(),
+            macros: self
+                .hotkeys
+                .macros
+                .iter()
+                .map(|m| m.settings.clone())
+                .collect(),
+            ..self.saved_settings.clone()
+        }
+    }
+
+    /// Apply saved settings, with the overrides on top. Invalid values are reported and ignored.
+    pub fn apply_settings(&mut self, settings: &Settings) {
+        let color = |name, value: &Option<String>| {
+            let color = palette::parse_color(value.as_ref()?);
+            color
+                .map_err(|e| warn!("Ignoring {} in settings: {:#}", name, e))
+                .ok()
+        };
+        let overrides = self.overrides;
+        let background = overrides
+            .background
+            .or_else(|| color("background", &settings.background));
+        let foreground = overrides
+            .foreground
+            .or_else(|| color("foreground", &settings.foreground));
+        self.set_custom_colors(background, foreground);
+        match overrides
+            .palette
+            .or_else(|| Palette::by_name(&settings.palette))
+        {
+            Some(palette) => self.set_palette(palette),
+            None => warn!("Unknown palette {:?} in settings", settings.palette),
+        }
+        self.set_afterglow(settings.afterglow);
+        self.speed = settings
+            .speed
+            .clamp(*menu::SPEED_RANGE.start(), *menu::SPEED_RANGE.end());
+        self.ips = overrides.ips.unwrap_or(settings.ips);
+        if let Some(keypad) = &settings.keypad {
+            if let Err(e) = self.hotkeys.set_keypad_names(keypad) {
+                warn!("Ignoring keypad bindings in settings: {}", e);
+            }
+        }
+        if let Err(e) = self.hotkeys.set_keymap(&settings.keys) {
+            warn!("Ignoring [keys] in settings: {:#}", e);
+        }
+        if let Err(e) = self.hotkeys.set_macros(&settings.macros) {
+            warn!("Ignoring macros in settings: {:#}", e);
+        }
+        self.saved_settings = settings.clone();
+        self.bind_gamepad_buttons();
+    }
+
+    /// Use `overrides` instead of the settings they cover, replacing the previous ones.
+    pub fn set_overrides(&mut self, overrides: Overrides) {
+        let settings = self.settings();
+        self.overrides = overrides;
+        self.apply_settings(&settings);
+    }
+
+    /// Persist the current settings so they're used on the next launch.
+    fn save_settings(&self) {
+        let path = match &self.settings_path {
+            Some(path) => path,
+            None => return warn!("Not saving settings: no config directory on this platform"),
+        };
+        if let Err(e) = self.settings().save(path) {
+            warn!("Failed to save settings: {:#}", e);
+        }
+    }
+
+    /// Apply the settings file if it has been edited since it was last read. Our own saves are
+    /// picked up as well, but leave the settings unchanged.
+    fn reload_settings(&mut self) {
+        match self.settings_watcher.poll() {
+            Ok(Some(settings)) if settings != self.settings() => {
+                info!("settings file changed, reloading");
+                self.apply_settings(&settings);
+            }
+            Ok(_) => {}
+            Err(e) => warn!("Failed to reload settings: {:#}", e),
+        }
+    }
+
+    /// Save the display to a timestamped PNG file in the current directory.
+    pub fn screenshot(&self) {
+        let path = PathBuf::from(screenshot::timestamped_name(SystemTime::now(), "png"));
+        let (display, width) = (self.chip8.display(), self.chip8.display_width());
+        match screenshot::save_png(&path, display, width, &self.palette, self.scale) {
+            Ok(()) => info!("saved screenshot to {}", path.display()),
+            Err(e) => error!("Failed to save screenshot: {:#}", e),
+        }
+    }
+
+    /// Start recording the display, or stop and save the recording to a timestamped APNG file
+    /// in the current directory.
+    pub fn toggle_recording(&mut self) {
+        match self.recorder.take() {
+            None => {
+                let mut recorder = Recorder::new();
+                let (display, width) = (self.chip8.display(), self.chip8.display_width());
+                if let Err(e) = recorder.capture(self.chip8.frames(), display, width) {
+                    return error!("Can't start recording: {:#}", e);
+                }
+                self.recorder = Some(recorder);
+                info!("recording started");
+            }
+            Some(recorder) => {
+                let path = PathBuf::from(screenshot::timestamped_name(SystemTime::now(), "apng"));
+                let end = self.chip8.frames();
+                match recorder.save(&path, end, &self.palette, (self.scale / 2).max(1)) {
+                    Ok(()) => info!(
+                        "saved {} frames of recording to {}",
+                        recorder.len(),
+                        path.display()
+                    ),
+                    Err(e) => error!("Failed to save recording: {:#}", e),
+                }
+            }
+        }
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.run_state = match self.run_state {
+            RunState::Running => {
+                info!("paused");
+                RunState::Paused
+            }
+            RunState::Paused | RunState::FrameAdvance => {
+                info!("resumed");
+                RunState::Running
+            }
+        };
+    }
+
+    /// Run one 60Hz frame on the next update, if paused with the pause key.
+    pub fn advance_frame(&mut self) {
+        if self.run_state == RunState::Paused {
+            self.run_state = RunState::FrameAdvance;
+        }
+    }
+
+    pub fn toggle_menu(&mut self) {
+        self.menu.open = !self.menu.open;
+        if self.menu.open {
+            self.settings_before_menu = Some(self.settings());
+        }
+    }
+
+    /// Run `chip8` alongside the main machine, with the same input.
+    pub fn set_comparison(&mut self, mut chip8: Chip8) {
+        chip8.interconnect.gfx.set_palette(self.palette);
+        self.compare = Some(chip8);
+    }
+
+    pub fn set_crowd(&mut self, crowd: Crowd) {
+        self.crowd = Some(crowd);
+    }
+
+    pub fn set_osc(&mut self, osc: OscBridge) {
+        self.osc = Some(osc);
+    }
+
+    pub fn set_gamepads(&mut self, gamepads: Gamepads) {
+        self.gamepads = Some(gamepads);
+        self.bind_gamepad_buttons();
+    }
+
+    pub fn set_buzzer(&mut self, buzzer: Buzzer) {
+        self.buzzer = Some(buzzer);
+    }
+
+    /// Save screenshots periodically, see `screenshot::Series`.
+    pub fn set_screenshots(&mut self, series: screenshot::Series) {
+        self.screenshots = Some(series);
+    }
+
+    pub fn add_watch(&mut self, watch: Watch) {
+        self.watches.push(watch);
+    }
+
+    /// Track the coverage of `rom` during the run and write it to `path` in `format` on exit.
+    pub fn set_coverage_output(&mut self, path: PathBuf, format: CoverageFormat, rom: &[u8]) {
+        let ram_size = self.chip8.interconnect.ram.size();
+        self.chip8
+            .enable_coverage(Coverage::new(self.chip8.load_addr(), rom, ram_size));
+        self.coverage_output = Some((path, format));
+    }
+
+    /// Profile subroutines during the run, writing a flat profile to `report` and/or folded
+    /// stacks for flame graphs to `flamegraph` on exit.
+    pub fn set_profile_output(&mut self, report: Option<PathBuf>, flamegraph: Option<PathBuf>) {
+        self.chip8.enable_profiler();
+        self.profile_output = report;
+        self.flamegraph_output = flamegraph;
+    }
+
+    /// Write the instruction profile to `path` on exit, see `write_instruction_profile`.
+    pub fn set_instruction_profile_output(&mut self, path: PathBuf) {
+        self.instruction_profile_output = Some(path);
+    }
+
+    /// Write the idle loop report to `path` on exit, see `write_idle_report`.
+    pub fn set_idle_report_output(&mut self, path: PathBuf) {
+        self.idle_report_output = Some(path);
+    }
+
+    /// Symbols used to name subroutines in reports.
+    pub fn set_symbols(&mut self, symbols: Symbols) {
+        self.symbols = symbols;
+    }
+
+    /// Write a summary of the run to `path` (or stdout if it's `-`) on exit.
+    pub fn set_stats_output(&mut self, path: PathBuf) {
+        self.stats_output = Some(path);
+    }
+
+    /// Write the final machine state to `path` on exit: as a save state if it ends in `.c8s`, as
+    /// JSON otherwise (or to stdout if it's `-`).
+    pub fn set_state_output(&mut self, path: PathBuf) {
+        self.state_output = Some(path);
+    }
+
+    /// Record the keypad input into `movie`, to be saved to `path` on exit.
+    pub fn record_movie(&mut self, movie: Movie, path: PathBuf) {
+        self.movie = Some(movie);
+        self.movie_output = Some(path);
+    }
+
+    /// Replay the keypad input of `movie`, ignoring the keyboard and the other inputs.
+    pub fn play_movie(&mut self, movie: &Movie) {
+        self.set_overrides(Overrides {
+            ips: Some(movie.ips),
+            ..self.overrides
+        });
+        self.movie_player = Some(Player::new(movie));
+    }
+
+    /// Save and load the machine state in `path` with F5 and F9.
+    pub fn set_state_file(&mut self, path: PathBuf) {
+        self.state_file = path;
+    }
+
+    /// Restart with the ROM at `path`, set up as the one given on the command line: assembled if
+    /// it's Octo source, with its quirks and the settings from the ROM database. The machines
+    /// being compared keep theirs. The state file moves next to the new ROM.
+    pub fn load_rom(&mut self, window: &Window, path: &Path) -> Result<()> {
+        ensure!(
+            self.movie.is_none(),
+            "Can't change the ROM while recording a movie"
+        );
+        let (file, data) = archive::read_rom(path)?;
+        let rom = LoadedRom::new(file, data, &self.rom_options)?;
+        let context = || format!("Can't run {}", path.display());
+        if let Some(compare) = &mut self.compare {
+            self.chip8
+                .reset_with_rom(&rom.bytes)
+                .with_context(context)?;
+            compare.reset_with_rom(&rom.bytes).with_context(context)?;
+        } else {
+            let preset = self.saved_settings.quirks_preset.as_deref();
+            let profile = rom.profile(&self.rom_options, preset)?;
+            self.chip8
+                .reset_with_rom_as(&rom.bytes, profile)
+                .with_context(context)?;
+            let quirks = self
+                .rom_options
+                .quirks(profile, &self.saved_settings.quirks);
+            self.chip8.set_quirks(quirks);
+        }
+        self.set_overrides(rom.overrides(self.rom_options.overrides));
+        info!("loaded {}", path.display());
+        self.fault = None;
+        self.movie_player = None;
+        self.history = Rewind::new(REWIND_FRAMES);
+        self.history_frame = None;
+        self.symbols = Symbols::default();
+        self.state_file = path.with_extension("c8s");
+        self.rom_name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned());
+        let name = rom.known.as_ref().and_then(Entry::display_name);
+        if let Some(name) = name.as_ref().or(self.rom_name.as_ref()) {
+            window.set_title(&format!("Chip8rs -- {}", name));
+        }
+        self.bind_gamepad_buttons();
+        self.add_recent_rom(path);
+        Ok(())
+    }
+
+    /// Write the machine state to the state file.
+    pub fn save_state(&self) {
+        match std::fs::write(&self.state_file, self.chip8.save_state().encode()) {
+            Ok(()) => info!("saved state to {}", self.state_file.display()),
+            Err(e) => error!("Failed to save state: {}", e),
+        }
+    }
+
+    /// Resume from the state file, clearing any fault. A movie being recorded couldn't be played
+    /// back past that point, so this is refused while recording.
+    pub fn load_state(&mut self) -> Result<()> {
+        ensure!(
+            self.movie.is_none(),
+            "Can't load a state while recording a movie"
+        );
+        let path = &self.state_file;
+        let bytes =
+            std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        let state = State::decode(&bytes)
+            .with_context(|| format!("Failed to load state from {}", path.display()))?;
+        self.chip8.load_state(&state)?;
+        info!("loaded state from {}", path.display());
+        self.fault = None;
+        Ok(())
+    }
+
+    /// Statistics about the run so far.
+    pub fn stats(&self) -> String {
+        self.metrics.summary(self.started.elapsed())
+    }
+
+    /// Print the run statistics, and the instruction profile and idle loops so far if they are
+    /// tracked.
+    pub fn print_stats(&self) {
+        print!("{}", self.stats());
+        if let Some(profiler) = self.chip8.instruction_profiler() {
+            print!("\n{}", profiler.report());
+        }
+        if let Some(idle) = self.chip8.idle_loops() {
+            print!("\n{}", idle.report());
+        }
+    }
+
+    /// Called once before exiting.
+    pub fn finish(&mut self) {
+        if let Some(path) = &self.stats_output {
+            if path.as_os_str() == "-" {
+                print!("{}", self.stats());
+            } else {
+                match std::fs::write(path, self.stats()) {
+                    Ok(()) => info!("wrote run statistics to {}", path.display()),
+                    Err(e) => error!("Failed to write run statistics: {}", e),
+                }
+            }
+        }
+        if let (Some(movie), Some(path)) = (&self.movie, &self.movie_output) {
+            match std::fs::write(path, movie.encode()) {
+                Ok(()) => info!("wrote movie to {}", path.display()),
+                Err(e) => error!("Failed to write movie: {}", e),
+            }
+        }
+        if let Some(path) = &self.state_output {
+            if path.as_os_str() == "-" {
+                print!("{}", self.chip8.state_json());
+            } else {
+                let state = if path.extension().is_some_and(|ext| ext == "c8s") {
+                    self.chip8.save_state().encode()
+                } else {
+                    self.chip8.state_json().into_bytes()
+                };
+                match std::fs::write(path, state) {
+                    Ok(()) => info!("wrote machine state to {}", path.display()),
+                    Err(e) => error!("Failed to write machine state: {}", e),
+                }
+            }
+        }
+        if let (Some((path, format)), Some(coverage)) =
+            (&self.coverage_output, self.chip8.coverage())
+        {
+            match std::fs::write(path, coverage.export(*format)) {
+                Ok(()) => info!("wrote coverage report to {}", path.display()),
+                Err(e) => error!("Failed to write coverage report: {}", e),
+            }
+        }
+        if let (Some(path), Some(profiler)) = (
+            &self.instruction_profile_output,
+            self.chip8.instruction_profiler(),
+        ) {
+            match write_instruction_profile(profiler, path) {
+                Ok(()) => info!("wrote instruction profile to {}", path.display()),
+                Err(e) => error!("Failed to write instruction profile: {:#}", e),
+            }
+        }
+        if let (Some(path), Some(idle)) = (&self.idle_report_output, self.chip8.idle_loops()) {
+            match write_idle_report(idle, path) {
+                Ok(()) => info!("wrote idle loop report to {}", path.display()),
+                Err(e) => error!("Failed to write idle loop report: {:#}", e),
+            }
+        }
+        if let Some(mut tracer) = self.chip8.take_tracer() {
+            if let Err(e) = tracer.finish() {
+                error!("Failed to write trace: {}", e);
+            }
+        }
+        if let Some(profiler) = self.chip8.profiler() {
+            let name = |addr| match self.symbols.name_of(addr) {
+                Some(name) => name.to_string(),
+                None => profiler::default_name(addr),
+            };
+            if let Some(path) = &self.profile_output {
+                match std::fs::write(path, profiler.report(name)) {
+                    Ok(()) => info!("wrote subroutine profile to {}", path.display()),
+                    Err(e) => error!("Failed to write subroutine profile: {}", e),
+                }
+            }
+            if let Some(path) = &self.flamegraph_output {
+                match std::fs::write(path, profiler.folded(name)) {
+                    Ok(()) => info!("wrote folded stacks to {}", path.display()),
+                    Err(e) => error!("Failed to write folded stacks: {}", e),
+                }
+            }
+        }
+    }
+
+    pub fn update(&mut self) {
+        if let Some(rate) = self.watchdog.update() {
+            self.report_lag(rate);
+        }
+        if let Some(crowd) = &mut self.crowd {
+            if crowd.update() {
+                self.sync_keys();
+            }
+        }
+        if let Some(osc) = &mut self.osc {
+            if osc.poll() {
+                self.sync_keys();
+            }
+        }
+        if let Some(gamepads) = &mut self.gamepads {
+            if gamepads.poll() {
+                self.sync_keys();
+            }
+        }
+        self.reload_settings();
+        if self.rewinding && !self.menu.open {
+            self.set_sound(false);
+            self.rewind();
+            return;
+        }
+        if self.menu.open
+            || self.fault.is_some()
+            || self.debugger.paused
+            || self.run_state == RunState::Paused
+        {
+            self.set_sound(false);
+            return;
+        }
+
+        if self.run_state == RunState::FrameAdvance {
+            self.run_state = RunState::Paused;
+            if !self.run_frame() {
+                return;
+            }
+        } else {
+            let speed = if self.turbo {
+                self.speed * TURBO_SPEED
+            } else {
+                self.speed
+            };
+            self.step_budget += speed / UPDATES_PER_SECOND as f32;
+            while self.step_budget > 0.0 {
+                if self.debugger.check(self.chip8.cpu.pc()) {
+                    self.step_budget = 0.0;
+                    break;
+                }
+                if !self.step() {
+                    return;
+                }
+            }
+        }
+
+        self.play_macro();
+        self.record_history();
+
+        if let Some(recorder) = &mut self.recorder {
+            if self.chip8.interconnect.gfx.is_dirty() {
+                let (display, width) = (self.chip8.display(), self.chip8.display_width());
+                if let Err(e) = recorder.capture(self.chip8.frames(), display, width) {
+                    error!("Stopping the recording: {:#}", e);
+                    self.toggle_recording();
+                }
+            }
+        }
+
+        if let Some(series) = &mut self.screenshots {
+            let (display, width) = (self.chip8.display(), self.chip8.display_width());
+            if let Err(e) = series.capture(self.chip8.frames(), display, width, &self.palette) {
+                error!("Stopping screenshots: {:#}", e);
+                self.screenshots = None;
+            }
+        }
+
+        if let Some(buzzer) = &mut self.buzzer {
+            buzzer.set_pattern(self.chip8.audio_pattern());
+        }
+        self.set_sound(self.chip8.sound_on());
+    }
+
+    /// Execute one instruction on each machine and update the watches. Return `false` if a
+    /// machine faulted. The timers advance by the duration of the instruction, so that they tick
+    /// after the same instructions whatever the speed and however many instructions are run
+    /// per update.
+    fn step(&mut self) -> bool {
+        let period = self.chip8.instruction_time(self.ips);
+        let compare_period = self.compare.as_ref().map(|c| c.instruction_time(self.ips));
+        self.step_budget -= period.as_secs_f32();
+        if let Some(player) = &mut self.movie_player {
+            player.apply(&mut self.chip8);
+            if let Some(compare) = &mut self.compare {
+                for (key, &down) in self.chip8.interconnect.keys.iter().enumerate() {
+                    compare.set_key(key as u8, down);
+                }
+            }
+        }
+        let result = self
+            .chip8
+            .try_step()
+            .and_then(|()| match &mut self.compare {
+                Some(compare) => compare.try_step(),
+                None => Ok(()),
+            });
+        if let Err(fault) = result {
+            error!("Machine fault at {:#05x}: {}", fault.pc, fault.message);
+            self.fault = Some(fault);
+            self.step_budget = 0.0;
+            self.set_sound(false);
+            return false;
+        }
+        self.chip8.advance_time(period);
+        if let (Some(compare), Some(period)) = (&mut self.compare, compare_period) {
+            compare.advance_time(period);
+        }
+        // Skipping would hide breakpoints in idle loops, and get ahead of the comparison machine
+        // or of the input of a movie.
+        if self.turbo
+            && self.compare.is_none()
+            && self.movie_player.is_none()
+            && !self.debugger.has_breakpoints()
+        {
+            let owed = (self.step_budget / period.as_secs_f32()).max(0.0) as u64;
+            if let Some(skipped) = self.chip8.skip_idle(owed) {
+                self.step_budget -= skipped.time.as_secs_f32();
+            }
+        }
+        for watch in &mut self.watches {
+            if watch.update(&self.chip8) {
+                info!("watch: {}", watch);
+            }
+        }
+        true
+    }
+
+    /// Execute instructions until the next 60Hz frame starts or a breakpoint is hit. Return
+    /// `false` if a machine faulted.
+    fn run_frame(&mut self) -> bool {
+        let frame = self.chip8.frames();
+        while self.chip8.frames() == frame {
+            if self.debugger.check(self.chip8.cpu.pc()) {
+                break;
+            }
+            if !self.step() {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Snapshot the machine once per frame, so that it can be rewound. The comparison machine
+    /// can't be rewound with it, so nothing is recorded when there is one.
+    fn record_history(&mut self) {
+        let frame = self.chip8.frames();
+        if self.compare.is_some() || self.history_frame == Some(frame) {
+            return;
+        }
+        self.history.push(self.chip8.save_state().encode());
+        self.history_frame = Some(frame);
+    }
+
+    /// Go back one frame per 60th of a second, getting out of a fault if needed.
+    fn rewind(&mut self) {
+        self.rewind_updates += 1;
+        if self.rewind_updates < UPDATES_PER_SECOND / 60 {
+            return;
+        }
+        self.rewind_updates = 0;
+        let snapshot = match self.history.pop() {
+            Some(snapshot) => snapshot,
+            None => return,
+        };
+        let result = State::decode(&snapshot).and_then(|state| self.chip8.load_state(&state));
+        match result {
+            Ok(()) => {
+                self.history_frame = Some(self.chip8.frames());
+                self.fault = None;
+            }
+            Err(e) => error!("Failed to rewind: {:#}", e),
+        }
+    }
+
+    /// Press the keypad key due at this point of the running macro.
+    fn play_macro(&mut self) {
+        let frame = self.chip8.frames();
+        let key = self.playback.as_ref().and_then(|p| p.key_at(frame));
+        if self.playback.as_ref().is_some_and(|p| p.is_done(frame)) {
+            self.playback = None;
+        }
+        if key != self.macro_key {
+            self.macro_key = key;
+            self.sync_keys();
+        }
+    }
+
+    /// Turn the buzzer and its other outputs on or off.
+    fn set_sound(&mut self, sound_on: bool) {
+        if sound_on == self.sound_on {
+            return;
+        }
+        self.sound_on = sound_on;
+        if let Some(buzzer) = &mut self.buzzer {
+            buzzer.set_active(sound_on);
+        }
+        if let Some(osc) = &self.osc {
+            osc.publish_sound(sound_on);
+        }
+        if let Some(gamepads) = &self.gamepads {
+            gamepads.set_rumble(sound_on);
+        }
+    }
+
+    /// Tell the user that emulation can't keep up, with suggestions based on what's enabled.
+    fn report_lag(&mut self, rate: f64) {
+        let mut remedies = Vec::new();
+        if cfg!(debug_assertions) {
+            remedies.push("use a release build (cargo run --release)");
+        }
+        if self.speed > 1.0 {
+            remedies.push("lower the emulation speed in the pause menu");
+        }
+        if self.ips > config::DEFAULT_IPS {
+            remedies.push("lower --ips");
+        }
+        if self.compare.is_some() {
+            remedies.push("run a single machine instead of --compare");
+        }
+        if self.chip8.coverage().is_some()
+            || self.chip8.profiler().is_some()
+            || self.chip8.is_tracing()
+        {
+            remedies.push("disable --coverage, --trace and profiling");
+        }
+        if !self.watches.is_empty() {
+            remedies.push("remove --watch expressions");
+        }
+        if self.sprite_editor.open {
+            remedies.push("close the sprite editor");
+        }
+        self.watchdog.report(rate, &remedies);
+    }
+
+    /// Whether the user asked to quit from the GUI.
+    pub fn quit_requested(&self) -> bool {
+        self.quit
+    }
+
+    fn handle_debug_action(&mut self, action: DebugAction) {
+        match action {
+            DebugAction::Continue => self.debugger.resume(),
+            DebugAction::Pause => self.debugger.pause(),
+            DebugAction::Step => {
+                if self.step() {
+                    self.record_history();
+                }
+            }
+        }
+    }
+
+    fn handle_fault_action(&mut self, action: FaultAction) {
+        match action {
+            FaultAction::Reset if self.movie.is_some() => {
+                error!("Can't reset while recording a movie");
+            }
+            FaultAction::Reset => {
+                self.chip8.reset();
+                if let Some(compare) = &mut self.compare {
+                    compare.reset();
+                }
+                self.fault = None;
+            }
+            FaultAction::DumpState => {
+                // Next to the save state, named after the ROM.
+                let path = self.state_file.with_extension("fault.txt");
+                let path = std::path::absolute(&path).unwrap_or(path);
+                match std::fs::write(&path, self.chip8.dump_state()) {
+                    Ok(()) => info!("wrote machine state to {}", path.display()),
+                    Err(e) => error!("Failed to write machine state: {}", e),
+                }
+            }
+            FaultAction::Quit => self.quit = true,
+        }
+    }
+
+    /// Whether the machine is blocked waiting for input, with nothing else to animate.
+    pub fn is_idle(&self) -> bool {
+        self.chip8.waiting_for_key()
+            && !self.chip8.interconnect.gfx.is_dirty()
+            && self
+                .compare
+                .as_ref()
+                .is_none_or(|c| c.waiting_for_key() && !c.interconnect.gfx.is_dirty())
+            && !self.sprite_editor.open
+            && !self.help.open
+            && !self.menu.open
+            && self.fault.is_none()
+    }
+
+    /// Draw the display and any open GUI window.
+    pub fn render(&mut self, window: &Window) -> Result<()> {
+        let dirty = self.chip8.interconnect.gfx.is_dirty()
+            || self
+                .compare
+                .as_ref()
+                .is_some_and(|c| c.interconnect.gfx.is_dirty());
+        let gui_visible = self.sprite_editor.open
+            || self.help.open
+            || self.menu.open
+            || self.debugger.open
+            || self.inspector.open
+            || self.fault.is_some()
+            || self.watchdog.is_open();
+        // Keep rendering while the GUI is visible, plus one more frame to erase it once closed.
+        if !dirty && !gui_visible && !self.gui_visible {
+            return Ok(());
+        }
+        self.gui_visible = gui_visible;
+
+        if dirty {
+            // The frame grows to the Mega-Chip resolution while a machine is in that mode.
+            let megachip = std::iter::once(&self.chip8)
+                .chain(self.compare.as_ref())
+                .any(|c| c.interconnect.gfx.is_megachip());
+            let frame_size = if megachip {
+                gfx::MEGACHIP
+            } else {
+                (WIDTH, HEIGHT)
+            };
+            let columns = 1 + self.compare.is_some() as usize;
+            // Only the rows that changed are copied, unless the frame was just resized.
+            let resized = frame_size != self.frame_size;
+            if resized {
+                self.frame_size = frame_size;
+                self.pixels
+                    .resize_buffer((frame_size.0 * columns) as u32, frame_size.1 as u32);
+            }
+            let (frame_width, frame_height) = frame_size;
+            // Machines are laid out side by side, so each line of the frame holds a line of each.
+            // Smaller displays are scaled up to fill the frame.
+            let screens: Vec<_> = std::iter::once(&mut self.chip8)
+                .chain(self.compare.as_mut())
+                .map(|c| {
+                    let gfx = &mut c.interconnect.gfx;
+                    let zoom = (frame_width / gfx.width(), frame_height / gfx.height());
+                    let dirty_rows = gfx.dirty_rows().to_vec();
+                    (zoom, dirty_rows, gfx.get_frame())
+                })
+                .collect();
+            for (y, line) in self
+                .pixels
+                .get_frame()
+                .chunks_exact_mut(4 * frame_width * columns)
+                .enumerate()
+            {
+                let columns = line.chunks_exact_mut(4 * frame_width);
+                for (column, (zoom, dirty_rows, rgba)) in columns.zip(&screens) {
+                    let (zoom_x, zoom_y) = *zoom;
+                    if !resized && !dirty_rows[y / zoom_y] {
+                        continue;
+                    }
+                    let row_len = 4 * frame_width / zoom_x;
+                    let row = &rgba[y / zoom_y * row_len..][..row_len];
+                    if zoom_x == 1 {
+                        column.copy_from_slice(row);
+                    } else {
+                        let pixels = column.chunks_exact_mut(4 * zoom_x);
+                        for (pixels, color) in pixels.zip(row.chunks_exact(4)) {
+                            pixels
+                                .chunks_exact_mut(4)
+                                .for_each(|pixel| pixel.copy_from_slice(color));
+                        }
+                    }
+                }
+            }
+        }
+
+        let old_palette = self.palette;
+        let Self {
+            framework,
+            sprite_editor,
+            help,
+            menu,
+            debugger,
+            inspector,
+            hotkeys,
+            speed,
+            palette,
+            chip8,
+            fault,
+            watchdog,
+            symbols,
+            recent,
+            ..
+        } = self;
+        let mut fault_action = None;
+        let mut debug_action = None;
+        let mut open_rom = None;
+        framework.prepare(window, |ctx| {
+            sprite_editor.ui(ctx, chip8.display(), chip8.display_width());
+            help.ui(ctx, hotkeys);
+            open_rom = menu.ui(
+                ctx,
+                menu::Settings {
+                    speed,
+                    palette,
+                    hotkeys,
+                    recent: recent.paths(),
+                },
+            );
+            watchdog.ui(ctx);
+            inspector.ui(ctx, chip8, symbols);
+            debug_action = debugger.ui(ctx, chip8, symbols);
+            if let Some(fault) = fault {
+                fault_action = fault::ui(ctx, fault);
+            }
+        });
+        if let Some(action) = debug_action {
+            self.handle_debug_action(action);
+        }
+        if let Some(action) = fault_action {
+            self.handle_fault_action(action);
+        }
+        if self.palette != old_palette {
+            self.set_palette(self.palette);
+        }
+        if let Some(path) = open_rom {
+            match self.load_rom(window, &path) {
+                Ok(()) => self.menu.open = false,
+                Err(e) => error!("{:#}", e),
+            }
+        }
+        if !self.menu.open {
+            if let Some(before) = self.settings_before_menu.take() {
+                if before != self.settings() {
+                    self.save_settings();
+                }
+            }
+        }
+
+        let framework = &mut self.framework;
+        self.pixels.render_with(|encoder, render_target, context| {
+            context.scaling_renderer.render(encoder, render_target);
+            framework.render(encoder, render_target, context)?;
+            Ok(())
+        })?;
+        Ok(())
+    }
+
+    /// Record that a frame was rendered, updating the FPS gauge about once per second.
+    pub fn frame_rendered(&mut self) {
+        self.metrics.inc_frames();
+        if let Some(osc) = &mut self.osc {
+            osc.publish_frame();
+        }
+        self.fps_frames += 1;
+        let elapsed = self.fps_start.elapsed().as_secs_f64();
+        if elapsed >= 1.0 {
+            self.metrics.set_fps(self.fps_frames as f64 / elapsed);
+            self.fps_start = Instant::now();
+            self.fps_frames = 0;
+        }
+    }
+
+    pub(crate) fn update_controls(&mut self, event: &Event<()>) {
+        self.input.update(event);
+        let triggered = self
+            .hotkeys
+            .macros
+            .iter()
+            .find(|m| self.input.key_pressed(m.key));
+        if let Some(m) = triggered {
+            self.playback = Some(Playback::new(m, self.chip8.frames()));
+        }
+        let mut rewind_keys = self
+            .hotkeys
+            .actions
+            .iter()
+            .filter(|(_, action)| *action == Action::Rewind);
+        if self.movie.is_some() {
+            // Like loading a state, rewinding would leave the recording behind the machine.
+            if rewind_keys.any(|(key, _)| self.input.key_pressed(*key)) {
+                warn!("Can't rewind while recording a movie");
+            }
+            self.rewinding = false;
+        } else {
+            self.rewinding = rewind_keys.any(|(key, _)| self.input.key_held(*key));
+        }
+        self.turbo = self
+            .hotkeys
+            .actions
+            .iter()
+            .any(|(key, action)| *action == Action::Turbo && self.input.key_held(*key));
+        self.sync_keys();
+    }
+
+    /// Actions whose hotkey was pressed since the last event.
+    pub(crate) fn pressed_actions(&self) -> Vec<Action> {
+        self.hotkeys
+            .actions
+            .iter()
+            .filter(|(key, _)| self.input.key_pressed(*key))
+            .map(|(_, action)| *action)
+            .collect()
+    }
+
+    /// Update the keypad state from the keyboard, macros, the crowd's vote and OSC peers, if any.
+    fn sync_keys(&mut self) {
+        // A movie being replayed is the only source of input.
+        if self.movie_player.is_some() {
+            return;
+        }
+        let crowd_key = self.crowd.as_ref().and_then(|c| c.pressed_key());
+        for i in 0..16 {
+            let is_down = self.hotkeys.keypad_held(i, |key| self.input.key_held(key))
+                || self.macro_key == Some(i)
+                || crowd_key == Some(i)
+                || self.osc.as_ref().is_some_and(|osc| osc.is_key_down(i))
+                || self.gamepads.as_ref().is_some_and(|g| g.is_key_down(i));
+            if let Some(movie) = &mut self.movie {
+                if self.chip8.interconnect.keys[i as usize] != is_down {
+                    movie.record(self.chip8.cycles(), i, is_down);
+                }
+            }
+            self.chip8.set_key(i, is_down);
+            if let Some(compare) = &mut self.compare {
+                compare.set_key(i, is_down);
+            }
+        }
+    }
+}
+
+fn main() -> Result<()> {
+    env_logger::init();
+
+    let app = App::new("chip8rs")
+        .author("Antoine Busch")
+        .version("0.1")
+        .setting(AppSettings::SubcommandsNegateReqs)
+        .subcommand(
+            App::new("disasm")
+                .about("Disassemble a ROM into Octo source")
+                .arg(Arg::new("ROM").index(1).required(true))
+                .arg(
+                    Arg::new("output")
+                        .short('o')
+                        .long("output")
+                        .takes_value(true)
+                        .value_name("FILE")
+                        .help("Write the source to FILE instead of stdout"),
+                ),
+        )
+        .subcommand(
+            App::new("asm")
+                .about("Assemble Octo source into a ROM")
+                .arg(Arg::new("SOURCE").index(1).required(true))
+                .arg(
+                    Arg::new("output")
+                        .short('o')
+                        .long("output")
+                        .takes_value(true)
+                        .required(true)
+                        .value_name("FILE")
+                        .help("Write the ROM to FILE"),
+                ),
+        )
+        .subcommand(
+            App::new("patch")
+                .about("Apply or create IPS/BPS patches")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    App::new("apply")
+                        .about("Apply PATCH to ROM")
+                        .arg(Arg::new("ROM").index(1).required(true))
+                        .arg(Arg::new("PATCH").index(2).required(true))
+                        .arg(
+                            Arg::new("output")
+                                .short('o')
+                                .long("output")
+                                .takes_value(true)
+                                .required(true)
+                                .value_name("FILE")
+                                .help("Write the patched ROM to FILE"),
+                        ),
+                )
+                .subcommand(
+                    App::new("create")
+                        .about("Create a patch turning ORIGINAL into MODIFIED")
+                        .arg(Arg::new("ORIGINAL").index(1).required(true))
+                        .arg(Arg::new("MODIFIED").index(2).required(true))
+                        .arg(
+                            Arg::new("output")
+                                .short('o')
+                                .long("output")
+                                .takes_value(true)
+                                .required(true)
+                                .value_name("FILE")
+                                .help("Write the patch to FILE"),
+                        )
+                        .arg(
+                            Arg::new("format")
+                                .long("format")
+                                .takes_value(true)
+                                .possible_values(["ips", "bps"])
+                                .help("Patch format (default: guessed from the output extension, else IPS)"),
+                        ),
+                ),
+        )
+        .subcommand(
+            App::new("conformance")
+                .about("Run the built-in per-opcode conformance suite")
+                .arg(
+                    Arg::new("out")
+                        .long("out")
+                        .takes_value(true)
+                        .value_name("DIR")
+                        .help("Also write the generated test ROMs to DIR"),
+                ),
+        )
+        .subcommand(
+            App::new("selftest")
+                .about("Run Timendus' CHIP-8 test suite, judging the results by their marks and by passing runs")
+                .arg(
+                    Arg::new("DIR")
+                        .index(1)
+                        .required(true)
+                        .help("Directory holding the ROMs of the suite"),
+                )
+                .arg(
+                    Arg::new("bless")
+                        .long("bless")
+                        .help("Record the results of this run as the passing ones, except for the tests marking results as failing"),
+                )
+                .arg(
+                    Arg::new("screenshots")
+                        .long("screenshots")
+                        .takes_value(true)
+                        .value_name("DIR")
+                        .help("Save the final display of each test as a PNG file in DIR"),
+                ),
+        )
+        .subcommand(
+            App::new("dap")
+                .about("Serve the Debug Adapter Protocol, for debugging ROMs from an editor")
+                .arg(
+                    Arg::new("listen")
+                        .long("listen")
+                        .takes_value(true)
+                        .value_name("ADDR")
+                        .help("Accept debugging sessions on ADDR (e.g. 127.0.0.1:4711) instead of talking on stdin and stdout"),
+                ),
+        )
+        .subcommand(
+            App::new("audio-devices").about("List the audio output devices usable with --audio-device"),
+        )
+        .subcommand(
+            App::new("rom")
+                .about("Trim, pad or splice ROM files")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    App::new("trim")
+                        .about("Strip trailing zero bytes")
+                        .arg(Arg::new("ROM").index(1).required(true))
+                        .arg(
+                            Arg::new("output")
+                                .short('o')
+                                .long("output")
+                                .takes_value(true)
+                                .required(true)
+                                .value_name("FILE")
+                                .help("Write the resulting ROM to FILE"),
+                        ),
+                )
+                .subcommand(
+                    App::new("pad")
+                        .about("Pad a ROM to the given size")
+                        .arg(Arg::new("ROM").index(1).required(true))
+                        .arg(
+                            Arg::new("size")
+                                .long("size")
+                                .takes_value(true)
+                                .required(true)
+                                .value_name("BYTES")
+                                .help("Size of the padded ROM, in decimal or 0x-prefixed hex"),
+                        )
+                        .arg(
+                            Arg::new("fill")
+                                .long("fill")
+                                .takes_value(true)
+                                .default_value("0")
+                                .value_name("BYTE")
+                                .help("Value of the padding bytes"),
+                        )
+                        .arg(
+                            Arg::new("output")
+                                .short('o')
+                                .long("output")
+                                .takes_value(true)
+                                .required(true)
+                                .value_name("FILE")
+                                .help("Write the resulting ROM to FILE"),
+                        ),
+                )
+                .subcommand(
+                    App::new("splice")
+                        .about("Overwrite parts of a ROM with other binaries")
+                        .arg(Arg::new("ROM").index(1).required(true))
+                        .arg(
+                            Arg::new("INSERT")
+                                .index(2)
+                                .required(true)
+                                .multiple_values(true)
+                                .help("Binaries to splice in, as FILE@OFFSET (offset in the ROM file)"),
+                        )
+                        .arg(
+                            Arg::new("output")
+                                .short('o')
+                                .long("output")
+                                .takes_value(true)
+                                .required(true)
+                                .value_name("FILE")
+                                .help("Write the resulting ROM to FILE"),
+                        ),
+                ),
+        )
+        .arg(
+            Arg::new("ROM")
+                .index(1)
+                .help("ROM to run, zip archive containing it, or Octo source (.8o) to assemble and run; - reads it from standard input, and an http(s) URL downloads it (if omitted, a file dialog asks for one, and a built-in demo runs if none is picked)"),
+        )
+        .arg(
+            Arg::new("scale")
+                .required(false)
+                .takes_value(true)
+                .value_name("SCALE")
+                .short('s')
+                .long("scale")
+                .help("Size of a low resolution pixel on screen, in pixels (8 by default, at least 2); the window can also be resized"),
+        )
+        .arg(
+            Arg::new("config")
+                .long("config")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("Read the settings from FILE instead of the default config file, and save changes there"),
+        )
+        .arg(
+            Arg::new("dpi-aware")
+                .long("dpi-aware")
+                .takes_value(true)
+                .value_name("BOOL")
+                .possible_values(["true", "false"])
+                .default_value("true")
+                .help("Scale the window and GUI by the monitor's scale factor (turn off if they're the wrong size on a HiDPI screen)"),
+        )
+        .arg(
+            Arg::new("patch")
+                .long("patch")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("Apply the IPS or BPS patch FILE to the ROM before running it"),
+        )
+        .arg(
+            Arg::new("palette")
+                .long("palette")
+                .takes_value(true)
+                .value_name("NAME")
+                .possible_values(palette::PRESETS.iter().map(|p| p.name))
+                .help("Display colors (F6 cycles through them at runtime)"),
+        )
+        .arg(
+            Arg::new("fg")
+                .long("fg")
+                .takes_value(true)
+                .value_name("RRGGBB")
+                .help("Foreground color in hex, replacing the palette's"),
+        )
+        .arg(
+            Arg::new("bg")
+                .long("bg")
+                .takes_value(true)
+                .value_name("RRGGBB")
+                .help("Background color in hex, replacing the palette's"),
+        )
+        .arg(
+            Arg::new("afterglow")
+                .long("afterglow")
+                .takes_value(true)
+                .value_name("FRAMES")
+                .help("Let pixels fade out over FRAMES 60ths of a second once turned off, to reduce flicker (0 turns it off)"),
+        )
+        .arg(
+            Arg::new("ips")
+                .long("ips")
+                .takes_value(true)
+                .value_name("N")
+                .help("Execute N instructions per second at normal speed (default 1000, many ROMs expect about 700); hold Tab to fast-forward"),
+        )
+        .arg(
+            Arg::new("vip-timing")
+                .long("vip-timing")
+                .help("Give each instruction the time it took on the COSMAC VIP instead of running --ips instructions per second"),
+        )
+        .arg(
+            Arg::new("block-cache")
+                .long("block-cache")
+                .requires("headless")
+                .help("With --headless, decode straight-line runs of instructions once and cache them, for faster long runs"),
+        )
+        .arg(
+            Arg::new("no-db")
+                .long("no-db")
+                .help("Don't apply the settings of the built-in database of known ROMs (their quirks, speed and colors), which the command line flags override otherwise"),
+        )
+        .arg(
+            Arg::new("seed")
+                .long("seed")
+                .takes_value(true)
+                .value_name("N")
+                .help("Seed the random number generator so that runs are reproducible"),
+        )
+        .arg(
+            Arg::new("quirks")
+                .long("quirks")
+                .alias("profile")
+                .takes_value(true)
+                .value_name("PRESET")
+                .possible_values(["chip8", "chip48", "schip", "schip-legacy", "xochip", "megachip"])
+                .help("Use the quirks of PRESET instead of guessing them from the ROM's extension (.ch8, .sc8, .xo8 or .mc8); xochip and megachip also enable their instructions and 64K of RAM"),
+        )
+        .arg(
+            Arg::new("out-of-range")
+                .long("out-of-range")
+                .takes_value(true)
+                .value_name("POLICY")
+                .possible_values(["error", "wrap", "zero"])
+                .help("What reads and writes past the end of RAM do: stop with an error (the default), wrap around to the start, or read zeros and drop writes"),
+        )
+        .arg(
+            Arg::new("machine-code")
+                .long("machine-code")
+                .takes_value(true)
+                .value_name("POLICY")
+                .possible_values(["ignore", "warn", "halt"])
+                .help("What 0NNN calls to machine code routines do: nothing, log a warning (the default), or stop with an error"),
+        )
+        .arg(
+            Arg::new("stack-depth")
+                .long("stack-depth")
+                .takes_value(true)
+                .value_name("N")
+                .help("Allow N nested subroutine calls (default 16; the COSMAC VIP allowed 12)"),
+        )
+        .arg(
+            Arg::new("ram-stack")
+                .long("ram-stack")
+                .help("Keep the return addresses in RAM below 0x200, where programs can read and change them, as on the COSMAC VIP"),
+        )
+        .arg(
+            Arg::new("load-addr")
+                .long("load-addr")
+                .takes_value(true)
+                .value_name("ADDR")
+                .help("Load the program at ADDR instead of 0x200 and start it there, e.g. 0x600 for the ETI-660"),
+        )
+        .arg(
+            Arg::new("quirk")
+                .long("quirk")
+                .takes_value(true)
+                .multiple_occurrences(true)
+                .value_name("NAME[=BOOL]")
+                .help("Turn a single quirk on or off, overriding the preset and the settings file (e.g. vf_reset=false)"),
+        )
+        .arg(
+            Arg::new("keymap")
+                .long("keymap")
+                .takes_value(true)
+                .multiple_occurrences(true)
+                .value_name("KEY=DIGIT")
+                .help("Also map keyboard KEY (a winit key name, e.g. Up or Space) to keypad key DIGIT, on top of the [keys] of the settings file"),
+        )
+        .arg(
+            Arg::new("debug")
+                .long("debug")
+                .help("Start paused with the debugger open (F8 shows or hides it)"),
+        )
+        .arg(
+            Arg::new("fullscreen")
+                .long("fullscreen")
+                .help("Start in borderless fullscreen"),
+        )
+        .arg(
+            Arg::new("monitor")
+                .long("monitor")
+                .takes_value(true)
+                .value_name("N|NAME")
+                .help("Open the window, or go fullscreen, on the Nth monitor (from 1) or the one called NAME instead of the primary one"),
+        )
+        .arg(
+            Arg::new("compare")
+                .long("compare")
+                .takes_value(true)
+                .value_name("A,B")
+                .help("Run the ROM side by side with the quirks of profiles A and B (chip8, chip48, schip, schip-legacy, xochip or megachip)"),
+        )
+        .arg(
+            Arg::new("metrics")
+                .long("metrics")
+                .takes_value(true)
+                .value_name("ADDR")
+                .help("Serve Prometheus metrics on http://ADDR/metrics"),
+        )
+        .arg(
+            Arg::new("serve")
+                .long("serve")
+                .takes_value(true)
+                .value_name("ADDR")
+                .help("Run headless, serving an independent machine to each WebSocket client on ADDR"),
+        )
+        .arg(
+            Arg::new("headless")
+                .long("headless")
+                .requires("cycles")
+                .help("Run without a window for --cycles instructions, then print a CRC-32 of the display or write it to --frame"),
+        )
+        .arg(
+            Arg::new("cycles")
+                .long("cycles")
+                .takes_value(true)
+                .value_name("N")
+                .help("Number of instructions to execute with --headless"),
+        )
+        .arg(
+            Arg::new("frame")
+                .long("frame")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("Write the final display of a --headless run to FILE, as PNG if it ends in .png and PBM otherwise, or as PBM to stdout if `-`"),
+        )
+        .arg(
+            Arg::new("osc-listen")
+                .long("osc-listen")
+                .takes_value(true)
+                .value_name("ADDR")
+                .help("Receive keypad presses as OSC messages on UDP address ADDR"),
+        )
+        .arg(
+            Arg::new("osc-send")
+                .long("osc-send")
+                .takes_value(true)
+                .value_name("ADDR")
+                .help("Publish machine events (sound, frames) as OSC messages to UDP address ADDR"),
+        )
+        .arg(
+            Arg::new("symbols")
+                .long("symbols")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("Load symbols (one `name address` pair per line) for watch expressions"),
+        )
+        .arg(
+            Arg::new("watch")
+                .long("watch")
+                .takes_value(true)
+                .multiple_occurrences(true)
+                .value_name("EXPR")
+                .help("Log the value of EXPR (e.g. `[score] * 10 + V3`) whenever it changes"),
+        )
+        .arg(
+            Arg::new("coverage")
+                .long("coverage")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("Write which ROM bytes were executed, read as data or written to FILE on exit"),
+        )
+        .arg(
+            Arg::new("coverage-format")
+                .long("coverage-format")
+                .takes_value(true)
+                .value_name("FORMAT")
+                .possible_values(["report", "mask", "disasm"])
+                .default_value("report")
+                .help("Format of the --coverage file: a summary with ranges of code and data, one byte of flags per RAM address (1: executed, 2: read, 4: written), or an annotated disassembly of the ROM"),
+        )
+        .arg(
+            Arg::new("call-profile")
+                .long("call-profile")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("Write a per-subroutine instruction profile to FILE on exit"),
+        )
+        .arg(
+            Arg::new("flamegraph")
+                .long("flamegraph")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("Write the subroutine profile as folded stacks for flame graph tools to FILE on exit"),
+        )
+        .arg(
+            Arg::new("instruction-profile")
+                .long("instruction-profile")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("Write how many times each kind of instruction and each address was executed to FILE on exit, as JSON if it ends in .json, or to stdout if `-` (also printed with the statistics key)"),
+        )
+        .arg(
+            Arg::new("trace")
+                .long("trace")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("Write every executed instruction, with the registers it changed, to FILE"),
+        )
+        .arg(
+            Arg::new("trace-format")
+                .long("trace-format")
+                .takes_value(true)
+                .value_name("FORMAT")
+                .possible_values(["compact", "json"])
+                .default_value("compact")
+                .help("Format of the --trace lines: compact hex columns or one JSON object per line"),
+        )
+        .arg(
+            Arg::new("idle-report")
+                .long("idle-report")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("Detect busy-wait loops and write how many instructions were spent in them to FILE on exit, or to stdout if `-` (also printed with the statistics key)"),
+        )
+        .arg(
+            Arg::new("idle-skip")
+                .long("idle-skip")
+                .help("Skip the iterations of busy-wait loops up to the next timer tick in headless runs and while turbo is held, advancing the timers directly"),
+        )
+        .arg(
+            Arg::new("screenshot-every")
+                .long("screenshot-every")
+                .takes_value(true)
+                .value_name("N")
+                .help("Save a PNG of the display every N frames (60 per second)"),
+        )
+        .arg(
+            Arg::new("screenshot-dir")
+                .long("screenshot-dir")
+                .takes_value(true)
+                .value_name("DIR")
+                .default_value("screenshots")
+                .help("Directory for the --screenshot-every images, named after their frame number"),
+        )
+        .arg(
+            Arg::new("stats")
+                .long("stats")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("Write run statistics (instructions, frames, draws, faults...) to FILE, or stdout if `-`, on exit"),
+        )
+        .arg(
+            Arg::new("dump-state-on-exit")
+                .long("dump-state-on-exit")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("Write the final registers, timers, stack, RAM and display as JSON to FILE, or stdout if `-`, on exit (as a save state if FILE ends in .c8s)"),
+        )
+        .arg(
+            Arg::new("state")
+                .long("state")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("Resume from the save state in FILE if it exists, and save to it with F5 (default: the ROM file with a .c8s extension)"),
+        )
+        .arg(
+            Arg::new("record-movie")
+                .long("record-movie")
+                .takes_value(true)
+                .value_name("FILE")
+                .conflicts_with_all(&["playback", "state"])
+                .help("Record the keypad input into the movie FILE on exit, to be replayed with --playback"),
+        )
+        .arg(
+            Arg::new("playback")
+                .long("playback")
+                .takes_value(true)
+                .value_name("FILE")
+                .conflicts_with("state")
+                .help("Replay the keypad input recorded in the movie FILE, with its seed and instructions per second, instead of reading input"),
+        )
+        .arg(
+            Arg::new("buzzer")
+                .long("buzzer")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("Loop the sound in FILE (WAV or Ogg Vorbis) while the sound timer is active, instead of a tone"),
+        )
+        .arg(
+            Arg::new("tone")
+                .long("tone")
+                .takes_value(true)
+                .value_name("HZ")
+                .help("Frequency of the tone played while the sound timer is active [default: 440]"),
+        )
+        .arg(
+            Arg::new("volume")
+                .long("volume")
+                .takes_value(true)
+                .value_name("VOLUME")
+                .help("Volume of the buzzer, from 0.0 (silent) to 1.0 [default: 1.0]"),
+        )
+        .arg(
+            Arg::new("audio-device")
+                .long("audio-device")
+                .takes_value(true)
+                .value_name("NAME")
+                .help("Play sound on the output device NAME (see the audio-devices command) instead of the default one"),
+        )
+        .arg(
+            Arg::new("rumble")
+                .long("rumble")
+                .takes_value(true)
+                .value_name("INTENSITY")
+                .help("Pulse the controller's rumble motor at INTENSITY (0.0 to 1.0) while the buzzer sounds"),
+        )
+        .arg(
+            Arg::new("crowd")
+                .long("crowd")
+                .takes_value(true)
+                .value_name("ADDR")
+                .help("Accept keypad votes (one hex digit per line) from TCP clients on ADDR"),
+        )
+        .arg(
+            Arg::new("crowd-window")
+                .long("crowd-window")
+                .takes_value(true)
+                .value_name("MS")
+                .default_value("2000")
+                .help("Length of a crowd voting window in milliseconds"),
+        )
+        .get_matches();
 
-        Ok(Self {
-            cpu: Cpu::new(),
-            interconnect: Interconnect {
-                ram,
-                gfx: Gfx::new(),
-                delay_timer: 0,
-                sound_timer: 0,
-                keys: [false; 16],
-            },
-            ticks: 0,
-        })
+    if let Some(("disasm", matches)) = app.subcommand() {
+        return run_disasm(matches);
     }
-
-    pub fn gfx_buffer(&mut self) -> &[u8] {
-        self.interconnect.gfx.get_frame()
+    if let Some(("asm", matches)) = app.subcommand() {
+        return run_asm(matches);
     }
-
-    pub fn set_key(&mut self, key: u8, is_down: bool) {
-        self.interconnect.keys[key as usize] = is_down;
+    if let Some(("patch", matches)) = app.subcommand() {
+        return run_patch(matches);
     }
-
-    pub fn step(&mut self) {
-        self.ticks += 1;
-        self.cpu.emulate_cycle(&mut self.interconnect);
-        if self.ticks == 16 {
-            self.interconnect.tick();
-            self.ticks = 0;
+    if let Some(("rom", matches)) = app.subcommand() {
+        return run_rom(matches);
+    }
+    if let Some(("conformance", matches)) = app.subcommand() {
+        return run_conformance(matches);
+    }
+    if let Some(("selftest", matches)) = app.subcommand() {
+        return run_selftest(matches);
+    }
+    if let Some(("dap", matches)) = app.subcommand() {
+        return match matches.value_of("listen") {
+            Some(addr) => dap::serve(addr),
+            None => dap::serve_stdio(),
+        };
+    }
+    if let Some(("audio-devices", _)) = app.subcommand() {
+        for name in audio::output_devices()? {
+            println!("{}", name);
         }
+        return Ok(());
     }
-}
 
-pub struct Game {
-    chip8: Chip8,
-    pixels: Pixels,
-    input: WinitInputHelper,
-}
+    // Without a ROM argument, the window asks for one, so that the emulator can be started by
+    // double-clicking it.
+    let picked = match app.value_of("ROM") {
+        None if !app.is_present("headless") && !app.is_present("serve") => pick_rom(),
+        _ => None,
+    };
+    let rom = app.value_of("ROM").or(picked.as_deref());
+    let settings = match app.value_of("config") {
+        Some(path) => Settings::load_from(Path::new(path))?,
+        None => Settings::load().unwrap_or_else(|e| {
+            warn!("Ignoring saved settings: {:#}", e);
+            Settings::default()
+        }),
+    };
+    let scale: f32 = match app.value_of("scale") {
+        Some(scale) => scale.parse().context("Invalid scale factor")?,
+        None => settings.scale,
+    };
+    ensure!(
+        scale.is_finite() && scale >= config::MIN_SCALE,
+        "The scale must be at least {}, got {}",
+        config::MIN_SCALE,
+        scale
+    );
+    // Screenshots and recordings can only use whole pixels.
+    let image_scale = (scale.round() as usize).max(1);
 
-impl Game {
-    pub fn new(pixels: Pixels, chip8: Chip8) -> Result<Self> {
-        let input = WinitInputHelper::new();
-        Ok(Self {
-            chip8,
-            pixels,
-            input,
-        })
+    if let Some(addr) = app.value_of("serve") {
+        let rom = rom.context("--serve needs a ROM file")?;
+        return server::serve(rom.into(), addr);
     }
 
-    pub fn update(&mut self) {
-        self.chip8.step();
-    }
+    let load_addr = match app.value_of("load-addr") {
+        Some(addr) => u16::try_from(rom::parse_number(addr)?)
+            .ok()
+            .with_context(|| format!("Invalid load address {}", addr))?,
+        None => config::PROG_ADDR,
+    };
 
-    pub(crate) fn update_controls(&mut self, event: &Event<()>) {
-        self.input.update(event);
-        for (i, key) in KEYS.iter().enumerate() {
-            self.chip8.set_key(i as u8, self.input.key_held(*key));
+    let mut quirks = QuirkOverrides::default();
+    for spec in app.values_of("quirk").into_iter().flatten() {
+        quirks
+            .parse_one(spec)
+            .with_context(|| format!("Invalid --quirk {}", spec))?;
+    }
+    let color = |name| app.value_of(name).map(palette::parse_color).transpose();
+    let rom_options = RomOptions {
+        load_addr,
+        use_db: !app.is_present("no-db"),
+        profile: app.value_of("quirks").map(str::parse).transpose()?,
+        quirks,
+        overrides: Overrides {
+            palette: app
+                .value_of("palette")
+                .map(|name| Palette::by_name(name).context("Unknown palette"))
+                .transpose()?,
+            background: color("bg").context("Invalid --bg")?,
+            foreground: color("fg").context("Invalid --fg")?,
+            ips: app
+                .value_of("ips")
+                .map(|ips| {
+                    ips.parse()
+                        .ok()
+                        .filter(|&ips| ips > 0)
+                        .context("Invalid instructions per second")
+                })
+                .transpose()?,
+        },
+    };
+    let mut loaded = match rom {
+        Some(rom) => {
+            let (file, data) = load_rom(rom)?;
+            LoadedRom::new(file, data, &rom_options)?
         }
+        None => {
+            info!("no ROM given, running the built-in demo");
+            LoadedRom::demo()
+        }
+    };
+    // Where the ROM's metadata and save state are: next to the ROM file, or in the current
+    // directory for a downloaded ROM. A ROM read from standard input has none, and saves its
+    // state to `demo.c8s` like the demo.
+    let rom_path = rom.filter(|rom| *rom != "-").and_then(|rom| {
+        let path = if fetch::is_url(rom) {
+            fetch::file_name(rom)
+        } else {
+            rom
+        };
+        (!path.is_empty()).then(|| PathBuf::from(path))
+    });
+    if let Some(path) = app.value_of("patch") {
+        info!("applying patch {}", path);
+        let p = std::fs::read(path).with_context(|| format!("Failed to read {}", path))?;
+        loaded.bytes =
+            patch::apply(&loaded.bytes, &p).with_context(|| format!("Failed to apply {}", path))?;
     }
-}
-
-fn main() -> Result<()> {
-    env_logger::init();
-
-    let app = App::new("chip8rs")
-        .author("Antoine Busch")
-        .version("0.1")
-        .arg(Arg::new("ROM").index(1).required(true))
-        .arg(
-            Arg::new("scale")
-                .required(false)
-                .default_value("8")
-                .possible_values(&["1", "2", "4", "8", "16", "32"])
-                .short('s')
-                .long("scale"),
-        )
-        .get_matches();
-
-    let rom = app.value_of("ROM").expect("Missing ROM file");
-    let scale = match app.value_of("scale").context("Missing scale")? {
-        "1" => 1.0,
-        "2" => 2.0,
-        "4" => 4.0,
-        "8" => 8.0,
-        "16" => 16.0,
-        "32" => 32.0,
-        _ => bail!("Invalid scale factor"),
+    let metadata = rom_path
+        .as_deref()
+        .and_then(|rom| match Metadata::load_sidecar(rom) {
+            Ok(metadata) => metadata,
+            Err(e) => {
+                warn!("Ignoring ROM metadata: {:#}", e);
+                None
+            }
+        });
+    let name = metadata
+        .as_ref()
+        .and_then(Metadata::display_name)
+        .or_else(|| loaded.known.as_ref().and_then(Entry::display_name));
+    let icon = metadata.as_ref().and_then(|metadata| {
+        let icon = metadata.load_icon().and_then(|icon| match icon {
+            Some((rgba, width, height)) => Ok(Some(Icon::from_rgba(rgba, width, height)?)),
+            None => Ok(None),
+        });
+        icon.unwrap_or_else(|e| {
+            warn!("Ignoring ROM icon: {:#}", e);
+            None
+        })
+    });
+    let profile = loaded.profile(&rom_options, settings.quirks_preset.as_deref())?;
+    let overrides = loaded.overrides(rom_options.overrides);
+    let bytes = loaded.bytes;
+    let mut chip8 = machine(&bytes, profile)?;
+    chip8.set_quirks(rom_options.quirks(profile, &settings.quirks));
+    let mut title = format!("Chip8rs -- {}", name.as_deref().unwrap_or("Chip8 Emulator"));
+    let mut compare = match app.value_of("compare") {
+        Some(profiles) => {
+            let (a, b) = profiles
+                .split_once(',')
+                .context("Expected two profiles to compare, e.g. chip8,schip")?;
+            let (a, b): (Profile, Profile) = (a.parse()?, b.parse()?);
+            chip8 = machine(&bytes, Some(a))?;
+            chip8.set_quirks(Quirks::for_profile(a));
+            let mut other = machine(&bytes, Some(b))?;
+            other.set_quirks(Quirks::for_profile(b));
+            let name = name.as_deref().unwrap_or("Chip8rs");
+            title = format!("{} -- {} (left) vs {} (right)", name, a, b);
+            Some(other)
+        }
+        None => None,
+    };
+    if app.is_present("vip-timing") {
+        chip8.set_vip_timing(true);
+        if let Some(other) = &mut compare {
+            other.set_vip_timing(true);
+        }
+    }
+    if app.is_present("block-cache") {
+        chip8.set_block_cache(true);
+    }
+    if let Some(path) = app.value_of("trace") {
+        let format = app
+            .value_of("trace-format")
+            .context("Missing trace format")?
+            .parse()?;
+        chip8.enable_trace(Tracer::create(Path::new(path), format)?);
+    }
+    if app.is_present("instruction-profile") {
+        chip8.enable_instruction_profiler();
+    }
+    if app.is_present("idle-report") || app.is_present("idle-skip") {
+        chip8.enable_idle_detection(app.is_present("idle-skip"));
+    }
+    if let Some(policy) = app.value_of("out-of-range") {
+        let policy: OutOfRange = policy.parse()?;
+        chip8.interconnect.ram.out_of_range = policy;
+        if let Some(other) = &mut compare {
+            other.interconnect.ram.out_of_range = policy;
+        }
+    }
+    if let Some(policy) = app.value_of("machine-code") {
+        let policy: SysPolicy = policy.parse()?;
+        chip8.cpu.sys_policy = policy;
+        if let Some(other) = &mut compare {
+            other.cpu.sys_policy = policy;
+        }
+    }
+    if load_addr != config::PROG_ADDR {
+        chip8.set_load_addr(load_addr)?;
+        if let Some(other) = &mut compare {
+            other.set_load_addr(load_addr)?;
+        }
+    }
+    if app.is_present("stack-depth") || app.is_present("ram-stack") {
+        let depth = match app.value_of("stack-depth") {
+            Some(depth) => depth.parse().context("Invalid stack depth")?,
+            None => config::DEFAULT_STACK_DEPTH,
+        };
+        let in_ram = app.is_present("ram-stack");
+        chip8.set_stack(depth, in_ram)?;
+        if let Some(other) = &mut compare {
+            other.set_stack(depth, in_ram)?;
+        }
+    }
+    let movie = match app.value_of("playback") {
+        Some(path) => {
+            let text = std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read {}", path))?;
+            let movie = Movie::decode(&text).with_context(|| format!("Invalid movie {}", path))?;
+            if movie.rom_crc != patch::crc32(&bytes) {
+                warn!(
+                    "{} was recorded on another ROM, it won't replay the same",
+                    path
+                );
+            }
+            Some(movie)
+        }
+        None => None,
+    };
+    // Movies can only be replayed with the random numbers they were recorded with.
+    let seed = match (&movie, app.value_of("seed")) {
+        (Some(movie), _) => Some(movie.seed),
+        (None, Some(seed)) => Some(seed.parse().context("Invalid seed")?),
+        (None, None) if app.is_present("record-movie") => Some(rand::random()),
+        (None, None) => None,
     };
+    if let Some(seed) = seed {
+        chip8.set_seed(seed);
+        if let Some(other) = &mut compare {
+            other.set_seed(seed);
+        }
+    }
+    let mut keymap = None;
+    for spec in app.values_of("keymap").into_iter().flatten() {
+        keymap
+            .get_or_insert_with(|| settings.keys.clone())
+            .parse_one(spec)
+            .with_context(|| format!("Invalid --keymap {}", spec))?;
+    }
 
-    info!("loading rom {}", rom);
-    let chip8 = Chip8::new(rom)?;
+    let coverage_format: CoverageFormat = app
+        .value_of("coverage-format")
+        .context("Missing coverage format")?
+        .parse()?;
+    if app.is_present("headless") {
+        if app.is_present("coverage") {
+            let ram_size = chip8.interconnect.ram.size();
+            chip8.enable_coverage(Coverage::new(chip8.load_addr(), &bytes, ram_size));
+        }
+        let cycles = app
+            .value_of("cycles")
+            .context("Missing cycles")?
+            .parse()
+            .context("Invalid cycle count")?;
+        let palette = overrides
+            .palette
+            .unwrap_or_default()
+            .with_colors(overrides.background, overrides.foreground);
+        let ips = match &movie {
+            Some(movie) => movie.ips,
+            None => overrides.ips.unwrap_or(config::DEFAULT_IPS),
+        };
+        let result = run_headless(
+            &mut chip8,
+            cycles,
+            ips,
+            movie.as_ref().map(Player::new),
+            app.value_of("frame"),
+            &palette,
+            image_scale,
+        );
+        if let (Some(path), Some(profiler)) = (
+            app.value_of("instruction-profile"),
+            chip8.instruction_profiler(),
+        ) {
+            write_instruction_profile(profiler, Path::new(path))?;
+        }
+        if let (Some(path), Some(idle)) = (app.value_of("idle-report"), chip8.idle_loops()) {
+            write_idle_report(idle, Path::new(path))?;
+        }
+        if let (Some(path), Some(coverage)) = (app.value_of("coverage"), chip8.coverage()) {
+            std::fs::write(path, coverage.export(coverage_format))
+                .with_context(|| format!("Failed to write {}", path))?;
+        }
+        return result;
+    }
+    let columns = if compare.is_some() { 2 } else { 1 };
+    if let Some(addr) = app.value_of("metrics") {
+        metrics::serve(chip8.metrics(), addr)?;
+    }
 
+    let dpi_aware = app.value_of("dpi-aware") != Some("false");
     let event_loop = EventLoop::new();
+    let monitor = match app.value_of("monitor") {
+        Some(spec) => Some(select_monitor(&event_loop, spec)?),
+        None => event_loop.primary_monitor(),
+    };
+    let scale_factor = if dpi_aware {
+        monitor
+            .as_ref()
+            .map_or(1.0, |monitor| monitor.scale_factor())
+    } else {
+        1.0
+    };
     let window = {
-        let size = LogicalSize::new(WIDTH as f64, HEIGHT as f64);
-        let scaled_size = LogicalSize::new(WIDTH as f64 * scale, HEIGHT as f64 * scale);
-        WindowBuilder::new()
-            .with_title("Chip8rs -- Chip8 Emulator")
+        // Sized in physical pixels. `scale` is the size of a low resolution pixel. The display is
+        // drawn with a whole number of physical pixels per high resolution pixel so that it stays
+        // sharp, and centered in what's left with other scales or once the window is resized.
+        let width = (WIDTH * columns) as u32;
+        let pixel_size = (scale as f64 * scale_factor / 2.0).max(1.0);
+        let size = PhysicalSize::new(width, HEIGHT as u32);
+        let scaled_size = PhysicalSize::new(
+            (width as f64 * pixel_size).round() as u32,
+            (HEIGHT as f64 * pixel_size).round() as u32,
+        );
+        let mut builder = WindowBuilder::new()
+            .with_title(title)
+            .with_window_icon(icon)
             .with_inner_size(scaled_size)
-            .with_min_inner_size(size)
-            .build(&event_loop)
-            .unwrap()
+            .with_min_inner_size(size);
+        if app.is_present("fullscreen") {
+            builder = builder.with_fullscreen(Some(Fullscreen::Borderless(monitor)));
+        } else if let (Some(monitor), true) = (monitor, app.is_present("monitor")) {
+            // Centered on the chosen monitor, as the window manager would otherwise pick one.
+            let (origin, area) = (monitor.position(), monitor.size());
+            builder = builder.with_position(PhysicalPosition::new(
+                origin.x + (area.width as i32 - scaled_size.width as i32) / 2,
+                origin.y + (area.height as i32 - scaled_size.height as i32) / 2,
+            ));
+        }
+        builder.build(&event_loop).unwrap()
     };
 
-    let pixels = {
+    let (pixels, framework) = {
         let window_size = window.inner_size();
         let surface_texture = SurfaceTexture::new(window_size.width, window_size.height, &window);
-        Pixels::new(WIDTH as u32, HEIGHT as u32, surface_texture)?
+        let pixels = Pixels::new((WIDTH * columns) as u32, HEIGHT as u32, surface_texture)?;
+        let gui_scale_factor = if dpi_aware {
+            window.scale_factor()
+        } else {
+            1.0
+        };
+        let framework = Framework::new(
+            window_size.width,
+            window_size.height,
+            gui_scale_factor as f32,
+            &pixels,
+        );
+        (pixels, framework)
     };
 
-    let game = Game::new(pixels, chip8)?;
+    let mut game = Game::new(pixels, framework, chip8)?;
+    game.set_scale(image_scale);
+    if let Some(path) = app.value_of("config") {
+        game.set_settings_path(path.into());
+    }
+    let rom_name = rom_path.as_deref().and_then(Path::file_name);
+    if let Some(name) = rom_name {
+        game.set_rom_name(name.to_string_lossy().into_owned());
+    }
+    if let Some(rom) = rom.filter(|rom| *rom != "-" && !fetch::is_url(rom)) {
+        game.add_recent_rom(Path::new(rom));
+    }
+    game.set_dpi_aware(dpi_aware);
+    if let Some(other) = compare {
+        game.set_comparison(other);
+    }
+    game.set_rom_options(rom_options);
+    game.apply_settings(&settings);
+    game.set_overrides(overrides);
+    if let Some(frames) = app.value_of("afterglow") {
+        game.set_afterglow(frames.parse().context("Invalid afterglow")?);
+    }
+    if let Some(keymap) = keymap {
+        game.set_keymap(&keymap).context("Invalid --keymap")?;
+    }
+    if let Some(addr) = app.value_of("crowd") {
+        let voting_window = app
+            .value_of("crowd-window")
+            .context("Missing crowd window")?
+            .parse()
+            .context("Invalid crowd window")?;
+        game.set_crowd(Crowd::listen(
+            addr,
+            Duration::from_millis(voting_window),
+        )?);
+    }
+    let symbols = match app.value_of("symbols") {
+        Some(path) => Symbols::load(path)?,
+        None => Symbols::default(),
+    };
+    for expr in app.values_of("watch").into_iter().flatten() {
+        game.add_watch(Watch::parse(expr, &symbols)?);
+    }
+    game.set_symbols(symbols);
+    if app.is_present("debug") {
+        game.debugger.pause();
+    }
+    let call_profile = app.value_of("call-profile");
+    let flamegraph = app.value_of("flamegraph");
+    if call_profile.is_some() || flamegraph.is_some() {
+        game.set_profile_output(call_profile.map(Into::into), flamegraph.map(Into::into));
+    }
+    if let Some(path) = app.value_of("coverage") {
+        game.set_coverage_output(path.into(), coverage_format, &bytes);
+    }
+    if let Some(path) = app.value_of("instruction-profile") {
+        game.set_instruction_profile_output(path.into());
+    }
+    if let Some(path) = app.value_of("idle-report") {
+        game.set_idle_report_output(path.into());
+    }
+    if let Some(every) = app.value_of("screenshot-every") {
+        let every = every.parse().context("Invalid screenshot interval")?;
+        let dir = app
+            .value_of("screenshot-dir")
+            .context("Missing screenshot dir")?;
+        game.set_screenshots(screenshot::Series::new(dir.into(), every, image_scale)?);
+    }
+    if let Some(path) = app.value_of("stats") {
+        game.set_stats_output(path.into());
+    }
+    if let Some(path) = app.value_of("dump-state-on-exit") {
+        game.set_state_output(path.into());
+    }
+    let state_file = match (app.value_of("state"), &rom_path) {
+        (Some(path), _) => PathBuf::from(path),
+        (None, Some(rom)) => rom.with_extension("c8s"),
+        (None, None) => PathBuf::from("demo.c8s"),
+    };
+    // A new state file is only saved to, with F5.
+    let resume = app.is_present("state") && state_file.exists();
+    game.set_state_file(state_file);
+    if resume {
+        game.load_state()?;
+    }
+    if let Some(path) = app.value_of("record-movie") {
+        let ips = overrides.ips.unwrap_or(settings.ips);
+        let movie = Movie::new(seed.unwrap_or_default(), ips, patch::crc32(&bytes));
+        game.record_movie(movie, path.into());
+    }
+    if let Some(movie) = &movie {
+        game.play_movie(movie);
+    }
+    let device = app
+        .value_of("audio-device")
+        .or(settings.audio_device.as_deref());
+    let frequency: f32 = match app.value_of("tone") {
+        Some(tone) => tone.parse().context("Invalid tone frequency")?,
+        None => settings.tone,
+    };
+    let volume: f32 = match app.value_of("volume") {
+        Some(volume) => volume.parse().context("Invalid volume")?,
+        None => settings.volume,
+    };
+    if frequency <= 0.0 {
+        bail!("The tone frequency must be positive");
+    }
+    if !(0.0..=1.0).contains(&volume) {
+        bail!("The volume must be between 0.0 and 1.0");
+    }
+    let sample = app
+        .value_of("buzzer")
+        .map(PathBuf::from)
+        .or_else(|| settings.buzzer.clone());
+    let buzzer = match sample {
+        Some(path) => Some(Buzzer::with_sample(path, device)?),
+        // Without a sample, a missing audio output only means no sound.
+        None => Buzzer::new(device)
+            .map_err(|e| warn!("Sound is off: {:#}", e))
+            .ok(),
+    };
+    if let Some(mut buzzer) = buzzer {
+        buzzer.set_frequency(frequency);
+        buzzer.set_volume(volume);
+        game.set_buzzer(buzzer);
+    }
+    let rumble = match app.value_of("rumble") {
+        Some(rumble) => rumble.parse().context("Invalid rumble intensity")?,
+        None => 0.0,
+    };
+    match Gamepads::new(rumble) {
+        Ok(gamepads) => game.set_gamepads(gamepads),
+        Err(e) => warn!("Controllers are off: {:#}", e),
+    }
+    let osc_listen = app.value_of("osc-listen");
+    let osc_send = app.value_of("osc-send");
+    if osc_listen.is_some() || osc_send.is_some() {
+        game.set_osc(OscBridge::new(osc_listen, osc_send)?);
+    }
 
     game_loop(
         event_loop,
         window,
         game,
-        1000,
+        UPDATES_PER_SECOND,
         0.1,
         |g| {
             /* update */
@@ -163,50 +2497,623 @@ fn main() -> Result<()> {
         },
         |g| {
             /* render */
-            if g.game.chip8.interconnect.gfx.dirty {
-                g.game
-                    .pixels
-                    .get_frame()
-                    .chunks_exact_mut(4)
-                    .zip(g.game.chip8.gfx_buffer().iter())
-                    .for_each(|(b, v)| {
-                        if *v == 0 {
-                            b.copy_from_slice(&[0, 0, 0, 0])
-                        } else {
-                            b.copy_from_slice(&[255, 255, 255, 255])
-                        }
-                    });
-                if let Err(e) = g.game.pixels.render() {
-                    error!("Render error: {}", e);
-                    g.exit();
-                }
+            g.game.frame_rendered();
+            if let Err(e) = g.game.render(&g.window) {
+                error!("Render error: {}", e);
+                g.game.finish();
+                g.exit();
+            }
+            if g.game.quit_requested() {
+                g.game.finish();
+                g.exit();
+            }
+            // Don't spin while the game waits for a key press: key events are still handled
+            // between iterations, and the timers catch up on the next updates.
+            if g.game.is_idle() {
+                std::thread::sleep(IDLE_SLEEP);
             }
         },
         |g, event| {
+            if let Event::WindowEvent { event, .. } = event {
+                let ignored =
+                    !g.game.dpi_aware && matches!(event, WindowEvent::ScaleFactorChanged { .. });
+                if !ignored {
+                    g.game.framework.handle_event(event);
+                }
+                if let WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            virtual_keycode: Some(key),
+                            state: ElementState::Pressed,
+                            ..
+                        },
+                    ..
+                } = event
+                {
+                    // While a key is being rebound in the menu, the press is only used for that.
+                    let Game { menu, hotkeys, .. } = &mut g.game;
+                    if menu.key_pressed(*key, hotkeys) {
+                        return;
+                    }
+                }
+                if let WindowEvent::DroppedFile(path) = event {
+                    if let Err(e) = g.game.load_rom(&g.window, path) {
+                        error!("{:#}", e);
+                    }
+                }
+            }
             g.game.update_controls(&event);
+            if let Some(size) = g.game.input.window_resized() {
+                g.game.resize(size.width, size.height);
+            }
+            if let Some(scale_factor) = g.game.input.scale_factor_changed() {
+                g.game.set_scale_factor(scale_factor);
+            }
+            for action in g.game.pressed_actions() {
+                match action {
+                    Action::Menu => g.game.toggle_menu(),
+                    Action::Help => g.game.help.open = !g.game.help.open,
+                    Action::SpriteEditor => {
+                        g.game.sprite_editor.open = !g.game.sprite_editor.open
+                    }
+                    Action::Debugger => g.game.debugger.open = !g.game.debugger.open,
+                    Action::Inspector => g.game.inspector.open = !g.game.inspector.open,
+                    Action::CyclePalette => g.game.cycle_palette(),
+                    Action::Stats => g.game.print_stats(),
+                    Action::SaveState => g.game.save_state(),
+                    Action::Screenshot => g.game.screenshot(),
+                    Action::Record => g.game.toggle_recording(),
+                    Action::Pause => g.game.toggle_pause(),
+                    Action::FrameAdvance => g.game.advance_frame(),
+                    Action::Fullscreen => toggle_fullscreen(&g.window),
+                    Action::LoadState => {
+                        if let Err(e) = g.game.load_state() {
+                            error!("{:#}", e);
+                        }
+                    }
+                    // Handled while held, in `update_controls`.
+                    Action::Rewind | Action::Turbo => {}
+                    Action::Quit => {
+                        g.game.finish();
+                        g.exit();
+                    }
+                }
+            }
             // Close events
-            if g.game.input.key_pressed(VirtualKeyCode::Escape) || g.game.input.quit() {
+            if g.game.input.quit() {
+                g.game.finish();
                 g.exit();
             }
         },
     );
 }
 
-const KEYS: [VirtualKeyCode; 16] = [
-    VirtualKeyCode::X,
-    VirtualKeyCode::Key1,
-    VirtualKeyCode::Key2,
-    VirtualKeyCode::Key3,
-    VirtualKeyCode::Q,
-    VirtualKeyCode::W,
-    VirtualKeyCode::E,
-    VirtualKeyCode::A,
-    VirtualKeyCode::S,
-    VirtualKeyCode::D,
-    VirtualKeyCode::Z,
-    VirtualKeyCode::C,
-    VirtualKeyCode::Key4,
-    VirtualKeyCode::R,
-    VirtualKeyCode::F,
-    VirtualKeyCode::V,
-];
+/// Switch `window` between a window and borderless fullscreen on its current monitor. The display
+/// keeps its aspect ratio, scaled by a whole number and centered.
+fn toggle_fullscreen(window: &Window) {
+    let fullscreen = match window.fullscreen() {
+        Some(_) => None,
+        None => Some(Fullscreen::Borderless(window.current_monitor())),
+    };
+    window.set_fullscreen(fullscreen);
+}
+
+/// Convert an sRGB color component to the linear value expected for the clear color.
+fn srgb_to_linear(c: u8) -> f64 {
+    let c = c as f64 / 255.0;
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// How to load ROMs, from the command line.
+#[derive(Debug, Clone)]
+pub struct RomOptions {
+    /// Where programs start in RAM.
+    pub load_addr: u16,
+    /// Whether to look ROMs up in the ROM database.
+    pub use_db: bool,
+    /// Quirks preset from `--quirks`, used whatever the ROM.
+    pub profile: Option<Profile>,
+    /// Quirks forced on or off with `--quirk`.
+    pub quirks: QuirkOverrides,
+    /// Settings from the command line, taking precedence over the database's.
+    pub overrides: Overrides,
+}
+
+impl Default for RomOptions {
+    fn default() -> Self {
+        Self {
+            load_addr: config::PROG_ADDR,
+            use_db: true,
+            profile: None,
+            quirks: QuirkOverrides::default(),
+            overrides: Overrides::default(),
+        }
+    }
+}
+
+impl RomOptions {
+    /// The quirks of `profile`, or the default ones without a profile, with the ones from
+    /// `settings` and then from the command line forced on or off.
+    fn quirks(&self, profile: Option<Profile>, settings: &QuirkOverrides) -> Quirks {
+        let mut quirks = profile.map(Quirks::for_profile).unwrap_or_default();
+        quirks.apply(settings);
+        quirks.apply(&self.quirks);
+        quirks
+    }
+}
+
+/// A ROM ready to run, with its entry in the ROM database.
+struct LoadedRom {
+    /// The file the ROM came from, inside the archive for a zipped ROM. `None` for the demo.
+    file: Option<PathBuf>,
+    bytes: Vec<u8>,
+    known: Option<Entry>,
+}
+
+impl LoadedRom {
+    /// The ROM read from `file`, assembled first if it's Octo source.
+    fn new(file: PathBuf, data: Vec<u8>, options: &RomOptions) -> Result<Self> {
+        let bytes = if file.extension().is_some_and(|ext| ext == "8o") {
+            info!("assembling {}", file.display());
+            let source = String::from_utf8(data)
+                .with_context(|| format!("{} isn't a text file", file.display()))?;
+            octo::assemble_at(&source, options.load_addr)
+                .with_context(|| format!("Failed to assemble {}", file.display()))?
+        } else {
+            data
+        };
+        // Looked up before any patch, since the database only knows the original ROMs.
+        let known = if options.use_db {
+            Database::bundled().lookup(&bytes).cloned()
+        } else {
+            None
+        };
+        if let Some(entry) = &known {
+            info!(
+                "found {} in the ROM database",
+                entry.display_name().as_deref().unwrap_or("the ROM")
+            );
+        }
+        Ok(Self {
+            file: Some(file),
+            bytes,
+            known,
+        })
+    }
+
+    fn demo() -> Self {
+        Self {
+            file: None,
+            bytes: demo::rom(),
+            known: None,
+        }
+    }
+
+    /// The quirks preset to run the ROM with: the one from the command line, the database's,
+    /// `preset` from the settings or the one for the file extension, if any. Logs what the ROM
+    /// looks like, with a warning if it doesn't match.
+    fn profile(&self, options: &RomOptions, preset: Option<&str>) -> Result<Option<Profile>> {
+        let detection = detect::detect_at(&self.bytes, options.load_addr);
+        info!("ROM looks like a {} program", detection.profile);
+        for reason in &detection.reasons {
+            info!("  {}", reason);
+        }
+        let profile = if let Some(profile) = options.profile {
+            Some(profile)
+        } else if let Some(profile) = self.known.as_ref().and_then(Entry::quirks) {
+            Some(profile)
+        } else if let Some(profile) = preset {
+            Some(profile.parse()?)
+        } else {
+            self.file.as_deref().and_then(detect::from_extension)
+        };
+        if let Some(profile) = profile {
+            info!("using the {} quirks", profile);
+            if profile != detection.profile {
+                warn!(
+                    "ROM looks like a {} program but runs as {}; use --quirks to change it",
+                    detection.profile, profile
+                );
+            }
+        }
+        Ok(profile)
+    }
+
+    /// `overrides`, with the speed and colors from the database where they aren't set.
+    fn overrides(&self, overrides: Overrides) -> Overrides {
+        let known = self.known.as_ref();
+        Overrides {
+            background: overrides
+                .background
+                .or_else(|| known.and_then(Entry::background)),
+            foreground: overrides
+                .foreground
+                .or_else(|| known.and_then(Entry::foreground)),
+            ips: overrides.ips.or_else(|| known.and_then(|entry| entry.ips)),
+            ..overrides
+        }
+    }
+}
+
+/// Read the ROM argument `rom`: a file, `-` for standard input or an HTTP(S) URL, any of which
+/// can be a zip archive. Also returns the path of the ROM file, as `archive::unpack`.
+fn load_rom(rom: &str) -> Result<(PathBuf, Vec<u8>)> {
+    if rom == "-" {
+        info!("reading the rom from standard input");
+        archive::unpack(Path::new(rom), fetch::stdin()?)
+    } else if fetch::is_url(rom) {
+        info!("downloading {}", rom);
+        archive::unpack(Path::new(fetch::file_name(rom)), fetch::download(rom)?)
+    } else {
+        info!("loading rom {}", rom);
+        archive::read_rom(Path::new(rom))
+    }
+}
+
+/// Ask for a ROM to run with the system's file dialog.
+fn pick_rom() -> Option<String> {
+    let path = rfd::FileDialog::new()
+        .set_title("Open a CHIP-8 ROM")
+        .add_filter(
+            "CHIP-8 ROMs",
+            &["ch8", "c8", "sc8", "xo8", "mc8", "8o", "zip"],
+        )
+        .pick_file()?;
+    match path.into_os_string().into_string() {
+        Ok(path) => Some(path),
+        Err(path) => {
+            warn!("Can't open {:?}: the path isn't valid UTF-8", path);
+            None
+        }
+    }
+}
+
+/// Create a machine running `rom`, with the XO-CHIP or Mega-Chip extensions if `profile` is one
+/// of those.
+fn machine(rom: &[u8], profile: Option<Profile>) -> Result<Chip8> {
+    Chip8::check_rom(
+        rom,
+        matches!(profile, Some(Profile::XoChip | Profile::MegaChip)),
+    )?;
+    Ok(match profile {
+        Some(Profile::XoChip) => Chip8::from_bytes_xochip(rom),
+        Some(Profile::MegaChip) => Chip8::from_bytes_megachip(rom),
+        _ => Chip8::from_bytes(rom),
+    })
+}
+
+/// Find a monitor by its position in the list of monitors, starting at 1, or by name.
+fn select_monitor(event_loop: &EventLoop<()>, spec: &str) -> Result<MonitorHandle> {
+    let monitors: Vec<MonitorHandle> = event_loop.available_monitors().collect();
+    let found = match spec.parse::<usize>() {
+        Ok(index) => index.checked_sub(1).and_then(|i| monitors.get(i)),
+        Err(_) => monitors
+            .iter()
+            .find(|monitor| monitor.name().as_deref() == Some(spec)),
+    };
+    if let Some(monitor) = found {
+        return Ok(monitor.clone());
+    }
+    let available: Vec<String> = monitors
+        .iter()
+        .enumerate()
+        .map(|(i, monitor)| {
+            let name = monitor.name().unwrap_or_else(|| "unnamed".to_string());
+            format!("{}: {}", i + 1, name)
+        })
+        .collect();
+    bail!(
+        "No monitor {:?} (available: {})",
+        spec,
+        available.join(", ")
+    )
+}
+
+/// Run `chip8` for `cycles` instructions without a window, timing them at `ips` instructions
+/// per second unless it has VIP timing, and replaying the input of `player` if given, then print
+/// a CRC-32 of the display, or save it to `frame`.
+fn run_headless(
+    chip8: &mut Chip8,
+    cycles: u64,
+    ips: u32,
+    mut player: Option<Player>,
+    frame: Option<&str>,
+    palette: &Palette,
+    scale: usize,
+) -> Result<()> {
+    let start = chip8.cycles();
+    let result = match &mut player {
+        // Without input to replay at given cycles, the whole run can be done at once.
+        None => chip8.run(cycles, ips),
+        Some(player) => (0..cycles).try_for_each(|_| {
+            player.apply(chip8);
+            let period = chip8.instruction_time(ips);
+            chip8.step()?;
+            chip8.advance_time(period);
+            Ok(())
+        }),
+    };
+    if let Some(mut tracer) = chip8.take_tracer() {
+        tracer.finish().context("Failed to write trace")?;
+    }
+    result.with_context(|| {
+        format!(
+            "Stopped at {:#05x} after {} cycles",
+            chip8.cpu.pc(),
+            chip8.cycles() - start
+        )
+    })?;
+    let (display, width) = (chip8.display(), chip8.display_width());
+    match frame {
+        None => println!("{:08x}", patch::crc32(display)),
+        Some("-") => print!("{}", screenshot::encode_pbm(display, width)),
+        Some(path) if path.ends_with(".png") => {
+            screenshot::save_png(Path::new(path), display, width, palette, scale)?
+        }
+        Some(path) => std::fs::write(path, screenshot::encode_pbm(display, width))
+            .with_context(|| format!("Failed to write {}", path))?,
+    }
+    Ok(())
+}
+
+/// Write the counts of `profiler` to `path`: as JSON if it ends in `.json`, and to stdout if it's
+/// `-`.
+fn write_instruction_profile(profiler: &InstructionProfiler, path: &Path) -> Result<()> {
+    let report = if path.extension().is_some_and(|ext| ext == "json") {
+        profiler.json()
+    } else {
+        profiler.report()
+    };
+    if path.as_os_str() == "-" {
+        print!("{}", report);
+        Ok(())
+    } else {
+        std::fs::write(path, report).with_context(|| format!("Failed to write {}", path.display()))
+    }
+}
+
+/// Write the report of `idle` to `path`, or to stdout if it's `-`.
+fn write_idle_report(idle: &IdleLoops, path: &Path) -> Result<()> {
+    if path.as_os_str() == "-" {
+        print!("{}", idle.report());
+        Ok(())
+    } else {
+        std::fs::write(path, idle.report())
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+}
+
+fn run_disasm(matches: &ArgMatches) -> Result<()> {
+    let path = matches.value_of("ROM").context("Missing ROM file")?;
+    let (_, rom) = archive::read_rom(Path::new(path))?;
+    let source = disasm::disassemble(&rom);
+    match matches.value_of("output") {
+        Some(output) => std::fs::write(output, source)?,
+        None => print!("{}", source),
+    }
+    Ok(())
+}
+
+fn run_asm(matches: &ArgMatches) -> Result<()> {
+    let path = matches.value_of("SOURCE").context("Missing source file")?;
+    let source =
+        std::fs::read_to_string(path).with_context(|| format!("Failed to read {}", path))?;
+    let rom = octo::assemble(&source).with_context(|| format!("Failed to assemble {}", path))?;
+    let output = matches.value_of("output").context("Missing output")?;
+    std::fs::write(output, &rom).with_context(|| format!("Failed to write {}", output))?;
+    info!("wrote {} bytes to {}", rom.len(), output);
+    Ok(())
+}
+
+fn run_patch(matches: &ArgMatches) -> Result<()> {
+    let read = |m: &ArgMatches, name: &str| {
+        let path = m.value_of(name).context("Missing argument")?;
+        std::fs::read(path).with_context(|| format!("Failed to read {}", path))
+    };
+    match matches.subcommand() {
+        Some(("apply", m)) => {
+            let out = patch::apply(&read(m, "ROM")?, &read(m, "PATCH")?)?;
+            std::fs::write(m.value_of("output").context("Missing output")?, out)?;
+        }
+        Some(("create", m)) => {
+            let output = m.value_of("output").context("Missing output")?;
+            let bps = match m.value_of("format") {
+                Some(format) => format == "bps",
+                None => Path::new(output)
+                    .extension()
+                    .is_some_and(|ext| ext.eq_ignore_ascii_case("bps")),
+            };
+            let (original, modified) = (read(m, "ORIGINAL")?, read(m, "MODIFIED")?);
+            let out = if bps {
+                patch::create_bps(&original, &modified)
+            } else {
+                patch::create_ips(&original, &modified)?
+            };
+            std::fs::write(output, out)?;
+        }
+        _ => bail!("Missing patch subcommand"),
+    }
+    Ok(())
+}
+
+fn run_rom(matches: &ArgMatches) -> Result<()> {
+    let (command, m) = matches.subcommand().context("Missing rom subcommand")?;
+    let path = m.value_of("ROM").context("Missing ROM file")?;
+    let input = std::fs::read(path).with_context(|| format!("Failed to read {}", path))?;
+    let out = match command {
+        "trim" => rom::trim(&input).to_vec(),
+        "pad" => {
+            let size = rom::parse_number(m.value_of("size").context("Missing size")?)?;
+            let fill = rom::parse_number(m.value_of("fill").context("Missing fill")?)?;
+            let fill = u8::try_from(fill).context("Invalid fill byte")?;
+            rom::pad(&input, size, fill)?
+        }
+        "splice" => {
+            let mut out = input;
+            for insert in m.values_of("INSERT").into_iter().flatten() {
+                let (file, offset) = insert
+                    .rsplit_once('@')
+                    .with_context(|| format!("Expected FILE@OFFSET, got {:?}", insert))?;
+                let data =
+                    std::fs::read(file).with_context(|| format!("Failed to read {}", file))?;
+                rom::splice(&mut out, rom::parse_number(offset)?, &data);
+            }
+            out
+        }
+        _ => bail!("Unknown rom subcommand {}", command),
+    };
+    std::fs::write(m.value_of("output").context("Missing output")?, out)?;
+    Ok(())
+}
+
+fn run_conformance(matches: &ArgMatches) -> Result<()> {
+    let cases = conformance::cases();
+    let mut failed = 0;
+    for case in &cases {
+        if let Some(dir) = matches.value_of("out") {
+            let name: String = case
+                .name
+                .chars()
+                .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+                .collect();
+            std::fs::create_dir_all(dir)?;
+            std::fs::write(Path::new(dir).join(name + ".ch8"), &case.rom)?;
+        }
+
+        let mut chip8 = Chip8::from_bytes(&case.rom);
+        let mut cycles = 0;
+        let mut error = None;
+        while chip8.cpu.pc() != case.halt && cycles < conformance::MAX_CYCLES {
+            if let Err(e) = chip8.step() {
+                error = Some(e);
+                break;
+            }
+            cycles += 1;
+        }
+        let mut errors = Vec::new();
+        if let Some(e) = error {
+            errors.push(format!("stopped at {:#05x}: {}", chip8.cpu.pc(), e));
+        } else if chip8.cpu.pc() != case.halt {
+            errors.push(format!("did not finish within {} cycles", cycles));
+        }
+        for expect in &case.expect {
+            let ok = match *expect {
+                Expect::Reg(x, v) => chip8.cpu.v(x) == v,
+                Expect::I(v) => chip8.cpu.i() == v,
+                Expect::Mem(addr, v) => chip8.interconnect.ram[addr] == v,
+                Expect::Pixel(x, y, on) => {
+                    let width = chip8.display_width();
+                    (chip8.display()[y as usize * width + x as usize] != 0) == on
+                }
+            };
+            if !ok {
+                errors.push(format!("expected {}", expect));
+            }
+        }
+
+        if errors.is_empty() {
+            println!("PASS {}", case.name);
+        } else {
+            failed += 1;
+            println!("FAIL {}: {}", case.name, errors.join(", "));
+        }
+    }
+    println!("{}/{} passed", cases.len() - failed, cases.len());
+    if failed > 0 {
+        bail!("{} conformance test(s) failed", failed);
+    }
+    Ok(())
+}
+
+fn run_selftest(matches: &ArgMatches) -> Result<()> {
+    let dir = Path::new(matches.value_of("DIR").context("Missing suite directory")?);
+    let expected_path = dir.join(selftest::EXPECTED_FILE);
+    let mut expected = selftest::load_expected(&expected_path)?;
+    let screenshots = matches.value_of("screenshots").map(Path::new);
+    if let Some(screenshots) = screenshots {
+        std::fs::create_dir_all(screenshots)?;
+    }
+
+    let (mut passed, mut failed, mut unknown) = (0, 0, 0);
+    for test in selftest::TESTS {
+        let path = dir.join(test.rom);
+        if !path.exists() {
+            println!("SKIP {}: {} not found", test.name, test.rom);
+            continue;
+        }
+        let rom =
+            std::fs::read(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+        let chip8 = match test.run(&rom) {
+            Ok(chip8) => chip8,
+            Err(e) => {
+                failed += 1;
+                println!("FAIL {} ({}): {:#}", test.name, test.covers, e);
+                continue;
+            }
+        };
+        if let Some(screenshots) = screenshots {
+            screenshot::save_png(
+                &screenshots.join(format!("{}.png", test.name)),
+                chip8.display(),
+                chip8.display_width(),
+                &Palette::default(),
+                4,
+            )?;
+        }
+
+        let hash = selftest::display_hash(&chip8);
+        let marks = selftest::read_marks(&rom, &chip8);
+        match (marks, expected.get(test.name)) {
+            (Some(Verdict::Fail { failed: f, total }), _) => {
+                failed += 1;
+                println!(
+                    "FAIL {} ({}): {} of {} results marked as failing",
+                    test.name, test.covers, f, total
+                );
+                // Never a passing run, whatever is recorded.
+                continue;
+            }
+            (_, Some(pass)) if *pass != hash => {
+                failed += 1;
+                println!(
+                    "FAIL {} ({}): display {}, expected {}",
+                    test.name, test.covers, hash, pass
+                );
+            }
+            (Some(Verdict::Pass { passed: n }), _) => {
+                passed += 1;
+                println!("PASS {} ({}): {} results", test.name, test.covers, n);
+            }
+            (None, Some(_)) => {
+                passed += 1;
+                println!("PASS {} ({})", test.name, test.covers);
+            }
+            (None, None) => {
+                unknown += 1;
+                println!("NEW  {} ({}): display {}", test.name, test.covers, hash);
+            }
+        }
+        if matches.is_present("bless") {
+            expected.insert(test.name.to_string(), hash);
+        }
+    }
+
+    println!(
+        "{} passed, {} failed, {} without a passing run",
+        passed, failed, unknown
+    );
+    if matches.is_present("bless") {
+        selftest::save_expected(&expected_path, &expected)?;
+        println!("Recorded the results in {}", expected_path.display());
+    } else if unknown > 0 {
+        println!("Check the new results (see --screenshots), then record them with --bless");
+    }
+    if failed > 0 {
+        bail!("{} self-test(s) failed", failed);
+    }
+    Ok(())
+}
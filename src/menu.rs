@@ -0,0 +1,120 @@
+use std::path::{Path, PathBuf};
+
+use egui::CtxRef;
+use winit::event::VirtualKeyCode;
+
+use crate::hotkeys::{key_name, Hotkeys};
+use crate::palette::{self, Palette};
+
+/// Minimum and maximum emulation speed, as a multiple of the normal speed.
+pub const SPEED_RANGE: std::ops::RangeInclusive<f32> = 0.25..=4.0;
+
+/// The settings that can be changed from the pause menu.
+pub struct Settings<'a> {
+    pub speed: &'a mut f32,
+    pub palette: &'a mut Palette,
+    pub hotkeys: &'a mut Hotkeys,
+    /// Recently opened ROMs, the most recent first.
+    pub recent: &'a [PathBuf],
+}
+
+/// The in-window pause menu. Emulation is suspended while it's open.
+pub struct PauseMenu {
+    pub open: bool,
+    /// Keypad key waiting for a keyboard key to be bound to it.
+    rebinding: Option<u8>,
+}
+
+impl PauseMenu {
+    pub fn new() -> Self {
+        Self {
+            open: false,
+            rebinding: None,
+        }
+    }
+
+    /// Handle a key press while the menu is open. Return `true` if the menu used it.
+    pub fn key_pressed(&mut self, key: VirtualKeyCode, hotkeys: &mut Hotkeys) -> bool {
+        match self.rebinding.take() {
+            Some(k) if self.open => {
+                hotkeys.keypad[k as usize] = key;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Show the menu, returning the recent ROM picked to be opened, if any.
+    pub fn ui(&mut self, ctx: &CtxRef, settings: Settings) -> Option<PathBuf> {
+        let mut open = self.open;
+        let mut open_rom = None;
+        egui::Window::new("Paused")
+            .open(&mut open)
+            .resizable(false)
+            .collapsible(false)
+            .show(ctx, |ui| {
+                ui.heading("Emulation");
+                ui.add(
+                    egui::Slider::new(settings.speed, SPEED_RANGE)
+                        .logarithmic(true)
+                        .text("speed"),
+                );
+
+                if !settings.recent.is_empty() {
+                    ui.separator();
+                    ui.heading("Recent ROMs");
+                    for rom in settings.recent {
+                        if ui
+                            .button(file_name(rom))
+                            .on_hover_text(rom.display().to_string())
+                            .clicked()
+                        {
+                            open_rom = Some(rom.clone());
+                        }
+                    }
+                }
+
+                ui.separator();
+                ui.heading("Display");
+                egui::ComboBox::from_label("palette")
+                    .selected_text(settings.palette.name)
+                    .show_ui(ui, |ui| {
+                        for preset in palette::PRESETS {
+                            ui.selectable_value(settings.palette, *preset, preset.name);
+                        }
+                    });
+
+                ui.separator();
+                ui.heading("Keypad");
+                egui::Grid::new("menu_keypad").show(ui, |ui| {
+                    for k in 0..16u8 {
+                        ui.monospace(format!("{:X}", k));
+                        let label = if self.rebinding == Some(k) {
+                            "press a key…".to_string()
+                        } else {
+                            key_name(settings.hotkeys.keypad[k as usize])
+                        };
+                        if ui.button(label).clicked() {
+                            self.rebinding = Some(k);
+                        }
+                        if k % 4 == 3 {
+                            ui.end_row();
+                        }
+                    }
+                });
+            });
+        self.open = open;
+        if !self.open {
+            self.rebinding = None;
+        }
+        open_rom
+    }
+}
+
+/// The file name of `path`, for a short label.
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .unwrap_or(path.as_os_str())
+        .to_string_lossy()
+        .into_owned()
+}
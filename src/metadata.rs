@@ -0,0 +1,84 @@
+//! Information about a ROM, e.g. its real name, read from a sidecar file next to it.
+//!
+//! A sidecar has the same name as the ROM with a `.toml` extension, e.g. `pong.toml` for
+//! `pong.ch8`:
+//!
+//! ```toml
+//! title = "Pong"
+//! author = "Paul Vervalin"
+//! icon = "pong.png"
+//! ```
+
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+
+#[derive(Debug, Default, Deserialize)]
+pub struct Metadata {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    /// PNG image to use as the window icon, relative to the sidecar file.
+    pub icon: Option<PathBuf>,
+}
+
+impl Metadata {
+    /// Read the sidecar file of `rom`, if there is one.
+    pub fn load_sidecar(rom: &Path) -> Result<Option<Self>> {
+        let path = rom.with_extension("toml");
+        if !path.exists() {
+            return Ok(None);
+        }
+        let content = std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let mut metadata: Metadata =
+            toml::from_str(&content).with_context(|| format!("Invalid {}", path.display()))?;
+        if let (Some(icon), Some(dir)) = (&metadata.icon, path.parent()) {
+            metadata.icon = Some(dir.join(icon));
+        }
+        Ok(Some(metadata))
+    }
+
+    /// A name for the program, with its author if known.
+    pub fn display_name(&self) -> Option<String> {
+        let title = self.title.as_ref()?;
+        Some(match &self.author {
+            Some(author) => format!("{} by {}", title, author),
+            None => title.clone(),
+        })
+    }
+
+    /// Decode the icon, if any, as RGBA pixels, returning them with the icon's width and height.
+    pub fn load_icon(&self) -> Result<Option<(Vec<u8>, u32, u32)>> {
+        let path = match &self.icon {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+        let file =
+            File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+        let mut decoder = png::Decoder::new(file);
+        decoder.set_transformations(png::Transformations::EXPAND);
+        let mut reader = decoder
+            .read_info()
+            .with_context(|| format!("Invalid PNG {}", path.display()))?;
+        let mut buf = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buf)?;
+        buf.truncate(info.buffer_size());
+
+        let rgba = match info.color_type {
+            png::ColorType::Rgba => buf,
+            png::ColorType::Rgb => buf
+                .chunks_exact(3)
+                .flat_map(|p| [p[0], p[1], p[2], 0xFF])
+                .collect(),
+            png::ColorType::GrayscaleAlpha => buf
+                .chunks_exact(2)
+                .flat_map(|p| [p[0], p[0], p[0], p[1]])
+                .collect(),
+            png::ColorType::Grayscale => buf.iter().flat_map(|&v| [v, v, v, 0xFF]).collect(),
+            png::ColorType::Indexed => bail!("Unexpected indexed colors in {}", path.display()),
+        };
+        Ok(Some((rgba, info.width, info.height)))
+    }
+}
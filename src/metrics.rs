@@ -0,0 +1,187 @@
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use log::{info, warn};
+
+/// How long a client of the metrics endpoint may take to send its request or read the response.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Counters and gauges describing the running machine.
+///
+/// They are updated from the emulation loop and can be exported in the Prometheus text format by
+/// `serve`.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    instructions: AtomicU64,
+    frames: AtomicU64,
+    draw_calls: AtomicU64,
+    collisions: AtomicU64,
+    key_presses: AtomicU64,
+    faults: AtomicU64,
+    /// Frames per second, stored as the bit pattern of an `f64`.
+    fps: AtomicU64,
+}
+
+impl Metrics {
+    pub fn inc_instructions(&self) {
+        self.instructions.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count `n` instructions at once, for skipped idle loops.
+    pub fn add_instructions(&self, n: u64) {
+        self.instructions.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn inc_frames(&self) {
+        self.frames.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn inc_draw_calls(&self) {
+        self.draw_calls.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn inc_collisions(&self) {
+        self.collisions.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn inc_key_presses(&self) {
+        self.key_presses.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn inc_faults(&self) {
+        self.faults.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn set_fps(&self, fps: f64) {
+        self.fps.store(fps.to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn fps(&self) -> f64 {
+        f64::from_bits(self.fps.load(Ordering::Relaxed))
+    }
+
+    /// Render all the metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let counters = [
+            (
+                "chip8_instructions_total",
+                "Number of instructions executed.",
+                &self.instructions,
+            ),
+            ("chip8_frames_total", "Number of frames rendered.", &self.frames),
+            (
+                "chip8_draw_calls_total",
+                "Number of sprites drawn.",
+                &self.draw_calls,
+            ),
+            (
+                "chip8_collisions_total",
+                "Number of sprites drawn over lit pixels.",
+                &self.collisions,
+            ),
+            (
+                "chip8_key_presses_total",
+                "Number of keypad key presses.",
+                &self.key_presses,
+            ),
+            (
+                "chip8_faults_total",
+                "Number of unknown or unimplemented opcodes encountered.",
+                &self.faults,
+            ),
+        ];
+        for (name, help, value) in counters {
+            out.push_str(&format!(
+                "# HELP {name} {help}\n# TYPE {name} counter\n{name} {}\n",
+                value.load(Ordering::Relaxed)
+            ));
+        }
+        out.push_str(&format!(
+            "# HELP chip8_fps Frames rendered per second.\n# TYPE chip8_fps gauge\nchip8_fps {}\n",
+            self.fps()
+        ));
+        out
+    }
+
+    /// A human readable summary of a run that lasted `elapsed`.
+    pub fn summary(&self, elapsed: Duration) -> String {
+        let secs = elapsed.as_secs_f64();
+        let instructions = self.instructions.load(Ordering::Relaxed);
+        let frames = self.frames.load(Ordering::Relaxed);
+        let rate = |n: u64| if secs > 0.0 { n as f64 / secs } else { 0.0 };
+        format!(
+            "run time:      {:.1}s\n\
+             instructions:  {} ({:.0}/s)\n\
+             frames:        {} ({:.1}/s)\n\
+             draw calls:    {}\n\
+             collisions:    {}\n\
+             key presses:   {}\n\
+             faults:        {}\n",
+            secs,
+            instructions,
+            rate(instructions),
+            frames,
+            rate(frames),
+            self.draw_calls.load(Ordering::Relaxed),
+            self.collisions.load(Ordering::Relaxed),
+            self.key_presses.load(Ordering::Relaxed),
+            self.faults.load(Ordering::Relaxed),
+        )
+    }
+}
+
+/// Serve `metrics` on `http://<addr>/metrics` from a background thread, each request on its own
+/// thread so that a slow client doesn't hold up the others.
+pub fn serve<A: ToSocketAddrs>(metrics: Arc<Metrics>, addr: A) -> Result<()> {
+    let listener = TcpListener::bind(addr).context("Failed to bind metrics endpoint")?;
+    info!("serving metrics on http://{}/metrics", listener.local_addr()?);
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let metrics = Arc::clone(&metrics);
+            thread::spawn(move || {
+                let result = stream.and_then(|s| handle_request(s, &metrics));
+                if let Err(e) = result {
+                    warn!("metrics request failed: {}", e);
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+fn handle_request(stream: TcpStream, metrics: &Metrics) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    stream.set_write_timeout(Some(REQUEST_TIMEOUT))?;
+    let mut reader = BufReader::new(stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // Drain the headers, we don't care about them.
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 2 {
+        line.clear();
+    }
+
+    let path = request_line.split_whitespace().nth(1).unwrap_or("/");
+    let (status, content_type, body) = if path == "/metrics" {
+        ("200 OK", "text/plain; version=0.0.4", metrics.render())
+    } else {
+        ("404 Not Found", "text/plain", "Not Found\n".to_string())
+    };
+
+    let mut stream = reader.into_inner();
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
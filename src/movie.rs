@@ -0,0 +1,166 @@
+//! Recording keypad input to replay a run exactly.
+//!
+//! A movie lists every keypad press and release with the number of instructions executed before
+//! it. Replaying it on the same ROM, with the same random seed and instructions per second (which
+//! fix the random numbers and the timer ticks), reproduces the original run instruction for
+//! instruction. Movies are text files:
+//!
+//! ```text
+//! # chip8rs movie
+//! seed 1234
+//! ips 1000
+//! rom 0f1e2d3c
+//! 1503 5 down
+//! 1620 5 up
+//! ```
+//!
+//! `rom` is the CRC-32 of the ROM, to warn about replaying a movie on another one. Blank lines
+//! and lines starting with `#` are ignored.
+
+use std::fmt::Write;
+
+use anyhow::{bail, ensure, Context, Result};
+
+use crate::Chip8;
+
+/// A keypad key changing state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEvent {
+    /// Instructions executed before the change.
+    pub cycle: u64,
+    pub key: u8,
+    pub down: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Movie {
+    pub seed: u64,
+    pub ips: u32,
+    /// CRC-32 of the ROM the movie was recorded on.
+    pub rom_crc: u32,
+    pub events: Vec<KeyEvent>,
+}
+
+impl Movie {
+    pub fn new(seed: u64, ips: u32, rom_crc: u32) -> Self {
+        Self {
+            seed,
+            ips,
+            rom_crc,
+            events: Vec::new(),
+        }
+    }
+
+    /// Record that `key` was pressed or released after `cycle` instructions.
+    pub fn record(&mut self, cycle: u64, key: u8, down: bool) {
+        self.events.push(KeyEvent { cycle, key, down });
+    }
+
+    /// The movie in the format described in the module documentation.
+    pub fn encode(&self) -> String {
+        let mut out = String::from("# chip8rs movie\n");
+        writeln!(out, "seed {}", self.seed).unwrap();
+        writeln!(out, "ips {}", self.ips).unwrap();
+        writeln!(out, "rom {:08x}", self.rom_crc).unwrap();
+        for event in &self.events {
+            let state = if event.down { "down" } else { "up" };
+            writeln!(out, "{} {:X} {}", event.cycle, event.key, state).unwrap();
+        }
+        out
+    }
+
+    pub fn decode(text: &str) -> Result<Self> {
+        let (mut seed, mut ips, mut rom_crc) = (None, None, None);
+        let mut events = Vec::new();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            Self::decode_line(line, &mut seed, &mut ips, &mut rom_crc, &mut events)
+                .with_context(|| format!("Invalid movie line {}: {:?}", n + 1, line))?;
+        }
+        ensure!(
+            events.windows(2).all(|w| w[0].cycle <= w[1].cycle),
+            "Movie events are out of order"
+        );
+        Ok(Self {
+            seed: seed.context("The movie has no seed")?,
+            ips: ips.context("The movie has no ips")?,
+            rom_crc: rom_crc.context("The movie has no rom checksum")?,
+            events,
+        })
+    }
+
+    fn decode_line(
+        line: &str,
+        seed: &mut Option<u64>,
+        ips: &mut Option<u32>,
+        rom_crc: &mut Option<u32>,
+        events: &mut Vec<KeyEvent>,
+    ) -> Result<()> {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        match fields[..] {
+            ["seed", value] => *seed = Some(value.parse()?),
+            ["ips", value] => {
+                *ips = Some(
+                    value
+                        .parse()
+                        .ok()
+                        .filter(|&ips| ips > 0)
+                        .context("Invalid instructions per second")?,
+                )
+            }
+            ["rom", value] => *rom_crc = Some(u32::from_str_radix(value, 16)?),
+            [cycle, key, state] => {
+                let key = u8::from_str_radix(key, 16)
+                    .ok()
+                    .filter(|&key| key < 16)
+                    .context("Invalid keypad key")?;
+                let down = match state {
+                    "down" => true,
+                    "up" => false,
+                    _ => bail!("Expected down or up"),
+                };
+                events.push(KeyEvent {
+                    cycle: cycle.parse()?,
+                    key,
+                    down,
+                });
+            }
+            _ => bail!("Unexpected line"),
+        }
+        Ok(())
+    }
+}
+
+/// Replays the key events of a movie.
+pub struct Player {
+    events: Vec<KeyEvent>,
+    next: usize,
+}
+
+impl Player {
+    pub fn new(movie: &Movie) -> Self {
+        Self {
+            events: movie.events.clone(),
+            next: 0,
+        }
+    }
+
+    /// Press and release the keys of `chip8` due before its next instruction.
+    pub fn apply(&mut self, chip8: &mut Chip8) {
+        while let Some(event) = self.events.get(self.next) {
+            if event.cycle > chip8.cycles() {
+                break;
+            }
+            chip8.set_key(event.key, event.down);
+            self.next += 1;
+        }
+    }
+
+    /// Whether all the events have been replayed.
+    pub fn is_done(&self) -> bool {
+        self.next == self.events.len()
+    }
+}
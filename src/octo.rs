@@ -0,0 +1,649 @@
+//! An assembler for the core of the Octo language (https://github.com/JohnEarnest/Octo).
+//!
+//! Supported: labels (`: name`), `:const`, `:alias`, raw bytes for sprite data, all the CHIP-8,
+//! SUPER-CHIP and XO-CHIP instructions in Octo syntax, `if ... then`,
+//! `if ... begin ... else ... end`, `loop ... while ... again`, and calls to labels by name.
+//! Execution starts at `: main`.
+//!
+//! ```
+//! let rom = chip8rs::octo::assemble(": main  v0 := 5  loop again").unwrap();
+//! assert_eq!(rom, [0x60, 0x05, 0x12, 0x02]);
+//! ```
+
+use std::collections::HashMap;
+
+use anyhow::{anyhow, bail, Error, Result};
+
+use crate::config;
+use crate::program::{Label, Program, Reg, Src, Target};
+
+/// Compile Octo `source` into a ROM. Errors mention the line they occurred on.
+pub fn assemble(source: &str) -> Result<Vec<u8>> {
+    assemble_at(source, config::PROG_ADDR)
+}
+
+/// Compile Octo `source` into a ROM loaded at address `origin`.
+pub fn assemble_at(source: &str, origin: u16) -> Result<Vec<u8>> {
+    assemble_mapped(source, origin).map(|(rom, _)| rom)
+}
+
+/// Compile Octo `source` into a ROM loaded at address `origin`, with where each line and label
+/// ended up, for debuggers.
+///
+/// ```
+/// let source = ": main\n  v0 := 5\n  loop again";
+/// let (rom, map) = chip8rs::octo::assemble_mapped(source, 0x200).unwrap();
+/// assert_eq!(rom, [0x60, 0x05, 0x12, 0x02]);
+/// assert_eq!(map.address(1), Some(0x200));
+/// assert_eq!(map.line(0x202), Some(3));
+/// assert_eq!(map.labels["main"], 0x200);
+/// ```
+pub fn assemble_mapped(source: &str, origin: u16) -> Result<(Vec<u8>, SourceMap)> {
+    let tokens = source
+        .lines()
+        .enumerate()
+        .flat_map(|(i, line)| {
+            let code = line.split('#').next().unwrap_or("");
+            code.split_whitespace()
+                .map(move |text| Token { line: i + 1, text })
+        })
+        .collect();
+    Assembler::new(tokens, origin).run()
+}
+
+/// Where the statements and labels of Octo source were assembled.
+#[derive(Debug, Default, Clone)]
+pub struct SourceMap {
+    /// The line, from 1, and the address of every statement that emitted bytes, in order.
+    pub lines: Vec<(usize, u16)>,
+    /// The address of every label.
+    pub labels: HashMap<String, u16>,
+}
+
+impl SourceMap {
+    /// The address of the first statement on `line` or after it.
+    pub fn address(&self, line: usize) -> Option<u16> {
+        self.lines
+            .iter()
+            .find(|&&(l, _)| l >= line)
+            .map(|&(_, addr)| addr)
+    }
+
+    /// The line of the statement whose bytes include `addr`.
+    pub fn line(&self, addr: u16) -> Option<usize> {
+        self.lines
+            .iter()
+            .take_while(|&&(_, a)| a <= addr)
+            .last()
+            .map(|&(line, _)| line)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Token<'a> {
+    line: usize,
+    text: &'a str,
+}
+
+/// A named label, created when it's first referenced or defined.
+struct NamedLabel {
+    label: Label,
+    defined: bool,
+    /// Where the label was first referenced, for reporting undefined labels.
+    line: usize,
+}
+
+/// A control structure waiting for its closing token.
+enum Block {
+    If {
+        line: usize,
+        /// Start of the `else` branch, or the end if there is none.
+        otherwise: Label,
+        /// End of the `else` branch, once `else` has been seen.
+        end: Option<Label>,
+    },
+    Loop {
+        line: usize,
+        start: Label,
+        end: Label,
+    },
+}
+
+/// A condition of `if` and `while`.
+enum Condition {
+    Eq(Reg, Src),
+    Ne(Reg, Src),
+    Key(Reg),
+    NotKey(Reg),
+}
+
+struct Assembler<'a> {
+    tokens: Vec<Token<'a>>,
+    pos: usize,
+    program: Program,
+    labels: HashMap<&'a str, NamedLabel>,
+    consts: HashMap<&'a str, i32>,
+    aliases: HashMap<&'a str, Reg>,
+    blocks: Vec<Block>,
+}
+
+impl<'a> Assembler<'a> {
+    fn new(tokens: Vec<Token<'a>>, origin: u16) -> Self {
+        Self {
+            tokens,
+            pos: 0,
+            program: Program::at(origin),
+            labels: HashMap::new(),
+            consts: HashMap::new(),
+            aliases: HashMap::new(),
+            blocks: Vec::new(),
+        }
+    }
+
+    fn run(mut self) -> Result<(Vec<u8>, SourceMap)> {
+        let starts_with_main = matches!(
+            self.tokens.get(..2),
+            Some([colon, name]) if colon.text == ":" && name.text == "main"
+        );
+        if !starts_with_main {
+            let main = self.label("main", 1);
+            self.program.jp(main);
+        }
+        let mut map = SourceMap::default();
+        while self.pos < self.tokens.len() {
+            let (line, start) = (self.tokens[self.pos].line, self.program.here());
+            self.statement()?;
+            if self.program.here() != start {
+                map.lines.push((line, start));
+            }
+        }
+        if let Some(block) = self.blocks.last() {
+            match block {
+                Block::If { line, .. } => bail!("line {}: `if` without `end`", line),
+                Block::Loop { line, .. } => bail!("line {}: `loop` without `again`", line),
+            }
+        }
+        let mut undefined: Vec<(&str, usize)> = self
+            .labels
+            .iter()
+            .filter(|(_, label)| !label.defined)
+            .map(|(name, label)| (*name, label.line))
+            .collect();
+        undefined.sort_by_key(|&(_, line)| line);
+        if let Some((name, line)) = undefined.first() {
+            if *name == "main" {
+                bail!("The program has no `: main` label to start at");
+            }
+            bail!("line {}: undefined label {:?}", line, name);
+        }
+        for (name, named) in &self.labels {
+            if let Some(addr) = self.program.address_of(named.label) {
+                map.labels.insert(name.to_string(), addr);
+            }
+        }
+        Ok((self.program.assemble()?, map))
+    }
+
+    fn next(&mut self) -> Result<Token<'a>> {
+        let token = self.tokens.get(self.pos).copied().ok_or_else(|| {
+            let line = self.tokens.last().map_or(1, |t| t.line);
+            anyhow!("line {}: unexpected end of file", line)
+        })?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.pos).map(|t| t.text)
+    }
+
+    /// Consume the next token, which must be `text`.
+    fn expect(&mut self, text: &str) -> Result<()> {
+        let token = self.next()?;
+        if token.text != text {
+            bail!(
+                "line {}: expected `{}`, found `{}`",
+                token.line,
+                text,
+                token.text
+            );
+        }
+        Ok(())
+    }
+
+    fn error(token: Token, message: &str) -> Error {
+        anyhow!("line {}: {} `{}`", token.line, message, token.text)
+    }
+
+    fn label(&mut self, name: &'a str, line: usize) -> Label {
+        if let Some(named) = self.labels.get(name) {
+            return named.label;
+        }
+        let label = self.program.label();
+        self.labels.insert(
+            name,
+            NamedLabel {
+                label,
+                defined: false,
+                line,
+            },
+        );
+        label
+    }
+
+    fn reg(&mut self) -> Result<Reg> {
+        let token = self.next()?;
+        self.parse_reg(token)
+            .ok_or_else(|| Self::error(token, "expected a register, found"))
+    }
+
+    fn parse_reg(&self, token: Token) -> Option<Reg> {
+        if let Some(&reg) = self.aliases.get(token.text) {
+            return Some(reg);
+        }
+        let text = token.text.to_ascii_lowercase();
+        let digit = text.strip_prefix('v')?;
+        if digit.len() != 1 {
+            return None;
+        }
+        u8::from_str_radix(digit, 16).ok().map(Reg)
+    }
+
+    fn parse_number(&self, token: Token) -> Option<i32> {
+        if let Some(&value) = self.consts.get(token.text) {
+            return Some(value);
+        }
+        let (negative, text) = match token.text.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, token.text),
+        };
+        let value = if let Some(hex) = text.strip_prefix("0x") {
+            i32::from_str_radix(hex, 16).ok()?
+        } else if let Some(bin) = text.strip_prefix("0b") {
+            i32::from_str_radix(bin, 2).ok()?
+        } else {
+            text.parse().ok()?
+        };
+        Some(if negative { -value } else { value })
+    }
+
+    fn byte(&mut self) -> Result<u8> {
+        let token = self.next()?;
+        match self.parse_number(token) {
+            Some(value @ -128..=255) => Ok(value as u8),
+            Some(_) => Err(Self::error(token, "byte out of range:")),
+            None => Err(Self::error(token, "expected a number, found")),
+        }
+    }
+
+    fn nibble(&mut self) -> Result<u8> {
+        let token = self.next()?;
+        match self.parse_number(token) {
+            Some(value @ 0..=15) => Ok(value as u8),
+            _ => Err(Self::error(token, "expected a number from 0 to 15, found")),
+        }
+    }
+
+    /// A register or a byte.
+    fn src(&mut self) -> Result<Src> {
+        let token = self.next()?;
+        if let Some(reg) = self.parse_reg(token) {
+            return Ok(Src::Reg(reg));
+        }
+        self.pos -= 1;
+        Ok(Src::Byte(self.byte()?))
+    }
+
+    /// An address or a label.
+    fn target(&mut self) -> Result<Target> {
+        self.target_up_to(0xFFF)
+    }
+
+    /// A label or an address up to `max`.
+    fn target_up_to(&mut self, max: i32) -> Result<Target> {
+        let token = self.next()?;
+        match self.parse_number(token) {
+            Some(addr) if (0..=max).contains(&addr) => Ok(Target::Addr(addr as u16)),
+            Some(_) => Err(Self::error(token, "address out of range:")),
+            None if is_identifier(token.text) => {
+                Ok(Target::Label(self.label(token.text, token.line)))
+            }
+            None => Err(Self::error(token, "expected an address or a label, found")),
+        }
+    }
+
+    fn condition(&mut self) -> Result<Condition> {
+        let x = self.reg()?;
+        let op = self.next()?;
+        Ok(match op.text {
+            "==" => Condition::Eq(x, self.src()?),
+            "!=" => Condition::Ne(x, self.src()?),
+            "key" => Condition::Key(x),
+            "-key" => Condition::NotKey(x),
+            _ => return Err(Self::error(op, "unsupported comparison")),
+        })
+    }
+
+    /// Emit an instruction skipping the next one if `condition` is true (or false if `negate`).
+    fn skip_if(&mut self, condition: Condition, negate: bool) {
+        let p = &mut self.program;
+        match (condition, negate) {
+            (Condition::Eq(x, src), false) | (Condition::Ne(x, src), true) => p.se(x, src),
+            (Condition::Ne(x, src), false) | (Condition::Eq(x, src), true) => p.sne(x, src),
+            (Condition::Key(x), false) | (Condition::NotKey(x), true) => p.skp(x),
+            (Condition::NotKey(x), false) | (Condition::Key(x), true) => p.sknp(x),
+        };
+    }
+
+    fn statement(&mut self) -> Result<()> {
+        let token = self.next()?;
+        match token.text {
+            ":" => {
+                let name = self.next()?;
+                if !is_identifier(name.text) {
+                    return Err(Self::error(name, "invalid label name"));
+                }
+                let label = self.label(name.text, name.line);
+                let named = self
+                    .labels
+                    .get_mut(name.text)
+                    .expect("label was just created");
+                if named.defined {
+                    return Err(Self::error(name, "label defined twice:"));
+                }
+                named.defined = true;
+                self.program.bind(label);
+            }
+            ":const" => {
+                let name = self.next()?;
+                let value = self.next()?;
+                let value = self
+                    .parse_number(value)
+                    .ok_or_else(|| Self::error(value, "expected a number, found"))?;
+                self.consts.insert(name.text, value);
+            }
+            ":alias" => {
+                let name = self.next()?;
+                let reg = self.reg()?;
+                self.aliases.insert(name.text, reg);
+            }
+            ":call" => {
+                let target = self.target()?;
+                self.program.call(target);
+            }
+            "clear" => {
+                self.program.cls();
+            }
+            "return" | ";" => {
+                self.program.ret();
+            }
+            "scroll-down" => {
+                let n = self.nibble()?;
+                self.program.scd(n);
+            }
+            "scroll-up" => {
+                let n = self.nibble()?;
+                self.program.scu(n);
+            }
+            "scroll-right" => {
+                self.program.scr();
+            }
+            "scroll-left" => {
+                self.program.scl();
+            }
+            "exit" => {
+                self.program.exit();
+            }
+            "lores" => {
+                self.program.low();
+            }
+            "hires" => {
+                self.program.high();
+            }
+            "jump" => {
+                let target = self.target()?;
+                self.program.jp(target);
+            }
+            "jump0" => {
+                let target = self.target()?;
+                self.program.jp_v0(target);
+            }
+            "native" => {
+                let target = self.target()?;
+                match target {
+                    Target::Addr(addr) => self.program.op(addr),
+                    Target::Label(_) => return Err(Self::error(token, "needs an address:")),
+                };
+            }
+            "bcd" => {
+                let x = self.reg()?;
+                self.program.bcd(x);
+            }
+            "save" | "load" => {
+                let x = self.reg()?;
+                let range = if self.peek() == Some("-") {
+                    self.pos += 1;
+                    Some(self.reg()?)
+                } else {
+                    None
+                };
+                match (token.text, range) {
+                    ("save", None) => self.program.store(x),
+                    ("save", Some(y)) => self.program.store_range(x, y),
+                    (_, None) => self.program.load(x),
+                    (_, Some(y)) => self.program.load_range(x, y),
+                };
+            }
+            "plane" => {
+                let n = self.nibble()?;
+                self.program.plane(n);
+            }
+            "audio" => {
+                self.program.audio();
+            }
+            "pitch" => {
+                self.expect(":=")?;
+                let x = self.reg()?;
+                self.program.pitch(x);
+            }
+            "saveflags" => {
+                let x = self.reg()?;
+                self.program.store_rpl(x);
+            }
+            "loadflags" => {
+                let x = self.reg()?;
+                self.program.load_rpl(x);
+            }
+            "sprite" => {
+                let (x, y) = (self.reg()?, self.reg()?);
+                let n = self.nibble()?;
+                self.program.drw(x, y, n);
+            }
+            "delay" | "buzzer" => {
+                self.expect(":=")?;
+                let x = self.reg()?;
+                if token.text == "delay" {
+                    self.program.ld_dt(x);
+                } else {
+                    self.program.ld_st(x);
+                }
+            }
+            "i" => self.assign_i()?,
+            "if" => self.conditional(token.line)?,
+            "else" => match self.blocks.last_mut() {
+                Some(Block::If { otherwise, end, .. }) if end.is_none() => {
+                    let label = self.program.label();
+                    self.program.jp(label).bind(*otherwise);
+                    *end = Some(label);
+                }
+                _ => return Err(Self::error(token, "unexpected")),
+            },
+            "end" => match self.blocks.pop() {
+                Some(Block::If { otherwise, end, .. }) => {
+                    self.program.bind(end.unwrap_or(otherwise));
+                }
+                _ => return Err(Self::error(token, "unexpected")),
+            },
+            "loop" => {
+                let (start, end) = (self.program.label(), self.program.label());
+                self.program.bind(start);
+                self.blocks.push(Block::Loop {
+                    line: token.line,
+                    start,
+                    end,
+                });
+            }
+            "while" => {
+                let end = self
+                    .blocks
+                    .iter()
+                    .rev()
+                    .find_map(|block| match block {
+                        Block::Loop { end, .. } => Some(*end),
+                        Block::If { .. } => None,
+                    })
+                    .ok_or_else(|| Self::error(token, "`while` outside of a loop:"))?;
+                let condition = self.condition()?;
+                self.skip_if(condition, false);
+                self.program.jp(end);
+            }
+            "again" => match self.blocks.pop() {
+                Some(Block::Loop { start, end, .. }) => {
+                    self.program.jp(start).bind(end);
+                }
+                _ => return Err(Self::error(token, "unexpected")),
+            },
+            _ => {
+                if let Some(x) = self.parse_reg(token) {
+                    self.assign_reg(x)?;
+                } else if self.parse_number(token).is_some() {
+                    self.pos -= 1;
+                    let byte = self.byte()?;
+                    self.program.db(&[byte]);
+                } else if is_identifier(token.text) {
+                    let label = self.label(token.text, token.line);
+                    self.program.call(label);
+                } else {
+                    return Err(Self::error(token, "unknown statement"));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// `if` followed by `then` and a statement, or by `begin`.
+    fn conditional(&mut self, line: usize) -> Result<()> {
+        let condition = self.condition()?;
+        let token = self.next()?;
+        match token.text {
+            "then" => {
+                self.skip_if(condition, true);
+                if self.peek().is_none() {
+                    bail!("line {}: `then` without a statement", token.line);
+                }
+            }
+            "begin" => {
+                let otherwise = self.program.label();
+                self.skip_if(condition, false);
+                self.program.jp(otherwise);
+                self.blocks.push(Block::If {
+                    line,
+                    otherwise,
+                    end: None,
+                });
+            }
+            _ => return Err(Self::error(token, "expected `then` or `begin`, found")),
+        }
+        Ok(())
+    }
+
+    fn assign_i(&mut self) -> Result<()> {
+        let op = self.next()?;
+        match op.text {
+            ":=" if self.peek() == Some("hex") => {
+                self.pos += 1;
+                let x = self.reg()?;
+                self.program.ld_font(x);
+            }
+            ":=" if self.peek() == Some("long") => {
+                self.pos += 1;
+                let target = self.target_up_to(0xFFFF)?;
+                self.program.ld_i_long(target);
+            }
+            ":=" if self.peek() == Some("bighex") => {
+                self.pos += 1;
+                let x = self.reg()?;
+                self.program.ld_big_font(x);
+            }
+            ":=" => {
+                let target = self.target()?;
+                self.program.ld_i(target);
+            }
+            "+=" => {
+                let x = self.reg()?;
+                self.program.add_i(x);
+            }
+            _ => return Err(Self::error(op, "unsupported operation on i:")),
+        }
+        Ok(())
+    }
+
+    fn assign_reg(&mut self, x: Reg) -> Result<()> {
+        let op = self.next()?;
+        match op.text {
+            ":=" => match self.peek() {
+                Some("key") => {
+                    self.pos += 1;
+                    self.program.ld_key(x);
+                }
+                Some("delay") => {
+                    self.pos += 1;
+                    self.program.ld_from_dt(x);
+                }
+                Some("random") => {
+                    self.pos += 1;
+                    let mask = self.byte()?;
+                    self.program.rnd(x, mask);
+                }
+                _ => {
+                    let src = self.src()?;
+                    self.program.ld(x, src);
+                }
+            },
+            "+=" => {
+                let src = self.src()?;
+                self.program.add(x, src);
+            }
+            "-=" => match self.src()? {
+                Src::Reg(y) => {
+                    self.program.sub(x, y);
+                }
+                Src::Byte(nn) => {
+                    self.program.add(x, nn.wrapping_neg());
+                }
+            },
+            _ => {
+                let y = self.reg()?;
+                let p = &mut self.program;
+                match op.text {
+                    "=-" => p.subn(x, y),
+                    "|=" => p.or(x, y),
+                    "&=" => p.and(x, y),
+                    "^=" => p.xor(x, y),
+                    ">>=" => p.shr(x, y),
+                    "<<=" => p.shl(x, y),
+                    _ => return Err(Self::error(op, "unsupported operation on a register:")),
+                };
+            }
+        }
+        Ok(())
+    }
+}
+
+fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
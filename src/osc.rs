@@ -0,0 +1,204 @@
+use std::io::ErrorKind;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+
+use anyhow::{Context, Result};
+use log::{debug, info, warn};
+
+/// Bridge between the machine and OSC (Open Sound Control) peers over UDP.
+///
+/// Incoming messages drive the keypad:
+/// - `/chip8/key/<k> <state>` where `k` is a hex digit and `state` a non-zero (down) or zero (up)
+///   int, float or boolean;
+/// - `/chip8/key <k> <state>` with both values as arguments.
+///
+/// Machine events are published to the target address, if any:
+/// - `/chip8/sound <0|1>` whenever the buzzer starts or stops;
+/// - `/chip8/frame <n>` on every rendered frame.
+pub struct OscBridge {
+    socket: UdpSocket,
+    target: Option<SocketAddr>,
+    keys: [bool; 16],
+    frames: u32,
+    buf: Vec<u8>,
+}
+
+/// An OSC argument, restricted to the types we know how to handle.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Arg {
+    Int(i32),
+    Float(f32),
+    Bool(bool),
+}
+
+impl Arg {
+    fn as_int(self) -> i32 {
+        match self {
+            Arg::Int(v) => v,
+            Arg::Float(v) => v as i32,
+            Arg::Bool(v) => v as i32,
+        }
+    }
+}
+
+impl OscBridge {
+    /// Create a bridge receiving on `listen` (if any) and publishing events to `target` (if any).
+    pub fn new(listen: Option<&str>, target: Option<&str>) -> Result<Self> {
+        let socket = UdpSocket::bind(listen.unwrap_or("0.0.0.0:0"))
+            .context("Failed to bind OSC socket")?;
+        socket.set_nonblocking(true)?;
+        if listen.is_some() {
+            info!("listening for OSC messages on {}", socket.local_addr()?);
+        }
+        let target = match target {
+            Some(addr) => Some(
+                addr.to_socket_addrs()?
+                    .next()
+                    .context("Invalid OSC target address")?,
+            ),
+            None => None,
+        };
+
+        Ok(Self {
+            socket,
+            target,
+            keys: [false; 16],
+            frames: 0,
+            buf: vec![0; 1536],
+        })
+    }
+
+    /// Whether keypad key `key` is currently held down by an OSC peer.
+    pub fn is_key_down(&self, key: u8) -> bool {
+        self.keys[key as usize]
+    }
+
+    /// Process all pending incoming messages. Return `true` if the keypad state changed.
+    pub fn poll(&mut self) -> bool {
+        let mut changed = false;
+        loop {
+            let len = match self.socket.recv_from(&mut self.buf) {
+                Ok((len, _)) => len,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    warn!("failed to receive OSC packet: {}", e);
+                    break;
+                }
+            };
+            let mut messages = Vec::new();
+            if decode_packet(&self.buf[..len], &mut messages).is_none() {
+                warn!("ignoring malformed OSC packet");
+                continue;
+            }
+            for (addr, args) in messages {
+                changed |= self.handle_message(&addr, &args);
+            }
+        }
+        changed
+    }
+
+    pub fn publish_sound(&self, on: bool) {
+        self.publish("/chip8/sound", on as i32);
+    }
+
+    pub fn publish_frame(&mut self) {
+        self.frames = self.frames.wrapping_add(1);
+        self.publish("/chip8/frame", self.frames as i32);
+    }
+
+    fn handle_message(&mut self, addr: &str, args: &[Arg]) -> bool {
+        let (key, state) = if let Some(key) = addr.strip_prefix("/chip8/key/") {
+            match (u8::from_str_radix(key, 16), args.first()) {
+                (Ok(key), Some(state)) => (key, *state),
+                _ => return false,
+            }
+        } else if addr == "/chip8/key" {
+            match args {
+                [key, state] => (key.as_int() as u8, *state),
+                _ => return false,
+            }
+        } else {
+            debug!("ignoring OSC message {}", addr);
+            return false;
+        };
+
+        if key > 0xF {
+            return false;
+        }
+        let is_down = state.as_int() != 0;
+        let changed = self.keys[key as usize] != is_down;
+        self.keys[key as usize] = is_down;
+        changed
+    }
+
+    fn publish(&self, addr: &str, value: i32) {
+        if let Some(target) = self.target {
+            let mut packet = Vec::with_capacity(32);
+            write_str(&mut packet, addr);
+            write_str(&mut packet, ",i");
+            packet.extend_from_slice(&value.to_be_bytes());
+            if let Err(e) = self.socket.send_to(&packet, target) {
+                warn!("failed to send OSC message to {}: {}", target, e);
+            }
+        }
+    }
+}
+
+/// Write an OSC string: null-terminated and padded to a multiple of 4 bytes.
+fn write_str(buf: &mut Vec<u8>, s: &str) {
+    buf.extend_from_slice(s.as_bytes());
+    let padding = 4 - s.len() % 4;
+    buf.resize(buf.len() + padding, 0);
+}
+
+/// Read an OSC string at the start of `data`, returning it and the rest of the data.
+fn read_str(data: &[u8]) -> Option<(&str, &[u8])> {
+    let len = data.iter().position(|b| *b == 0)?;
+    let s = std::str::from_utf8(&data[..len]).ok()?;
+    let padded = (len / 4 + 1) * 4;
+    Some((s, data.get(padded..)?))
+}
+
+fn read_u32(data: &[u8]) -> Option<(u32, &[u8])> {
+    let bytes = data.get(..4)?;
+    let v = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    Some((v, &data[4..]))
+}
+
+/// Decode a packet (either a single message or a bundle) into `messages`.
+fn decode_packet(data: &[u8], messages: &mut Vec<(String, Vec<Arg>)>) -> Option<()> {
+    if let Some(mut elements) = data.strip_prefix(b"#bundle\0") {
+        // Skip the time tag: we execute everything immediately.
+        elements = elements.get(8..)?;
+        while !elements.is_empty() {
+            let (size, rest) = read_u32(elements)?;
+            let size = size as usize;
+            decode_packet(rest.get(..size)?, messages)?;
+            elements = &rest[size..];
+        }
+        return Some(());
+    }
+
+    let (addr, rest) = read_str(data)?;
+    let (tags, mut rest) = read_str(rest)?;
+    let mut args = Vec::new();
+    for tag in tags.strip_prefix(',')?.chars() {
+        let arg = match tag {
+            'i' => {
+                let (v, r) = read_u32(rest)?;
+                rest = r;
+                Arg::Int(v as i32)
+            }
+            'f' => {
+                let (v, r) = read_u32(rest)?;
+                rest = r;
+                Arg::Float(f32::from_bits(v))
+            }
+            'T' => Arg::Bool(true),
+            'F' => Arg::Bool(false),
+            _ => return None,
+        };
+        args.push(arg);
+    }
+    messages.push((addr.to_string(), args));
+    Some(())
+}
@@ -0,0 +1,124 @@
+//! Display color palettes.
+
+use anyhow::{ensure, Result};
+
+/// An RGBA color.
+pub type Color = [u8; 4];
+
+const fn rgb(hex: u32) -> Color {
+    [(hex >> 16) as u8, (hex >> 8) as u8, hex as u8, 0xFF]
+}
+
+/// The colors used to draw the display.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Palette {
+    pub name: &'static str,
+    /// Background, foreground, and the colors of the second plane and of both planes overlapping
+    /// (XO-CHIP).
+    pub colors: [Color; 4],
+}
+
+/// Built-in palettes. The first one is the default.
+pub const PRESETS: &[Palette] = &[
+    Palette {
+        name: "classic",
+        colors: [rgb(0x000000), rgb(0xFFFFFF), rgb(0xAAAAAA), rgb(0x555555)],
+    },
+    // Maximum luminance contrast between all four colors.
+    Palette {
+        name: "high-contrast",
+        colors: [rgb(0x000000), rgb(0xFFFF00), rgb(0x00FFFF), rgb(0xFFFFFF)],
+    },
+    Palette {
+        name: "high-contrast-light",
+        colors: [rgb(0xFFFFFF), rgb(0x000000), rgb(0x0000CC), rgb(0x800000)],
+    },
+    // Okabe & Ito's palette, distinguishable with all common forms of color blindness.
+    Palette {
+        name: "okabe-ito",
+        colors: [rgb(0x000000), rgb(0xE69F00), rgb(0x56B4E9), rgb(0xF0E442)],
+    },
+    // Paul Tol's "vibrant" scheme, also designed to be colorblind safe.
+    Palette {
+        name: "tol-vibrant",
+        colors: [rgb(0x000000), rgb(0xEE7733), rgb(0x33BBEE), rgb(0xEE3377)],
+    },
+    // Monochrome CRT phosphors, the other planes in dimmer shades.
+    Palette {
+        name: "green-phosphor",
+        colors: [rgb(0x0A140A), rgb(0x33FF66), rgb(0x1F9C3E), rgb(0x14662A)],
+    },
+    Palette {
+        name: "amber",
+        colors: [rgb(0x140D00), rgb(0xFFB000), rgb(0xA67300), rgb(0x664700)],
+    },
+    // The four shades of the original Game Boy's LCD.
+    Palette {
+        name: "gameboy",
+        colors: [rgb(0x9BBC0F), rgb(0x0F380F), rgb(0x8BAC0F), rgb(0x306230)],
+    },
+];
+
+impl Palette {
+    /// Find a built-in palette by name.
+    pub fn by_name(name: &str) -> Option<Palette> {
+        PRESETS.iter().find(|p| p.name == name).copied()
+    }
+
+    /// The built-in palette following this one, wrapping around.
+    pub fn next(&self) -> Palette {
+        let i = PRESETS.iter().position(|p| p.name == self.name);
+        PRESETS[i.map_or(0, |i| (i + 1) % PRESETS.len())]
+    }
+
+    /// This palette with its background and foreground replaced by the colors given.
+    pub fn with_colors(mut self, background: Option<Color>, foreground: Option<Color>) -> Palette {
+        if let Some(background) = background {
+            self.colors[0] = background;
+        }
+        if let Some(foreground) = foreground {
+            self.colors[1] = foreground;
+        }
+        self
+    }
+
+    /// The color of a display pixel. Values 1 to 3 select an XO-CHIP plane combination, any other
+    /// non-zero value is drawn in the foreground color.
+    pub fn color(&self, pixel: u8) -> Color {
+        match pixel {
+            0..=3 => self.colors[pixel as usize],
+            _ => self.colors[1],
+        }
+    }
+
+    /// The color of a pixel of value `pixel` shown at `intensity` out of 255, blended with the
+    /// background.
+    pub fn dimmed(&self, pixel: u8, intensity: u8) -> Color {
+        let (color, background) = (self.color(pixel), self.colors[0]);
+        let (a, b) = (intensity as u32, 255 - intensity as u32);
+        std::array::from_fn(|i| ((color[i] as u32 * a + background[i] as u32 * b) / 255) as u8)
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        PRESETS[0]
+    }
+}
+
+/// Parse a color written as `RRGGBB` hex, optionally preceded by `#`.
+pub fn parse_color(s: &str) -> Result<Color> {
+    let hex = s.strip_prefix('#').unwrap_or(s);
+    ensure!(
+        hex.len() == 6 && hex.chars().all(|c| c.is_ascii_hexdigit()),
+        "Invalid color {:?} (expected RRGGBB hex)",
+        s
+    );
+    Ok(rgb(u32::from_str_radix(hex, 16)?))
+}
+
+/// `color` as `#RRGGBB` hex, as read by `parse_color`.
+pub fn color_hex(color: Color) -> String {
+    let [r, g, b, _] = color;
+    format!("#{:02X}{:02X}{:02X}", r, g, b)
+}
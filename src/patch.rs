@@ -0,0 +1,277 @@
+//! Creation and application of IPS and BPS patches.
+
+use anyhow::{bail, ensure, Context, Result};
+
+const IPS_MAGIC: &[u8] = b"PATCH";
+const IPS_EOF: &[u8] = b"EOF";
+const BPS_MAGIC: &[u8] = b"BPS1";
+
+/// Maximum size of an IPS record.
+const IPS_MAX_RECORD: usize = 0xFFFF;
+
+/// Patches producing larger ROMs than this are refused, since no ROM comes near it.
+const MAX_TARGET_SIZE: usize = 16 << 20;
+
+/// Apply `patch` (IPS or BPS, detected from its header) to `rom`.
+pub fn apply(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>> {
+    if patch.starts_with(IPS_MAGIC) {
+        apply_ips(rom, patch)
+    } else if patch.starts_with(BPS_MAGIC) {
+        apply_bps(rom, patch)
+    } else {
+        bail!("Unknown patch format")
+    }
+}
+
+/// Apply an IPS patch to `rom`.
+pub fn apply_ips(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>> {
+    ensure!(patch.starts_with(IPS_MAGIC), "Not an IPS patch");
+    let mut out = rom.to_vec();
+    let mut reader = Reader::new(&patch[IPS_MAGIC.len()..]);
+    loop {
+        let header = reader.bytes(3).context("Truncated IPS patch")?;
+        if header == IPS_EOF {
+            break;
+        }
+        let offset = u32::from_be_bytes([0, header[0], header[1], header[2]]) as usize;
+        let size = reader.u16_be()? as usize;
+        let (data, len) = if size == 0 {
+            // RLE record
+            let len = reader.u16_be()? as usize;
+            (None, len)
+        } else {
+            (Some(reader.bytes(size)?), size)
+        };
+        if out.len() < offset + len {
+            out.resize(offset + len, 0);
+        }
+        match data {
+            Some(data) => out[offset..offset + len].copy_from_slice(data),
+            None => {
+                let value = reader.u8()?;
+                out[offset..offset + len].fill(value);
+            }
+        }
+    }
+    // Optional truncation extension
+    if let Ok(size) = reader.bytes(3) {
+        out.truncate(u32::from_be_bytes([0, size[0], size[1], size[2]]) as usize);
+    }
+    Ok(out)
+}
+
+/// Create an IPS patch turning `source` into `target`.
+pub fn create_ips(source: &[u8], target: &[u8]) -> Result<Vec<u8>> {
+    ensure!(target.len() < 0x454F46, "ROM too large for an IPS patch");
+    let mut patch = IPS_MAGIC.to_vec();
+    let mut offset = 0;
+    while offset < target.len() {
+        if source.get(offset) == Some(&target[offset]) {
+            offset += 1;
+            continue;
+        }
+        let start = offset;
+        while offset < target.len()
+            && offset - start < IPS_MAX_RECORD
+            && source.get(offset) != Some(&target[offset])
+        {
+            offset += 1;
+        }
+        patch.extend_from_slice(&(start as u32).to_be_bytes()[1..]);
+        patch.extend_from_slice(&((offset - start) as u16).to_be_bytes());
+        patch.extend_from_slice(&target[start..offset]);
+    }
+    patch.extend_from_slice(IPS_EOF);
+    if target.len() < source.len() {
+        patch.extend_from_slice(&(target.len() as u32).to_be_bytes()[1..]);
+    }
+    Ok(patch)
+}
+
+/// Apply a BPS patch to `rom`, verifying the checksums it contains.
+pub fn apply_bps(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>> {
+    ensure!(patch.starts_with(BPS_MAGIC), "Not a BPS patch");
+    ensure!(patch.len() >= BPS_MAGIC.len() + 12, "Truncated BPS patch");
+    let footer = patch.len() - 12;
+    let checksum =
+        |i: usize| u32::from_le_bytes([patch[i], patch[i + 1], patch[i + 2], patch[i + 3]]);
+    ensure!(
+        crc32(&patch[..footer + 8]) == checksum(footer + 8),
+        "BPS patch is corrupted"
+    );
+    ensure!(
+        crc32(rom) == checksum(footer),
+        "BPS patch doesn't apply to this ROM"
+    );
+
+    let mut reader = Reader::new(&patch[BPS_MAGIC.len()..footer]);
+    let source_size = reader.varint()? as usize;
+    let target_size = reader.varint()? as usize;
+    let metadata_size = reader.varint()? as usize;
+    reader.bytes(metadata_size)?;
+    ensure!(
+        source_size == rom.len(),
+        "BPS patch doesn't apply to this ROM"
+    );
+    ensure!(
+        target_size <= MAX_TARGET_SIZE,
+        "BPS patch produces a ROM too large ({} bytes)",
+        target_size
+    );
+
+    let mut out = Vec::with_capacity(target_size);
+    let mut source_offset: i64 = 0;
+    let mut target_offset: i64 = 0;
+    while !reader.is_empty() {
+        let action = reader.varint()?;
+        let len = (action >> 2) as usize + 1;
+        ensure!(len <= target_size - out.len(), "Invalid BPS patch");
+        match action & 3 {
+            // SourceRead
+            0 => {
+                let start = out.len();
+                out.extend_from_slice(rom.get(start..start + len).context("Invalid BPS patch")?);
+            }
+            // TargetRead
+            1 => out.extend_from_slice(reader.bytes(len)?),
+            // SourceCopy
+            2 => {
+                source_offset += reader.signed_varint()?;
+                let start = usize::try_from(source_offset).context("Invalid BPS patch")?;
+                out.extend_from_slice(rom.get(start..start + len).context("Invalid BPS patch")?);
+                source_offset += len as i64;
+            }
+            // TargetCopy
+            _ => {
+                target_offset += reader.signed_varint()?;
+                let start = usize::try_from(target_offset).context("Invalid BPS patch")?;
+                // The source and destination ranges may overlap: copy byte by byte.
+                for i in start..start + len {
+                    let b = *out.get(i).context("Invalid BPS patch")?;
+                    out.push(b);
+                }
+                target_offset += len as i64;
+            }
+        }
+    }
+
+    ensure!(out.len() == target_size, "Invalid BPS patch");
+    ensure!(
+        crc32(&out) == checksum(footer + 4),
+        "BPS patch produced an invalid ROM"
+    );
+    Ok(out)
+}
+
+/// Create a BPS patch turning `source` into `target`.
+///
+/// Bytes that are unchanged at the same offset are copied from the source, everything else is
+/// stored in the patch.
+pub fn create_bps(source: &[u8], target: &[u8]) -> Vec<u8> {
+    let mut patch = BPS_MAGIC.to_vec();
+    write_varint(&mut patch, source.len() as u64);
+    write_varint(&mut patch, target.len() as u64);
+    write_varint(&mut patch, 0);
+
+    let mut offset = 0;
+    while offset < target.len() {
+        let start = offset;
+        let same = source.get(offset) == Some(&target[offset]);
+        while offset < target.len() && (source.get(offset) == Some(&target[offset])) == same {
+            offset += 1;
+        }
+        let len = (offset - start) as u64;
+        if same {
+            write_varint(&mut patch, (len - 1) << 2);
+        } else {
+            write_varint(&mut patch, ((len - 1) << 2) | 1);
+            patch.extend_from_slice(&target[start..offset]);
+        }
+    }
+
+    patch.extend_from_slice(&crc32(source).to_le_bytes());
+    patch.extend_from_slice(&crc32(target).to_le_bytes());
+    let checksum = crc32(&patch);
+    patch.extend_from_slice(&checksum.to_le_bytes());
+    patch
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let x = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(0x80 | x);
+            break;
+        }
+        out.push(x);
+        value -= 1;
+    }
+}
+
+/// CRC-32 (IEEE) as used by BPS.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &b in data {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8]> {
+        ensure!(self.data.len() >= n, "Truncated patch");
+        let (bytes, rest) = self.data.split_at(n);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16_be(&mut self) -> Result<u16> {
+        let b = self.bytes(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn varint(&mut self) -> Result<u64> {
+        let mut value = 0u64;
+        let mut shift = 1u64;
+        loop {
+            let x = self.u8()?;
+            value += (x & 0x7F) as u64 * shift;
+            if x & 0x80 != 0 {
+                return Ok(value);
+            }
+            shift <<= 7;
+            value += shift;
+            ensure!(shift < 1 << 56, "Invalid varint in patch");
+        }
+    }
+
+    fn signed_varint(&mut self) -> Result<i64> {
+        let v = self.varint()?;
+        let magnitude = (v >> 1) as i64;
+        Ok(if v & 1 != 0 { -magnitude } else { magnitude })
+    }
+}
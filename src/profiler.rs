@@ -0,0 +1,226 @@
+//! Profiles of executed instructions: attributed to subroutines, or counted by kind and by
+//! address.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use crate::config;
+use crate::instruction::Instruction;
+
+/// Addresses listed in the text report of `InstructionProfiler`, hottest first.
+const HOT_ADDRESSES: usize = 32;
+
+/// Follows `2NNN` calls and `00EE` returns to charge every executed instruction to the
+/// subroutine it belongs to.
+pub struct CallProfiler {
+    /// Entry points of the subroutines currently on the call stack, the program entry first.
+    stack: Vec<u16>,
+    self_counts: HashMap<u16, u64>,
+    total_counts: HashMap<u16, u64>,
+    calls: HashMap<u16, u64>,
+    /// Instruction counts per distinct call stack.
+    stacks: HashMap<Vec<u16>, u64>,
+    instructions: u64,
+}
+
+impl CallProfiler {
+    pub fn new() -> Self {
+        Self::at(config::PROG_ADDR)
+    }
+
+    /// A profiler for a program whose entry point is `entry`.
+    pub fn at(entry: u16) -> Self {
+        Self {
+            stack: vec![entry],
+            self_counts: HashMap::new(),
+            total_counts: HashMap::new(),
+            calls: HashMap::new(),
+            stacks: HashMap::new(),
+            instructions: 0,
+        }
+    }
+
+    /// Record the execution of `opcode`, which is about to run.
+    pub fn record(&mut self, opcode: u16) {
+        self.instructions += 1;
+        let current = *self.stack.last().unwrap();
+        *self.self_counts.entry(current).or_default() += 1;
+        for (i, &addr) in self.stack.iter().enumerate() {
+            // Only count recursive subroutines once.
+            if !self.stack[..i].contains(&addr) {
+                *self.total_counts.entry(addr).or_default() += 1;
+            }
+        }
+        match self.stacks.get_mut(&self.stack[..]) {
+            Some(count) => *count += 1,
+            None => {
+                self.stacks.insert(self.stack.clone(), 1);
+            }
+        }
+
+        if opcode & 0xF000 == 0x2000 {
+            let addr = opcode & 0x0FFF;
+            *self.calls.entry(addr).or_default() += 1;
+            self.stack.push(addr);
+        } else if opcode == 0x00EE && self.stack.len() > 1 {
+            self.stack.pop();
+        }
+    }
+
+    /// A flat profile, sorted by the number of instructions executed in each subroutine itself.
+    ///
+    /// `name` gives the name to show for a subroutine's address.
+    pub fn report(&self, name: impl Fn(u16) -> String) -> String {
+        let mut subs: Vec<_> = self.total_counts.keys().copied().collect();
+        subs.sort_by_key(|addr| {
+            std::cmp::Reverse((self.self_counts.get(addr).copied().unwrap_or(0), *addr))
+        });
+        let percent = |n: u64| 100.0 * n as f64 / self.instructions.max(1) as f64;
+
+        let mut out = String::new();
+        writeln!(
+            out,
+            "{:>7} {:>12} {:>7} {:>12} {:>8}  subroutine",
+            "self%", "self", "total%", "total", "calls"
+        )
+        .unwrap();
+        for addr in subs {
+            let own = self.self_counts.get(&addr).copied().unwrap_or(0);
+            let total = self.total_counts[&addr];
+            let calls = self.calls.get(&addr).copied().unwrap_or(0);
+            writeln!(
+                out,
+                "{:>6.2}% {:>12} {:>6.2}% {:>12} {:>8}  {}",
+                percent(own),
+                own,
+                percent(total),
+                total,
+                calls,
+                name(addr)
+            )
+            .unwrap();
+        }
+        out
+    }
+
+    /// The profile in the "folded stacks" format understood by flame graph tools such as
+    /// `inferno-flamegraph` or `flamegraph.pl`.
+    pub fn folded(&self, name: impl Fn(u16) -> String) -> String {
+        let mut lines: Vec<String> = self
+            .stacks
+            .iter()
+            .map(|(stack, count)| {
+                let frames: Vec<String> = stack.iter().map(|&addr| name(addr)).collect();
+                format!("{} {}", frames.join(";"), count)
+            })
+            .collect();
+        lines.sort();
+        lines.join("\n") + "\n"
+    }
+}
+
+impl Default for CallProfiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Counts executed instructions by kind (opcode pattern) and by address.
+#[derive(Default)]
+pub struct InstructionProfiler {
+    kinds: HashMap<&'static str, u64>,
+    /// The last instruction executed at each address, and how many were.
+    addresses: HashMap<u16, (Instruction, u64)>,
+    instructions: u64,
+}
+
+impl InstructionProfiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record the execution of `instruction`, at `addr`.
+    pub fn record(&mut self, addr: u16, instruction: Instruction) {
+        self.instructions += 1;
+        *self.kinds.entry(instruction.pattern()).or_default() += 1;
+        let entry = self.addresses.entry(addr).or_insert((instruction, 0));
+        *entry = (instruction, entry.1 + 1);
+    }
+
+    /// Kinds of instructions with their counts, most executed first.
+    fn kinds(&self) -> Vec<(&'static str, u64)> {
+        let mut kinds: Vec<_> = self.kinds.iter().map(|(&k, &n)| (k, n)).collect();
+        kinds.sort_by_key(|&(kind, n)| (std::cmp::Reverse(n), kind));
+        kinds
+    }
+
+    /// Addresses with their last instruction and counts, most executed first.
+    fn addresses(&self) -> Vec<(u16, Instruction, u64)> {
+        let mut addresses: Vec<_> = self
+            .addresses
+            .iter()
+            .map(|(&addr, &(instruction, n))| (addr, instruction, n))
+            .collect();
+        addresses.sort_by_key(|&(addr, _, n)| (std::cmp::Reverse(n), addr));
+        addresses
+    }
+
+    /// Every kind of instruction executed, and the hottest addresses, by number of executions.
+    pub fn report(&self) -> String {
+        let percent = |n: u64| 100.0 * n as f64 / self.instructions.max(1) as f64;
+
+        let mut out = String::new();
+        writeln!(out, "{:>7} {:>12}  kind", "%", "count").unwrap();
+        for (kind, n) in self.kinds() {
+            writeln!(out, "{:>6.2}% {:>12}  {}", percent(n), n, kind).unwrap();
+        }
+        writeln!(out).unwrap();
+        writeln!(out, "{:>7} {:>12}  address", "%", "count").unwrap();
+        for (addr, instruction, n) in self.addresses().into_iter().take(HOT_ADDRESSES) {
+            writeln!(
+                out,
+                "{:>6.2}% {:>12}  {:#05x}  {}",
+                percent(n),
+                n,
+                addr,
+                instruction
+            )
+            .unwrap();
+        }
+        out
+    }
+
+    /// All the counts, as JSON.
+    pub fn json(&self) -> String {
+        let kinds: Vec<String> = self
+            .kinds()
+            .into_iter()
+            .map(|(kind, n)| format!("    {{\"kind\": \"{}\", \"count\": {}}}", kind, n))
+            .collect();
+        let addresses: Vec<String> = self
+            .addresses()
+            .into_iter()
+            .map(|(addr, instruction, n)| {
+                format!(
+                    "    {{\"addr\": {}, \"instruction\": \"{}\", \"count\": {}}}",
+                    addr, instruction, n
+                )
+            })
+            .collect();
+        let mut out = String::from("{\n");
+        writeln!(out, "  \"instructions\": {},", self.instructions).unwrap();
+        writeln!(out, "  \"kinds\": [\n{}\n  ],", kinds.join(",\n")).unwrap();
+        writeln!(out, "  \"addresses\": [\n{}\n  ]", addresses.join(",\n")).unwrap();
+        out.push_str("}\n");
+        out
+    }
+}
+
+/// The name the disassembler gives to the subroutine at `addr`.
+pub fn default_name(addr: u16) -> String {
+    if addr == config::PROG_ADDR {
+        "main".to_string()
+    } else {
+        format!("sub_{:03x}", addr)
+    }
+}
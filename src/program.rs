@@ -0,0 +1,444 @@
+//! A small DSL for building CHIP-8 programs from Rust.
+//!
+//! ```
+//! use chip8rs::program::*;
+//!
+//! let mut p = Program::new();
+//! let sub = p.label();
+//! let end = p.label();
+//! p.ld(V0, 5).call(sub).bind(end).jp(end);
+//! p.bind(sub).add(V0, 1).ret();
+//! let rom = p.assemble().unwrap();
+//! assert_eq!(&rom[..4], &[0x60, 0x05, 0x22, 0x06]);
+//! ```
+
+use anyhow::{bail, Result};
+
+use crate::config;
+
+/// One of the 16 general purpose registers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Reg(pub u8);
+
+pub const V0: Reg = Reg(0x0);
+pub const V1: Reg = Reg(0x1);
+pub const V2: Reg = Reg(0x2);
+pub const V3: Reg = Reg(0x3);
+pub const V4: Reg = Reg(0x4);
+pub const V5: Reg = Reg(0x5);
+pub const V6: Reg = Reg(0x6);
+pub const V7: Reg = Reg(0x7);
+pub const V8: Reg = Reg(0x8);
+pub const V9: Reg = Reg(0x9);
+pub const VA: Reg = Reg(0xA);
+pub const VB: Reg = Reg(0xB);
+pub const VC: Reg = Reg(0xC);
+pub const VD: Reg = Reg(0xD);
+pub const VE: Reg = Reg(0xE);
+pub const VF: Reg = Reg(0xF);
+
+/// Second operand of instructions that accept either an immediate byte or a register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Src {
+    Byte(u8),
+    Reg(Reg),
+}
+
+impl From<u8> for Src {
+    fn from(v: u8) -> Self {
+        Src::Byte(v)
+    }
+}
+
+impl From<Reg> for Src {
+    fn from(r: Reg) -> Self {
+        Src::Reg(r)
+    }
+}
+
+/// A position in the program, created with `Program::label` and bound with `Program::bind`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Label(usize);
+
+/// Target of jumps, calls and `LD I`: either a fixed address or a label.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    Addr(u16),
+    Label(Label),
+}
+
+impl From<u16> for Target {
+    fn from(addr: u16) -> Self {
+        Target::Addr(addr)
+    }
+}
+
+impl From<Label> for Target {
+    fn from(label: Label) -> Self {
+        Target::Label(label)
+    }
+}
+
+/// A CHIP-8 program being built.
+///
+/// Instructions are appended by calling the methods named after their mnemonic, which can be
+/// chained. Labels can be referenced before they are bound; they are resolved by `assemble`.
+pub struct Program {
+    origin: u16,
+    bytes: Vec<u8>,
+    labels: Vec<Option<u16>>,
+    /// Offsets in `bytes` of instructions whose low 12 bits must be patched with a label address.
+    fixups: Vec<(usize, Label)>,
+    /// Offsets in `bytes` of 16-bit words that must be patched with a label address (XO-CHIP).
+    long_fixups: Vec<(usize, Label)>,
+}
+
+impl Program {
+    /// Create a program loaded at the usual program address.
+    pub fn new() -> Self {
+        Self::at(config::PROG_ADDR)
+    }
+
+    /// Create a program loaded at address `origin`.
+    pub fn at(origin: u16) -> Self {
+        Self {
+            origin,
+            bytes: Vec::new(),
+            labels: Vec::new(),
+            fixups: Vec::new(),
+            long_fixups: Vec::new(),
+        }
+    }
+
+    /// The address of the next instruction.
+    pub fn here(&self) -> u16 {
+        self.origin + self.bytes.len() as u16
+    }
+
+    /// Create a new unbound label.
+    pub fn label(&mut self) -> Label {
+        self.labels.push(None);
+        Label(self.labels.len() - 1)
+    }
+
+    /// Bind `label` to the current address.
+    pub fn bind(&mut self, label: Label) -> &mut Self {
+        self.labels[label.0] = Some(self.here());
+        self
+    }
+
+    /// The address `label` is bound to, if it is bound yet.
+    pub fn address_of(&self, label: Label) -> Option<u16> {
+        self.labels[label.0]
+    }
+
+    /// Emit raw bytes, e.g. sprite data.
+    pub fn db(&mut self, data: &[u8]) -> &mut Self {
+        self.bytes.extend_from_slice(data);
+        self
+    }
+
+    /// Emit a raw 16-bit opcode.
+    pub fn op(&mut self, opcode: u16) -> &mut Self {
+        self.bytes.extend_from_slice(&opcode.to_be_bytes());
+        self
+    }
+
+    fn op_target(&mut self, opcode: u16, target: impl Into<Target>) -> &mut Self {
+        match target.into() {
+            Target::Addr(addr) => self.op(opcode | (addr & 0x0FFF)),
+            Target::Label(label) => {
+                self.fixups.push((self.bytes.len(), label));
+                self.op(opcode)
+            }
+        }
+    }
+
+    fn op_xy(&mut self, opcode: u16, x: Reg, y: Reg) -> &mut Self {
+        self.op(opcode | ((x.0 as u16) << 8) | ((y.0 as u16) << 4))
+    }
+
+    fn op_xnn(&mut self, opcode: u16, x: Reg, nn: u8) -> &mut Self {
+        self.op(opcode | ((x.0 as u16) << 8) | nn as u16)
+    }
+
+    /// `00E0`: clear the screen.
+    pub fn cls(&mut self) -> &mut Self {
+        self.op(0x00E0)
+    }
+
+    /// `00EE`: return from a subroutine.
+    pub fn ret(&mut self) -> &mut Self {
+        self.op(0x00EE)
+    }
+
+    /// `00CN`: scroll the display down by `n` lines (SCHIP).
+    pub fn scd(&mut self, n: u8) -> &mut Self {
+        self.op(0x00C0 | (n as u16 & 0xF))
+    }
+
+    /// `00DN`: scroll the display up by `n` lines (XO-CHIP).
+    pub fn scu(&mut self, n: u8) -> &mut Self {
+        self.op(0x00D0 | (n as u16 & 0xF))
+    }
+
+    /// `00FB`: scroll the display right by 4 pixels (SCHIP).
+    pub fn scr(&mut self) -> &mut Self {
+        self.op(0x00FB)
+    }
+
+    /// `00FC`: scroll the display left by 4 pixels (SCHIP).
+    pub fn scl(&mut self) -> &mut Self {
+        self.op(0x00FC)
+    }
+
+    /// `00FD`: exit the interpreter (SCHIP).
+    pub fn exit(&mut self) -> &mut Self {
+        self.op(0x00FD)
+    }
+
+    /// `00FE`: switch to low resolution (SCHIP).
+    pub fn low(&mut self) -> &mut Self {
+        self.op(0x00FE)
+    }
+
+    /// `00FF`: switch to high resolution (SCHIP).
+    pub fn high(&mut self) -> &mut Self {
+        self.op(0x00FF)
+    }
+
+    /// `1NNN`: jump to `target`.
+    pub fn jp(&mut self, target: impl Into<Target>) -> &mut Self {
+        self.op_target(0x1000, target)
+    }
+
+    /// `BNNN`: jump to `target` + V0.
+    pub fn jp_v0(&mut self, target: impl Into<Target>) -> &mut Self {
+        self.op_target(0xB000, target)
+    }
+
+    /// `2NNN`: call the subroutine at `target`.
+    pub fn call(&mut self, target: impl Into<Target>) -> &mut Self {
+        self.op_target(0x2000, target)
+    }
+
+    /// `3XNN`/`5XY0`: skip the next instruction if VX == `src`.
+    pub fn se(&mut self, x: Reg, src: impl Into<Src>) -> &mut Self {
+        match src.into() {
+            Src::Byte(nn) => self.op_xnn(0x3000, x, nn),
+            Src::Reg(y) => self.op_xy(0x5000, x, y),
+        }
+    }
+
+    /// `4XNN`/`9XY0`: skip the next instruction if VX != `src`.
+    pub fn sne(&mut self, x: Reg, src: impl Into<Src>) -> &mut Self {
+        match src.into() {
+            Src::Byte(nn) => self.op_xnn(0x4000, x, nn),
+            Src::Reg(y) => self.op_xy(0x9000, x, y),
+        }
+    }
+
+    /// `6XNN`/`8XY0`: VX = `src`.
+    pub fn ld(&mut self, x: Reg, src: impl Into<Src>) -> &mut Self {
+        match src.into() {
+            Src::Byte(nn) => self.op_xnn(0x6000, x, nn),
+            Src::Reg(y) => self.op_xy(0x8000, x, y),
+        }
+    }
+
+    /// `7XNN`/`8XY4`: VX += `src`. Only the register form sets the carry in VF.
+    pub fn add(&mut self, x: Reg, src: impl Into<Src>) -> &mut Self {
+        match src.into() {
+            Src::Byte(nn) => self.op_xnn(0x7000, x, nn),
+            Src::Reg(y) => self.op_xy(0x8004, x, y),
+        }
+    }
+
+    /// `8XY1`: VX |= VY.
+    pub fn or(&mut self, x: Reg, y: Reg) -> &mut Self {
+        self.op_xy(0x8001, x, y)
+    }
+
+    /// `8XY2`: VX &= VY.
+    pub fn and(&mut self, x: Reg, y: Reg) -> &mut Self {
+        self.op_xy(0x8002, x, y)
+    }
+
+    /// `8XY3`: VX ^= VY.
+    pub fn xor(&mut self, x: Reg, y: Reg) -> &mut Self {
+        self.op_xy(0x8003, x, y)
+    }
+
+    /// `8XY5`: VX -= VY.
+    pub fn sub(&mut self, x: Reg, y: Reg) -> &mut Self {
+        self.op_xy(0x8005, x, y)
+    }
+
+    /// `8XY6`: shift right.
+    pub fn shr(&mut self, x: Reg, y: Reg) -> &mut Self {
+        self.op_xy(0x8006, x, y)
+    }
+
+    /// `8XY7`: VX = VY - VX.
+    pub fn subn(&mut self, x: Reg, y: Reg) -> &mut Self {
+        self.op_xy(0x8007, x, y)
+    }
+
+    /// `8XYE`: shift left.
+    pub fn shl(&mut self, x: Reg, y: Reg) -> &mut Self {
+        self.op_xy(0x800E, x, y)
+    }
+
+    /// `ANNN`: I = `target`.
+    pub fn ld_i(&mut self, target: impl Into<Target>) -> &mut Self {
+        self.op_target(0xA000, target)
+    }
+
+    /// `CXNN`: VX = random & NN.
+    pub fn rnd(&mut self, x: Reg, nn: u8) -> &mut Self {
+        self.op_xnn(0xC000, x, nn)
+    }
+
+    /// `DXYN`: draw the `n`-row sprite at I at coordinates (VX, VY), or the 16x16 one if `n` is 0
+    /// (SCHIP).
+    pub fn drw(&mut self, x: Reg, y: Reg, n: u8) -> &mut Self {
+        self.op_xy(0xD000 | (n as u16 & 0xF), x, y)
+    }
+
+    /// `EX9E`: skip the next instruction if key VX is pressed.
+    pub fn skp(&mut self, x: Reg) -> &mut Self {
+        self.op_xnn(0xE09E, x, 0)
+    }
+
+    /// `EXA1`: skip the next instruction if key VX is not pressed.
+    pub fn sknp(&mut self, x: Reg) -> &mut Self {
+        self.op_xnn(0xE0A1, x, 0)
+    }
+
+    /// `FX07`: VX = delay timer.
+    pub fn ld_from_dt(&mut self, x: Reg) -> &mut Self {
+        self.op_xnn(0xF007, x, 0)
+    }
+
+    /// `FX0A`: wait for a key press and store it in VX.
+    pub fn ld_key(&mut self, x: Reg) -> &mut Self {
+        self.op_xnn(0xF00A, x, 0)
+    }
+
+    /// `FX15`: delay timer = VX.
+    pub fn ld_dt(&mut self, x: Reg) -> &mut Self {
+        self.op_xnn(0xF015, x, 0)
+    }
+
+    /// `FX18`: sound timer = VX.
+    pub fn ld_st(&mut self, x: Reg) -> &mut Self {
+        self.op_xnn(0xF018, x, 0)
+    }
+
+    /// `FX1E`: I += VX.
+    pub fn add_i(&mut self, x: Reg) -> &mut Self {
+        self.op_xnn(0xF01E, x, 0)
+    }
+
+    /// `FX29`: I = address of the font glyph for digit VX.
+    pub fn ld_font(&mut self, x: Reg) -> &mut Self {
+        self.op_xnn(0xF029, x, 0)
+    }
+
+    /// `FX30`: I = address of the large font glyph for digit VX (SCHIP).
+    pub fn ld_big_font(&mut self, x: Reg) -> &mut Self {
+        self.op_xnn(0xF030, x, 0)
+    }
+
+    /// `FX33`: store the BCD representation of VX at I, I+1, I+2.
+    pub fn bcd(&mut self, x: Reg) -> &mut Self {
+        self.op_xnn(0xF033, x, 0)
+    }
+
+    /// `FX55`: store V0..=VX at I.
+    pub fn store(&mut self, x: Reg) -> &mut Self {
+        self.op_xnn(0xF055, x, 0)
+    }
+
+    /// `FX65`: load V0..=VX from I.
+    pub fn load(&mut self, x: Reg) -> &mut Self {
+        self.op_xnn(0xF065, x, 0)
+    }
+
+    /// `5XY2`: store VX..=VY at I, leaving I unchanged (XO-CHIP).
+    pub fn store_range(&mut self, x: Reg, y: Reg) -> &mut Self {
+        self.op_xy(0x5002, x, y)
+    }
+
+    /// `5XY3`: load VX..=VY from I, leaving I unchanged (XO-CHIP).
+    pub fn load_range(&mut self, x: Reg, y: Reg) -> &mut Self {
+        self.op_xy(0x5003, x, y)
+    }
+
+    /// `F000 NNNN`: I = `target`, anywhere in the 64K of RAM (XO-CHIP).
+    pub fn ld_i_long(&mut self, target: impl Into<Target>) -> &mut Self {
+        self.op(0xF000);
+        match target.into() {
+            Target::Addr(addr) => self.op(addr),
+            Target::Label(label) => {
+                self.long_fixups.push((self.bytes.len(), label));
+                self.op(0)
+            }
+        }
+    }
+
+    /// `FN01`: select the drawing planes `n` (XO-CHIP).
+    pub fn plane(&mut self, n: u8) -> &mut Self {
+        self.op(0xF001 | ((n as u16 & 0xF) << 8))
+    }
+
+    /// `F002`: load the 16-byte audio pattern at I (XO-CHIP).
+    pub fn audio(&mut self) -> &mut Self {
+        self.op(0xF002)
+    }
+
+    /// `FX3A`: set the audio pitch to VX (XO-CHIP).
+    pub fn pitch(&mut self, x: Reg) -> &mut Self {
+        self.op_xnn(0xF03A, x, 0)
+    }
+
+    /// `FX75`: store V0..=VX in the RPL user flags, X <= 7 (SCHIP).
+    pub fn store_rpl(&mut self, x: Reg) -> &mut Self {
+        self.op_xnn(0xF075, x, 0)
+    }
+
+    /// `FX85`: load V0..=VX from the RPL user flags, X <= 7 (SCHIP).
+    pub fn load_rpl(&mut self, x: Reg) -> &mut Self {
+        self.op_xnn(0xF085, x, 0)
+    }
+
+    /// Resolve all labels and return the program's bytes.
+    pub fn assemble(&self) -> Result<Vec<u8>> {
+        let mut bytes = self.bytes.clone();
+        for &(offset, label) in &self.fixups {
+            let addr = match self.labels[label.0] {
+                Some(addr) => addr,
+                None => bail!("label {} is never bound", label.0),
+            };
+            if addr > 0x0FFF {
+                bail!("label {} is out of range ({:#06x})", label.0, addr);
+            }
+            bytes[offset] |= (addr >> 8) as u8;
+            bytes[offset + 1] = addr as u8;
+        }
+        for &(offset, label) in &self.long_fixups {
+            let addr = match self.labels[label.0] {
+                Some(addr) => addr,
+                None => bail!("label {} is never bound", label.0),
+            };
+            bytes[offset..offset + 2].copy_from_slice(&addr.to_be_bytes());
+        }
+        Ok(bytes)
+    }
+}
+
+impl Default for Program {
+    fn default() -> Self {
+        Self::new()
+    }
+}
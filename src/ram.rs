@@ -1,29 +1,169 @@
+use std::borrow::Cow;
+use std::fmt;
+use std::ops::Range;
+use std::str::FromStr;
+
+use anyhow::{bail, Error};
 use log::debug;
 
+use crate::config;
+use crate::error::Chip8Error;
+
+/// What reads and writes past the end of RAM do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutOfRange {
+    /// Stop the program with `Chip8Error::AddressOutOfRange`.
+    #[default]
+    Error,
+    /// Wrap around to the start of RAM, as the 12-bit addresses of the original interpreters.
+    Wrap,
+    /// Read zeros and ignore writes, like an unconnected bus.
+    Zero,
+}
+
+impl fmt::Display for OutOfRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            OutOfRange::Error => "error",
+            OutOfRange::Wrap => "wrap",
+            OutOfRange::Zero => "zero",
+        })
+    }
+}
+
+impl FromStr for OutOfRange {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "error" => OutOfRange::Error,
+            "wrap" => OutOfRange::Wrap,
+            "zero" => OutOfRange::Zero,
+            _ => bail!(
+                "Unknown out of range policy {:?} (expected error, wrap or zero)",
+                s
+            ),
+        })
+    }
+}
+
 /// the RAM of the Chip-8 machine.
 ///
-/// It consists of 4096 bytes that can be individually addressed using 16-bit addresses.
-pub struct Ram(Box<[u8]>);
+/// It consists of 4096 bytes (64K with XO-CHIP) that can be individually addressed using 16-bit
+/// addresses.
+pub struct Ram {
+    bytes: Box<[u8]>,
+    pub out_of_range: OutOfRange,
+    /// Bytes of the instructions cached by a `BlockCache`.
+    code: Box<[bool]>,
+    /// The range of them written since the last `take_code_written`, if any.
+    code_written: Option<Range<usize>>,
+}
 
 impl Ram {
+    pub fn with_size(size: usize) -> Self {
+        Self {
+            bytes: vec![0u8; size].into_boxed_slice(),
+            out_of_range: OutOfRange::default(),
+            code: vec![false; size].into_boxed_slice(),
+            code_written: None,
+        }
+    }
+
+    /// Number of bytes of RAM.
+    pub fn size(&self) -> usize {
+        self.bytes.len()
+    }
+
     /// Load the content of `data` into RAM at address `addr`.
     pub fn load_at(&mut self, addr: u16, data: &[u8]) {
         let addr = addr as usize;
         let data_size = data.len();
-        let dest = &mut self.0[addr..addr + data_size];
+        let dest = &mut self.bytes[addr..addr + data_size];
         debug!("Writing {} bytes into ram", data.len());
         dest.copy_from_slice(data);
+        self.note_write(addr..addr + data_size);
+    }
+
+    /// Return the `len` bytes of sprite data at address `addr`.
+    pub fn get_sprite(&self, addr: u16, len: u16) -> Result<Cow<'_, [u8]>, Chip8Error> {
+        let start = addr as usize;
+        match self.bytes.get(start..start + len as usize) {
+            Some(sprite) => Ok(Cow::Borrowed(sprite)),
+            None => (start..start + len as usize)
+                .map(|addr| self.read(addr))
+                .collect(),
+        }
+    }
+
+    /// The byte at `addr`, or `None` if it's past the end of RAM whatever `out_of_range` says.
+    pub fn get(&self, addr: usize) -> Option<u8> {
+        self.bytes.get(addr).copied()
+    }
+
+    /// The byte at `addr`.
+    pub fn read(&self, addr: usize) -> Result<u8, Chip8Error> {
+        match (self.get(addr), self.out_of_range) {
+            (Some(value), _) => Ok(value),
+            (None, OutOfRange::Error) => Err(Chip8Error::AddressOutOfRange(addr)),
+            (None, OutOfRange::Wrap) => Ok(self.bytes[addr % self.bytes.len()]),
+            (None, OutOfRange::Zero) => Ok(0),
+        }
+    }
+
+    /// Store `value` at `addr`.
+    pub fn write(&mut self, addr: usize, value: u8) -> Result<(), Chip8Error> {
+        let size = self.bytes.len();
+        let addr = match self.out_of_range {
+            OutOfRange::Wrap => addr % size,
+            OutOfRange::Zero if addr >= size => return Ok(()),
+            _ => addr,
+        };
+        let byte = self
+            .bytes
+            .get_mut(addr)
+            .ok_or(Chip8Error::AddressOutOfRange(addr))?;
+        *byte = value;
+        self.note_write(addr..addr + 1);
+        Ok(())
+    }
+
+    /// Mark the `len` bytes at `addr` as holding cached instructions, so that writing to them is
+    /// noticed by `take_code_written`.
+    pub fn mark_code(&mut self, addr: usize, len: usize) {
+        let end = (addr + len).min(self.code.len());
+        if let Some(code) = self.code.get_mut(addr..end) {
+            code.fill(true);
+        }
+    }
+
+    /// Forget which bytes hold cached instructions.
+    pub fn clear_code(&mut self) {
+        self.code.fill(false);
+        self.code_written = None;
+    }
+
+    /// The range of addresses covering the bytes marked with `mark_code` written since the last
+    /// call, if any.
+    pub fn take_code_written(&mut self) -> Option<Range<usize>> {
+        self.code_written.take()
     }
 
-    /// Return the data for the sprite at address `addr` with height `height`.
-    pub fn get_sprite(&self, addr: u16, height: u8) -> &[u8] {
-        &self.0[(addr as usize)..((addr + height as u16) as usize)]
+    /// Record that `range` was written, if it holds cached instructions.
+    fn note_write(&mut self, range: Range<usize>) {
+        if !self.code[range.clone()].contains(&true) {
+            return;
+        }
+        self.code_written = Some(match self.code_written.take() {
+            Some(written) => written.start.min(range.start)..written.end.max(range.end),
+            None => range,
+        });
     }
 }
 
 impl Default for Ram {
     fn default() -> Self {
-        Self(vec![0u8; 4096].into_boxed_slice())
+        Self::with_size(config::RAM_SIZE)
     }
 }
 
@@ -31,12 +171,14 @@ impl std::ops::Index<u16> for Ram {
     type Output = u8;
 
     fn index(&self, idx: u16) -> &u8 {
-        &self.0[idx as usize]
+        &self.bytes[idx as usize]
     }
 }
 
 impl std::ops::IndexMut<u16> for Ram {
     fn index_mut(&mut self, idx: u16) -> &mut u8 {
-        &mut self.0[idx as usize]
+        let idx = idx as usize;
+        self.note_write(idx..idx + 1);
+        &mut self.bytes[idx]
     }
 }
@@ -0,0 +1,64 @@
+//! The list of recently opened ROMs, kept in the config directory with one path per line, the
+//! most recent first.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use directories::ProjectDirs;
+
+/// Number of ROMs remembered.
+pub const MAX_RECENT: usize = 10;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RecentRoms {
+    paths: Vec<PathBuf>,
+}
+
+impl RecentRoms {
+    /// Location of the list, if the platform has a config directory.
+    pub fn path() -> Option<PathBuf> {
+        ProjectDirs::from("", "", "chip8rs").map(|dirs| dirs.config_dir().join("recent.txt"))
+    }
+
+    /// Load the list at `path`, which is empty if there is no file yet.
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let paths = content
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(PathBuf::from)
+            .take(MAX_RECENT)
+            .collect();
+        Ok(Self { paths })
+    }
+
+    /// Write the list to `path`, creating its directory if needed.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let mut content = String::new();
+        for rom in &self.paths {
+            content.push_str(&rom.to_string_lossy());
+            content.push('\n');
+        }
+        std::fs::write(path, content).with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// Move `rom` to the top of the list, forgetting the oldest ROM if the list is full.
+    pub fn add(&mut self, rom: &Path) {
+        let rom = rom.canonicalize().unwrap_or_else(|_| rom.to_path_buf());
+        self.paths.retain(|path| *path != rom);
+        self.paths.insert(0, rom);
+        self.paths.truncate(MAX_RECENT);
+    }
+
+    /// The ROMs, the most recent first.
+    pub fn paths(&self) -> &[PathBuf] {
+        &self.paths
+    }
+}
@@ -0,0 +1,112 @@
+//! Recording the display as an animated PNG (APNG).
+//!
+//! Frames are kept in memory while recording, since an APNG has to start with its number of
+//! frames, and are only encoded when the recording is saved. Each frame is shown for as many
+//! 60Hz frames as the display didn't change.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use anyhow::{ensure, Context, Result};
+
+use crate::gfx;
+use crate::palette::Palette;
+use crate::screenshot;
+
+/// A capture of the display.
+struct Frame {
+    /// 60Hz frame at which the display started looking like this.
+    start: u64,
+    width: usize,
+    pixels: Vec<u8>,
+}
+
+/// The frames of a recording in progress.
+pub struct Recorder {
+    frames: Vec<Frame>,
+}
+
+impl Recorder {
+    pub fn new() -> Self {
+        Self { frames: Vec::new() }
+    }
+
+    /// Number of distinct frames recorded so far.
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// Record `display` as it is at 60Hz frame `frame`. Later captures during the same frame
+    /// replace the earlier ones, and captures identical to the previous frame are dropped. The
+    /// Mega-Chip and hires CHIP-8 displays don't fit the frame of the recording, and can't be
+    /// captured.
+    pub fn capture(&mut self, frame: u64, display: &[u8], width: usize) -> Result<()> {
+        ensure!(
+            width <= gfx::HIRES.0 && display.len() == width * width / 2,
+            "The {}x{} display can't be recorded",
+            width,
+            display.len() / width.max(1)
+        );
+        if let Some(last) = self.frames.last_mut() {
+            if last.width == width && last.pixels == display {
+                return Ok(());
+            }
+            if last.start == frame {
+                last.width = width;
+                last.pixels = display.to_vec();
+                return Ok(());
+            }
+        }
+        self.frames.push(Frame {
+            start: frame,
+            width,
+            pixels: display.to_vec(),
+        });
+        Ok(())
+    }
+
+    /// Save the recording as an APNG file at `path`, the last frame lasting until `end`. Each
+    /// high resolution pixel is drawn as a `scale` x `scale` square in the colors of `palette`,
+    /// and low resolution pixels twice as large.
+    pub fn save(&self, path: &Path, end: u64, palette: &Palette, scale: usize) -> Result<()> {
+        ensure!(!self.frames.is_empty(), "Nothing was recorded");
+        let file =
+            File::create(path).with_context(|| format!("Failed to create {}", path.display()))?;
+        self.encode(BufWriter::new(file), end, palette, scale)
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    fn encode<W: Write>(&self, out: W, end: u64, palette: &Palette, scale: usize) -> Result<()> {
+        let (width, height) = gfx::HIRES;
+        let mut encoder = png::Encoder::new(out, (width * scale) as u32, (height * scale) as u32);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.set_animated(self.frames.len() as u32, 0)?;
+        let mut writer = encoder.write_header()?;
+        for (i, frame) in self.frames.iter().enumerate() {
+            let next = self.frames.get(i + 1).map_or(end, |next| next.start);
+            let duration = next.saturating_sub(frame.start).clamp(1, u16::MAX as u64);
+            writer.set_frame_delay(duration as u16, 60)?;
+            let pixel_scale = scale * width / frame.width;
+            writer.write_image_data(&screenshot::to_rgba(
+                &frame.pixels,
+                frame.width,
+                palette,
+                pixel_scale,
+            ))?;
+        }
+        writer.finish()?;
+        Ok(())
+    }
+}
+
+impl Default for Recorder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,107 @@
+//! History of recent machine states, to step backwards through them.
+//!
+//! Only the latest snapshot is kept in full. Each older one is stored as its difference with the
+//! snapshot that followed it: the runs of bytes that changed, XORed together. From one frame to
+//! the next, a program usually touches a few registers, RAM bytes and pixels, so a few seconds
+//! of history take little more memory than a single snapshot.
+
+use std::collections::VecDeque;
+
+/// A bounded history of snapshots, as encoded by `savestate::State::encode`.
+pub struct Rewind {
+    /// The most recent snapshot.
+    latest: Option<Vec<u8>>,
+    /// How to get each older snapshot back from the one after it, oldest first.
+    deltas: VecDeque<Delta>,
+    capacity: usize,
+}
+
+struct Delta {
+    /// Length of the older snapshot.
+    len: usize,
+    /// Runs of changed bytes: the number of unchanged bytes before the run, and the XOR of the
+    /// two snapshots over the run.
+    runs: Vec<(usize, Vec<u8>)>,
+}
+
+impl Delta {
+    /// The difference that turns `new` back into `old`.
+    fn between(old: &[u8], new: &[u8]) -> Self {
+        let mut runs = Vec::new();
+        let mut skip = 0;
+        let mut run = Vec::new();
+        for i in 0..old.len().max(new.len()) {
+            let diff = old.get(i).unwrap_or(&0) ^ new.get(i).unwrap_or(&0);
+            if diff != 0 {
+                run.push(diff);
+            } else if run.is_empty() {
+                skip += 1;
+            } else {
+                runs.push((skip, std::mem::take(&mut run)));
+                skip = 1;
+            }
+        }
+        if !run.is_empty() {
+            runs.push((skip, run));
+        }
+        Self {
+            len: old.len(),
+            runs,
+        }
+    }
+
+    /// Turn `new` back into the older snapshot.
+    fn apply(&self, new: &[u8]) -> Vec<u8> {
+        let mut old = new.to_vec();
+        old.resize(self.len.max(new.len()), 0);
+        let mut pos = 0;
+        for (skip, run) in &self.runs {
+            pos += skip;
+            for (byte, diff) in old[pos..].iter_mut().zip(run) {
+                *byte ^= diff;
+            }
+            pos += run.len();
+        }
+        old.truncate(self.len);
+        old
+    }
+}
+
+impl Rewind {
+    /// Keep up to `capacity` snapshots before the latest one.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            latest: None,
+            deltas: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// Record a new snapshot, forgetting the oldest one if the history is full.
+    pub fn push(&mut self, snapshot: Vec<u8>) {
+        if let Some(latest) = &self.latest {
+            if self.deltas.len() == self.capacity {
+                self.deltas.pop_front();
+            }
+            self.deltas.push_back(Delta::between(latest, &snapshot));
+        }
+        self.latest = Some(snapshot);
+    }
+
+    /// Go back to the snapshot before the latest one and return it, or `None` if there is none.
+    pub fn pop(&mut self) -> Option<Vec<u8>> {
+        let delta = self.deltas.pop_back()?;
+        let previous = delta.apply(self.latest.as_ref()?);
+        self.latest = Some(previous.clone());
+        Some(previous)
+    }
+
+    /// Number of snapshots that `pop` can go back to.
+    pub fn len(&self) -> usize {
+        self.deltas.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.deltas.is_empty()
+    }
+}
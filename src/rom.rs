@@ -0,0 +1,39 @@
+//! Small ROM-management chores: trimming, padding and splicing binaries.
+
+use anyhow::{ensure, Context, Result};
+
+/// Return `rom` without its trailing zero bytes.
+pub fn trim(rom: &[u8]) -> &[u8] {
+    let len = rom.iter().rposition(|&b| b != 0).map_or(0, |i| i + 1);
+    &rom[..len]
+}
+
+/// Pad `rom` with `fill` bytes up to `size` bytes.
+pub fn pad(rom: &[u8], size: usize, fill: u8) -> Result<Vec<u8>> {
+    ensure!(
+        rom.len() <= size,
+        "ROM is already larger than {} bytes ({} bytes)",
+        size,
+        rom.len()
+    );
+    let mut out = rom.to_vec();
+    out.resize(size, fill);
+    Ok(out)
+}
+
+/// Overwrite `base` with `data` starting at `offset`, growing it (with zeros) if needed.
+pub fn splice(base: &mut Vec<u8>, offset: usize, data: &[u8]) {
+    if base.len() < offset + data.len() {
+        base.resize(offset + data.len(), 0);
+    }
+    base[offset..offset + data.len()].copy_from_slice(data);
+}
+
+/// Parse a size or offset given either in decimal or in hex with a `0x` prefix.
+pub fn parse_number(s: &str) -> Result<usize> {
+    let n = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => s.parse(),
+    };
+    n.with_context(|| format!("Invalid number {:?}", s))
+}
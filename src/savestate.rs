@@ -0,0 +1,260 @@
+//! The save state file format.
+//!
+//! The layout is spelled out here rather than left to a serialization library, so that a state
+//! saved on one platform loads identically on any other. All integers are little-endian.
+//!
+//! | Bytes | Field                                          |
+//! |-------|------------------------------------------------|
+//! | 4     | Magic number: `C8SS`                           |
+//! | 2     | Format version, currently 3                    |
+//! | 2     | Program counter                                |
+//! | 2     | I                                              |
+//! | 16    | V0 to VF                                       |
+//! | 1     | Delay timer                                    |
+//! | 1     | Sound timer                                    |
+//! | 2     | Keys held down, bit N set for key N            |
+//! | 1     | Stack depth N                                  |
+//! | 2 * N | Stack, the oldest return address first         |
+//! | 1     | Time since the last frame, in 256ths of frame  |
+//! | 8     | 60Hz frames elapsed                            |
+//! | 4     | RAM size M                                     |
+//! | M     | RAM                                            |
+//! | 2     | Display width W                                |
+//! | 2     | Display height H                               |
+//! | W * H | Display, one byte per pixel, row by row        |
+//!
+//! Version 2 appends the SUPER-CHIP and XO-CHIP state:
+//!
+//! | Bytes | Field                                          |
+//! |-------|------------------------------------------------|
+//! | 16    | RPL user flags                                 |
+//! | 1     | Selected planes, bit N set for plane N         |
+//! | 1     | Audio pitch                                    |
+//! | 1     | 1 if an audio pattern follows, 0 otherwise     |
+//! | 16    | Audio pattern, only if present                 |
+//!
+//! Version 3 appends the Mega-Chip registers:
+//!
+//! | Bytes | Field                                          |
+//! |-------|------------------------------------------------|
+//! | 1     | 1 if the registers follow, 0 otherwise         |
+//! | 765   | Colors 1 to 255, as RGB                        |
+//! | 2     | Sprite width                                   |
+//! | 2     | Sprite height                                  |
+//! | 1     | 1 if a collision color follows, 0 otherwise    |
+//! | 1     | Collision color, only if present               |
+//!
+//! Later versions may only append fields, so that older states can still be read.
+
+use anyhow::{bail, ensure, Result};
+
+const MAGIC: &[u8; 4] = b"C8SS";
+const VERSION: u16 = 3;
+
+/// A snapshot of the whole machine.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct State {
+    pub pc: u16,
+    pub i: u16,
+    pub v: [u8; 16],
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+    pub keys: [bool; 16],
+    pub stack: Vec<u16>,
+    /// Time elapsed since the last 60Hz frame, in 256ths of a frame.
+    pub frame_phase: u8,
+    pub frames: u64,
+    pub ram: Vec<u8>,
+    pub display_width: u16,
+    pub display_height: u16,
+    pub display: Vec<u8>,
+    /// The SUPER-CHIP `FX75`/`FX85` flags.
+    pub rpl: [u8; 16],
+    /// The XO-CHIP planes selected by `FN01`, one bit per plane.
+    pub planes: u8,
+    pub pitch: u8,
+    pub audio_pattern: Option<[u8; 16]>,
+    /// The Mega-Chip registers, for Mega-Chip machines.
+    pub megachip: Option<MegaChipRegisters>,
+}
+
+/// The state of the Mega-Chip display that isn't in the pixels.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MegaChipRegisters {
+    /// Colors 1 to 255 loaded by `02NN`, as RGB.
+    pub colors: Vec<[u8; 3]>,
+    pub sprite_width: u16,
+    pub sprite_height: u16,
+    /// Color whose pixels report a collision when drawn over, if set by `09NN`.
+    pub collision_color: Option<u8>,
+}
+
+impl State {
+    /// Serialize the state in the format described in the module documentation.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(96 + self.ram.len() + self.display.len());
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&VERSION.to_le_bytes());
+        out.extend_from_slice(&self.pc.to_le_bytes());
+        out.extend_from_slice(&self.i.to_le_bytes());
+        out.extend_from_slice(&self.v);
+        out.push(self.delay_timer);
+        out.push(self.sound_timer);
+        let keys = (0..16)
+            .filter(|&k| self.keys[k])
+            .fold(0u16, |bits, k| bits | 1 << k);
+        out.extend_from_slice(&keys.to_le_bytes());
+        out.push(self.stack.len() as u8);
+        for addr in &self.stack {
+            out.extend_from_slice(&addr.to_le_bytes());
+        }
+        out.push(self.frame_phase);
+        out.extend_from_slice(&self.frames.to_le_bytes());
+        out.extend_from_slice(&(self.ram.len() as u32).to_le_bytes());
+        out.extend_from_slice(&self.ram);
+        out.extend_from_slice(&self.display_width.to_le_bytes());
+        out.extend_from_slice(&self.display_height.to_le_bytes());
+        out.extend_from_slice(&self.display);
+        out.extend_from_slice(&self.rpl);
+        out.push(self.planes);
+        out.push(self.pitch);
+        match &self.audio_pattern {
+            Some(pattern) => {
+                out.push(1);
+                out.extend_from_slice(pattern);
+            }
+            None => out.push(0),
+        }
+        match &self.megachip {
+            Some(megachip) => {
+                out.push(1);
+                for color in &megachip.colors {
+                    out.extend_from_slice(color);
+                }
+                out.extend_from_slice(&megachip.sprite_width.to_le_bytes());
+                out.extend_from_slice(&megachip.sprite_height.to_le_bytes());
+                match megachip.collision_color {
+                    Some(color) => out.extend_from_slice(&[1, color]),
+                    None => out.push(0),
+                }
+            }
+            None => out.push(0),
+        }
+        out
+    }
+
+    /// Read a state written by `encode`.
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        let mut r = Reader { bytes };
+        ensure!(r.take(4)? == MAGIC, "Not a chip8rs save state");
+        let version = r.u16()?;
+        if version > VERSION {
+            bail!(
+                "Save state version {} is newer than this chip8rs supports",
+                version
+            );
+        }
+        let pc = r.u16()?;
+        let i = r.u16()?;
+        let v = r.array()?;
+        let delay_timer = r.u8()?;
+        let sound_timer = r.u8()?;
+        let key_bits = r.u16()?;
+        let mut keys = [false; 16];
+        for (k, key) in keys.iter_mut().enumerate() {
+            *key = key_bits & (1 << k) != 0;
+        }
+        let depth = r.u8()? as usize;
+        let stack = (0..depth).map(|_| r.u16()).collect::<Result<_>>()?;
+        let frame_phase = r.u8()?;
+        let frames = r.u64()?;
+        let ram_size = r.u32()? as usize;
+        let ram = r.take(ram_size)?.to_vec();
+        let display_width = r.u16()?;
+        let display_height = r.u16()?;
+        let display = r
+            .take(display_width as usize * display_height as usize)?
+            .to_vec();
+        let (rpl, planes, pitch, audio_pattern) = if version >= 2 {
+            let rpl = r.array()?;
+            let planes = r.u8()?;
+            let pitch = r.u8()?;
+            let audio_pattern = match r.u8()? {
+                0 => None,
+                _ => Some(r.array()?),
+            };
+            (rpl, planes, pitch, audio_pattern)
+        } else {
+            ([0; 16], 1, 64, None)
+        };
+        let megachip = if version >= 3 && r.u8()? != 0 {
+            Some(MegaChipRegisters {
+                colors: (0..255).map(|_| r.array()).collect::<Result<_>>()?,
+                sprite_width: r.u16()?,
+                sprite_height: r.u16()?,
+                collision_color: match r.u8()? {
+                    0 => None,
+                    _ => Some(r.u8()?),
+                },
+            })
+        } else {
+            None
+        };
+        Ok(Self {
+            pc,
+            i,
+            v,
+            delay_timer,
+            sound_timer,
+            keys,
+            stack,
+            frame_phase,
+            frames,
+            ram,
+            display_width,
+            display_height,
+            display,
+            rpl,
+            planes,
+            pitch,
+            audio_pattern,
+            megachip,
+        })
+    }
+}
+
+/// Reads little-endian fields from the front of a byte slice.
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        ensure!(self.bytes.len() >= n, "Truncated save state");
+        let (head, tail) = self.bytes.split_at(n);
+        self.bytes = tail;
+        Ok(head)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.take(N)?);
+        Ok(array)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        self.array().map(u16::from_le_bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        self.array().map(u32::from_le_bytes)
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        self.array().map(u64::from_le_bytes)
+    }
+}
@@ -0,0 +1,149 @@
+//! Saving the display as PNG images.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{ensure, Context, Result};
+
+use crate::palette::Palette;
+
+/// Encode `display` (one byte per pixel, `width` pixels per line) as a PNG, drawing each pixel as
+/// a `scale` x `scale` square in the colors of `palette`.
+pub fn encode_png<W: Write>(
+    out: W,
+    display: &[u8],
+    width: usize,
+    palette: &Palette,
+    scale: usize,
+) -> Result<()> {
+    let height = display.len() / width;
+    let mut encoder = png::Encoder::new(out, (width * scale) as u32, (height * scale) as u32);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&to_rgba(display, width, palette, scale))?;
+    Ok(())
+}
+
+/// The RGBA pixels of `display` drawn with `scale` x `scale` squares, as in `encode_png`.
+pub(crate) fn to_rgba(display: &[u8], width: usize, palette: &Palette, scale: usize) -> Vec<u8> {
+    let mut data = Vec::with_capacity(display.len() * scale * scale * 4);
+    for line in display.chunks(width) {
+        let scaled: Vec<u8> = line
+            .iter()
+            .flat_map(|pixel| palette.color(*pixel).repeat(scale))
+            .collect();
+        for _ in 0..scale {
+            data.extend_from_slice(&scaled);
+        }
+    }
+    data
+}
+
+/// Save `display` as a PNG file at `path`. See `encode_png`.
+pub fn save_png(
+    path: &Path,
+    display: &[u8],
+    width: usize,
+    palette: &Palette,
+    scale: usize,
+) -> Result<()> {
+    let file =
+        File::create(path).with_context(|| format!("Failed to create {}", path.display()))?;
+    encode_png(BufWriter::new(file), display, width, palette, scale)
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// A file name for a capture taken at `time`, e.g. `chip8rs-20240131-235959.123.png` for the
+/// `png` extension, in UTC.
+pub fn timestamped_name(time: SystemTime, extension: &str) -> String {
+    let elapsed = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = elapsed.as_secs();
+    let (year, month, day) = civil_date((secs / 86400) as i64);
+    format!(
+        "chip8rs-{:04}{:02}{:02}-{:02}{:02}{:02}.{:03}.{}",
+        year,
+        month,
+        day,
+        secs / 3600 % 24,
+        secs / 60 % 60,
+        secs % 60,
+        elapsed.subsec_millis(),
+        extension
+    )
+}
+
+/// The year, month and day of the `days`th day since 1970-01-01, in the proleptic Gregorian
+/// calendar (Howard Hinnant's `civil_from_days`).
+fn civil_date(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Encode `display` (one byte per pixel, `width` pixels per line) as a plain PBM image, with
+/// every pixel that isn't 0 black.
+pub fn encode_pbm(display: &[u8], width: usize) -> String {
+    let mut out = format!("P1\n{} {}\n", width, display.len() / width);
+    for line in display.chunks(width) {
+        let bits: Vec<&str> = line
+            .iter()
+            .map(|pixel| if *pixel != 0 { "1" } else { "0" })
+            .collect();
+        out.push_str(&bits.join(" "));
+        out.push('\n');
+    }
+    out
+}
+
+/// Saves the display every few frames, to files named after the frame number.
+pub struct Series {
+    dir: PathBuf,
+    every: u64,
+    scale: usize,
+    /// Frame at which the next screenshot is due.
+    next: u64,
+}
+
+impl Series {
+    /// Save a screenshot every `every` frames in `dir`, which is created if needed.
+    pub fn new(dir: PathBuf, every: u64, scale: usize) -> Result<Self> {
+        ensure!(
+            every > 0,
+            "The screenshot interval must be at least 1 frame"
+        );
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+        Ok(Self {
+            dir,
+            every,
+            scale,
+            next: every,
+        })
+    }
+
+    /// Save `display` if a screenshot is due at `frame`.
+    pub fn capture(
+        &mut self,
+        frame: u64,
+        display: &[u8],
+        width: usize,
+        palette: &Palette,
+    ) -> Result<()> {
+        if frame < self.next {
+            return Ok(());
+        }
+        self.next = frame - frame % self.every + self.every;
+        let path = self.dir.join(format!("frame-{:06}.png", frame));
+        save_png(&path, display, width, palette, self.scale)
+    }
+}
@@ -0,0 +1,196 @@
+//! Runs Timendus' CHIP-8 test suite (https://github.com/Timendus/chip8-test-suite) without a
+//! window.
+//!
+//! Each test ROM draws its results on the display and then loops forever. The ROMs that mark their
+//! results with known sprites are judged by the marks on their final display, see `read_marks`.
+//! Every run is also judged by a hash of its final display, compared with the hash of a run known
+//! to pass. The ROMs aren't distributed with the emulator: the suite is read from a directory,
+//! with the hashes of passing runs kept next to the ROMs in `EXPECTED_FILE`.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+
+use crate::config::Quirks;
+use crate::detect::Profile;
+use crate::{patch, Chip8};
+
+/// Name of the file of expected display hashes, in the directory of the suite.
+pub const EXPECTED_FILE: &str = "selftest.toml";
+
+/// Frames each test runs for, at `CYCLES_PER_FRAME` instructions each: long enough for the
+/// slowest test, the quirks test, to draw all its results.
+pub const FRAMES: u64 = 600;
+pub const CYCLES_PER_FRAME: u32 = 1000;
+
+/// Where the tests that ask which platform to test look for an answer, to skip their menu.
+const PLATFORM_ADDR: u16 = 0x1FF;
+
+/// One run of a ROM of the suite.
+pub struct Test {
+    /// Name of the run in reports and in the file of expected hashes.
+    pub name: &'static str,
+    /// File name of the ROM in the suite.
+    pub rom: &'static str,
+    /// What the run checks.
+    pub covers: &'static str,
+    /// The machine and quirks to run it with.
+    pub profile: Profile,
+    /// The answer to the platform menu, for the ROMs that have one.
+    pub platform: Option<u8>,
+}
+
+/// The runs of the suite that need no input. The keypad and sound tests need someone at the
+/// keyboard, and are left out.
+pub const TESTS: &[Test] = &[
+    Test {
+        name: "chip8-logo",
+        rom: "1-chip8-logo.ch8",
+        covers: "drawing a splash screen",
+        profile: Profile::Chip8,
+        platform: None,
+    },
+    Test {
+        name: "ibm-logo",
+        rom: "2-ibm-logo.ch8",
+        covers: "00E0, 1NNN, 6XNN, 7XNN, ANNN, DXYN",
+        profile: Profile::Chip8,
+        platform: None,
+    },
+    Test {
+        name: "corax+",
+        rom: "3-corax+.ch8",
+        covers: "opcodes",
+        profile: Profile::Chip8,
+        platform: None,
+    },
+    Test {
+        name: "flags",
+        rom: "4-flags.ch8",
+        covers: "VF after arithmetic",
+        profile: Profile::Chip8,
+        platform: None,
+    },
+    Test {
+        name: "quirks-chip8",
+        rom: "5-quirks.ch8",
+        covers: "CHIP-8 quirks",
+        profile: Profile::Chip8,
+        platform: Some(1),
+    },
+    Test {
+        name: "quirks-schip",
+        rom: "5-quirks.ch8",
+        covers: "SUPER-CHIP quirks",
+        profile: Profile::Schip,
+        platform: Some(2),
+    },
+    Test {
+        name: "quirks-xochip",
+        rom: "5-quirks.ch8",
+        covers: "XO-CHIP quirks",
+        profile: Profile::XoChip,
+        platform: Some(3),
+    },
+];
+
+impl Test {
+    /// Run the test on `rom` until its results are on the display.
+    pub fn run(&self, rom: &[u8]) -> Result<Chip8> {
+        let xochip = self.profile == Profile::XoChip;
+        Chip8::check_rom(rom, xochip)?;
+        let mut chip8 = if xochip {
+            Chip8::from_bytes_xochip(rom)
+        } else {
+            Chip8::from_bytes(rom)
+        };
+        chip8.set_quirks(Quirks::for_profile(self.profile));
+        if let Some(platform) = self.platform {
+            chip8.interconnect.ram[PLATFORM_ADDR] = platform;
+        }
+        chip8.run(FRAMES * CYCLES_PER_FRAME as u64, CYCLES_PER_FRAME * 60)?;
+        Ok(chip8)
+    }
+}
+
+/// The sprites a test ROM draws next to each of its results.
+pub struct Marks {
+    pub pass: &'static [u8],
+    pub fail: &'static [u8],
+}
+
+/// The marks that can be read off the display: "OK" and "NO", as drawn by corax89's opcode test
+/// and the ROMs based on it.
+pub const MARKS: &[Marks] = &[Marks {
+    pass: &[0xEA, 0xAC, 0xAA, 0xEA],
+    fail: &[0xCE, 0xAA, 0xAA, 0xAE],
+}];
+
+/// What the marks on the display of a finished test say.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    /// All the results, `passed` of them, are marked as passing.
+    Pass { passed: usize },
+    /// `failed` of the `total` results are marked as failing.
+    Fail { failed: usize, total: usize },
+}
+
+/// The verdict of the marks on the display of `chip8`, which ran `rom`. There is none if `rom`
+/// doesn't hold the sprites of any of `MARKS`, or if the display shows none of them.
+pub fn read_marks(rom: &[u8], chip8: &Chip8) -> Option<Verdict> {
+    let holds = |sprite: &[u8]| rom.windows(sprite.len()).any(|bytes| bytes == sprite);
+    let marks = MARKS.iter().find(|m| holds(m.pass) && holds(m.fail))?;
+    let passed = count_sprite(chip8, marks.pass);
+    let failed = count_sprite(chip8, marks.fail);
+    match (passed, failed) {
+        (0, 0) => None,
+        (passed, 0) => Some(Verdict::Pass { passed }),
+        (passed, failed) => Some(Verdict::Fail {
+            failed,
+            total: passed + failed,
+        }),
+    }
+}
+
+/// Number of places where `sprite` is on the display of `chip8`, with the rest of the 8 pixels
+/// of each of its rows off.
+fn count_sprite(chip8: &Chip8, sprite: &[u8]) -> usize {
+    let (display, width) = (chip8.display(), chip8.display_width());
+    let height = display.len() / width;
+    let matches = |x: usize, y: usize| {
+        sprite.iter().enumerate().all(|(row, bits)| {
+            (0..8).all(|bit| {
+                let lit = display[(y + row) * width + x + bit] != 0;
+                lit == (bits & (0x80 >> bit) != 0)
+            })
+        })
+    };
+    let rows = (height + 1).saturating_sub(sprite.len());
+    let columns = (width + 1).saturating_sub(8);
+    (0..rows)
+        .flat_map(|y| (0..columns).map(move |x| (x, y)))
+        .filter(|&(x, y)| matches(x, y))
+        .count()
+}
+
+/// Hash of the display of `chip8`, as printed by headless runs.
+pub fn display_hash(chip8: &Chip8) -> String {
+    format!("{:08x}", patch::crc32(chip8.display()))
+}
+
+/// The display hashes of passing runs, by test name, from `path`. There are none if the file
+/// doesn't exist yet.
+pub fn load_expected(path: &Path) -> Result<BTreeMap<String, String>> {
+    if !path.exists() {
+        return Ok(BTreeMap::new());
+    }
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    toml::from_str(&content).with_context(|| format!("Invalid {}", path.display()))
+}
+
+pub fn save_expected(path: &Path, expected: &BTreeMap<String, String>) -> Result<()> {
+    std::fs::write(path, toml::to_string(expected)?)
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
@@ -0,0 +1,128 @@
+use std::io::ErrorKind;
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use log::{info, warn};
+use tungstenite::{Message, WebSocket};
+
+use crate::Chip8;
+
+/// Number of CPU steps executed per 60Hz frame, matching the 1000 updates/sec of the windowed
+/// frontend.
+const STEPS_PER_FRAME: u32 = 1000 / 60;
+const FRAME_DURATION: Duration = Duration::from_micros(1_000_000 / 60);
+
+/// Run the emulator without a window, serving `rom` to WebSocket clients connecting on `addr`.
+///
+/// Every client gets its own independent machine running in a dedicated thread. The display is
+/// sent as a binary message of 64x32 bytes (one byte per pixel, or 128x64 in SUPER-CHIP high
+/// resolution) whenever it changes, and clients press and release keypad keys by sending text
+/// messages such as `down 5` or `up a`.
+pub fn serve<A: ToSocketAddrs>(rom: PathBuf, addr: A) -> Result<()> {
+    let listener = TcpListener::bind(addr).context("Failed to bind server address")?;
+    info!("serving {} on ws://{}", rom.display(), listener.local_addr()?);
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                warn!("connection failed: {}", e);
+                continue;
+            }
+        };
+        let rom = rom.clone();
+        thread::spawn(move || {
+            let peer = stream.peer_addr().ok();
+            info!("session started for {:?}", peer);
+            if let Err(e) = run_session(&rom, stream) {
+                warn!("session for {:?} failed: {:#}", peer, e);
+            }
+            info!("session ended for {:?}", peer);
+        });
+    }
+    Ok(())
+}
+
+fn run_session(rom: &Path, stream: TcpStream) -> Result<()> {
+    let mut chip8 = Chip8::new(rom)?;
+    let mut socket = tungstenite::accept(stream).context("WebSocket handshake failed")?;
+    socket.get_mut().set_nonblocking(true)?;
+
+    loop {
+        let frame_start = Instant::now();
+        if !handle_messages(&mut socket, &mut chip8)? {
+            return Ok(());
+        }
+
+        for _ in 0..STEPS_PER_FRAME {
+            chip8.step()?;
+        }
+        chip8.advance_time(FRAME_DURATION);
+
+        if chip8.interconnect.gfx.is_dirty() {
+            chip8.interconnect.gfx.clear_dirty();
+            let frame = chip8.display().to_vec();
+            send(&mut socket, Message::Binary(frame))?;
+        } else {
+            flush(&mut socket)?;
+        }
+
+        if let Some(remaining) = FRAME_DURATION.checked_sub(frame_start.elapsed()) {
+            thread::sleep(remaining);
+        }
+    }
+}
+
+/// Process all pending client messages. Return `false` once the client has closed the session.
+fn handle_messages(socket: &mut WebSocket<TcpStream>, chip8: &mut Chip8) -> Result<bool> {
+    loop {
+        match socket.read_message() {
+            Ok(Message::Text(text)) => {
+                if let Some((key, is_down)) = parse_input(&text) {
+                    chip8.set_key(key, is_down);
+                } else {
+                    warn!("ignoring invalid input message {:?}", text);
+                }
+            }
+            Ok(Message::Close(_)) => return Ok(false),
+            Ok(_) => {}
+            Err(tungstenite::Error::Io(e)) if e.kind() == ErrorKind::WouldBlock => {
+                return Ok(true)
+            }
+            Err(tungstenite::Error::ConnectionClosed) => return Ok(false),
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+fn send(socket: &mut WebSocket<TcpStream>, msg: Message) -> Result<()> {
+    match socket.write_message(msg) {
+        Err(tungstenite::Error::Io(e)) if e.kind() == ErrorKind::WouldBlock => Ok(()),
+        res => Ok(res?),
+    }
+}
+
+fn flush(socket: &mut WebSocket<TcpStream>) -> Result<()> {
+    match socket.write_pending() {
+        Err(tungstenite::Error::Io(e)) if e.kind() == ErrorKind::WouldBlock => Ok(()),
+        res => Ok(res?),
+    }
+}
+
+/// Parse an input message of the form `down <key>` or `up <key>`, `key` being a hex digit.
+fn parse_input(text: &str) -> Option<(u8, bool)> {
+    let mut words = text.split_whitespace();
+    let is_down = match words.next()? {
+        "down" => true,
+        "up" => false,
+        _ => return None,
+    };
+    let key = u8::from_str_radix(words.next()?, 16).ok()?;
+    if key > 0xF || words.next().is_some() {
+        return None;
+    }
+    Some((key, is_down))
+}
@@ -0,0 +1,196 @@
+use egui::{Color32, CtxRef, Rect, Sense, Stroke, Ui, Vec2};
+
+const EDIT_CELL: f32 = 16.0;
+/// Width of the display preview, whatever the display resolution.
+const PREVIEW_W: f32 = 256.0;
+
+/// A small editor for drawing sprites, previewing them over the current display and exporting
+/// their bytes.
+///
+/// Sprites are either 8 pixels wide and 1 to 15 rows high (`DXYN`), or 16x16 (`DXY0`).
+pub struct SpriteEditor {
+    pub open: bool,
+    pixels: [[bool; 16]; 16],
+    large: bool,
+    height: u8,
+    x: u8,
+    y: u8,
+    octo: bool,
+    /// Value being painted while the pointer is held down on the grid.
+    paint_value: Option<bool>,
+}
+
+impl SpriteEditor {
+    pub fn new() -> Self {
+        Self {
+            open: false,
+            pixels: [[false; 16]; 16],
+            large: false,
+            height: 8,
+            x: 0,
+            y: 0,
+            octo: false,
+            paint_value: None,
+        }
+    }
+
+    fn width(&self) -> usize {
+        if self.large {
+            16
+        } else {
+            8
+        }
+    }
+
+    fn rows(&self) -> usize {
+        if self.large {
+            16
+        } else {
+            self.height as usize
+        }
+    }
+
+    /// Return the sprite data as it would be laid out in RAM.
+    pub fn bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for row in &self.pixels[..self.rows()] {
+            for chunk in row[..self.width()].chunks(8) {
+                bytes.push(
+                    chunk
+                        .iter()
+                        .fold(0u8, |byte, &set| (byte << 1) | set as u8),
+                );
+            }
+        }
+        bytes
+    }
+
+    /// Format the sprite data either as a comma-separated hex list or as Octo source.
+    pub fn export(&self) -> String {
+        let bytes = self.bytes();
+        let lines = bytes.chunks(self.width() / 8).map(|row| {
+            row.iter()
+                .map(|b| format!("0x{:02X}", b))
+                .collect::<Vec<_>>()
+                .join(if self.octo { " " } else { ", " })
+        });
+        if self.octo {
+            let mut out = String::from(": sprite\n");
+            for line in lines {
+                out.push_str("  ");
+                out.push_str(&line);
+                out.push('\n');
+            }
+            out
+        } else {
+            lines.collect::<Vec<_>>().join(",\n")
+        }
+    }
+
+    pub fn ui(&mut self, ctx: &CtxRef, display: &[u8], display_w: usize) {
+        let mut open = self.open;
+        egui::Window::new("Sprite editor")
+            .open(&mut open)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.radio_value(&mut self.large, false, "8xN");
+                    ui.radio_value(&mut self.large, true, "16x16");
+                    if !self.large {
+                        ui.add(egui::DragValue::new(&mut self.height).clamp_range(1..=15));
+                        ui.label("rows");
+                    }
+                    if ui.button("Clear").clicked() {
+                        self.pixels = [[false; 16]; 16];
+                    }
+                    if ui.button("Invert").clicked() {
+                        for row in self.pixels.iter_mut() {
+                            for px in row.iter_mut() {
+                                *px = !*px;
+                            }
+                        }
+                    }
+                });
+                self.edit_grid(ui);
+
+                ui.separator();
+                ui.horizontal(|ui| {
+                    ui.label("Preview at");
+                    let display_h = display.len() / display_w;
+                    ui.add(egui::DragValue::new(&mut self.x).clamp_range(0..=display_w - 1));
+                    ui.add(egui::DragValue::new(&mut self.y).clamp_range(0..=display_h - 1));
+                });
+                self.preview(ui, display, display_w);
+
+                ui.separator();
+                ui.horizontal(|ui| {
+                    ui.radio_value(&mut self.octo, false, "Hex");
+                    ui.radio_value(&mut self.octo, true, "Octo");
+                    if ui.button("Copy").clicked() {
+                        ui.output().copied_text = self.export();
+                    }
+                });
+                let mut text = self.export();
+                ui.add(egui::TextEdit::multiline(&mut text).code_editor());
+            });
+        self.open = open;
+    }
+
+    fn edit_grid(&mut self, ui: &mut Ui) {
+        let (w, h) = (self.width(), self.rows());
+        let size = Vec2::new(w as f32, h as f32) * EDIT_CELL;
+        let (rect, response) = ui.allocate_exact_size(size, Sense::click_and_drag());
+
+        if let Some(pos) = response.interact_pointer_pos() {
+            let cell = (pos - rect.min) / EDIT_CELL;
+            let (cx, cy) = (cell.x.floor(), cell.y.floor());
+            if cx >= 0.0 && cy >= 0.0 && (cx as usize) < w && (cy as usize) < h {
+                let px = &mut self.pixels[cy as usize][cx as usize];
+                *px = *self.paint_value.get_or_insert(!*px);
+            }
+        }
+        if !response.is_pointer_button_down_on() {
+            self.paint_value = None;
+        }
+
+        let painter = ui.painter_at(rect);
+        painter.rect_filled(rect, 0.0, Color32::BLACK);
+        for (y, row) in self.pixels[..h].iter().enumerate() {
+            for (x, &set) in row[..w].iter().enumerate() {
+                let min = rect.min + Vec2::new(x as f32, y as f32) * EDIT_CELL;
+                let cell = Rect::from_min_size(min, Vec2::splat(EDIT_CELL));
+                if set {
+                    painter.rect_filled(cell, 0.0, Color32::WHITE);
+                }
+                painter.rect_stroke(cell, 0.0, Stroke::new(1.0, Color32::DARK_GRAY));
+            }
+        }
+    }
+
+    /// Draw the current display with the sprite XORed over it at the preview coordinates.
+    fn preview(&self, ui: &mut Ui, display: &[u8], display_w: usize) {
+        let display_h = display.len() / display_w;
+        let cell_size = PREVIEW_W / display_w as f32;
+        let size = Vec2::new(display_w as f32, display_h as f32) * cell_size;
+        let (rect, _) = ui.allocate_exact_size(size, Sense::hover());
+        let painter = ui.painter_at(rect);
+        painter.rect_filled(rect, 0.0, Color32::BLACK);
+
+        for y in 0..display_h {
+            for x in 0..display_w {
+                let mut set = display.get(y * display_w + x).is_some_and(|v| *v != 0);
+                let (sx, sy) = (x as isize - self.x as isize, y as isize - self.y as isize);
+                if sx >= 0 && sy >= 0 && (sx as usize) < self.width() && (sy as usize) < self.rows()
+                {
+                    set ^= self.pixels[sy as usize][sx as usize];
+                }
+                if set {
+                    let min = rect.min + Vec2::new(x as f32, y as f32) * cell_size;
+                    let cell = Rect::from_min_size(min, Vec2::splat(cell_size));
+                    painter.rect_filled(cell, 0.0, Color32::WHITE);
+                }
+            }
+        }
+        painter.rect_stroke(rect, 0.0, Stroke::new(1.0, Color32::DARK_GRAY));
+    }
+}
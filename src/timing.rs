@@ -0,0 +1,65 @@
+//! How long the COSMAC VIP interpreter took to run each instruction, for a timing closer to the
+//! original than a flat number of instructions per second.
+//!
+//! Costs are counted in 1802 machine cycles of 8 clock cycles, and include the interpreter's
+//! fetch and decode. They are approximations: on the VIP, the display interrupt also steals time
+//! from whatever instruction is running when it fires.
+
+use std::time::Duration;
+
+use crate::cpu::Cpu;
+use crate::instruction::Instruction;
+
+/// Frequency of the COSMAC VIP's 1802 clock, in Hz.
+const CLOCK_HZ: u64 = 1_760_640;
+
+/// Duration of one 1802 machine cycle.
+pub const MACHINE_CYCLE: Duration = Duration::from_nanos(8 * 1_000_000_000 / CLOCK_HZ);
+
+/// Fetching and decoding an instruction, whatever it is.
+const FETCH: u32 = 40;
+
+/// Machine cycles the VIP interpreter takes to run `instruction`, fetched by `cpu`. Instructions
+/// the VIP doesn't have are charged like a register load.
+pub fn cycles(instruction: Instruction, cpu: &Cpu) -> u32 {
+    use Instruction::*;
+
+    let execute = match instruction {
+        // The interpreter clears the 256 bytes of display memory one at a time.
+        Clear => 3078,
+        Return => 10,
+        Jump(_) => 12,
+        Call(_) => 26,
+        SkipEq(..) | SkipNe(..) => 10,
+        SkipEqReg(..) | SkipNeReg(..) | SkipKey(_) | SkipNoKey(_) => 14,
+        Load(..) => 6,
+        Add(..) | GetDelay(_) | SetDelay(_) | SetSound(_) => 10,
+        Move(..) | Or(..) | And(..) | Xor(..) | AddReg(..) | Sub(..) | ShiftRight(..)
+        | SubReverse(..) | ShiftLeft(..) => 44,
+        LoadI(_) => 12,
+        JumpOffset(_) => 22,
+        Random(..) => 36,
+        Draw(x, _, n) => draw_cycles(cpu.v(x), n),
+        WaitKey(_) => 10,
+        AddI(_) | Font(_) => 16,
+        // Each digit is found by repeated subtraction.
+        Bcd(x) => {
+            let v = cpu.v(x) as u32;
+            84 + 16 * (v / 100 + v / 10 % 10 + v % 10)
+        }
+        Store(x) | Restore(x) => 14 + 14 * (x as u32 + 1),
+        // What machine code routines cost is unknown.
+        Sys(_) => 0,
+        _ => 6,
+    };
+    FETCH + execute
+}
+
+/// Machine cycles of `DXYN` drawing `n` rows at `vx`. Each row is shifted into place one bit per
+/// pixel of offset from a byte boundary, then spans a second byte of display memory.
+fn draw_cycles(vx: u8, n: u8) -> u32 {
+    let rows = if n == 0 { 16 } else { n as u32 };
+    let offset = (vx % 8) as u32;
+    let row = if offset == 0 { 46 } else { 68 + 8 * offset };
+    26 + rows * row
+}
@@ -0,0 +1,194 @@
+//! A log of every executed instruction, with the registers it changed, to compare a run with
+//! another emulator's when a program misbehaves.
+
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::{bail, Context, Error, Result};
+
+use crate::cpu::Cpu;
+use crate::error::Chip8Error;
+use crate::interconnect::Interconnect;
+
+/// How each executed instruction is written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceFormat {
+    /// `0200 6A02 va := 0x02            va 00->02`, in hex.
+    Compact,
+    /// `{"pc":512,"opcode":27138,"instruction":"va := 0x02","changes":{"va":[0,2]}}`.
+    Json,
+}
+
+impl FromStr for TraceFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "compact" => TraceFormat::Compact,
+            "json" => TraceFormat::Json,
+            _ => bail!("Unknown trace format {:?} (expected compact or json)", s),
+        })
+    }
+}
+
+/// The registers an instruction can change.
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct Registers {
+    v: [u8; 16],
+    i: u16,
+    dt: u8,
+    st: u8,
+}
+
+impl Registers {
+    fn of(cpu: &Cpu, interconnect: &Interconnect) -> Self {
+        Self {
+            v: std::array::from_fn(|x| cpu.v(x as u8)),
+            i: cpu.i(),
+            dt: interconnect.delay_timer,
+            st: interconnect.sound_timer,
+        }
+    }
+}
+
+/// A register changed by an instruction.
+struct Change {
+    name: String,
+    old: u16,
+    new: u16,
+    /// Hex digits of the register, for the compact format.
+    digits: usize,
+}
+
+/// Writes one line per executed instruction.
+pub struct Tracer {
+    out: Box<dyn Write + Send>,
+    format: TraceFormat,
+    /// Address, opcode and registers of the instruction about to run, set by `before`.
+    pending: Option<(u16, u16, Registers)>,
+    /// The first error writing the trace, after which nothing more is written.
+    error: Option<io::Error>,
+}
+
+impl Tracer {
+    pub fn new(out: impl Write + Send + 'static, format: TraceFormat) -> Self {
+        Self {
+            out: Box::new(out),
+            format,
+            pending: None,
+            error: None,
+        }
+    }
+
+    /// A tracer writing to the file at `path`.
+    pub fn create(path: &Path, format: TraceFormat) -> Result<Self> {
+        let file =
+            File::create(path).with_context(|| format!("Failed to create {}", path.display()))?;
+        Ok(Self::new(BufWriter::new(file), format))
+    }
+
+    /// Note the state of `cpu` before it runs `opcode`.
+    pub fn before(&mut self, cpu: &Cpu, interconnect: &Interconnect, opcode: u16) {
+        self.pending = Some((cpu.pc(), opcode, Registers::of(cpu, interconnect)));
+    }
+
+    /// Write the line of the instruction noted by `before`, now that `cpu` has run it.
+    pub fn after(&mut self, cpu: &Cpu, interconnect: &Interconnect) {
+        self.write(cpu, interconnect, None);
+    }
+
+    /// Write the line of the instruction noted by `before`, which failed with `fault`.
+    pub fn fault(&mut self, cpu: &Cpu, interconnect: &Interconnect, fault: &Chip8Error) {
+        self.write(cpu, interconnect, Some(fault));
+    }
+
+    fn write(&mut self, cpu: &Cpu, interconnect: &Interconnect, fault: Option<&Chip8Error>) {
+        let (pc, opcode, before) = match self.pending.take() {
+            Some(pending) if self.error.is_none() => pending,
+            _ => return,
+        };
+        let after = Registers::of(cpu, interconnect);
+        let instruction = match cpu.decode(opcode) {
+            Ok(instruction) => instruction.to_string(),
+            Err(_) => "?".to_string(),
+        };
+        let changes: Vec<Change> = (0..16)
+            .map(|x| Change {
+                name: format!("v{:x}", x),
+                old: before.v[x] as u16,
+                new: after.v[x] as u16,
+                digits: 2,
+            })
+            .chain([
+                Change {
+                    name: "i".to_string(),
+                    old: before.i,
+                    new: after.i,
+                    digits: 4,
+                },
+                Change {
+                    name: "dt".to_string(),
+                    old: before.dt as u16,
+                    new: after.dt as u16,
+                    digits: 2,
+                },
+                Change {
+                    name: "st".to_string(),
+                    old: before.st as u16,
+                    new: after.st as u16,
+                    digits: 2,
+                },
+            ])
+            .filter(|change| change.old != change.new)
+            .collect();
+
+        let mut line = String::new();
+        match self.format {
+            TraceFormat::Compact => {
+                write!(line, "{:04X} {:04X} {:<24}", pc, opcode, instruction).unwrap();
+                for c in changes {
+                    let w = c.digits;
+                    write!(line, " {} {:0w$X}->{:0w$X}", c.name, c.old, c.new, w = w).unwrap();
+                }
+                if let Some(fault) = fault {
+                    write!(line, " fault: {}", fault).unwrap();
+                }
+                let len = line.trim_end().len();
+                line.truncate(len);
+            }
+            TraceFormat::Json => {
+                let changes: Vec<String> = changes
+                    .iter()
+                    .map(|c| format!("\"{}\":[{},{}]", c.name, c.old, c.new))
+                    .collect();
+                write!(
+                    line,
+                    "{{\"pc\":{},\"opcode\":{},\"instruction\":\"{}\",\"changes\":{{{}}}",
+                    pc,
+                    opcode,
+                    instruction,
+                    changes.join(",")
+                )
+                .unwrap();
+                if let Some(fault) = fault {
+                    write!(line, ",\"fault\":\"{}\"", fault).unwrap();
+                }
+                line.push('}');
+            }
+        }
+        if let Err(e) = writeln!(self.out, "{}", line) {
+            self.error = Some(e);
+        }
+    }
+
+    /// Write out what is left of the trace, failing with the first error writing it.
+    pub fn finish(&mut self) -> io::Result<()> {
+        match self.error.take() {
+            Some(e) => Err(e),
+            None => self.out.flush(),
+        }
+    }
+}
@@ -0,0 +1,316 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Context, Result};
+
+use crate::Chip8;
+
+/// Named addresses, e.g. ROM labels, that can be referred to in watch expressions.
+#[derive(Debug, Default)]
+pub struct Symbols(HashMap<String, u16>);
+
+impl Symbols {
+    /// Load a symbol file containing one `name address` (or `name = address`) pair per line.
+    ///
+    /// Empty lines and lines starting with `#` are ignored.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read symbol file {}", path.display()))?;
+        let mut symbols = HashMap::new();
+        for (i, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let words: Vec<&str> = line
+                .split(|c: char| c.is_whitespace() || c == '=')
+                .filter(|w| !w.is_empty())
+                .collect();
+            match words[..] {
+                [name, addr] => {
+                    let addr = parse_number(addr)
+                        .and_then(|addr| u16::try_from(addr).ok())
+                        .with_context(|| format!("{}:{}: invalid address", path.display(), i + 1))?;
+                    symbols.insert(name.to_string(), addr);
+                }
+                _ => bail!("{}:{}: invalid symbol definition", path.display(), i + 1),
+            }
+        }
+        Ok(Self(symbols))
+    }
+
+    pub fn insert(&mut self, name: String, addr: u16) {
+        self.0.insert(name, addr);
+    }
+
+    pub fn get(&self, name: &str) -> Option<u16> {
+        self.0.get(name).copied()
+    }
+
+    /// The name of a symbol at `addr`, if any. The alphabetically first one wins if there are
+    /// several.
+    pub fn name_of(&self, addr: u16) -> Option<&str> {
+        self.0
+            .iter()
+            .filter(|(_, a)| **a == addr)
+            .map(|(name, _)| name.as_str())
+            .min()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    And,
+    Or,
+    Xor,
+    Shl,
+    Shr,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Num(i64),
+    Reg(u8),
+    I,
+    Pc,
+    Delay,
+    Sound,
+    /// A symbol, resolved when the expression is parsed.
+    Sym(u16),
+    /// Read of the byte at the given address.
+    Mem(Box<Expr>),
+    Neg(Box<Expr>),
+    Bin(BinOp, Box<Expr>, Box<Expr>),
+}
+
+/// An expression involving registers, symbols and memory reads, evaluated against the machine
+/// after every step.
+///
+/// Supported syntax: decimal and `0x` hex numbers, `V0`-`VF`, `I`, `PC`, `DT`, `ST`, symbols,
+/// `[addr]` for the byte at `addr`, the binary operators `+ - * / % & | ^ << >>`, unary `-` and
+/// parentheses.
+pub struct Watch {
+    pub source: String,
+    expr: Expr,
+    last: Option<i64>,
+}
+
+impl Watch {
+    pub fn parse(source: &str, symbols: &Symbols) -> Result<Self> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser {
+            tokens: &tokens,
+            pos: 0,
+            symbols,
+        };
+        let expr = parser.expr(0)?;
+        if parser.pos != tokens.len() {
+            bail!("unexpected {:?} in watch expression {:?}", tokens[parser.pos], source);
+        }
+        Ok(Self {
+            source: source.to_string(),
+            expr,
+            last: None,
+        })
+    }
+
+    /// Re-evaluate the expression against `chip8`. Return `true` if its value changed.
+    pub fn update(&mut self, chip8: &Chip8) -> bool {
+        let value = eval(&self.expr, chip8);
+        let changed = value != self.last;
+        self.last = value;
+        changed
+    }
+
+    /// The value computed by the last `update`, if it could be evaluated.
+    pub fn value(&self) -> Option<i64> {
+        self.last
+    }
+}
+
+/// Shows the value computed by the last `update`, or `<error>` if it couldn't be evaluated (e.g.
+/// division by zero or out-of-range memory read).
+impl fmt::Display for Watch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.last {
+            Some(v) => write!(f, "{} = {} ({:#x})", self.source, v, v),
+            None => write!(f, "{} = <error>", self.source),
+        }
+    }
+}
+
+fn eval(expr: &Expr, chip8: &Chip8) -> Option<i64> {
+    let cpu = &chip8.cpu;
+    let io = &chip8.interconnect;
+    Some(match expr {
+        Expr::Num(n) => *n,
+        Expr::Reg(x) => cpu.v(*x) as i64,
+        Expr::I => cpu.i() as i64,
+        Expr::Pc => cpu.pc() as i64,
+        Expr::Delay => io.delay_timer as i64,
+        Expr::Sound => io.sound_timer as i64,
+        Expr::Sym(addr) => *addr as i64,
+        Expr::Mem(addr) => {
+            let addr = usize::try_from(eval(addr, chip8)?).ok()?;
+            io.ram.get(addr)? as i64
+        }
+        Expr::Neg(e) => eval(e, chip8)?.wrapping_neg(),
+        Expr::Bin(op, l, r) => {
+            let (l, r) = (eval(l, chip8)?, eval(r, chip8)?);
+            match op {
+                BinOp::Add => l.wrapping_add(r),
+                BinOp::Sub => l.wrapping_sub(r),
+                BinOp::Mul => l.wrapping_mul(r),
+                BinOp::Div => l.checked_div(r)?,
+                BinOp::Rem => l.checked_rem(r)?,
+                BinOp::And => l & r,
+                BinOp::Or => l | r,
+                BinOp::Xor => l ^ r,
+                BinOp::Shl => l.checked_shl(r.try_into().ok()?)?,
+                BinOp::Shr => l.checked_shr(r.try_into().ok()?)?,
+            }
+        }
+    })
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Num(i64),
+    Ident(String),
+    Op(&'static str),
+}
+
+fn parse_number(s: &str) -> Option<i64> {
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => i64::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>> {
+    const OPS: [&str; 14] = [
+        "<<", ">>", "+", "-", "*", "/", "%", "&", "|", "^", "(", ")", "[", "]",
+    ];
+    let mut tokens = Vec::new();
+    let mut rest = source.trim_start();
+    while !rest.is_empty() {
+        if let Some(op) = OPS.iter().find(|op| rest.starts_with(*op)) {
+            tokens.push(Token::Op(op));
+            rest = &rest[op.len()..];
+        } else {
+            let end = rest
+                .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            if end == 0 {
+                bail!("unexpected character in watch expression {:?}", source);
+            }
+            let word = &rest[..end];
+            if word.starts_with(|c: char| c.is_ascii_digit()) {
+                let n = parse_number(word)
+                    .ok_or_else(|| anyhow!("invalid number {:?} in watch expression", word))?;
+                tokens.push(Token::Num(n));
+            } else {
+                tokens.push(Token::Ident(word.to_string()));
+            }
+            rest = &rest[end..];
+        }
+        rest = rest.trim_start();
+    }
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+    symbols: &'a Symbols,
+}
+
+impl<'a> Parser<'a> {
+    fn next(&mut self) -> Option<&'a Token> {
+        let token = self.tokens.get(self.pos);
+        self.pos += 1;
+        token
+    }
+
+    fn expect(&mut self, op: &str) -> Result<()> {
+        match self.next() {
+            Some(Token::Op(o)) if *o == op => Ok(()),
+            t => bail!("expected {:?} in watch expression, found {:?}", op, t),
+        }
+    }
+
+    /// Parse a binary expression whose operators all have a precedence of at least `min_prec`.
+    fn expr(&mut self, min_prec: u8) -> Result<Expr> {
+        let mut lhs = self.unary()?;
+        while let Some(Token::Op(op)) = self.tokens.get(self.pos) {
+            let (prec, binop) = match *op {
+                "|" => (0, BinOp::Or),
+                "^" => (1, BinOp::Xor),
+                "&" => (2, BinOp::And),
+                "<<" => (3, BinOp::Shl),
+                ">>" => (3, BinOp::Shr),
+                "+" => (4, BinOp::Add),
+                "-" => (4, BinOp::Sub),
+                "*" => (5, BinOp::Mul),
+                "/" => (5, BinOp::Div),
+                "%" => (5, BinOp::Rem),
+                _ => break,
+            };
+            if prec < min_prec {
+                break;
+            }
+            self.pos += 1;
+            let rhs = self.expr(prec + 1)?;
+            lhs = Expr::Bin(binop, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Expr> {
+        match self.next() {
+            Some(Token::Num(n)) => Ok(Expr::Num(*n)),
+            Some(Token::Op("-")) => Ok(Expr::Neg(Box::new(self.unary()?))),
+            Some(Token::Op("(")) => {
+                let e = self.expr(0)?;
+                self.expect(")")?;
+                Ok(e)
+            }
+            Some(Token::Op("[")) => {
+                let e = self.expr(0)?;
+                self.expect("]")?;
+                Ok(Expr::Mem(Box::new(e)))
+            }
+            Some(Token::Ident(name)) => self.ident(name),
+            t => bail!("unexpected {:?} in watch expression", t),
+        }
+    }
+
+    fn ident(&self, name: &str) -> Result<Expr> {
+        let upper = name.to_ascii_uppercase();
+        match upper.as_str() {
+            "I" => return Ok(Expr::I),
+            "PC" => return Ok(Expr::Pc),
+            "DT" => return Ok(Expr::Delay),
+            "ST" => return Ok(Expr::Sound),
+            _ => {}
+        }
+        if let Some(reg) = upper.strip_prefix('V') {
+            if reg.len() == 1 {
+                if let Ok(x) = u8::from_str_radix(reg, 16) {
+                    return Ok(Expr::Reg(x));
+                }
+            }
+        }
+        match self.symbols.get(name) {
+            Some(addr) => Ok(Expr::Sym(addr)),
+            None => bail!("unknown symbol {:?} in watch expression", name),
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use std::time::Instant;
+
+use egui::CtxRef;
+use log::{info, warn};
+
+/// Fraction of the target update rate below which emulation is considered to be lagging.
+const TOLERANCE: f64 = 0.9;
+/// Number of consecutive slow seconds before reporting, so that hiccups (e.g. while the window is
+/// being moved) are ignored.
+const GRACE_SECONDS: u32 = 3;
+
+/// Notices when the update loop chronically falls behind its target rate.
+pub struct Watchdog {
+    target: f64,
+    window_start: Instant,
+    updates: u32,
+    slow_seconds: u32,
+    /// Whether the current slowdown has already been reported.
+    reported: bool,
+    /// Diagnostic shown to the user, until dismissed.
+    message: Option<String>,
+}
+
+impl Watchdog {
+    /// Watch a loop meant to run `target` updates per second.
+    pub fn new(target: u32) -> Self {
+        Self {
+            target: target as f64,
+            window_start: Instant::now(),
+            updates: 0,
+            slow_seconds: 0,
+            reported: false,
+            message: None,
+        }
+    }
+
+    /// Count an update. About once per second, return the measured rate if emulation has been
+    /// lagging for a while and this hasn't been reported yet.
+    pub fn update(&mut self) -> Option<f64> {
+        self.updates += 1;
+        let elapsed = self.window_start.elapsed().as_secs_f64();
+        if elapsed < 1.0 {
+            return None;
+        }
+        let rate = self.updates as f64 / elapsed;
+        self.window_start = Instant::now();
+        self.updates = 0;
+
+        if rate >= self.target * TOLERANCE {
+            if self.reported {
+                info!("emulation is back to full speed");
+            }
+            self.slow_seconds = 0;
+            self.reported = false;
+            return None;
+        }
+        self.slow_seconds += 1;
+        if self.slow_seconds < GRACE_SECONDS || self.reported {
+            return None;
+        }
+        self.reported = true;
+        Some(rate)
+    }
+
+    /// Report that emulation runs at `rate` updates per second, suggesting `remedies`.
+    pub fn report(&mut self, rate: f64, remedies: &[&str]) {
+        let mut message = format!(
+            "Emulation is running at {:.0}% of its normal speed ({:.0} of {:.0} updates per second).",
+            100.0 * rate / self.target,
+            rate,
+            self.target
+        );
+        if !remedies.is_empty() {
+            message.push_str("\n\nTry to:");
+            for remedy in remedies {
+                message.push_str("\n• ");
+                message.push_str(remedy);
+            }
+        }
+        warn!("{}", message);
+        self.message = Some(message);
+    }
+
+    /// Whether a diagnostic is being shown.
+    pub fn is_open(&self) -> bool {
+        self.message.is_some()
+    }
+
+    pub fn ui(&mut self, ctx: &CtxRef) {
+        let mut dismissed = false;
+        if let Some(message) = &self.message {
+            egui::Window::new("Running slow")
+                .resizable(false)
+                .collapsible(false)
+                .show(ctx, |ui| {
+                    ui.label(message);
+                    if ui.button("Dismiss").clicked() {
+                        dismissed = true;
+                    }
+                });
+        }
+        if dismissed {
+            self.message = None;
+        }
+    }
+}
@@ -0,0 +1,111 @@
+//! Properties of the arithmetic instructions over all their inputs: the result in VX, the carry or
+//! borrow in VF, and what happens when VF is itself an operand.
+
+use chip8rs::config::Quirks;
+use chip8rs::detect::Profile;
+use chip8rs::program::*;
+use chip8rs::Chip8;
+use proptest::prelude::*;
+
+/// One of the `8XYN` instructions, added to a program.
+type Op = fn(&mut Program, Reg, Reg) -> &mut Program;
+
+/// Run the first `steps` instructions of `program` on a CHIP-8, with `shift_vx_only` as given.
+fn run(program: &Program, steps: usize, shift_vx_only: bool) -> Chip8 {
+    let rom = program
+        .assemble()
+        .expect("the programs only use bound labels");
+    let mut chip8 = Chip8::from_bytes(&rom);
+    chip8.set_quirks(Quirks {
+        shift_vx_only,
+        ..Quirks::for_profile(Profile::Chip8)
+    });
+    for _ in 0..steps {
+        chip8
+            .step()
+            .expect("the programs only use valid instructions");
+    }
+    chip8
+}
+
+/// VX and VF after running `op` on X and Y, which hold `a` and `b` beforehand.
+fn alu(op: Op, x: u8, y: u8, a: u8, b: u8, shift_vx_only: bool) -> (u8, u8) {
+    let mut p = Program::new();
+    p.ld(Reg(y), b).ld(Reg(x), a);
+    op(&mut p, Reg(x), Reg(y));
+    let chip8 = run(&p, 3, shift_vx_only);
+    (chip8.cpu.v(x), chip8.cpu.v(0xF))
+}
+
+/// Two different registers below VF.
+fn operands() -> impl Strategy<Value = (u8, u8)> {
+    (0..0xFu8, 0..0xFu8).prop_filter("X and Y must differ", |(x, y)| x != y)
+}
+
+proptest! {
+    #[test]
+    fn add_sets_carry((x, y) in operands(), a: u8, b: u8) {
+        let (sum, carry) = a.overflowing_add(b);
+        prop_assert_eq!(alu(|p, x, y| p.add(x, y), x, y, a, b, false), (sum, carry as u8));
+    }
+
+    #[test]
+    fn sub_sets_not_borrow((x, y) in operands(), a: u8, b: u8) {
+        let diff = a.wrapping_sub(b);
+        prop_assert_eq!(alu(Program::sub, x, y, a, b, false), (diff, (a >= b) as u8));
+    }
+
+    #[test]
+    fn subn_sets_not_borrow((x, y) in operands(), a: u8, b: u8) {
+        let diff = b.wrapping_sub(a);
+        prop_assert_eq!(alu(Program::subn, x, y, a, b, false), (diff, (b >= a) as u8));
+    }
+
+    #[test]
+    fn shr_sets_lsb((x, y) in operands(), a: u8, b: u8, shift_vx_only: bool) {
+        let v = if shift_vx_only { a } else { b };
+        prop_assert_eq!(alu(Program::shr, x, y, a, b, shift_vx_only), (v >> 1, v & 1));
+    }
+
+    #[test]
+    fn shl_sets_msb((x, y) in operands(), a: u8, b: u8, shift_vx_only: bool) {
+        let v = if shift_vx_only { a } else { b };
+        prop_assert_eq!(alu(Program::shl, x, y, a, b, shift_vx_only), (v << 1, v >> 7));
+    }
+
+    /// With VF as VX, the flag is written last and overwrites the result.
+    #[test]
+    fn flag_wins_over_result_in_vf(y in 0..0xFu8, a: u8, b: u8) {
+        let ops: [(Op, u8); 5] = [
+            (|p, x, y| p.add(x, y), a.overflowing_add(b).1 as u8),
+            (Program::sub, (a >= b) as u8),
+            (Program::subn, (b >= a) as u8),
+            (Program::shr, b & 1),
+            (Program::shl, b >> 7),
+        ];
+        for (op, flag) in ops {
+            prop_assert_eq!(alu(op, 0xF, y, a, b, false).1, flag);
+        }
+    }
+
+    /// With VF as VY, its value is read before the flag is written.
+    #[test]
+    fn vf_as_operand_is_read_first(x in 0..0xFu8, a: u8, b: u8) {
+        let (sum, carry) = a.overflowing_add(b);
+        prop_assert_eq!(alu(|p, x, y| p.add(x, y), x, 0xF, a, b, false), (sum, carry as u8));
+        prop_assert_eq!(alu(Program::shl, x, 0xF, a, b, false), (b << 1, b >> 7));
+    }
+
+    #[test]
+    fn bcd_stores_decimal_digits(x in 0..=0xFu8, v: u8) {
+        let mut p = Program::new();
+        let digits = p.label();
+        p.ld(Reg(x), v).ld_i(digits).bcd(Reg(x)).exit();
+        p.bind(digits).db(&[0xFF; 3]);
+        let chip8 = run(&p, 3, false);
+        let addr = chip8.cpu.i();
+        let ram = &chip8.interconnect.ram;
+        let stored = [ram[addr], ram[addr + 1], ram[addr + 2]];
+        prop_assert_eq!(stored, [v / 100, v / 10 % 10, v % 10]);
+    }
+}
@@ -0,0 +1,51 @@
+//! The block cache runs programs exactly like the plain interpreter, including programs that
+//! rewrite code it has already cached.
+
+use chip8rs::config;
+use chip8rs::program::*;
+use chip8rs::Chip8;
+
+const SPACE_INVADERS: &[u8] = include_bytes!("../roms/Space Invaders [David Winter].ch8");
+
+/// `rom` after `cycles` instructions, with and without the block cache.
+fn run_both(rom: &[u8], cycles: u64) -> (Chip8, Chip8) {
+    let run = |block_cache| {
+        let mut chip8 = Chip8::from_bytes(rom);
+        chip8.set_seed(1);
+        chip8.set_block_cache(block_cache);
+        chip8
+            .run(cycles, config::DEFAULT_IPS)
+            .unwrap_or_else(|e| panic!("stopped at {:#05x}: {}", chip8.cpu.pc(), e));
+        chip8
+    };
+    (run(true), run(false))
+}
+
+#[test]
+fn space_invaders_same_state() {
+    let (cached, plain) = run_both(SPACE_INVADERS, 40_000);
+    assert_eq!(cached.cycles(), plain.cycles());
+    assert_eq!(cached.save_state(), plain.save_state());
+}
+
+#[test]
+fn self_modifying_code() {
+    // The loop runs twice: the first time through, it rewrites its second instruction from
+    // `va += 0x01` to `va += 0x10`. It is entered with a jump so that it starts a block.
+    let mut p = Program::new();
+    let start = p.label();
+    let patched = p.label();
+    let end = p.label();
+    p.ld(VA, 0).ld(VB, 0).jp(start);
+    p.bind(start).add(VB, 1);
+    p.bind(patched).add(VA, 0x01);
+    p.se(VB, 1).jp(end);
+    p.ld(V0, 0x7A).ld(V1, 0x10).ld_i(patched).store(V1);
+    p.jp(start);
+    p.bind(end).jp(end);
+    let rom = p.assemble().expect("the labels are bound");
+
+    let (cached, plain) = run_both(&rom, 100);
+    assert_eq!(cached.cpu.v(0xA), 0x11);
+    assert_eq!(cached.save_state(), plain.save_state());
+}
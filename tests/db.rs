@@ -0,0 +1,62 @@
+//! The ROM database: SHA-1 digests of ROMs, and the settings found with them.
+
+use chip8rs::db::{self, Database};
+use chip8rs::detect::Profile;
+
+const SPACE_INVADERS: &[u8] = include_bytes!("../roms/Space Invaders [David Winter].ch8");
+
+#[test]
+fn sha1_known_vectors() {
+    assert_eq!(
+        db::sha1_hex(b""),
+        "da39a3ee5e6b4b0d3255bfef95601890afd80709"
+    );
+    assert_eq!(
+        db::sha1_hex(b"abc"),
+        "a9993e364706816aba3e25717850c26c9cd0d89d"
+    );
+    // Long enough for the padding to spill into a second block.
+    assert_eq!(
+        db::sha1_hex(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"),
+        "84983e441c3bd26ebaae4aa1f95129e5e54670f1"
+    );
+}
+
+#[test]
+fn finds_bundled_rom() {
+    let db = Database::bundled();
+    let entry = db
+        .lookup(SPACE_INVADERS)
+        .expect("Space Invaders is in the database");
+    assert_eq!(
+        entry.display_name().as_deref(),
+        Some("Space Invaders by David Winter")
+    );
+    assert_eq!(entry.quirks(), Some(Profile::Schip));
+}
+
+#[test]
+fn looks_up_by_exact_rom() {
+    let db = Database::parse(
+        r##"
+        ["a9993e364706816aba3e25717850c26c9cd0d89d"]
+        title = "abc"
+        ips = 1000
+        foreground = "#ffb000"
+        "##,
+    )
+    .unwrap();
+    let entry = db.lookup(b"abc").expect("the hash matches");
+    assert_eq!(entry.ips, Some(1000));
+    assert_eq!(entry.foreground(), Some([0xFF, 0xB0, 0x00, 0xFF]));
+    assert!(db.lookup(b"abd").is_none());
+    assert!(db.lookup(b"abc\0").is_none());
+}
+
+#[test]
+fn rejects_invalid_entries() {
+    assert!(Database::parse("[\"not a hash\"]\n").is_err());
+    let hash = "a9993e364706816aba3e25717850c26c9cd0d89d";
+    assert!(Database::parse(&format!("[\"{}\"]\nquirks = \"nope\"\n", hash)).is_err());
+    assert!(Database::parse(&format!("[\"{}\"]\nips = 0\n", hash)).is_err());
+}
@@ -0,0 +1,31 @@
+//! The disassembler's output assembles back to the ROM it was made from.
+
+use chip8rs::{disasm, octo};
+use proptest::prelude::*;
+
+const SPACE_INVADERS: &[u8] = include_bytes!("../roms/Space Invaders [David Winter].ch8");
+
+/// Disassemble `rom` and assemble the result again.
+fn round_trip(rom: &[u8]) -> Vec<u8> {
+    let source = disasm::disassemble(rom);
+    octo::assemble(&source).unwrap_or_else(|e| panic!("{:#} in\n{}", e, source))
+}
+
+#[test]
+fn space_invaders_round_trip() {
+    assert_eq!(round_trip(SPACE_INVADERS), SPACE_INVADERS);
+}
+
+#[test]
+fn trailing_skip_round_trip() {
+    // v0 := 1, if v0 != 1 then, with nothing left to skip
+    let rom = [0x60, 0x01, 0x30, 0x01];
+    assert_eq!(round_trip(&rom), rom);
+}
+
+proptest! {
+    #[test]
+    fn random_rom_round_trip(rom in prop::collection::vec(any::<u8>(), 1..64)) {
+        prop_assert_eq!(round_trip(&rom), rom);
+    }
+}
@@ -0,0 +1,96 @@
+//! `DXYN` sets VF to 1 when the sprite turns a lit pixel off, and to 0 otherwise, whatever VF
+//! held before. Mega-Chip sprites only collide with pixels of the color set by `09NN`.
+
+use chip8rs::config::Quirks;
+use chip8rs::detect::Profile;
+use chip8rs::gfx::Gfx;
+use chip8rs::program::*;
+use chip8rs::Chip8;
+
+/// A sprite lighting 2 pixels, 7 apart.
+const SPRITE: u8 = 0b1000_0001;
+
+/// Run `program` until it reaches its end, with V0 and V1 at (10, 5) and I on `SPRITE`.
+fn run(program: impl FnOnce(&mut Program)) -> Chip8 {
+    let mut p = Program::new();
+    let sprite = p.label();
+    let end = p.label();
+    p.ld(V0, 10).ld(V1, 5).ld_i(sprite);
+    program(&mut p);
+    p.jp(end).bind(sprite).db(&[SPRITE]).bind(end);
+    let rom = p.assemble().expect("the programs only use bound labels");
+
+    let mut chip8 = Chip8::from_bytes(&rom);
+    chip8.set_quirks(Quirks {
+        display_wait: false,
+        ..Quirks::for_profile(Profile::Chip8)
+    });
+    let end = 0x200 + rom.len() as u16;
+    while chip8.cpu.pc() != end {
+        chip8
+            .step()
+            .expect("the programs only use valid instructions");
+    }
+    chip8
+}
+
+fn pixel(chip8: &Chip8, x: usize, y: usize) -> bool {
+    chip8.display()[y * chip8.display_width() + x] != 0
+}
+
+#[test]
+fn collision_sets_vf() {
+    let chip8 = run(|p| {
+        p.drw(V0, V1, 1).ld(VF, 0).drw(V0, V1, 1);
+    });
+    assert_eq!(chip8.cpu.v(0xF), 1);
+    assert!(!pixel(&chip8, 10, 5));
+    assert!(!pixel(&chip8, 17, 5));
+}
+
+#[test]
+fn no_collision_clears_vf() {
+    let chip8 = run(|p| {
+        p.ld(VF, 1).drw(V0, V1, 1);
+    });
+    assert_eq!(chip8.cpu.v(0xF), 0);
+    assert!(pixel(&chip8, 10, 5));
+    assert!(pixel(&chip8, 17, 5));
+}
+
+#[test]
+fn drawing_next_to_lit_pixels_clears_vf() {
+    let chip8 = run(|p| {
+        p.drw(V0, V1, 1).ld(V0, 11).ld(VF, 1).drw(V0, V1, 1);
+    });
+    assert_eq!(chip8.cpu.v(0xF), 0);
+    assert!(pixel(&chip8, 10, 5));
+    assert!(pixel(&chip8, 11, 5));
+}
+
+#[test]
+fn drawing_over_cleared_pixels_clears_vf() {
+    // The second draw erases the sprite, so the third one doesn't collide with anything.
+    let chip8 = run(|p| {
+        p.drw(V0, V1, 1).drw(V0, V1, 1).ld(V2, VF);
+        p.ld(VF, 1).drw(V0, V1, 1);
+    });
+    assert_eq!(chip8.cpu.v(2), 1);
+    assert_eq!(chip8.cpu.v(0xF), 0);
+    assert!(pixel(&chip8, 10, 5));
+    assert!(pixel(&chip8, 17, 5));
+}
+
+#[test]
+fn megachip_collides_with_collision_color_only() {
+    let mut gfx = Gfx::new();
+    gfx.set_megachip(true);
+    gfx.set_sprite_width(2);
+    gfx.set_sprite_height(1);
+    // Without a collision color, drawing over blank or lit pixels never collides.
+    assert!(!gfx.draw_indexed(0, 0, &[1, 1]));
+    assert!(!gfx.draw_indexed(0, 0, &[2, 2]));
+    gfx.set_collision_color(2);
+    assert!(!gfx.draw_indexed(4, 0, &[1, 1]));
+    assert!(gfx.draw_indexed(0, 0, &[1, 1]));
+}
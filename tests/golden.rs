@@ -0,0 +1,185 @@
+//! Golden frames: programs run without a window for a number of instructions, with their final
+//! display compared to an image kept in `tests/golden`.
+//!
+//! Run with `BLESS=1` to record the current displays as the new golden images, after checking
+//! that they are right.
+
+use std::path::Path;
+
+use chip8rs::config::{self, Quirks};
+use chip8rs::detect::Profile;
+use chip8rs::program::*;
+use chip8rs::{screenshot, Chip8};
+
+const GOLDEN_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/golden");
+
+const SPACE_INVADERS: &[u8] = include_bytes!("../roms/Space Invaders [David Winter].ch8");
+
+/// The IBM logo program, which only clears the screen, draws and jumps.
+const IBM_LOGO: [u8; 132] = [
+    0x00, 0xE0, 0xA2, 0x2A, 0x60, 0x0C, 0x61, 0x08, 0xD0, 0x1F, 0x70, 0x09, 0xA2, 0x39, 0xD0, 0x1F,
+    0xA2, 0x48, 0x70, 0x08, 0xD0, 0x1F, 0x70, 0x04, 0xA2, 0x57, 0xD0, 0x1F, 0x70, 0x08, 0xA2, 0x66,
+    0xD0, 0x1F, 0x70, 0x08, 0xA2, 0x75, 0xD0, 0x1F, 0x12, 0x28, 0xFF, 0x00, 0xFF, 0x00, 0x3C, 0x00,
+    0x3C, 0x00, 0x3C, 0x00, 0x3C, 0x00, 0xFF, 0x00, 0xFF, 0xFF, 0x00, 0xFF, 0x00, 0x38, 0x00, 0x3F,
+    0x00, 0x3F, 0x00, 0x38, 0x00, 0xFF, 0x00, 0xFF, 0x80, 0x00, 0xE0, 0x00, 0xE0, 0x00, 0x80, 0x00,
+    0x80, 0x00, 0xE0, 0x00, 0xE0, 0x00, 0x80, 0xF8, 0x00, 0xFC, 0x00, 0x3E, 0x00, 0x3F, 0x00, 0x3B,
+    0x00, 0x39, 0x00, 0xF8, 0x00, 0xF8, 0x03, 0x00, 0x07, 0x00, 0x0F, 0x00, 0xBF, 0x00, 0xFB, 0x00,
+    0xF3, 0x00, 0xE3, 0x00, 0x43, 0xE0, 0x00, 0xE0, 0x00, 0x80, 0x00, 0x80, 0x00, 0x80, 0x00, 0x80,
+    0x00, 0xE0, 0x00, 0xE0,
+];
+
+/// Run `chip8` for `cycles` instructions at the default speed, then compare its display with the
+/// golden image `name`, or record it as the golden image when blessing.
+fn assert_golden(name: &str, mut chip8: Chip8, cycles: u64) {
+    chip8
+        .run(cycles, config::DEFAULT_IPS)
+        .unwrap_or_else(|e| panic!("{} stopped at {:#05x}: {}", name, chip8.cpu.pc(), e));
+    let actual = screenshot::encode_pbm(chip8.display(), chip8.display_width());
+    let path = Path::new(GOLDEN_DIR).join(format!("{}.pbm", name));
+    if std::env::var_os("BLESS").is_some() {
+        std::fs::create_dir_all(GOLDEN_DIR).expect("the golden directory can be created");
+        std::fs::write(&path, &actual).expect("the golden image can be written");
+        return;
+    }
+
+    let golden = std::fs::read_to_string(&path).unwrap_or_else(|_| {
+        panic!(
+            "No golden image {}: run with BLESS=1 to record it",
+            path.display()
+        )
+    });
+    if actual != golden {
+        let out = Path::new(env!("CARGO_TARGET_TMPDIR")).join(format!("{}.pbm", name));
+        std::fs::write(&out, &actual).expect("the actual image can be written");
+        let differing = actual
+            .bytes()
+            .zip(golden.bytes())
+            .filter(|(a, b)| a != b)
+            .count();
+        panic!(
+            "The display of {} differs from {} ({} pixels, or a different size): it was saved to {}",
+            name,
+            path.display(),
+            differing,
+            out.display()
+        );
+    }
+}
+
+/// A machine running `program`, which ends by looping forever, with the quirks of `profile`.
+fn machine(program: &mut Program, profile: Profile) -> Chip8 {
+    let end = program.label();
+    program.bind(end).jp(end);
+    let rom = program
+        .assemble()
+        .expect("the golden programs only use bound labels");
+    let mut chip8 = if profile == Profile::XoChip {
+        Chip8::from_bytes_xochip(&rom)
+    } else {
+        Chip8::from_bytes(&rom)
+    };
+    chip8.set_quirks(Quirks::for_profile(profile));
+    chip8
+}
+
+/// Draw the digit sprites `digits` in rows of 8, `spacing` pixels apart, loading each with `font`
+/// and drawing it `n` rows high.
+fn draw_digits(
+    p: &mut Program,
+    digits: std::ops::Range<u8>,
+    spacing: u8,
+    font: fn(&mut Program, Reg) -> &mut Program,
+    n: u8,
+) {
+    for (i, digit) in digits.enumerate() {
+        let i = i as u8;
+        p.ld(V0, 2 + i % 8 * spacing)
+            .ld(V1, 2 + i / 8 * (n + 2))
+            .ld(V2, digit);
+        font(p, V2);
+        p.drw(V0, V1, n);
+    }
+}
+
+#[test]
+fn space_invaders_title() {
+    let mut chip8 = Chip8::from_bytes(SPACE_INVADERS);
+    chip8.set_seed(1);
+    assert_golden("space_invaders_title", chip8, 2_000);
+}
+
+#[test]
+fn space_invaders_attract() {
+    let mut chip8 = Chip8::from_bytes(SPACE_INVADERS);
+    chip8.set_seed(1);
+    assert_golden("space_invaders_attract", chip8, 40_000);
+}
+
+/// Check that Space Invaders draws the same attract mode with `setup` applied as without.
+fn assert_attract_unchanged(setup: impl FnOnce(&mut Chip8)) {
+    let mut plain = Chip8::from_bytes(SPACE_INVADERS);
+    let mut changed = Chip8::from_bytes(SPACE_INVADERS);
+    setup(&mut changed);
+    for chip8 in [&mut plain, &mut changed] {
+        chip8.set_seed(1);
+        chip8
+            .run(40_000, config::DEFAULT_IPS)
+            .unwrap_or_else(|e| panic!("stopped at {:#05x}: {}", chip8.cpu.pc(), e));
+    }
+    assert!(
+        changed.display() == plain.display(),
+        "The display differs from a plain run"
+    );
+}
+
+/// Skipping idle loops must not change what is drawn.
+#[test]
+fn space_invaders_attract_idle_skip() {
+    assert_attract_unchanged(|chip8| chip8.enable_idle_detection(true));
+}
+
+/// The block cache must not change what is drawn either.
+#[test]
+fn space_invaders_attract_block_cache() {
+    assert_attract_unchanged(|chip8| chip8.set_block_cache(true));
+}
+
+#[test]
+fn ibm_logo() {
+    assert_golden("ibm_logo", Chip8::from_bytes(&IBM_LOGO), 100);
+}
+
+#[test]
+fn font() {
+    let mut p = Program::new();
+    draw_digits(&mut p, 0..16, 8, Program::ld_font, 5);
+    assert_golden("font", machine(&mut p, Profile::Chip8), 200);
+}
+
+#[test]
+fn big_font_hires() {
+    let mut p = Program::new();
+    p.high();
+    draw_digits(&mut p, 0..10, 12, Program::ld_big_font, 10);
+    assert_golden("big_font_hires", machine(&mut p, Profile::Schip), 200);
+}
+
+#[test]
+fn scroll_hires() {
+    let mut p = Program::new();
+    p.high();
+    draw_digits(&mut p, 0..16, 8, Program::ld_font, 5);
+    p.scd(3).scr().scr().scl();
+    assert_golden("scroll_hires", machine(&mut p, Profile::Schip), 200);
+}
+
+#[test]
+fn wrapped_sprites() {
+    let mut p = Program::new();
+    p.ld(V0, 0xE)
+        .ld_font(V0)
+        .ld(V1, 62)
+        .ld(V2, 30)
+        .drw(V1, V2, 5);
+    assert_golden("wrapped_sprites", machine(&mut p, Profile::XoChip), 100);
+}
@@ -0,0 +1,96 @@
+//! IPS and BPS patches: created from two ROMs, they turn one into the other, and broken or
+//! malicious patches are rejected rather than crash.
+
+use chip8rs::patch::{self, crc32};
+
+const SOURCE: &[u8] = include_bytes!("../roms/Space Invaders [David Winter].ch8");
+
+/// `SOURCE` with a few bytes changed, a run of them, and `tail` appended or cut off.
+fn target(tail: isize) -> Vec<u8> {
+    let mut target = SOURCE.to_vec();
+    target[0] ^= 0xFF;
+    target[100..140].fill(0xAA);
+    target[SOURCE.len() / 2] = 0;
+    if tail >= 0 {
+        target.extend((0..tail).map(|i| i as u8));
+    } else {
+        target.truncate(SOURCE.len() - tail.unsigned_abs());
+    }
+    target
+}
+
+#[test]
+fn ips_round_trip() {
+    for tail in [0, 300, -300] {
+        let target = target(tail);
+        let patch = patch::create_ips(SOURCE, &target).unwrap();
+        assert_eq!(patch::apply(SOURCE, &patch).unwrap(), target);
+    }
+}
+
+#[test]
+fn bps_round_trip() {
+    for tail in [0, 300, -300] {
+        let target = target(tail);
+        let patch = patch::create_bps(SOURCE, &target);
+        assert_eq!(patch::apply(SOURCE, &patch).unwrap(), target);
+    }
+}
+
+#[test]
+fn bps_rejects_other_rom() {
+    let patch = patch::create_bps(SOURCE, &target(0));
+    assert!(patch::apply(&SOURCE[1..], &patch).is_err());
+}
+
+#[test]
+fn short_patches_are_rejected() {
+    for patch in [&b""[..], b"P", b"PATC", b"PATCH", b"BPS1"] {
+        assert!(patch::apply_ips(SOURCE, patch).is_err());
+        assert!(patch::apply_bps(SOURCE, patch).is_err());
+    }
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let x = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(0x80 | x);
+            break;
+        }
+        out.push(x);
+        value -= 1;
+    }
+}
+
+/// A BPS patch for `SOURCE` with valid checksums, `target_size` in its header and `actions`.
+fn bps(target_size: u64, actions: &[u8]) -> Vec<u8> {
+    let mut patch = b"BPS1".to_vec();
+    write_varint(&mut patch, SOURCE.len() as u64);
+    write_varint(&mut patch, target_size);
+    write_varint(&mut patch, 0);
+    patch.extend_from_slice(actions);
+    patch.extend_from_slice(&crc32(SOURCE).to_le_bytes());
+    patch.extend_from_slice(&0u32.to_le_bytes());
+    let checksum = crc32(&patch);
+    patch.extend_from_slice(&checksum.to_le_bytes());
+    patch
+}
+
+#[test]
+fn bps_rejects_huge_target_size() {
+    assert!(patch::apply(SOURCE, &bps(1 << 48, &[])).is_err());
+}
+
+#[test]
+fn bps_rejects_actions_past_target_size() {
+    // TargetRead of 1 byte, then a TargetCopy of 2^40 bytes from offset 0, which would keep
+    // copying the bytes it has just written.
+    let mut actions = Vec::new();
+    write_varint(&mut actions, 1);
+    actions.push(0x42);
+    write_varint(&mut actions, ((1 << 40) - 1) << 2 | 3);
+    write_varint(&mut actions, 0);
+    assert!(patch::apply(SOURCE, &bps(4, &actions)).is_err());
+}
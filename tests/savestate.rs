@@ -0,0 +1,147 @@
+//! Save states: encoded and decoded without loss, still readable in older versions of the
+//! format, and only loaded into a machine they fit.
+
+use chip8rs::savestate::{MegaChipRegisters, State};
+use chip8rs::Chip8;
+
+const SPACE_INVADERS: &[u8] = include_bytes!("../roms/Space Invaders [David Winter].ch8");
+
+/// The state of Space Invaders a few frames into its title screen.
+fn state() -> State {
+    let mut chip8 = Chip8::from_bytes(SPACE_INVADERS);
+    chip8.set_seed(1);
+    for _ in 0..30 {
+        chip8.step_frame(10).unwrap();
+    }
+    chip8.set_key(5, true);
+    chip8.save_state()
+}
+
+#[test]
+fn round_trip() {
+    let state = state();
+    assert_eq!(State::decode(&state.encode()).unwrap(), state);
+}
+
+#[test]
+fn round_trip_with_audio_pattern() {
+    let state = State {
+        rpl: [7; 16],
+        planes: 3,
+        pitch: 100,
+        audio_pattern: Some([0xA5; 16]),
+        ..state()
+    };
+    assert_eq!(State::decode(&state.encode()).unwrap(), state);
+}
+
+#[test]
+fn round_trip_with_megachip_registers() {
+    let state = State {
+        megachip: Some(MegaChipRegisters {
+            colors: (0..255).map(|c| [c as u8, 0x80, 0xFF - c as u8]).collect(),
+            sprite_width: 256,
+            sprite_height: 16,
+            collision_color: Some(7),
+        }),
+        ..state()
+    };
+    assert_eq!(State::decode(&state.encode()).unwrap(), state);
+}
+
+#[test]
+fn load_round_trip() {
+    let state = state();
+    let mut chip8 = Chip8::from_bytes(SPACE_INVADERS);
+    chip8.load_state(&state).unwrap();
+    assert_eq!(chip8.save_state(), state);
+}
+
+#[test]
+fn megachip_load_round_trip() {
+    let mut chip8 = Chip8::from_bytes_megachip(SPACE_INVADERS);
+    let gfx = &mut chip8.interconnect.gfx;
+    gfx.set_megachip(true);
+    gfx.load_palette(&[0xFF, 0x10, 0x20, 0x30]);
+    gfx.set_sprite_width(0);
+    gfx.set_sprite_height(8);
+    gfx.set_collision_color(1);
+    let state = chip8.save_state();
+
+    let mut other = Chip8::from_bytes_megachip(SPACE_INVADERS);
+    other.load_state(&state).unwrap();
+    assert_eq!(other.save_state(), state);
+    assert_eq!(other.interconnect.gfx.sprite_size(), (256, 8));
+    assert_eq!(other.interconnect.gfx.color(1), [0x10, 0x20, 0x30, 0xFF]);
+}
+
+#[test]
+fn decodes_version_1() {
+    let state = state();
+    let mut bytes = state.encode();
+    // Version 1 ends before the RPL flags, planes, pitch, audio pattern flag and Mega-Chip flag.
+    bytes.truncate(bytes.len() - 20);
+    bytes[4..6].copy_from_slice(&1u16.to_le_bytes());
+    let expected = State {
+        rpl: [0; 16],
+        planes: 1,
+        pitch: 64,
+        audio_pattern: None,
+        ..state
+    };
+    assert_eq!(State::decode(&bytes).unwrap(), expected);
+}
+
+#[test]
+fn decodes_version_2() {
+    let state = state();
+    let mut bytes = state.encode();
+    // Version 2 ends before the Mega-Chip flag.
+    bytes.pop();
+    bytes[4..6].copy_from_slice(&2u16.to_le_bytes());
+    assert_eq!(State::decode(&bytes).unwrap(), state);
+}
+
+#[test]
+fn rejects_newer_version() {
+    let mut bytes = state().encode();
+    bytes[4..6].copy_from_slice(&4u16.to_le_bytes());
+    assert!(State::decode(&bytes).is_err());
+}
+
+#[test]
+fn rejects_truncated_state() {
+    let bytes = state().encode();
+    assert!(State::decode(&bytes[..bytes.len() - 1]).is_err());
+}
+
+#[test]
+fn load_rejects_other_ram_size() {
+    let state = Chip8::from_bytes_xochip(SPACE_INVADERS).save_state();
+    assert!(Chip8::from_bytes(SPACE_INVADERS)
+        .load_state(&state)
+        .is_err());
+}
+
+#[test]
+fn load_rejects_megachip_state_on_other_machine() {
+    let state = Chip8::from_bytes_megachip(SPACE_INVADERS).save_state();
+    assert!(Chip8::from_bytes_xochip(SPACE_INVADERS)
+        .load_state(&state)
+        .is_err());
+}
+
+#[test]
+fn load_rejects_bad_display_size() {
+    let mut chip8 = Chip8::from_bytes(SPACE_INVADERS);
+    let unknown_size = State {
+        display_width: 50,
+        display: vec![0; 50 * 32],
+        ..state()
+    };
+    assert!(chip8.load_state(&unknown_size).is_err());
+
+    let mut short_display = state();
+    short_display.display.pop();
+    assert!(chip8.load_state(&short_display).is_err());
+}
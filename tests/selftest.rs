@@ -0,0 +1,61 @@
+//! The self-test reads the results of the suite's ROMs off the marks they draw.
+
+use chip8rs::program::*;
+use chip8rs::selftest::{self, Verdict, MARKS};
+use chip8rs::Chip8;
+
+/// A ROM drawing `marks` in a row, with the sprites of both marks in it, and the machine after
+/// running it.
+fn run(marks: &[bool]) -> (Vec<u8>, Chip8) {
+    let mut p = Program::new();
+    let pass = p.label();
+    let fail = p.label();
+    let end = p.label();
+    p.ld(V1, 2);
+    for (i, &passed) in marks.iter().enumerate() {
+        let sprite = if passed { pass } else { fail };
+        p.ld(V0, 2 + 10 * i as u8).ld_i(sprite).drw(V0, V1, 4);
+    }
+    p.bind(end).jp(end);
+    p.bind(pass).db(MARKS[0].pass);
+    p.bind(fail).db(MARKS[0].fail);
+    let rom = p.assemble().expect("the labels are bound");
+
+    let mut chip8 = Chip8::from_bytes(&rom);
+    chip8.run(1000, 1000 * 60).unwrap();
+    (rom, chip8)
+}
+
+#[test]
+fn all_marked_as_passing() {
+    let (rom, chip8) = run(&[true, true, true]);
+    assert_eq!(
+        selftest::read_marks(&rom, &chip8),
+        Some(Verdict::Pass { passed: 3 })
+    );
+}
+
+#[test]
+fn some_marked_as_failing() {
+    let (rom, chip8) = run(&[true, false, true]);
+    assert_eq!(
+        selftest::read_marks(&rom, &chip8),
+        Some(Verdict::Fail {
+            failed: 1,
+            total: 3
+        })
+    );
+}
+
+#[test]
+fn no_marks_drawn() {
+    let (rom, chip8) = run(&[]);
+    assert_eq!(selftest::read_marks(&rom, &chip8), None);
+}
+
+#[test]
+fn unknown_marks() {
+    // A ROM without the sprites of the marks isn't judged by them, whatever is on the display.
+    let (_, chip8) = run(&[true]);
+    assert_eq!(selftest::read_marks(&[0x12, 0x00], &chip8), None);
+}
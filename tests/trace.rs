@@ -0,0 +1,77 @@
+//! Traces show every register an instruction changed, including the timers, and the instruction
+//! that faulted.
+
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+
+use chip8rs::config;
+use chip8rs::program::*;
+use chip8rs::trace::{TraceFormat, Tracer};
+use chip8rs::Chip8;
+
+/// A trace output that can still be read once the tracer owns it.
+#[derive(Clone, Default)]
+struct Output(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The trace lines of a program that sets the timers, then returns without a call.
+fn trace(format: TraceFormat) -> Vec<String> {
+    let mut p = Program::new();
+    p.ld(V0, 5).ld_dt(V0).ld_st(V0).ret();
+    let rom = p.assemble().expect("there are no labels");
+
+    let output = Output::default();
+    let mut chip8 = Chip8::from_bytes(&rom);
+    chip8.enable_trace(Tracer::new(output.clone(), format));
+    assert!(chip8.run(10, config::DEFAULT_IPS).is_err());
+    chip8.take_tracer().unwrap().finish().unwrap();
+
+    let bytes = output.0.lock().unwrap().clone();
+    String::from_utf8(bytes)
+        .unwrap()
+        .lines()
+        .map(String::from)
+        .collect()
+}
+
+#[test]
+fn compact_timers_and_fault() {
+    let lines = trace(TraceFormat::Compact);
+    assert_eq!(lines.len(), 4);
+    assert!(lines[0].ends_with(" v0 00->05"), "{}", lines[0]);
+    assert!(lines[1].ends_with(" dt 00->05"), "{}", lines[1]);
+    assert!(lines[2].ends_with(" st 00->05"), "{}", lines[2]);
+    assert!(lines[3].starts_with("0206 00EE "), "{}", lines[3]);
+    assert!(lines[3].ends_with(" fault: stack underflow: return without a call"));
+}
+
+#[test]
+fn json_timers_and_fault() {
+    let lines = trace(TraceFormat::Json);
+    assert_eq!(lines.len(), 4);
+    assert!(
+        lines[1].ends_with(",\"changes\":{\"dt\":[0,5]}}"),
+        "{}",
+        lines[1]
+    );
+    assert!(
+        lines[2].ends_with(",\"changes\":{\"st\":[0,5]}}"),
+        "{}",
+        lines[2]
+    );
+    assert!(
+        lines[3].ends_with(",\"changes\":{},\"fault\":\"stack underflow: return without a call\"}"),
+        "{}",
+        lines[3]
+    );
+}
@@ -0,0 +1,89 @@
+//! Programs that run the PC, I or a return address past the end of the 16-bit address space,
+//! found by the `cpu` fuzz target. They wrap around to the start of RAM rather than panic.
+
+use chip8rs::config::{self, Quirks};
+use chip8rs::detect::Profile;
+use chip8rs::Chip8;
+
+/// An XO-CHIP with its 64K of RAM, with `code` at the very end of it and the PC on it.
+fn xochip_at_end(code: &[u8]) -> Chip8 {
+    let mut chip8 = Chip8::from_bytes_xochip(&[]);
+    chip8.set_quirks(Quirks::for_profile(Profile::XoChip));
+    let start = config::XO_RAM_SIZE - code.len();
+    for (offset, byte) in code.iter().enumerate() {
+        chip8.interconnect.ram.write(start + offset, *byte).unwrap();
+    }
+    chip8.cpu.set_pc(start as u16);
+    chip8
+}
+
+#[test]
+fn pc_wraps_after_last_instruction() {
+    // v0 := 0
+    let mut chip8 = xochip_at_end(&[0x60, 0x00]);
+    chip8.step().unwrap();
+    assert_eq!(chip8.cpu.pc(), 0);
+}
+
+#[test]
+fn skip_wraps_past_end() {
+    // if v0 != 0 then, skipping the instruction at 0x0000
+    let mut chip8 = xochip_at_end(&[0x30, 0x00]);
+    chip8.step().unwrap();
+    assert_eq!(chip8.cpu.pc(), 2);
+}
+
+#[test]
+fn long_load_wraps_past_end() {
+    // i := long 0x1234
+    let mut chip8 = xochip_at_end(&[0xF0, 0x00, 0x12, 0x34]);
+    chip8.step().unwrap();
+    assert_eq!((chip8.cpu.pc(), chip8.cpu.i()), (0, 0x1234));
+}
+
+/// `i := long 0xFFFF` then `save vF` or `load vF`, which walk I past 0xFFFF.
+fn load_store_at_end(opcode: u8, keeps_i: bool, i_by_x: bool) -> Chip8 {
+    let mut chip8 = Chip8::from_bytes_xochip(&[0xF0, 0x00, 0xFF, 0xFF, 0xFF, opcode]);
+    chip8.set_quirks(Quirks {
+        load_store_keeps_i: keeps_i,
+        load_store_i_by_x: i_by_x,
+        ..Quirks::for_profile(Profile::XoChip)
+    });
+    chip8.step().unwrap();
+    chip8.step().unwrap();
+    chip8
+}
+
+#[test]
+fn store_wraps_i() {
+    assert_eq!(load_store_at_end(0x55, false, false).cpu.i(), 0x000F);
+    assert_eq!(load_store_at_end(0x55, false, true).cpu.i(), 0x000E);
+    assert_eq!(load_store_at_end(0x55, true, false).cpu.i(), 0xFFFF);
+}
+
+#[test]
+fn restore_wraps_i() {
+    assert_eq!(load_store_at_end(0x65, false, false).cpu.i(), 0x000F);
+    assert_eq!(load_store_at_end(0x65, false, true).cpu.i(), 0x000E);
+    assert_eq!(load_store_at_end(0x65, true, false).cpu.i(), 0xFFFF);
+}
+
+#[test]
+fn return_to_overwritten_address_in_ram_stack_wraps() {
+    let rom = [
+        0x22, 0x06, // call 0x206
+        0x00, 0x00, //
+        0x00, 0x00, //
+        0x60, 0xFF, // v0 := 0xFF
+        0x61, 0xFF, // v1 := 0xFF
+        0xA1, 0xFE, // i := 0x1FE, the first entry of the stack
+        0xF1, 0x55, // save v1
+        0x00, 0xEE, // return
+    ];
+    let mut chip8 = Chip8::from_bytes(&rom);
+    chip8.set_stack(16, true).unwrap();
+    for _ in 0..6 {
+        chip8.step().unwrap();
+    }
+    assert_eq!(chip8.cpu.pc(), 0x0001);
+}